@@ -0,0 +1,204 @@
+//! Board description parser and pin setup generator, run by `build.rs` and tested by the host
+//! tools. A board file maps named signals to pins and modes:
+//!
+//! ```toml
+//! name = "v1"
+//! [signals]
+//! can_stby = { pin = "PA15", mode = "output-push-pull" }
+//! estop_a = { pin = "PB0", mode = "input-pull-up", features = ["module-button"] }
+//! ```
+//!
+//! Signals with `features` only exist when any of them is enabled, so that modules can reuse
+//! pins. Two signals present at the same time on one pin are an error.
+
+use std::fmt::Write;
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum Mode {
+    InputFloating,
+    InputPullUp,
+    InputPullDown,
+    OutputPushPull,
+    OutputOpenDrain,
+    Analog,
+    /// Alternate function 0..=7
+    Alternate(u8),
+}
+
+impl Mode {
+    fn parse(s: &str) -> Option<Mode> {
+        Some(match s {
+            "input-floating" => Mode::InputFloating,
+            "input-pull-up" => Mode::InputPullUp,
+            "input-pull-down" => Mode::InputPullDown,
+            "output-push-pull" => Mode::OutputPushPull,
+            "output-open-drain" => Mode::OutputOpenDrain,
+            "analog" => Mode::Analog,
+            _ => {
+                let af = s.strip_prefix("af")?.parse().ok()?;
+                if af > 7 {
+                    return None;
+                }
+                Mode::Alternate(af)
+            }
+        })
+    }
+
+    fn type_name(&self) -> String {
+        match self {
+            Mode::InputFloating => "Input<Floating>".into(),
+            Mode::InputPullUp => "Input<PullUp>".into(),
+            Mode::InputPullDown => "Input<PullDown>".into(),
+            Mode::OutputPushPull => "Output<PushPull>".into(),
+            Mode::OutputOpenDrain => "Output<OpenDrain>".into(),
+            Mode::Analog => "Analog".into(),
+            Mode::Alternate(af) => format!("Alternate<AF{}>", af),
+        }
+    }
+
+    fn conversion(&self) -> String {
+        match self {
+            Mode::InputFloating => "into_floating_input".into(),
+            Mode::InputPullUp => "into_pull_up_input".into(),
+            Mode::InputPullDown => "into_pull_down_input".into(),
+            Mode::OutputPushPull => "into_push_pull_output".into(),
+            Mode::OutputOpenDrain => "into_open_drain_output".into(),
+            Mode::Analog => "into_analog".into(),
+            Mode::Alternate(af) => format!("into_alternate_af{}", af),
+        }
+    }
+}
+
+/// Ports split in `main.rs`
+pub const PORTS: [char; 3] = ['a', 'b', 'c'];
+
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct Signal {
+    pub name: String,
+    /// Lowercase port letter
+    pub port: char,
+    pub pin: u8,
+    pub mode: Mode,
+    pub features: Vec<String>,
+}
+
+impl Signal {
+    /// As in HAL, e.g. PA15
+    pub fn pin_name(&self) -> String {
+        format!("P{}{}", self.port.to_ascii_uppercase(), self.pin)
+    }
+
+    /// CamelCase type alias for the signal
+    pub fn type_name(&self) -> String {
+        self.name.split('_').map(|word| {
+            let mut chars = word.chars();
+            match chars.next() {
+                Some(first) => first.to_ascii_uppercase().to_string() + chars.as_str(),
+                None => String::new(),
+            }
+        }).collect()
+    }
+
+    fn is_present(&self, enabled: &dyn Fn(&str) -> bool) -> bool {
+        self.features.is_empty() || self.features.iter().any(|f| enabled(f))
+    }
+}
+
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct Board {
+    pub name: String,
+    /// Sorted by name
+    pub signals: Vec<Signal>,
+}
+
+pub fn parse(source: &str) -> Result<Board, String> {
+    let root: toml::Value = source.parse().map_err(|e| format!("{}", e))?;
+    let name = root.get("name").and_then(|n| n.as_str()).ok_or("board name missing")?.to_string();
+    let table = root.get("signals").and_then(|s| s.as_table()).ok_or("[signals] table missing")?;
+    let mut signals = Vec::new();
+    for (signal, value) in table {
+        let field = |key: &str| value.get(key).and_then(|v| v.as_str()).ok_or(format!("{}: {} missing", signal, key));
+        let pin = field("pin")?;
+        let (port, pin) = parse_pin(pin).ok_or(format!("{}: no such pin {}", signal, pin))?;
+        let mode = field("mode")?;
+        let mode = Mode::parse(mode).ok_or(format!("{}: unknown mode {}", signal, mode))?;
+        let features = match value.get("features") {
+            Some(list) => list.as_array()
+                .and_then(|list| list.iter().map(|f| f.as_str().map(String::from)).collect::<Option<Vec<_>>>())
+                .ok_or(format!("{}: features must be a list of strings", signal))?,
+            None => Vec::new(),
+        };
+        if !signal.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_') {
+            return Err(format!("{}: signal names are snake_case", signal));
+        }
+        signals.push(Signal { name: signal.clone(), port, pin, mode, features });
+    }
+    Ok(Board { name, signals })
+}
+
+fn parse_pin(pin: &str) -> Option<(char, u8)> {
+    let mut chars = pin.strip_prefix('P')?.chars();
+    let port = chars.next()?.to_ascii_lowercase();
+    let number: u8 = chars.as_str().parse().ok()?;
+    if PORTS.contains(&port) && number <= 15 {
+        Some((port, number))
+    } else {
+        None
+    }
+}
+
+impl Board {
+    /// Signals present with the `enabled` features, or every pin claimed more than once.
+    pub fn select(&self, enabled: &dyn Fn(&str) -> bool) -> Result<Vec<&Signal>, String> {
+        let present: Vec<&Signal> = self.signals.iter().filter(|s| s.is_present(enabled)).collect();
+        let mut conflicts = Vec::new();
+        for (i, a) in present.iter().enumerate() {
+            for b in &present[i + 1..] {
+                if (a.port, a.pin) == (b.port, b.pin) {
+                    conflicts.push(format!("{} claimed by both {} and {}", a.pin_name(), a.name, b.name));
+                }
+            }
+        }
+        if conflicts.is_empty() {
+            Ok(present)
+        } else {
+            Err(conflicts.join(", "))
+        }
+    }
+}
+
+/// Type alias per signal and `Pins`, configured from the split ports.
+pub fn generate(board: &Board, signals: &[&Signal], source_path: &str) -> String {
+    let mut out = String::new();
+    writeln!(out, "// Generated by build.rs from {}, do not edit.", source_path).unwrap();
+    writeln!(out).unwrap();
+    writeln!(out, "#[allow(unused_imports)]").unwrap();
+    writeln!(out, "use crate::hal::gpio::{{self, Alternate, Analog, Floating, Input, OpenDrain, Output, PullDown, PullUp, PushPull}};").unwrap();
+    writeln!(out, "#[allow(unused_imports)]").unwrap();
+    writeln!(out, "use crate::hal::gpio::{{AF0, AF1, AF2, AF3, AF4, AF5, AF6, AF7}};").unwrap();
+    writeln!(out).unwrap();
+    writeln!(out, "pub const NAME: &str = {:?};", board.name).unwrap();
+    writeln!(out).unwrap();
+    for s in signals {
+        writeln!(out, "pub type {} = gpio::gpio{}::{}<{}>;", s.type_name(), s.port, s.pin_name(), s.mode.type_name()).unwrap();
+    }
+    writeln!(out).unwrap();
+    writeln!(out, "pub struct Pins {{").unwrap();
+    for s in signals {
+        writeln!(out, "    pub {}: {},", s.name, s.type_name()).unwrap();
+    }
+    writeln!(out, "}}").unwrap();
+    writeln!(out).unwrap();
+    writeln!(out, "impl Pins {{").unwrap();
+    writeln!(out, "    #[allow(unused_variables)]").unwrap();
+    let ports: Vec<String> = PORTS.iter().map(|p| format!("gpio{}: gpio::gpio{}::Parts", p, p)).collect();
+    writeln!(out, "    pub fn new({}) -> Self {{", ports.join(", ")).unwrap();
+    writeln!(out, "        cortex_m::interrupt::free(|cs| Pins {{").unwrap();
+    for s in signals {
+        writeln!(out, "            {}: gpio{}.{}.{}(cs),", s.name, s.port, s.pin_name().to_lowercase(), s.mode.conversion()).unwrap();
+    }
+    writeln!(out, "        }})").unwrap();
+    writeln!(out, "    }}").unwrap();
+    writeln!(out, "}}").unwrap();
+    out
+}
@@ -0,0 +1,46 @@
+//! Copies `memory.x` into the output directory, so that the linker can find it, and generates the
+//! CAN pin setup from the same board file as the application.
+
+use std::env;
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+#[path = "../boards/gen.rs"]
+mod board_gen;
+
+/// Built when `BOARD` is not set, same as the application
+const DEFAULT_BOARD: &str = "v1";
+/// Board signals the bootloader configures, the rest are left in reset state
+const SIGNALS: [&str; 7] = ["can_rx", "can_tx", "can_stby", "mcp_sck", "mcp_miso", "mcp_mosi", "mcp_cs"];
+
+fn main() {
+    let out = &PathBuf::from(env::var_os("OUT_DIR").unwrap());
+    File::create(out.join("memory.x"))
+        .unwrap()
+        .write_all(include_bytes!("memory.x"))
+        .unwrap();
+    println!("cargo:rustc-link-search={}", out.display());
+    println!("cargo:rerun-if-changed=memory.x");
+
+    emit_board(out);
+}
+
+/// CAN pins of `boards/$BOARD.toml` for `src/board.rs`.
+fn emit_board(out: &Path) {
+    let name = env::var("BOARD").unwrap_or_else(|_| String::from(DEFAULT_BOARD));
+    let path = format!("../boards/{}.toml", name);
+    let source = std::fs::read_to_string(&path).unwrap_or_else(|e| panic!("{}: {}", path, e));
+    let board = board_gen::parse(&source).unwrap_or_else(|e| panic!("{}: {}", path, e));
+    let enabled = |feature: &str| env::var_os(format!("CARGO_FEATURE_{}", feature.to_uppercase().replace('-', "_"))).is_some();
+    let signals = board.select(&enabled).unwrap_or_else(|e| panic!("{}: {}", path, e));
+    let signals: Vec<_> = signals.into_iter().filter(|s| SIGNALS.contains(&s.name.as_str())).collect();
+    File::create(out.join("board.rs"))
+        .unwrap()
+        .write_all(board_gen::generate(&board, &signals, &path).as_bytes())
+        .unwrap();
+
+    println!("cargo:rerun-if-changed={}", path);
+    println!("cargo:rerun-if-changed=../boards/gen.rs");
+    println!("cargo:rerun-if-env-changed=BOARD");
+}
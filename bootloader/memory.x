@@ -0,0 +1,8 @@
+MEMORY
+{
+  /* NOTE 1 K = 1 KiBi = 1024 bytes */
+  /* Bootloader gets the first 12K, see src/boot.rs of the application */
+  FLASH : ORIGIN = 0x08000000, LENGTH = 12K
+  /* First 192 bytes are for the vector table copy, last 64 for the boot request and 128 below for the crash report */
+  RAM : ORIGIN = 0x20000000 + 192, LENGTH = 8K - 192 - 64 - 128
+}
@@ -0,0 +1,4 @@
+//! CAN pins of the board revision selected with `BOARD` at build time, generated from the same
+//! `boards/*.toml` as the application's.
+
+include!(concat!(env!("OUT_DIR"), "/board.rs"));
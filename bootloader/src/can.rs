@@ -0,0 +1,141 @@
+//! Polling CAN drivers, interrupts are not used in the bootloader.
+//! Bit timings and filters are the same as in the application `canbus` module.
+
+use crate::{hal, pac, config, SYS_CLK_HZ};
+use crate::bit_timing::{self, Bitrate, BitTiming};
+use vhrdcan::{Frame, FrameId};
+
+pub trait CanBus {
+    /// Returns false if there is no free mailbox, frame should be retried later.
+    fn send(&mut self, frame: &Frame<8>) -> bool;
+    fn receive(&mut self) -> Option<Frame<8>>;
+}
+
+#[cfg(feature = "can-stm")]
+mod stm {
+    use super::*;
+    use hal::can::bxcan::{self, Id, StandardId, ExtendedId, Data, filter::{BankConfig, Mask32}};
+    use crate::board::{CanTx, CanRx};
+
+    pub struct StmCan(bxcan::Can<hal::can::CanInstance<CanTx, CanRx>>);
+
+    // Fallback of a stored bitrate that is not reachable
+    const_assert!(BitTiming::calculate(SYS_CLK_HZ, config::CAN_BITRATE.bps(), config::CAN_SAMPLE_POINT_PERMILLE, &bit_timing::BXCAN).is_some());
+
+    impl StmCan {
+        /// Falls back to the default bitrate if the stored one is not reachable from SYS_CLK_HZ.
+        pub fn new(can: pac::CAN, tx: CanTx, rx: CanRx, bitrate: Bitrate, rcc: &mut hal::rcc::Rcc) -> Self {
+            let can = hal::can::CanInstance::new(can, tx, rx, rcc);
+            let mut can = bxcan::Can::new(can);
+            let timing = |bitrate: Bitrate| BitTiming::calculate(SYS_CLK_HZ, bitrate.bps(), config::CAN_SAMPLE_POINT_PERMILLE, &bit_timing::BXCAN);
+            let timing = timing(bitrate).or_else(|| timing(config::CAN_BITRATE)).unwrap();
+            can.modify_config()
+                .set_loopback(false)
+                .set_silent(false)
+                .set_bit_timing(timing.bxcan_btr());
+            can.modify_filters().enable_bank(0, BankConfig::Mask32(Mask32::accept_all()));
+            can.enable().ok();
+            StmCan(can)
+        }
+    }
+
+    impl CanBus for StmCan {
+        fn send(&mut self, frame: &Frame<8>) -> bool {
+            let id = match frame.id {
+                FrameId::Standard(sid) => Id::Standard(StandardId::new(sid.inner()).unwrap()),
+                FrameId::Extended(eid) => Id::Extended(ExtendedId::new(eid.inner()).unwrap()),
+            };
+            let frame = bxcan::Frame::new_data(id, Data::new(frame.data()).unwrap());
+            // Do not let bxCAN push out a pending frame, ordering within a transfer must be kept
+            match self.0.transmit(&frame) {
+                Ok(None) => true,
+                Ok(Some(pushed_out)) => {
+                    self.0.transmit(&pushed_out).ok();
+                    false
+                }
+                Err(_) => false,
+            }
+        }
+
+        fn receive(&mut self) -> Option<Frame<8>> {
+            use vhrdcan::id::{StandardId, ExtendedId};
+            let frame = self.0.receive().ok()?;
+            let id = match frame.id() {
+                Id::Standard(sid) => FrameId::Standard(unsafe { StandardId::new_unchecked(sid.as_raw()) }),
+                Id::Extended(eid) => FrameId::Extended(unsafe { ExtendedId::new_unchecked(eid.as_raw()) }),
+            };
+            Frame::new(id, frame.data()?)
+        }
+    }
+}
+#[cfg(feature = "can-stm")]
+pub use stm::*;
+
+#[cfg(feature = "can-mcp25625")]
+mod mcp {
+    use super::*;
+    use crate::board::{McpSck, McpMiso, McpMosi, McpCs};
+    use hal::spi::{Spi, EightBit};
+    use hal::time::MegaHertz;
+    use mcp25625::{MCP25625, MCP25625Config, FiltersConfig, McpOperationMode, McpReceiveBuffer, McpPriority, TxBufferChoice, McpErrorKind};
+
+    const SPI_FREQ: MegaHertz = MegaHertz(1);
+
+    pub type Mcp25625Instance = MCP25625<Spi<pac::SPI1, McpSck, McpMiso, McpMosi, EightBit>, McpCs>;
+
+    pub struct McpCan(Mcp25625Instance);
+
+    // Fallback of a stored bitrate that is not reachable
+    const_assert!(BitTiming::calculate(config::MCP25625_OSC_HZ, config::CAN_BITRATE.bps(), config::CAN_SAMPLE_POINT_PERMILLE, &bit_timing::MCP25625).is_some());
+
+    impl McpCan {
+        pub fn new(
+            spi: pac::SPI1,
+            sck: McpSck,
+            miso: McpMiso,
+            mosi: McpMosi,
+            cs: McpCs,
+            bitrate: Bitrate,
+            rcc: &mut hal::rcc::Rcc
+        ) -> Result<Self, McpErrorKind> {
+            let spi = Spi::spi1(spi, (sck, miso, mosi), embedded_hal::spi::MODE_0, SPI_FREQ, rcc);
+            let mut mcp25625 = MCP25625::new(spi, cs, SPI_FREQ.0 * 1_000_000, rcc.clocks.sysclk().0);
+            let timing = |bitrate: Bitrate| BitTiming::calculate(config::MCP25625_OSC_HZ, bitrate.bps(), config::CAN_SAMPLE_POINT_PERMILLE, &bit_timing::MCP25625);
+            let timing = timing(bitrate).or_else(|| timing(config::CAN_BITRATE)).unwrap()
+                .with_sjw(bit_timing::MCP25625_SJW)
+                .mcp25625();
+            mcp25625.apply_config(MCP25625Config {
+                brp: timing.brp,
+                prop_seg: timing.prop_seg,
+                ph_seg1: timing.ph_seg1,
+                ph_seg2: timing.ph_seg2,
+                sync_jump_width: timing.sync_jump_width,
+                rollover_to_buffer1: true,
+                filters_config: FiltersConfig::ReceiveAll,
+                operation_mode: McpOperationMode::Normal
+            })?;
+            Ok(McpCan(mcp25625))
+        }
+    }
+
+    impl CanBus for McpCan {
+        fn send(&mut self, frame: &Frame<8>) -> bool {
+            // Single buffer keeps frames of a transfer in order
+            self.0.send(frame.as_frame_ref(), TxBufferChoice::OnlyOne(0), McpPriority::Highest).is_ok()
+        }
+
+        fn receive(&mut self) -> Option<Frame<8>> {
+            let intf = self.0.interrupt_flags();
+            let frame = if intf.rx0if_is_set() {
+                self.0.receive(McpReceiveBuffer::Buffer0)
+            } else if intf.rx1if_is_set() {
+                self.0.receive(McpReceiveBuffer::Buffer1)
+            } else {
+                return None;
+            };
+            Some(frame)
+        }
+    }
+}
+#[cfg(feature = "can-mcp25625")]
+pub use mcp::*;
@@ -0,0 +1,25 @@
+//! Subset of the application `config` used by shared modules, values must match it.
+
+use crate::bit_timing::Bitrate;
+
+pub const NVCONFIG_FLASH_ADDRESS: usize = 0x0800_0000 + 64 * 1024 - NVCONFIG_FLASH_SIZE;
+pub const NVCONFIG_FLASH_SIZE: usize = 12 * 1024;
+#[cfg(feature = "f051c8u")]
+pub const FLASH_PAGE_SIZE: usize = 1024;
+#[cfg(feature = "f072c8u")]
+pub const FLASH_PAGE_SIZE: usize = 2048;
+
+/// Used when the application never stored a bitrate
+pub const CAN_BITRATE: Bitrate = Bitrate::Mbps1;
+pub const CAN_SAMPLE_POINT_PERMILLE: u32 = 875;
+#[cfg(feature = "can-mcp25625")]
+pub const MCP25625_OSC_HZ: u32 = 16_000_000;
+
+/// Used when the application never stored a node ID
+pub const DEFAULT_NODE_ID: u8 = 125;
+/// Heartbeat subject and period, same as application health check
+pub const HEARTBEAT_SUBJECT_ID: u16 = 10;
+pub const HEARTBEAT_PERIOD_MS: u32 = 1000;
+/// file.Read request is repeated after this time without a response
+pub const FILE_READ_TIMEOUT_MS: u32 = 500;
+pub const FILE_READ_RETRIES: u8 = 10;
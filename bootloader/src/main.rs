@@ -0,0 +1,118 @@
+//! UAVCAN CAN bootloader.
+//!
+//! Starts the application right away, unless it asked to stay in the bootloader or to update
+//! itself via the boot request, or the image left by the last update is not valid. Otherwise
+//! sends heartbeats in bootloader mode and waits for uavcan.node.ExecuteCommand.
+
+#![no_std]
+#![no_main]
+#![feature(const_option)]
+
+#[macro_use]
+extern crate static_assertions;
+
+use cortex_m_rt::entry;
+use stm32f0xx_hal as hal;
+use hal::stm32 as pac;
+use hal::prelude::*;
+
+#[allow(dead_code)]
+mod board;
+mod can;
+mod config;
+mod nvconfig;
+mod update;
+#[path = "../../src/boot.rs"]
+mod boot;
+#[allow(dead_code)]
+#[path = "../../src/uavcan/mod.rs"]
+mod uavcan;
+#[allow(dead_code)]
+#[path = "../../src/bit_timing.rs"]
+mod bit_timing;
+
+use boot::{BootRequest, ImageDescriptor};
+use nvconfig::{key, NvStore, flash::InternalFlash};
+
+const SYS_CLK_HZ: u32 = 8_000_000;
+
+#[entry]
+fn main() -> ! {
+    let request = BootRequest::take();
+    let store = NvStore::new(InternalFlash::nvconfig());
+    if request.is_none() && is_app_bootable(&store) {
+        unsafe { start_app() }
+    }
+
+    let mut dp = pac::Peripherals::take().unwrap();
+    let cp = cortex_m::Peripherals::take().unwrap();
+    let mut rcc = dp.RCC.configure().sysclk(SYS_CLK_HZ.hz()).freeze(&mut dp.FLASH);
+    let mut pins = board::Pins::new(dp.GPIOA.split(&mut rcc), dp.GPIOB.split(&mut rcc), dp.GPIOC.split(&mut rcc));
+    pins.can_stby.set_low().ok();
+
+    let bitrate = nvconfig::can_bitrate(&store);
+    #[cfg(feature = "can-stm")]
+    let mut bus = can::StmCan::new(dp.CAN, pins.can_tx, pins.can_rx, bitrate, &mut rcc);
+    #[cfg(feature = "can-mcp25625")]
+    let mut bus = match can::McpCan::new(dp.SPI1, pins.mcp_sck, pins.mcp_miso, pins.mcp_mosi, pins.mcp_cs, bitrate, &mut rcc) {
+        Ok(bus) => bus,
+        Err(_) => cortex_m::peripheral::SCB::sys_reset(),
+    };
+
+    // SysTick wraps every millisecond
+    let mut syst = cp.SYST;
+    syst.set_clock_source(cortex_m::peripheral::syst::SystClkSource::Core);
+    syst.set_reload(SYS_CLK_HZ / 1000 - 1);
+    syst.clear_current();
+    syst.enable_counter();
+
+    let mut updater = update::Updater::new(store, request);
+    let mut now_ms = 0u32;
+    loop {
+        if syst.has_wrapped() {
+            now_ms = now_ms.wrapping_add(1);
+        }
+        updater.poll(&mut bus, now_ms);
+    }
+}
+
+/// Image written by the bootloader must still match its descriptor, CRC is recomputed on every
+/// boot (a fraction of a second at 8MHz) to catch flash corruption. One flashed with a debugger has
+/// no descriptor and is started if its vector table looks sane.
+fn is_app_bootable(store: &NvStore) -> bool {
+    let mut descriptor = [0u8; 8];
+    let descriptor_ok = match store.read(key::IMAGE_DESCRIPTOR, key::RECORD_VERSION, &mut descriptor) {
+        Some(_) => ImageDescriptor::from_bytes(&descriptor).matches_flash(),
+        None => true,
+    };
+    descriptor_ok && boot::app_vectors_look_valid()
+}
+
+/// Cortex-M0 cannot relocate the vector table, so application vectors are copied to the start of
+/// RAM, which is then mapped at 0. Must be called before any peripheral is touched.
+unsafe fn start_app() -> ! {
+    let src = boot::APP_ADDRESS as *const u32;
+    let dst = boot::RAM_ADDRESS as *mut u32;
+    for i in 0..boot::VECTORS_SIZE / 4 {
+        core::ptr::write_volatile(dst.add(i), core::ptr::read_volatile(src.add(i)));
+    }
+    let rcc = &*pac::RCC::ptr();
+    rcc.apb2enr.modify(|_, w| w.syscfgen().set_bit());
+    let syscfg = &*pac::SYSCFG::ptr();
+    syscfg.cfgr1.modify(|_, w| w.mem_mode().bits(0b11));
+    let sp = core::ptr::read_volatile(src);
+    let reset = core::ptr::read_volatile(src.add(1));
+    core::arch::asm!(
+        "msr msp, {sp}",
+        "bx {reset}",
+        sp = in(reg) sp,
+        reset = in(reg) reset,
+        options(noreturn)
+    )
+}
+
+#[inline(never)]
+#[panic_handler]
+fn panic(_info: &core::panic::PanicInfo) -> ! {
+    cortex_m::peripheral::SCB::sys_reset();
+}
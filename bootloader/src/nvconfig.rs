@@ -0,0 +1,22 @@
+//! Parts of the application non-volatile config store that the bootloader needs.
+
+#[path = "../../src/nvconfig/storage.rs"]
+pub mod storage;
+#[path = "../../src/nvconfig/flash.rs"]
+pub mod flash;
+#[path = "../../src/nvconfig/key.rs"]
+pub mod key;
+
+use crate::config;
+use crate::bit_timing::Bitrate;
+
+pub type NvStore = storage::Store<flash::InternalFlash>;
+
+/// Bitrate the application is configured for, the bootloader has to be reachable on the same bus.
+pub fn can_bitrate(store: &NvStore) -> Bitrate {
+    let mut buf = [0u8; 4];
+    match store.read(key::CAN_BITRATE, key::RECORD_VERSION, &mut buf) {
+        Some(4) => Bitrate::from_bps(u32::from_le_bytes(buf)).unwrap_or(config::CAN_BITRATE),
+        _ => config::CAN_BITRATE,
+    }
+}
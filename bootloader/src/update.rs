@@ -0,0 +1,291 @@
+//! Update state machine: waits for a BEGIN_SOFTWARE_UPDATE command (or starts with the one left by
+//! the application in the boot request), reads the image from the file server with
+//! uavcan.file.Read, programs it into the application region and verifies it.
+
+use core::convert::TryFrom;
+use heapless::Vec;
+use uavcan_llr::types::{CanId, NodeId, ServiceId, SubjectId, TransferId, TransferKind, Priority};
+use vhrdcan::{Frame, FrameId};
+use crate::boot::{self, BootRequest, Crc32, ImageDescriptor, MAX_PATH_LEN};
+use crate::can::CanBus;
+use crate::config;
+use crate::nvconfig::{key, NvStore, flash::InternalFlash, storage::Flash};
+use crate::uavcan::assembler::Assembler;
+use crate::uavcan::execute_command::{self as cmd, Status};
+use crate::uavcan::heartbeat::{self, Health, Mode};
+use crate::uavcan::register::Reader;
+use crate::uavcan::tx::{self, TxQueue};
+
+const FILE_READ_SERVICE_ID: u16 = 408;
+/// Full chunk, shorter one marks the end of file
+const FILE_READ_CHUNK: usize = 256;
+/// Error u16, length u16, data and transfer CRC
+const MAX_TRANSFER_SIZE: usize = 2 + 2 + FILE_READ_CHUNK + 2;
+
+enum State {
+    Idle,
+    Downloading {
+        server: NodeId,
+        path: Vec<u8, MAX_PATH_LEN>,
+        /// Size and CRC announced with the update, the download is rejected if it ends up different
+        expected: ImageDescriptor,
+        offset: usize,
+        crc: Crc32,
+        transfer_id: TransferId,
+        requested_at_ms: Option<u32>,
+        retries: u8,
+    },
+    /// Reset once responses are sent, into the application if the image is good
+    Reset { at_ms: u32 },
+}
+
+pub struct Updater {
+    node_id: NodeId,
+    store: NvStore,
+    app: InternalFlash,
+    assembler: Assembler<2, MAX_TRANSFER_SIZE>,
+    tx: TxQueue<8, 48>,
+    state: State,
+    heartbeat_transfer_id: TransferId,
+    read_transfer_id: TransferId,
+    next_heartbeat_ms: u32,
+}
+
+impl Updater {
+    pub fn new(store: NvStore, request: Option<BootRequest>) -> Self {
+        let mut node_id = [0u8; 1];
+        let node_id = store.read(key::NODE_ID, key::RECORD_VERSION, &mut node_id)
+            .and_then(|_| NodeId::new(node_id[0]))
+            .unwrap_or(NodeId::new(config::DEFAULT_NODE_ID).unwrap());
+        let mut updater = Updater {
+            node_id,
+            store,
+            app: InternalFlash::new(boot::APP_ADDRESS, boot::APP_SIZE),
+            assembler: Assembler::new(config::FILE_READ_TIMEOUT_MS),
+            tx: TxQueue::new(),
+            state: State::Idle,
+            heartbeat_transfer_id: TransferId::default(),
+            read_transfer_id: TransferId::default(),
+            next_heartbeat_ms: 0,
+        };
+        if let Some(BootRequest::Update { server, image, path }) = request {
+            if let Some(server) = NodeId::new(server) {
+                updater.begin(server, image, path);
+            }
+        }
+        updater
+    }
+
+    pub fn poll<B: CanBus>(&mut self, bus: &mut B, now_ms: u32) {
+        while let Some(frame) = bus.receive() {
+            self.on_frame(&frame, now_ms);
+        }
+
+        if now_ms.wrapping_sub(self.next_heartbeat_ms) < u32::MAX / 2 {
+            self.next_heartbeat_ms = now_ms.wrapping_add(config::HEARTBEAT_PERIOD_MS);
+            self.send_heartbeat(now_ms);
+        }
+
+        let mut request_chunk = false;
+        match &mut self.state {
+            State::Downloading { requested_at_ms, retries, .. } => {
+                let timed_out = match requested_at_ms {
+                    Some(t) => now_ms.wrapping_sub(*t) > config::FILE_READ_TIMEOUT_MS,
+                    None => true,
+                };
+                if timed_out && *retries >= config::FILE_READ_RETRIES {
+                    self.state = State::Idle;
+                } else if timed_out {
+                    *retries += 1;
+                    *requested_at_ms = Some(now_ms);
+                    request_chunk = true;
+                }
+            }
+            State::Reset { at_ms } => {
+                if self.tx.is_empty() && now_ms.wrapping_sub(*at_ms) < u32::MAX / 2 {
+                    cortex_m::peripheral::SCB::sys_reset();
+                }
+            }
+            State::Idle => {}
+        }
+        if request_chunk {
+            self.request_chunk();
+        }
+
+        while let Some(frame) = self.tx.peek() {
+            if !bus.send(&frame.frame) {
+                break;
+            }
+            self.tx.pop();
+        }
+    }
+
+    fn begin(&mut self, server: NodeId, expected: ImageDescriptor, path: Vec<u8, MAX_PATH_LEN>) {
+        // Until the new image is complete the old one is not to be trusted
+        if self.store.write(key::IMAGE_DESCRIPTOR, key::RECORD_VERSION, &ImageDescriptor::INVALID.to_bytes()).is_err() {
+            return;
+        }
+        self.state = State::Downloading {
+            server,
+            path,
+            expected,
+            offset: 0,
+            crc: Crc32::new(),
+            transfer_id: TransferId::default(),
+            requested_at_ms: None,
+            retries: 0,
+        };
+    }
+
+    fn on_frame(&mut self, frame: &Frame<8>, now_ms: u32) {
+        let eid = match frame.id {
+            FrameId::Extended(eid) => eid.inner(),
+            FrameId::Standard(_) => return,
+        };
+        let id = match CanId::try_from(frame.id) {
+            Ok(id) => id,
+            Err(_) => return,
+        };
+        let service = match id.transfer_kind {
+            TransferKind::Service(service) if service.destination_node_id == self.node_id => service,
+            _ => return,
+        };
+        let transfer = match self.assembler.push(eid, frame.data(), now_ms) {
+            Ok(Some(transfer)) => transfer,
+            _ => return,
+        };
+        let transfer_id = TransferId::new(transfer.transfer_id).unwrap_or_default();
+        let mut payload = [0u8; MAX_TRANSFER_SIZE];
+        let len = transfer.payload.len();
+        payload[..len].copy_from_slice(transfer.payload);
+        let payload = &payload[..len];
+
+        match (service.service_id.inner(), service.is_request) {
+            (cmd::SERVICE_ID, true) => self.on_command(id.source_node_id, service.service_id, transfer_id, payload, now_ms),
+            (FILE_READ_SERVICE_ID, false) => self.on_chunk(id.source_node_id, transfer_id, payload, now_ms),
+            _ => {}
+        }
+    }
+
+    fn on_command(&mut self, source: NodeId, service_id: ServiceId, transfer_id: TransferId, payload: &[u8], now_ms: u32) {
+        let request = match cmd::Request::decode(payload) {
+            Ok(request) => request,
+            Err(_) => return,
+        };
+        let status = match request.command {
+            cmd::COMMAND_RESTART => {
+                self.state = State::Reset { at_ms: now_ms.wrapping_add(100) };
+                Status::Success
+            }
+            cmd::COMMAND_BEGIN_SOFTWARE_UPDATE => match ImageDescriptor::split_update_parameter(request.parameter)
+                .and_then(|(image, path)| Some((image, Vec::from_slice(path).ok()?)))
+            {
+                Some((image, path)) => {
+                    self.begin(source, image, path);
+                    if let State::Downloading { .. } = self.state {
+                        Status::Success
+                    } else {
+                        Status::InternalError
+                    }
+                }
+                _ => Status::BadParameter,
+            },
+            _ => Status::BadCommand,
+        };
+        let mut response = [0u8; 1];
+        if let Ok(len) = cmd::encode_response(status, &mut response) {
+            let id = CanId::new_service_kind(self.node_id, source, service_id, false, Priority::Nominal);
+            self.tx.push_transfer(id.into(), &response[..len], transfer_id).ok();
+        }
+    }
+
+    fn request_chunk(&mut self) {
+        if let State::Downloading { server, path, offset, transfer_id, .. } = &mut self.state {
+            let mut request = [0u8; 5 + 1 + MAX_PATH_LEN];
+            request[0..5].copy_from_slice(&(*offset as u64).to_le_bytes()[..5]);
+            request[5] = path.len() as u8;
+            request[6..6 + path.len()].copy_from_slice(path);
+            let len = 6 + path.len();
+            *transfer_id = tx::next_transfer_id(&mut self.read_transfer_id);
+            let service_id = ServiceId::new(FILE_READ_SERVICE_ID).unwrap();
+            let id = CanId::new_service_kind(self.node_id, *server, service_id, true, Priority::Slow);
+            self.tx.push_transfer(id.into(), &request[..len], *transfer_id).ok();
+        }
+    }
+
+    fn on_chunk(&mut self, source: NodeId, response_transfer_id: TransferId, payload: &[u8], now_ms: u32) {
+        let (offset, done) = match &mut self.state {
+            State::Downloading { server, expected, offset, crc, transfer_id, requested_at_ms, retries, .. } => {
+                if *server != source || *transfer_id != response_transfer_id {
+                    return;
+                }
+                let mut r = Reader::new(payload);
+                let error = r.u16();
+                let data = r.u16().and_then(|len| r.bytes(len as usize));
+                let data = match (error, data) {
+                    (Ok(0), Ok(data)) if *offset + data.len() <= expected.size as usize => data,
+                    _ => {
+                        self.state = State::Idle;
+                        return;
+                    }
+                };
+                if write_chunk(&mut self.app, *offset, data).is_err() {
+                    self.state = State::Idle;
+                    return;
+                }
+                crc.add(data);
+                *offset += data.len();
+                *retries = 0;
+                *requested_at_ms = None;
+                (*offset, data.len() < FILE_READ_CHUNK)
+            }
+            _ => return,
+        };
+        if done {
+            self.finish(offset, now_ms);
+        }
+    }
+
+    /// Compare received image with the announced one and with what ended up in flash, descriptor is
+    /// written only if all three match.
+    fn finish(&mut self, size: usize, now_ms: u32) {
+        let (descriptor, expected) = match &self.state {
+            State::Downloading { crc, expected, .. } => (ImageDescriptor { size: size as u32, crc: crc.get() }, *expected),
+            _ => return,
+        };
+        let ok = descriptor == expected
+            && descriptor.matches_flash()
+            && boot::app_vectors_look_valid()
+            && self.store.write(key::IMAGE_DESCRIPTOR, key::RECORD_VERSION, &descriptor.to_bytes()).is_ok();
+        self.state = if ok {
+            State::Reset { at_ms: now_ms }
+        } else {
+            State::Idle
+        };
+    }
+
+    fn send_heartbeat(&mut self, now_ms: u32) {
+        let payload = heartbeat::encode(now_ms / 1000, Health::Norminal, Mode::Bootloader, 0);
+        let subject_id = SubjectId::new(config::HEARTBEAT_SUBJECT_ID).unwrap();
+        let id = CanId::new_message_kind(self.node_id, subject_id, false, Priority::Nominal);
+        let transfer_id = tx::next_transfer_id(&mut self.heartbeat_transfer_id);
+        self.tx.push_transfer(id.into(), &payload, transfer_id).ok();
+    }
+}
+
+/// Erase pages as the image grows, last chunk might be odd and is padded with erased value.
+fn write_chunk(app: &mut InternalFlash, offset: usize, data: &[u8]) -> Result<(), ()> {
+    let page = InternalFlash::PAGE_SIZE;
+    let first_page = (offset + page - 1) / page * page;
+    let mut p = first_page;
+    while p < offset + data.len() {
+        app.erase_page(p).map_err(|_| ())?;
+        p += page;
+    }
+    let even = data.len() & !1;
+    app.write(offset, &data[..even]).map_err(|_| ())?;
+    if even != data.len() {
+        app.write(offset + even, &[data[even], 0xFF]).map_err(|_| ())?;
+    }
+    Ok(())
+}
@@ -11,15 +11,29 @@
 use std::env;
 use std::fs::File;
 use std::io::Write;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[path = "boards/gen.rs"]
+mod board_gen;
+
+/// Built when `BOARD` is not set
+const DEFAULT_BOARD: &str = "v1";
 
 fn main() {
     // Put `memory.x` in our output directory and ensure it's
     // on the linker search path.
     let out = &PathBuf::from(env::var_os("OUT_DIR").unwrap());
+    // Application is linked after the bootloader when it is used
+    let memory_x: &[u8] = if env::var_os("CARGO_FEATURE_BOOTLOADER").is_some() {
+        include_bytes!("memory-bootloader.x")
+    } else {
+        include_bytes!("memory.x")
+    };
     File::create(out.join("memory.x"))
         .unwrap()
-        .write_all(include_bytes!("memory.x"))
+        .write_all(memory_x)
         .unwrap();
     File::create(out.join("counters.x"))
         .unwrap()
@@ -32,5 +46,88 @@ fn main() {
     // here, we ensure the build script is only re-run when
     // `memory.x` is changed.
     println!("cargo:rerun-if-changed=memory.x");
+    println!("cargo:rerun-if-changed=memory-bootloader.x");
     println!("cargo:rerun-if-changed=counters.x");
+
+    emit_build_info();
+    emit_board(out);
+    emit_defmt();
+}
+
+/// defmt needs its linker script, and every level enabled in its macros, as `log-level-*` already
+/// filter in `log_*!`.
+fn emit_defmt() {
+    if env::var_os("CARGO_FEATURE_DEFMT").is_none() {
+        return;
+    }
+    println!("cargo:rustc-link-arg=-Tdefmt.x");
+    if env::var_os("DEFMT_LOG").is_none() {
+        println!("cargo:rustc-env=DEFMT_LOG=trace");
+    }
+    println!("cargo:rerun-if-env-changed=DEFMT_LOG");
+}
+
+/// Pin setup of `boards/$BOARD.toml` for `src/board.rs`. Fails the build when two signals present
+/// with the enabled features claim the same pin.
+fn emit_board(out: &Path) {
+    let name = env::var("BOARD").unwrap_or_else(|_| String::from(DEFAULT_BOARD));
+    let path = format!("boards/{}.toml", name);
+    let source = std::fs::read_to_string(&path).unwrap_or_else(|e| panic!("{}: {}", path, e));
+    let board = board_gen::parse(&source).unwrap_or_else(|e| panic!("{}: {}", path, e));
+    let enabled = |feature: &str| env::var_os(format!("CARGO_FEATURE_{}", feature.to_uppercase().replace('-', "_"))).is_some();
+    let signals = board.select(&enabled).unwrap_or_else(|e| panic!("{}: {}", path, e));
+    File::create(out.join("board.rs"))
+        .unwrap()
+        .write_all(board_gen::generate(&board, &signals, &path).as_bytes())
+        .unwrap();
+
+    println!("cargo:rerun-if-changed={}", path);
+    println!("cargo:rerun-if-changed=boards/gen.rs");
+    println!("cargo:rerun-if-env-changed=BOARD");
+}
+
+/// Build metadata reported by GetInfo and `sys.info.*` registers, see `src/node_info.rs`.
+fn emit_build_info() {
+    let git_hash = git(&["rev-parse", "HEAD"]).unwrap_or_else(|| String::from("unknown"));
+    let dirty = git(&["status", "--porcelain", "--untracked-files=no"]).map(|s| !s.is_empty()).unwrap_or(false);
+    println!("cargo:rustc-env=BUILD_GIT_HASH={}{}", git_hash, if dirty { "-dirty" } else { "" });
+
+    // Honour SOURCE_DATE_EPOCH for reproducible builds
+    let timestamp = env::var("SOURCE_DATE_EPOCH").ok().and_then(|s| s.parse::<u64>().ok()).unwrap_or_else(|| {
+        SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+    });
+    println!("cargo:rustc-env=BUILD_TIMESTAMP={}", timestamp);
+
+    let mut features: Vec<String> = env::vars()
+        .filter_map(|(k, _)| k.strip_prefix("CARGO_FEATURE_").map(|f| f.to_lowercase().replace('_', "-")))
+        .collect();
+    features.sort();
+    println!("cargo:rustc-env=BUILD_FEATURES={}", features_string(&features, FEATURES_MAX_LEN));
+
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/index");
+    println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
+}
+
+/// Longest string register value, `register::MAX_STRING_LEN`
+const FEATURES_MAX_LEN: usize = 128;
+
+/// Comma separated, features that do not fit are left out and counted as `,+N` at the end.
+fn features_string(features: &[String], max_len: usize) -> String {
+    let mut joined = features.join(",");
+    let mut kept = features.len();
+    while joined.len() > max_len && kept > 0 {
+        kept -= 1;
+        let left_out = format!("+{}", features.len() - kept);
+        joined = features[..kept].iter().chain(core::iter::once(&left_out)).cloned().collect::<Vec<_>>().join(",");
+    }
+    joined
+}
+
+fn git(args: &[&str]) -> Option<String> {
+    let output = Command::new("git").args(args).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    String::from_utf8(output.stdout).ok().map(|s| s.trim().to_string())
 }
\ No newline at end of file
@@ -11,3 +11,10 @@ arm-none-eabi-objcopy -O binary ./target/thumbv6m-none-eabi/release/vhrd-module-
 
 cargo +nightly build --release --features="module-pi, f072c8u, can-stm, log-text-rtt, log-level-debug" --color=always
 arm-none-eabi-objcopy -O binary ./target/thumbv6m-none-eabi/release/vhrd-module-template ./target/thumbv6m-none-eabi/release/pi_f072c8u.bin
+
+# CAN bootloaders, applications have to be built with the "bootloader" feature to be placed after them
+(cd bootloader && cargo +nightly build --release --features="f051c8u, can-mcp25625" --color=always)
+arm-none-eabi-objcopy -O binary ./bootloader/target/thumbv6m-none-eabi/release/vhrd-module-bootloader ./target/thumbv6m-none-eabi/release/bootloader_mcp25625_f051c8u.bin
+
+(cd bootloader && cargo +nightly build --release --features="can-stm" --color=always)
+arm-none-eabi-objcopy -O binary ./bootloader/target/thumbv6m-none-eabi/release/vhrd-module-bootloader ./target/thumbv6m-none-eabi/release/bootloader_stm_f072c8u.bin
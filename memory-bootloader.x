@@ -0,0 +1,8 @@
+MEMORY
+{
+  /* NOTE 1 K = 1 KiBi = 1024 bytes */
+  /* First 12K are taken by the bootloader, last 12K by the non-volatile config store */
+  FLASH : ORIGIN = 0x08000000 + 12K, LENGTH = 64K - 12K - 12K
+  /* First 192 bytes are for the vector table copy, last 64 for the boot request and 128 below for the crash report, see boot.rs */
+  RAM : ORIGIN = 0x20000000 + 192, LENGTH = 8K - 192 - 64 - 128
+}
@@ -1,7 +1,9 @@
 MEMORY
 {
   /* NOTE 1 K = 1 KiBi = 1024 bytes */
-  #FLASH : ORIGIN = 0x08000000 + 12K, LENGTH = 64K - 12K
+  /* Last 12K of flash are used by the non-volatile config store, see nvconfig and config::NVCONFIG_* */
+  /* With the bootloader feature memory-bootloader.x is used instead, see boot.rs for the layout */
   FLASH : ORIGIN = 0x08000000 + 0K, LENGTH = 64K - 12K
-  RAM : ORIGIN = 0x20000000 + 192, LENGTH = 8K - 192
-}
\ No newline at end of file
+  /* First 192 bytes are for the vector table copy, last 64 for the boot request and 128 below for the crash report, see boot.rs */
+  RAM : ORIGIN = 0x20000000 + 192, LENGTH = 8K - 192 - 64 - 128
+}
@@ -0,0 +1,226 @@
+//! CAN bit timing calculator.
+//!
+//! Finds time quanta lengths for a controller clock, bitrate and sample point, then encodes them
+//! for bxCAN, MCP2515 family and MCP2518FD. Everything is const, so that a clock unsuitable for
+//! one of the bitrates fails the build instead of silently breaking the bus. Also included by
+//! the bootloader and by host tools, where it is checked against known-good tables.
+
+/// Bitrates selectable in non-volatile config, all nodes on a bus must agree on it.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum Bitrate {
+    Kbps125,
+    Kbps250,
+    Kbps500,
+    Mbps1,
+}
+
+impl Bitrate {
+    pub const ALL: [Bitrate; 4] = [Bitrate::Kbps125, Bitrate::Kbps250, Bitrate::Kbps500, Bitrate::Mbps1];
+
+    pub const fn bps(self) -> u32 {
+        match self {
+            Bitrate::Kbps125 => 125_000,
+            Bitrate::Kbps250 => 250_000,
+            Bitrate::Kbps500 => 500_000,
+            Bitrate::Mbps1 => 1_000_000,
+        }
+    }
+
+    pub const fn from_bps(bps: u32) -> Option<Bitrate> {
+        match bps {
+            125_000 => Some(Bitrate::Kbps125),
+            250_000 => Some(Bitrate::Kbps250),
+            500_000 => Some(Bitrate::Kbps500),
+            1_000_000 => Some(Bitrate::Mbps1),
+            _ => None,
+        }
+    }
+}
+
+/// What a controller can do, segment lengths are in time quanta.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct Limits {
+    /// Prescaler input is the clock divided by this
+    pub clock_divider: u32,
+    pub brp: [u32; 2],
+    /// Propagation and phase 1 segments together
+    pub tseg1: [u32; 2],
+    pub tseg2: [u32; 2],
+    /// Fewer quanta per bit leave no room to place the sample point
+    pub min_quanta: u32,
+}
+
+pub const BXCAN: Limits = Limits {
+    clock_divider: 1,
+    brp: [1, 1024],
+    tseg1: [1, 16],
+    tseg2: [1, 8],
+    min_quanta: 8,
+};
+
+/// Quanta are 2 oscillator periods long at BRP 0. Phase 2 must be longer than the 2 quanta of
+/// information processing time.
+pub const MCP25625: Limits = Limits {
+    clock_divider: 2,
+    brp: [1, 64],
+    tseg1: [2, 16],
+    tseg2: [2, 8],
+    min_quanta: 8,
+};
+/// Jump width MCP25625 modules shipped with before the calculator, kept so that their timing stays
+/// the same on existing buses.
+pub const MCP25625_SJW: u32 = 2;
+
+/// Nominal (arbitration) phase, data phase of CAN FD is not covered.
+pub const MCP2518FD_NOMINAL: Limits = Limits {
+    clock_divider: 1,
+    brp: [1, 256],
+    tseg1: [2, 256],
+    tseg2: [1, 128],
+    min_quanta: 8,
+};
+
+/// Segment lengths in time quanta, not register values. Synchronization jump width is 1 quantum,
+/// all boards run from crystals, see [BitTiming::with_sjw] otherwise.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct BitTiming {
+    pub brp: u32,
+    pub tseg1: u32,
+    pub tseg2: u32,
+    pub sjw: u32,
+}
+
+impl BitTiming {
+    /// Exact bitrate with the sample point closest to the requested one, more quanta per bit
+    /// win a tie. None if the bitrate cannot be derived from the clock exactly.
+    pub const fn calculate(clock_hz: u32, bitrate: u32, sample_point_permille: u32, limits: &Limits) -> Option<BitTiming> {
+        let clock_hz = clock_hz / limits.clock_divider;
+        if bitrate == 0 || clock_hz % bitrate != 0 || sample_point_permille >= 1000 {
+            return None;
+        }
+        let quanta_total = clock_hz / bitrate;
+        let mut best: Option<BitTiming> = None;
+        let mut best_error = u32::MAX;
+        let mut quanta = 1 + limits.tseg1[1] + limits.tseg2[1];
+        while quanta >= limits.min_quanta {
+            let brp = quanta_total / quanta;
+            if quanta_total % quanta == 0 && brp >= limits.brp[0] && brp <= limits.brp[1] {
+                if let Some(timing) = Self::split(brp, quanta, sample_point_permille, limits) {
+                    let error = abs_diff(timing.sample_point_permille(), sample_point_permille);
+                    if error < best_error {
+                        best = Some(timing);
+                        best_error = error;
+                    }
+                }
+            }
+            quanta -= 1;
+        }
+        best
+    }
+
+    /// Place the sample point in a bit of `quanta` length.
+    const fn split(brp: u32, quanta: u32, sample_point_permille: u32, limits: &Limits) -> Option<BitTiming> {
+        let mut tseg2 = (quanta * (1000 - sample_point_permille) + 500) / 1000;
+        tseg2 = clamp(tseg2, limits.tseg2[0], limits.tseg2[1]);
+        if quanta < 1 + tseg2 + limits.tseg1[0] {
+            return None;
+        }
+        let mut tseg1 = quanta - 1 - tseg2;
+        if tseg1 > limits.tseg1[1] {
+            tseg1 = limits.tseg1[1];
+            tseg2 = quanta - 1 - tseg1;
+            if tseg2 > limits.tseg2[1] {
+                return None;
+            }
+        }
+        Some(BitTiming { brp, tseg1, tseg2, sjw: 1 })
+    }
+
+    /// Clamped to phase 2 length.
+    pub const fn with_sjw(self, sjw: u32) -> Self {
+        let sjw = clamp(sjw, 1, self.tseg2);
+        BitTiming { sjw, ..self }
+    }
+
+    pub const fn quanta(&self) -> u32 {
+        1 + self.tseg1 + self.tseg2
+    }
+
+    pub const fn sample_point_permille(&self) -> u32 {
+        (1 + self.tseg1) * 1000 / self.quanta()
+    }
+
+    pub const fn bitrate(&self, clock_hz: u32, limits: &Limits) -> u32 {
+        clock_hz / limits.clock_divider / self.brp / self.quanta()
+    }
+
+    /// BTR register value, loopback and silent mode bits cleared.
+    pub const fn bxcan_btr(&self) -> u32 {
+        (self.sjw - 1) << 24 | (self.tseg2 - 1) << 20 | (self.tseg1 - 1) << 16 | (self.brp - 1)
+    }
+
+    /// Phase 1 gets half of tseg1, propagation segment the rest.
+    pub const fn mcp25625(&self) -> Mcp25625Timing {
+        let ph_seg1 = self.tseg1 / 2;
+        Mcp25625Timing {
+            brp: (self.brp - 1) as u8,
+            prop_seg: (self.tseg1 - ph_seg1) as u8,
+            ph_seg1: ph_seg1 as u8,
+            ph_seg2: self.tseg2 as u8,
+            sync_jump_width: self.sjw as u8,
+        }
+    }
+
+    /// NBTCFG fields, register values.
+    pub const fn mcp2518fd(&self) -> [u8; 4] {
+        [(self.brp - 1) as u8, (self.tseg1 - 1) as u8, (self.tseg2 - 1) as u8, (self.sjw - 1) as u8]
+    }
+}
+
+/// Fields as the mcp25625 driver config takes them: prescaler as a register value, segments in
+/// time quanta.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct Mcp25625Timing {
+    pub brp: u8,
+    pub prop_seg: u8,
+    pub ph_seg1: u8,
+    pub ph_seg2: u8,
+    pub sync_jump_width: u8,
+}
+
+impl Mcp25625Timing {
+    /// CNF1, CNF2 and CNF3 register values: phase 2 set by CNF3, single sampling, SOF and wake-up
+    /// filter disabled.
+    pub const fn cnf(&self) -> [u8; 3] {
+        let cnf1 = (self.sync_jump_width - 1) << 6 | self.brp;
+        let cnf2 = 0x80 | (self.ph_seg1 - 1) << 3 | (self.prop_seg - 1);
+        let cnf3 = self.ph_seg2 - 1;
+        [cnf1, cnf2, cnf3]
+    }
+}
+
+/// Whether every selectable bitrate can be derived from the clock.
+pub const fn all_bitrates_supported(clock_hz: u32, sample_point_permille: u32, limits: &Limits) -> bool {
+    let mut i = 0;
+    while i < Bitrate::ALL.len() {
+        if BitTiming::calculate(clock_hz, Bitrate::ALL[i].bps(), sample_point_permille, limits).is_none() {
+            return false;
+        }
+        i += 1;
+    }
+    true
+}
+
+const fn clamp(x: u32, min: u32, max: u32) -> u32 {
+    if x < min {
+        min
+    } else if x > max {
+        max
+    } else {
+        x
+    }
+}
+
+const fn abs_diff(a: u32, b: u32) -> u32 {
+    if a > b { a - b } else { b - a }
+}
@@ -0,0 +1,5 @@
+//! Pins of the board revision selected with `BOARD` at build time, `boards/v1.toml` by default.
+//! Modules take their pins as the types defined here, already configured, so that a new revision
+//! only needs a new board file.
+
+include!(concat!(env!("OUT_DIR"), "/board.rs"));
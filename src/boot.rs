@@ -0,0 +1,213 @@
+//! Flash layout and hand-over between the application and the CAN bootloader in `bootloader/`.
+//!
+//! Included by both via `#[path]`, so only core, heapless and `uavcan::crc` are allowed in here.
+//!
+//! | 0x0800_0000 | bootloader, 12K |
+//! | 0x0800_3000 | application, 40K, linked there with the `bootloader` feature |
+//! | 0x0800_D000 | non-volatile config store, 12K, image descriptor lives there as well |
+
+use crate::uavcan::crc::crc16;
+use heapless::Vec;
+
+pub const FLASH_ADDRESS: usize = 0x0800_0000;
+pub const FLASH_SIZE: usize = 64 * 1024;
+pub const BOOTLOADER_SIZE: usize = 12 * 1024;
+pub const APP_ADDRESS: usize = FLASH_ADDRESS + BOOTLOADER_SIZE;
+/// Everything up to the non-volatile config store
+pub const APP_SIZE: usize = FLASH_SIZE - BOOTLOADER_SIZE - 12 * 1024;
+
+pub const RAM_ADDRESS: usize = 0x2000_0000;
+/// Smallest of supported chips
+pub const RAM_SIZE: usize = 8 * 1024;
+/// First 48 words of RAM hold the vector table copy, Cortex-M0 has no VTOR, so the bootloader
+/// copies application vectors there and remaps RAM to 0.
+pub const VECTORS_SIZE: usize = 192;
+/// Last bytes of RAM are excluded from RAM region in memory.x of both and survive a reset.
+pub const BOOT_REQUEST_ADDRESS: usize = RAM_ADDRESS + RAM_SIZE - BOOT_REQUEST_SIZE;
+pub const BOOT_REQUEST_SIZE: usize = 64;
+/// Right below the boot request, written by the application's panic and HardFault handlers and
+/// read after the reset, bootloader only has to leave it alone.
+#[allow(dead_code)]
+pub const CRASH_REPORT_ADDRESS: usize = BOOT_REQUEST_ADDRESS - CRASH_REPORT_SIZE;
+pub const CRASH_REPORT_SIZE: usize = 128;
+
+/// Longest file path the bootloader will request, what is left from the boot request block.
+pub const MAX_PATH_LEN: usize = BOOT_REQUEST_SIZE - 4 - 3 - 8 - 2;
+const BOOT_REQUEST_MAGIC: u32 = 0x424F_4F54; // "BOOT"
+const KIND_STAY: u8 = 1;
+const KIND_UPDATE: u8 = 2;
+
+/// What the application asks the bootloader to do after a reset.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub enum BootRequest {
+    /// Do not start the application, wait for an update command
+    Stay,
+    /// Download image from file server `server` at `path` with uavcan.file.Read, it must end up
+    /// with the size and CRC given in `image`
+    Update { server: u8, image: ImageDescriptor, path: Vec<u8, MAX_PATH_LEN> },
+}
+
+impl BootRequest {
+    /// magic u32 | kind u8 | server u8 | size u32 | crc u32 | path len u8 | path | crc u16 at the very end
+    pub fn encode(&self) -> [u8; BOOT_REQUEST_SIZE] {
+        let mut buf = [0u8; BOOT_REQUEST_SIZE];
+        buf[0..4].copy_from_slice(&BOOT_REQUEST_MAGIC.to_le_bytes());
+        match self {
+            BootRequest::Stay => {
+                buf[4] = KIND_STAY;
+            }
+            BootRequest::Update { server, image, path } => {
+                buf[4] = KIND_UPDATE;
+                buf[5] = *server;
+                buf[6..14].copy_from_slice(&image.to_bytes());
+                buf[14] = path.len() as u8;
+                buf[15..15 + path.len()].copy_from_slice(path);
+            }
+        }
+        let crc = crc16(&buf[..BOOT_REQUEST_SIZE - 2]);
+        buf[BOOT_REQUEST_SIZE - 2..].copy_from_slice(&crc.to_le_bytes());
+        buf
+    }
+
+    /// RAM content after power-on is random, so magic and CRC are both checked.
+    pub fn decode(buf: &[u8; BOOT_REQUEST_SIZE]) -> Option<Self> {
+        let crc = u16::from_le_bytes([buf[BOOT_REQUEST_SIZE - 2], buf[BOOT_REQUEST_SIZE - 1]]);
+        if buf[0..4] != BOOT_REQUEST_MAGIC.to_le_bytes() || crc16(&buf[..BOOT_REQUEST_SIZE - 2]) != crc {
+            return None;
+        }
+        match buf[4] {
+            KIND_STAY => Some(BootRequest::Stay),
+            KIND_UPDATE => {
+                let mut image = [0u8; 8];
+                image.copy_from_slice(&buf[6..14]);
+                let len = buf[14] as usize;
+                let path = Vec::from_slice(buf.get(15..15 + len)?).ok()?;
+                Some(BootRequest::Update { server: buf[5], image: ImageDescriptor::from_bytes(&image), path })
+            }
+            _ => None,
+        }
+    }
+
+    /// Store into the reserved RAM block, to be picked up after a reset.
+    pub fn write(&self) {
+        let buf = self.encode();
+        for (i, b) in buf.iter().enumerate() {
+            unsafe { core::ptr::write_volatile((BOOT_REQUEST_ADDRESS + i) as *mut u8, *b) };
+        }
+    }
+
+    /// Read and invalidate the request, so it is acted upon only once.
+    pub fn take() -> Option<Self> {
+        let mut buf = [0u8; BOOT_REQUEST_SIZE];
+        for (i, b) in buf.iter_mut().enumerate() {
+            unsafe {
+                *b = core::ptr::read_volatile((BOOT_REQUEST_ADDRESS + i) as *const u8);
+                core::ptr::write_volatile((BOOT_REQUEST_ADDRESS + i) as *mut u8, 0);
+            }
+        }
+        Self::decode(&buf)
+    }
+}
+
+/// Stored by the bootloader under `nvconfig::key::IMAGE_DESCRIPTOR`. Size of zero marks an
+/// interrupted update, no record at all means the application was flashed with a debugger.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct ImageDescriptor {
+    pub size: u32,
+    pub crc: u32,
+}
+
+impl ImageDescriptor {
+    pub const INVALID: ImageDescriptor = ImageDescriptor { size: 0, crc: 0 };
+
+    pub fn to_bytes(&self) -> [u8; 8] {
+        let mut buf = [0u8; 8];
+        buf[0..4].copy_from_slice(&self.size.to_le_bytes());
+        buf[4..8].copy_from_slice(&self.crc.to_le_bytes());
+        buf
+    }
+
+    pub fn from_bytes(buf: &[u8; 8]) -> Self {
+        ImageDescriptor {
+            size: u32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]]),
+            crc: u32::from_le_bytes([buf[4], buf[5], buf[6], buf[7]]),
+        }
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.size != 0 && self.size as usize <= APP_SIZE
+    }
+
+    /// BEGIN_SOFTWARE_UPDATE parameter: size u32 | crc u32 | path, so that the bootloader knows what
+    /// image to expect before it downloads anything.
+    pub fn split_update_parameter(parameter: &[u8]) -> Option<(Self, &[u8])> {
+        if parameter.len() <= 8 {
+            return None;
+        }
+        let mut image = [0u8; 8];
+        image.copy_from_slice(&parameter[..8]);
+        let image = Self::from_bytes(&image);
+        if image.is_valid() {
+            Some((image, &parameter[8..]))
+        } else {
+            None
+        }
+    }
+
+    /// Image in flash as far as the descriptor goes, None if it is not valid.
+    pub fn flash_image(&self) -> Option<&'static [u8]> {
+        if !self.is_valid() {
+            return None;
+        }
+        Some(unsafe { core::slice::from_raw_parts(APP_ADDRESS as *const u8, self.size as usize) })
+    }
+
+    /// Check the image in flash against the descriptor.
+    pub fn matches_flash(&self) -> bool {
+        self.flash_image().map(|image| crc32(image) == self.crc).unwrap_or(false)
+    }
+}
+
+/// Initial stack pointer must be in RAM and reset vector inside of the application region.
+pub fn app_vectors_look_valid() -> bool {
+    let (sp, reset) = unsafe {
+        (core::ptr::read_volatile(APP_ADDRESS as *const u32), core::ptr::read_volatile((APP_ADDRESS + 4) as *const u32))
+    };
+    let sp_ok = sp as usize > RAM_ADDRESS && sp as usize <= RAM_ADDRESS + RAM_SIZE;
+    let reset_ok = (reset as usize) >= APP_ADDRESS && (reset as usize) < APP_ADDRESS + APP_SIZE && reset & 1 == 1;
+    sp_ok && reset_ok
+}
+
+/// CRC-32/ISO-HDLC (zlib), so that host tools can check images with any library.
+pub struct Crc32(u32);
+
+impl Crc32 {
+    pub const fn new() -> Self {
+        Crc32(0xFFFF_FFFF)
+    }
+
+    pub fn add(&mut self, bytes: &[u8]) {
+        for b in bytes {
+            self.0 ^= *b as u32;
+            for _ in 0..8 {
+                self.0 = if self.0 & 1 != 0 { (self.0 >> 1) ^ 0xEDB8_8320 } else { self.0 >> 1 };
+            }
+        }
+    }
+
+    pub const fn get(&self) -> u32 {
+        !self.0
+    }
+}
+
+impl Default for Crc32 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = Crc32::new();
+    crc.add(bytes);
+    crc.get()
+}
@@ -0,0 +1,244 @@
+//! CAN controller health, common to all drivers.
+//!
+//! Drivers feed error counters and events from their interrupt handlers. [InterfaceHealth::update]
+//! tracks error state transitions and tells the driver when to take the controller off the bus
+//! after bus-off and when to restart it, according to [RecoveryPolicy]. Counters are published by
+//! `task::can_health` and degrade node health reported in heartbeats.
+//!
+//! Also included by tools, only [RecoveryPolicy::current] reaches into the firmware.
+
+use crate::uavcan::heartbeat::Health;
+
+/// Goes into published statistics, so that bridged interfaces can be told apart
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum Interface {
+    Mcp = 0,
+    Stm = 1,
+}
+
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum ErrorState {
+    Active = 0,
+    Passive = 1,
+    BusOff = 2,
+    /// Controller is kept off the bus after bus-off, waiting for a restart
+    HeldOff = 3,
+}
+
+const ERROR_PASSIVE_LIMIT: u8 = 128;
+/// Error warning limit from the CAN spec, counters above it make node health a warning
+const ERROR_WARNING_LIMIT: u8 = 96;
+
+/// As read from the controller
+#[derive(Copy, Clone, Default, Eq, PartialEq, Debug)]
+pub struct ErrorStatus {
+    pub tec: u8,
+    pub rec: u8,
+    pub bus_off: bool,
+}
+
+impl ErrorStatus {
+    pub fn state(&self) -> ErrorState {
+        if self.bus_off {
+            ErrorState::BusOff
+        } else if self.tec >= ERROR_PASSIVE_LIMIT || self.rec >= ERROR_PASSIVE_LIMIT {
+            ErrorState::Passive
+        } else {
+            ErrorState::Active
+        }
+    }
+}
+
+/// What to do after bus-off, can be changed in non-volatile config.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct RecoveryPolicy {
+    /// If false, controller stays off the bus until reboot
+    pub auto: bool,
+    /// Initial and maximum time off the bus, doubles on every bus-off shortly after a restart
+    pub backoff_ms: [u16; 2],
+}
+
+impl RecoveryPolicy {
+    #[cfg(target_os = "none")]
+    pub fn current() -> Self {
+        let cfg = crate::nvconfig::get();
+        RecoveryPolicy {
+            auto: cfg.can_auto_recovery,
+            backoff_ms: cfg.can_recovery_backoff_ms,
+        }
+    }
+}
+
+/// What a driver has to do with its controller before returning from the interrupt handler
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum Action {
+    None,
+    /// Stop participating in bus activity, configuration mode or similar
+    HoldOff,
+    /// Reinitialize and go back to normal operation
+    Restart,
+}
+
+#[derive(Copy, Clone, Default, Eq, PartialEq, Debug)]
+pub struct Counters {
+    pub bus_off: u32,
+    /// Transitions into error passive state
+    pub error_passive: u32,
+    /// Frames lost in the controller or in the RX queue
+    pub rx_overflows: u32,
+    pub tx_errors: u32,
+    /// Normal on a busy bus, not counted as an error. A lower bound, only bxCAN reports them and
+    /// only for the last attempt of a request.
+    pub arbitration_lost: u32,
+    pub recoveries: u32,
+}
+
+impl Counters {
+    fn errors(&self) -> u32 {
+        self.bus_off
+            .wrapping_add(self.error_passive)
+            .wrapping_add(self.rx_overflows)
+            .wrapping_add(self.tx_errors)
+    }
+}
+
+/// Interface, state, TEC, REC, then all the counters as u32
+pub const STATISTICS_SIZE: usize = 4 + 6 * 4;
+
+pub struct InterfaceHealth {
+    interface: Interface,
+    status: ErrorStatus,
+    state: ErrorState,
+    counters: Counters,
+    backoff_ms: u32,
+    restart_at_ms: Option<u32>,
+    last_restart_ms: Option<u32>,
+    /// Error count at the previous [InterfaceHealth::health] call
+    errors_checked: u32,
+}
+
+impl InterfaceHealth {
+    pub const fn new(interface: Interface) -> Self {
+        InterfaceHealth {
+            interface,
+            status: ErrorStatus { tec: 0, rec: 0, bus_off: false },
+            state: ErrorState::Active,
+            counters: Counters {
+                bus_off: 0,
+                error_passive: 0,
+                rx_overflows: 0,
+                tx_errors: 0,
+                arbitration_lost: 0,
+                recoveries: 0,
+            },
+            backoff_ms: 0,
+            restart_at_ms: None,
+            last_restart_ms: None,
+            errors_checked: 0,
+        }
+    }
+
+    pub fn is_held_off(&self) -> bool {
+        self.state == ErrorState::HeldOff
+    }
+
+    pub fn on_rx_overflow(&mut self) {
+        self.counters.rx_overflows = self.counters.rx_overflows.wrapping_add(1);
+    }
+
+    pub fn on_tx_error(&mut self) {
+        self.counters.tx_errors = self.counters.tx_errors.wrapping_add(1);
+    }
+
+    pub fn on_arbitration_lost(&mut self) {
+        self.counters.arbitration_lost = self.counters.arbitration_lost.wrapping_add(1);
+    }
+
+    /// Feed controller status on every interrupt and on periodic polls, status is ignored while
+    /// the controller is held off.
+    pub fn update(&mut self, status: ErrorStatus, now_ms: u32, policy: RecoveryPolicy) -> Action {
+        if self.state == ErrorState::HeldOff {
+            if !policy.auto {
+                self.restart_at_ms = None;
+                return Action::None;
+            }
+            let backoff_ms = self.backoff_ms;
+            let restart_at_ms = *self.restart_at_ms.get_or_insert(now_ms.wrapping_add(backoff_ms));
+            if !is_due(now_ms, restart_at_ms) {
+                return Action::None;
+            }
+            log_info!(target: Canbus, "CAN {:?}: restart after bus-off", self.interface);
+            self.restart_at_ms = None;
+            self.last_restart_ms = Some(now_ms);
+            self.counters.recoveries = self.counters.recoveries.wrapping_add(1);
+            self.status = ErrorStatus::default();
+            self.state = ErrorState::Active;
+            return Action::Restart;
+        }
+
+        self.status = status;
+        let state = status.state();
+        if state == self.state {
+            return Action::None;
+        }
+        log_warn!(target: Canbus, "CAN {:?}: {:?} -> {:?}, TEC: {}, REC: {}", self.interface, self.state, state, status.tec, status.rec);
+        self.state = state;
+        match state {
+            ErrorState::Passive => {
+                self.counters.error_passive = self.counters.error_passive.wrapping_add(1);
+                Action::None
+            }
+            ErrorState::BusOff => {
+                self.counters.bus_off = self.counters.bus_off.wrapping_add(1);
+                let [initial_ms, max_ms] = [policy.backoff_ms[0] as u32, policy.backoff_ms[1] as u32];
+                let unstable = self.last_restart_ms.map(|t| now_ms.wrapping_sub(t) < max_ms).unwrap_or(false);
+                self.backoff_ms = if unstable {
+                    (self.backoff_ms * 2).max(initial_ms).min(max_ms)
+                } else {
+                    initial_ms
+                };
+                self.state = ErrorState::HeldOff;
+                self.restart_at_ms = None;
+                Action::HoldOff
+            }
+            _ => Action::None,
+        }
+    }
+
+    /// Failure while off the bus, warning when error passive, close to it or when errors were
+    /// counted since the previous call.
+    pub fn health(&mut self) -> Health {
+        let errors = self.counters.errors();
+        let new_errors = errors != self.errors_checked;
+        self.errors_checked = errors;
+        let warning_level = self.status.tec >= ERROR_WARNING_LIMIT || self.status.rec >= ERROR_WARNING_LIMIT;
+        match self.state {
+            ErrorState::BusOff | ErrorState::HeldOff => Health::Failure,
+            ErrorState::Passive => Health::Warning,
+            ErrorState::Active if new_errors || warning_level => Health::Warning,
+            ErrorState::Active => Health::Norminal,
+        }
+    }
+
+    /// Little endian: u8 interface, u8 state, u8 TEC, u8 REC, u32 bus-off count, error passive
+    /// count, RX overflows, TX errors, arbitration losses, recoveries.
+    pub fn encode(&self, buf: &mut [u8; STATISTICS_SIZE]) -> usize {
+        buf[0] = self.interface as u8;
+        buf[1] = self.state as u8;
+        buf[2] = self.status.tec;
+        buf[3] = self.status.rec;
+        let c = &self.counters;
+        let counters = [c.bus_off, c.error_passive, c.rx_overflows, c.tx_errors, c.arbitration_lost, c.recoveries];
+        for (chunk, counter) in buf[4..].chunks_exact_mut(4).zip(counters.iter()) {
+            chunk.copy_from_slice(&counter.to_le_bytes());
+        }
+        STATISTICS_SIZE
+    }
+}
+
+fn is_due(now_ms: u32, at_ms: u32) -> bool {
+    now_ms.wrapping_sub(at_ms) < u32::MAX / 2
+}
@@ -0,0 +1,164 @@
+//! Which interfaces frames go to and come from when both the MCP and the STM ones are enabled.
+//!
+//! Interfaces are either two separate buses or redundant transports of the same bus. Separate
+//! buses get the node's own transfers on the interfaces selected in config, responses go back
+//! where the request came from, and [BridgeRule]s forward matching frames from one bus to the
+//! other. Redundant transports get everything on both, received transfers are deduplicated with
+//! `uavcan::redundancy` and the bridge is off.
+//!
+//! With a single interface all of this collapses to that interface.
+//!
+//! Also included by tools, what reads the runtime config is only built for the MCU.
+
+use crate::can_health::Interface;
+use crate::uavcan::filter::Filter;
+#[cfg(target_os = "none")]
+use crate::uavcan::router::{Subscription, Endpoint};
+#[cfg(target_os = "none")]
+use core::cell::Cell;
+use vhrdcan::FrameId;
+
+/// Both the MCP and the STM interface are enabled
+pub const DUAL: bool = cfg!(all(feature = "can-stm", any(feature = "can-mcp25625", feature = "can-mcp2518fd")));
+
+/// Set of interfaces, bit number is `Interface` value.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct Interfaces(u8);
+
+impl Interfaces {
+    pub const ALL: Interfaces = Interfaces(0b11);
+
+    pub const fn only(interface: Interface) -> Self {
+        Interfaces(1 << interface as u8)
+    }
+
+    /// None if empty or with unknown bits set
+    pub const fn from_bits(bits: u8) -> Option<Self> {
+        if bits == 0 || bits & !Self::ALL.0 != 0 {
+            None
+        } else {
+            Some(Interfaces(bits))
+        }
+    }
+
+    pub const fn bits(self) -> u8 {
+        self.0
+    }
+
+    pub const fn contains(self, interface: Interface) -> bool {
+        self.0 & (1 << interface as u8) != 0
+    }
+}
+
+/// Part of the runtime config needed for every frame, kept apart so that the whole config is not
+/// copied each time.
+#[derive(Copy, Clone)]
+pub struct Routing {
+    redundant: bool,
+    tx_interfaces: Interfaces,
+    bridge_rules: BridgeRules,
+}
+
+impl Routing {
+    /// Redundancy and bridging need both interfaces, rules are off with redundant transports.
+    pub const fn new(redundant: bool, tx_interfaces: Interfaces, bridge_rules: BridgeRules) -> Self {
+        let redundant = DUAL && redundant;
+        Routing {
+            redundant,
+            tx_interfaces: if redundant { Interfaces::ALL } else { tx_interfaces },
+            bridge_rules: if DUAL && !redundant { bridge_rules } else { [BridgeRule::DISABLED; BRIDGE_RULES] },
+        }
+    }
+}
+
+#[cfg(target_os = "none")]
+static ROUTING: bare_metal::Mutex<Cell<Routing>> = bare_metal::Mutex::new(Cell::new(
+    Routing::new(crate::config::CAN_REDUNDANT, crate::config::CAN_TX_INTERFACES, crate::config::CAN_BRIDGE_RULES)
+));
+
+/// Kept in sync with the runtime config by `nvconfig::set`.
+#[cfg(target_os = "none")]
+pub fn set_routing(routing: Routing) {
+    cortex_m::interrupt::free(|cs| ROUTING.borrow(cs).set(routing));
+}
+
+#[cfg(target_os = "none")]
+fn routing() -> Routing {
+    cortex_m::interrupt::free(|cs| ROUTING.borrow(cs).get())
+}
+
+#[cfg(target_os = "none")]
+pub fn is_redundant() -> bool {
+    routing().redundant
+}
+
+/// Where the node's own transfers and frames go.
+#[cfg(target_os = "none")]
+pub fn tx_interfaces() -> Interfaces {
+    routing().tx_interfaces
+}
+
+/// Where responses to a request received on `interface` go.
+#[cfg(target_os = "none")]
+pub fn response_interfaces(interface: Interface) -> Interfaces {
+    if is_redundant() {
+        Interfaces::ALL
+    } else {
+        Interfaces::only(interface)
+    }
+}
+
+pub const fn other(interface: Interface) -> Interface {
+    match interface {
+        Interface::Mcp => Interface::Stm,
+        Interface::Stm => Interface::Mcp,
+    }
+}
+
+/// Forward extended ID frames received on `from` and accepted by `filter` to the other interface,
+/// as is. Rules are independent of the router, bridged frames are also handled locally if
+/// subscribed to.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct BridgeRule {
+    /// None for an unused rule
+    pub from: Option<Interface>,
+    pub filter: Filter,
+}
+
+impl BridgeRule {
+    pub const DISABLED: BridgeRule = BridgeRule { from: None, filter: Filter::ACCEPT_ALL };
+
+    pub const fn new(from: Interface, filter: Filter) -> Self {
+        BridgeRule { from: Some(from), filter }
+    }
+}
+
+pub const BRIDGE_RULES: usize = 4;
+pub type BridgeRules = [BridgeRule; BRIDGE_RULES];
+
+/// Rules in effect, none unless both interfaces are enabled as separate buses.
+#[cfg(target_os = "none")]
+pub fn bridge_rules() -> BridgeRules {
+    routing().bridge_rules
+}
+
+/// Interface to forward a frame received on `from` to, if any of `rules` matches.
+pub fn bridge_target(rules: &BridgeRules, from: Interface, id: FrameId) -> Option<Interface> {
+    let id = match id {
+        FrameId::Extended(eid) => eid.inner(),
+        FrameId::Standard(_) => return None,
+    };
+    rules.iter()
+        .any(|r| r.from == Some(from) && r.filter.matches(id))
+        .then(|| other(from))
+}
+
+/// Rules forwarding from `interface` as subscriptions, so that its hardware filters let the
+/// frames through.
+#[cfg(target_os = "none")]
+pub fn bridge_subscriptions(interface: Interface) -> heapless::Vec<Subscription, BRIDGE_RULES> {
+    bridge_rules().iter()
+        .filter(|r| r.from == Some(interface))
+        .map(|r| Subscription::raw_extended_masked(r.filter.id, r.filter.mask, Endpoint::Bridge))
+        .collect()
+}
@@ -1,91 +1,350 @@
 use crate::{config, hal, app};
 use rtic::Mutex;
 
+/// Enqueue a classic frame on the interfaces selected for the node's own traffic, see
+/// `can_interfaces::tx_interfaces`.
 macro_rules! can_send {
     ($cx:expr, $frame:expr) => {
-        #[cfg(feature = "can-mcp25625")]
-        $cx.shared.can_mcp_tx.lock(|tx| tx.push($frame)).ok();
-        #[cfg(feature = "can-mcp25625")]
-        rtic::pend(crate::config::MCP25625_IRQ_HANDLER);
+        can_send_on!($cx, crate::can_interfaces::tx_interfaces(), $frame)
+    };
+}
 
+/// Enqueue a classic frame on the given interfaces, dropped on the ones with a full queue.
+macro_rules! can_send_on {
+    ($cx:expr, $interfaces:expr, $frame:expr) => {{
+        // Unused without any CAN interface enabled
+        #[allow(unused_variables)]
+        let interfaces: crate::can_interfaces::Interfaces = $interfaces;
+        #[allow(unused_variables)]
+        let frame: vhrdcan::Frame<8> = $frame;
+        #[cfg(feature = "can-mcp25625")]
+        if interfaces.contains(crate::can_health::Interface::Mcp) {
+            $cx.shared.can_mcp_tx.lock(|tx| tx.push(frame)).ok();
+            rtic::pend(crate::config::MCP25625_IRQ_HANDLER);
+        }
+        #[cfg(feature = "can-mcp2518fd")]
+        if interfaces.contains(crate::can_health::Interface::Mcp) {
+            $cx.shared.can_mcp_tx.lock(|tx| {
+                crate::config::CanFdFrame::new(frame.id, frame.data()).map(|frame| tx.push(frame))
+            });
+            rtic::pend(crate::config::MCP2518FD_IRQ_HANDLER);
+        }
         #[cfg(feature = "can-stm")]
-        $cx.shared.can_stm_tx.lock(|tx| tx.push($frame)).ok();
-        #[cfg(feature = "can-stm")]
-        rtic::pend(crate::pac::Interrupt::CEC_CAN);
+        if interfaces.contains(crate::can_health::Interface::Stm) {
+            $cx.shared.can_stm_tx.lock(|tx| tx.push(frame)).ok();
+            rtic::pend(crate::pac::Interrupt::CEC_CAN);
+        }
+    }};
+}
+
+/// Enqueue a single or multi-frame transfer on the interfaces selected for the node's own traffic,
+/// whole transfer or nothing. Evaluates to `Result<(), TxError>`, transfer ID is advanced in any case.
+macro_rules! can_send_transfer {
+    ($cx:expr, $id:expr, $payload:expr, $transfer_id:expr) => {{
+        let transfer_id = crate::uavcan::tx::next_transfer_id($transfer_id);
+        can_send_transfer_with_id!($cx, $id, $payload, transfer_id)
+    }};
+}
+
+/// Same as `can_send_transfer!`, but with an explicit transfer ID.
+macro_rules! can_send_transfer_with_id {
+    ($cx:expr, $id:expr, $payload:expr, $transfer_id:expr) => {
+        can_send_transfer_on!($cx, crate::can_interfaces::tx_interfaces(), $id, $payload, $transfer_id)
     };
 }
 
+/// Same as `can_send_transfer_with_id!` on the given interfaces, as needed for service responses.
+macro_rules! can_send_transfer_on {
+    ($cx:expr, $interfaces:expr, $id:expr, $payload:expr, $transfer_id:expr) => {{
+        // Unused without any CAN interface enabled
+        #[allow(unused_variables)]
+        let interfaces: crate::can_interfaces::Interfaces = $interfaces;
+        #[allow(unused_variables)]
+        let id: vhrdcan::FrameId = $id.into();
+        #[allow(unused_variables)]
+        let payload: &[u8] = $payload;
+        #[allow(unused_variables)]
+        let transfer_id: uavcan_llr::types::TransferId = $transfer_id;
+        #[allow(unused_mut)]
+        let mut result: Result<(), crate::uavcan::tx::TxError> = Ok(());
+        #[cfg(feature = "can-mcp25625")]
+        if interfaces.contains(crate::can_health::Interface::Mcp) {
+            result = result.and($cx.shared.can_mcp_tx.lock(|tx| tx.push_transfer(id, payload, transfer_id)));
+            rtic::pend(crate::config::MCP25625_IRQ_HANDLER);
+        }
+        #[cfg(feature = "can-mcp2518fd")]
+        if interfaces.contains(crate::can_health::Interface::Mcp) {
+            result = result.and($cx.shared.can_mcp_tx.lock(|tx| tx.push_transfer(id, payload, transfer_id)));
+            rtic::pend(crate::config::MCP2518FD_IRQ_HANDLER);
+        }
+        #[cfg(feature = "can-stm")]
+        if interfaces.contains(crate::can_health::Interface::Stm) {
+            result = result.and($cx.shared.can_stm_tx.lock(|tx| tx.push_transfer(id, payload, transfer_id)));
+            rtic::pend(crate::pac::Interrupt::CEC_CAN);
+        }
+        result
+    }};
+}
+
+/// Transfers not listed in any of these tables are dropped.
+const RX_TABLES: &[&[Subscription]] = &[
+    COMMON_SUBSCRIPTIONS,
+    crate::ramp_vesc::SUBSCRIPTIONS,
+    crate::module::SUBSCRIPTIONS,
+];
+
+const COMMON_SUBSCRIPTIONS: &[Subscription] = &[
+    Subscription::service(Source::Any, config::REBOOT_SERVICE_ID, Endpoint::Reboot),
+    Subscription::service(Source::Any, config::REGISTER_ACCESS_SERVICE_ID, Endpoint::RegisterAccess),
+    Subscription::service(Source::Any, config::REGISTER_LIST_SERVICE_ID, Endpoint::RegisterList),
+    Subscription::service(Source::Any, config::GET_INFO_SERVICE_ID, Endpoint::GetInfo),
+    Subscription::service(Source::Any, config::EXECUTE_COMMAND_SERVICE_ID, Endpoint::ExecuteCommand),
+    Subscription::service(Source::Any, config::CRASH_REPORT_SERVICE_ID, Endpoint::CrashReport),
+    Subscription::subject(Source::Any, config::PNP_ALLOCATION_SUBJECT_ID, Endpoint::PnpAllocation),
+];
+
+/// Register access response: timestamp, flags, tag, length and a register string of maximum size.
+const REGISTER_RESPONSE_SIZE: usize = 8 + 3 + crate::uavcan::register::MAX_STRING_LEN;
+/// Largest service response, GetInfo with a full build description
+const RESPONSE_BUFFER_SIZE: usize = crate::uavcan::get_info::MAX_RESPONSE_SIZE;
+const_assert!(RESPONSE_BUFFER_SIZE >= REGISTER_RESPONSE_SIZE);
+const_assert!(RESPONSE_BUFFER_SIZE >= crate::uavcan::crash_report::MAX_SIZE);
+/// Sent as a whole or not at all, leave room for other traffic
+const_assert!(crate::uavcan::tx::frame_count(RESPONSE_BUFFER_SIZE, 8) <= config::CAN_TX_QUEUE_LEN * 7 / 8);
+
+/// Hardware acceptance filters are generated from [RX_TABLES], the node ID, as services are
+/// filtered by destination, and the bridge rules forwarding from the interface. Drivers check on
+/// every interrupt whether any of them changed since.
+pub struct FiltersState {
+    applied_for: Option<NodeId>,
+    bridge_rules: BridgeRules,
+}
+
+impl FiltersState {
+    /// Filters are applied in driver init, from the current config
+    pub fn new() -> Self {
+        FiltersState { applied_for: crate::nvconfig::node_id(), bridge_rules: can_interfaces::bridge_rules() }
+    }
+
+    /// Current node ID, if filters have to be regenerated.
+    fn outdated(&self) -> Option<Option<NodeId>> {
+        let node_id = crate::nvconfig::node_id();
+        if node_id != self.applied_for || can_interfaces::bridge_rules() != self.bridge_rules {
+            Some(node_id)
+        } else {
+            None
+        }
+    }
+
+    fn applied(&mut self, node_id: Option<NodeId>) {
+        self.applied_for = node_id;
+        self.bridge_rules = can_interfaces::bridge_rules();
+        log_debug!(target: Canbus, "CAN filters set for node ID {:?}", node_id.map(|id| id.inner()));
+    }
+}
+
+/// Filters for [RX_TABLES] and for the frames bridged from `interface`, merged down to `N`.
+fn rx_filters<const N: usize>(interface: Interface, node_id: Option<NodeId>) -> heapless::Vec<Filter, N> {
+    let bridged = can_interfaces::bridge_subscriptions(interface);
+    let mut tables: [&[Subscription]; RX_TABLES.len() + 1] = [&[]; RX_TABLES.len() + 1];
+    tables[..RX_TABLES.len()].copy_from_slice(RX_TABLES);
+    tables[RX_TABLES.len()] = &bridged[..];
+    filter::generate(&tables, node_id)
+}
+
+/// Regenerates the filters for `interface` and hands them to the controller, `N` is how many it has.
+fn set_rx_filters<C: CanInterface, const N: usize>(can: &mut C, interface: Interface, node_id: Option<NodeId>) -> Result<(), C::Error> {
+    let filters: heapless::Vec<Filter, N> = rx_filters(interface, node_id);
+    can.set_filters(&filters)
+}
+
+/// Controller error state, failed reads count as no errors.
+#[cfg(any(feature = "can-stm", feature = "can-mcp25625", feature = "can-mcp2518fd"))]
+fn error_status<C: CanInterface>(can: &mut C) -> ErrorStatus {
+    let (tec, rec) = can.error_counters().unwrap_or((0, 0));
+    ErrorStatus { tec, rec, bus_off: can.is_bus_off().unwrap_or(false) }
+}
+
+/// One per interface, transfers are reassembled from the frames of a single interface.
+pub struct RxAssemblers {
+    #[cfg(any(feature = "can-mcp25625", feature = "can-mcp2518fd"))]
+    mcp: config::RxAssembler,
+    #[cfg(feature = "can-stm")]
+    stm: config::RxAssembler,
+}
+
+impl RxAssemblers {
+    pub const fn new() -> Self {
+        RxAssemblers {
+            #[cfg(any(feature = "can-mcp25625", feature = "can-mcp2518fd"))]
+            mcp: config::RxAssembler::new(config::UAVCAN_RX_TRANSFER_TIMEOUT.0),
+            #[cfg(feature = "can-stm")]
+            stm: config::RxAssembler::new(config::UAVCAN_RX_TRANSFER_TIMEOUT.0),
+        }
+    }
+
+    /// None for an interface that is not enabled, no frames come from it.
+    fn get(&mut self, interface: Interface) -> Option<&mut config::RxAssembler> {
+        match interface {
+            #[cfg(any(feature = "can-mcp25625", feature = "can-mcp2518fd"))]
+            Interface::Mcp => Some(&mut self.mcp),
+            #[cfg(not(any(feature = "can-mcp25625", feature = "can-mcp2518fd")))]
+            Interface::Mcp => None,
+            #[cfg(feature = "can-stm")]
+            Interface::Stm => Some(&mut self.stm),
+            #[cfg(not(feature = "can-stm"))]
+            Interface::Stm => None,
+        }
+    }
+}
+
+/// Interfaces take turns, so that a busy one cannot starve the other.
 pub fn can_rx_router(mut cx: app::can_rx_router::Context) {
     loop {
+        let mut idle = true;
         #[cfg(feature = "can-mcp25625")]
-        let frame: Option<Frame<8>> = cx.shared.can_mcp_rx.lock(|rx| rx.pop());
+        if let Some(frame) = cx.shared.can_mcp_rx.lock(|rx: &mut config::CanRxQueue| rx.pop()) {
+            route_frame(&mut cx, Interface::Mcp, &frame);
+            idle = false;
+        }
+        #[cfg(feature = "can-mcp2518fd")]
+        if let Some(frame) = cx.shared.can_mcp_rx.lock(|rx: &mut config::CanFdRxQueue| rx.pop()) {
+            route_frame(&mut cx, Interface::Mcp, &frame);
+            idle = false;
+        }
         #[cfg(feature = "can-stm")]
-        let frame: Option<Frame<8>> = cx.shared.can_stm_rx.lock(|rx| rx.pop());
+        if let Some(frame) = cx.shared.can_stm_rx.lock(|rx: &mut config::CanRxQueue| rx.pop()) {
+            route_frame(&mut cx, Interface::Stm, &frame);
+            idle = false;
+        }
+        if idle {
+            return;
+        }
+    }
+}
 
-        match frame {
-            Some(frame) => {
-                match frame.id {
-                    FrameId::Standard(_) => continue,
-                    FrameId::Extended(eid) => {
-                        if eid.inner() == 0x907 {
-                            #[cfg(feature = "vesc-ctrl")]
-                            if let Some(feedback) = crate::ramp_vesc::VescFeedback::new(frame) {
-                                cx.shared.vesc_feedback.lock(|f| *f = Some(feedback));
-                            }
-                            continue;
-                        }
+fn route_frame<const MTU: usize>(cx: &mut app::can_rx_router::Context, interface: Interface, frame: &Frame<MTU>) {
+    if let Some(to) = can_interfaces::bridge_target(&can_interfaces::bridge_rules(), interface, frame.id) {
+        if !bridge_forward(cx, to, frame) {
+            log_debug!(target: Canbus, "Bridged frame {:?} dropped", crate::logging::Fmt(frame.id));
+        }
+    }
+    let assembler = match cx.local.assemblers.get(interface) {
+        Some(assembler) => assembler,
+        None => return,
+    };
+    let deduplicator: &mut config::RxDeduplicator = cx.local.deduplicator;
+    let now = crate::utils::millis();
+    // Raw frames are not deduplicated
+    let accept = |id, transfer_id| !can_interfaces::is_redundant() || deduplicator.accept(id, transfer_id, interface as u8, now);
+    let (dispatch, transfer_id) = match router::receive(RX_TABLES, crate::nvconfig::node_id(), frame, assembler, now, accept) {
+        Ok(Some(received)) => received,
+        Ok(None) => {
+            return;
+        }
+        Err(_e) => {
+            log_debug!(target: Canbus, "RX transfer from {:?} dropped: {:?}", crate::logging::Fmt(frame.id), _e);
+            return;
+        }
+    };
+    match dispatch {
+        #[cfg(feature = "vesc-ctrl")]
+        Dispatch::Raw { endpoint: Endpoint::VescFeedback, id, payload } => {
+            if let Some(feedback) = crate::vesc_control::VescFeedback::new(id, payload, crate::nvconfig::get().vesc_id) {
+                cx.shared.vesc_feedback.lock(|f| *f = Some(feedback));
+            }
+        }
+        #[cfg(feature = "vesc-ctrl")]
+        Dispatch::Message { endpoint: Endpoint::VescControl, source, message, payload } => {
+            if let Some(input) = crate::vesc_control::control_input(source, message, payload) {
+                cx.shared.vesc_control_input.lock(|i| *i = Some(input));
+            }
+        }
+        Dispatch::Message { endpoint: Endpoint::Module, source, message, payload } => {
+            crate::module::handle_message(source, message, payload);
+        }
+        Dispatch::Message { endpoint: Endpoint::PnpAllocation, payload, .. } => {
+            crate::task::pnp::handle_allocation(payload, cx.local.nvstore);
+        }
+        Dispatch::Service { endpoint: Endpoint::Reboot, source, payload, .. } => {
+            log_debug!(target: Canbus, "Reset requested from: {}", source.inner());
+            crate::commands::prepare_reboot(payload);
+            cortex_m::asm::delay(10_000); // Minimum seems to be 3_000 @ 8MHz and JLink 255
+            cortex_m::peripheral::SCB::sys_reset();
+        }
+        Dispatch::Service {
+            endpoint: endpoint @ (Endpoint::RegisterAccess | Endpoint::RegisterList | Endpoint::GetInfo | Endpoint::ExecuteCommand | Endpoint::CrashReport),
+            source,
+            service,
+            payload
+        } => {
+            let nvstore: &mut crate::nvconfig::NvStore = cx.local.nvstore;
+            let mut response = [0u8; RESPONSE_BUFFER_SIZE];
+            let mut reset = false;
+            let result = match endpoint {
+                Endpoint::RegisterAccess => crate::registers::handle_access(payload, nvstore, &mut response),
+                Endpoint::RegisterList => crate::registers::handle_list(payload, &mut response),
+                Endpoint::ExecuteCommand => crate::commands::handle(payload, source, nvstore, &mut response)
+                    .map(|(len, r)| { reset = r; len }),
+                Endpoint::CrashReport => crate::crash::encode_last(&mut response),
+                _ => crate::node_info::node_info().encode(&mut response),
+            };
+            match result {
+                Ok(len) => {
+                    // Node ID might change while handling a register write, response goes from the old one
+                    let id = CanId::new_service_kind(service.destination_node_id, source, service.service_id, false, Priority::Nominal);
+                    let interfaces = can_interfaces::response_interfaces(interface);
+                    if let Err(_e) = can_send_transfer_on!(cx, interfaces, id, &response[..len], transfer_id) {
+                        log_warn!(target: Canbus, "Response to {} not sent: {:?}", source.inner(), _e);
+                    }
+                    if reset {
+                        app::reboot_task::spawn_after(config::REBOOT_DELAY).ok();
+                    } else if crate::nvconfig::node_id().is_none() {
+                        // uavcan.node.id was unset, allocate a new one
+                        app::pnp_task::spawn().ok();
                     }
                 }
-                match CanId::try_from(frame.id) {
-                    Ok(uavcan_id) => {
-                        match uavcan_id.transfer_kind {
-                            TransferKind::Message(message) => {
-                                #[cfg(feature = "vesc-ctrl")]
-                                if let Some(input) = crate::ramp_vesc::ControlInput::new(uavcan_id.source_node_id, message, frame.data()) {
-                                    cx.shared.vesc_control_input.lock(|i| *i = Some(input));
-                                    continue;
-                                }
-                                if uavcan_id.source_node_id == config::BUTTON_UAVCAN_NODE_ID && message.subject_id == config::SAFETY_BUTTON_SUBJECT {
-                                    log_debug!("Estop pressed");
-                                    //cx.shared.stand_state.lock(|s| s.is_estop_pressed = true);
-                                    //let _ = app::unpress_estop::spawn_after(Milliseconds::new(500u32));
-                                } else if uavcan_id.source_node_id == config::BUTTON_UAVCAN_NODE_ID && message.subject_id == config::POWER_BUTTON_SUBJECT {
-                                    log_debug!("Pwr pressed");
-//                                    cx.shared.stand_state.lock(|s| s.is_power_enabled = !s.is_power_enabled);
-                                } else if uavcan_id.source_node_id == config::PI_NODE_ID && message.subject_id == SubjectId::new(77).unwrap() {
-                                    log_debug!("Pwr pressed virt");
- //                                   cx.shared.stand_state.lock(|s| s.is_power_enabled = !s.is_power_enabled);
-                                } else if uavcan_id.source_node_id == config::PI_NODE_ID && message.subject_id == config::POWER_BUTTON_SUBJECT {
-                                    log_info!("UI Power button pressed");
-  //                                  cx.shared.stand_state.lock(|s| s.is_power_enabled = false);
-                                }
-                                if false {
-
-                                } else {
-                                    crate::module::handle_message(uavcan_id.source_node_id, message, frame.data());
-                                }
-                            }
-                            TransferKind::Service(service) => {
-                                if service.destination_node_id != config::UAVCAN_NODE_ID {
-                                    continue;
-                                }
-                                if service.service_id == config::REBOOT_SERVICE_ID {
-                                    log_debug!("Reset requested from: {}", uavcan_id.source_node_id);
-                                    cortex_m::asm::delay(10_000); // Minimum seems to be 3_000 @ 8MHz and JLink 255
-                                    cortex_m::peripheral::SCB::sys_reset();
-                                } else {
-                                    crate::module::handle_service_request(uavcan_id.source_node_id, service, frame.data());
-                                }
-                            }
-                        }
-                    },
-                    Err(_) => {}
+                Err(_e) => {
+                    log_warn!(target: Canbus, "{:?} request from {} failed: {:?}", endpoint, source.inner(), _e);
                 }
-            },
-            None => {
-                return;
             }
         }
+        Dispatch::Service { endpoint: Endpoint::Module, source, service, payload } => {
+            crate::module::handle_service_request(source, service, payload);
+        }
+        _d => {
+            log_warn!(target: Canbus, "Unhandled dispatch: {:?}", crate::logging::Fmt(_d));
+        }
+    }
+}
+
+/// Enqueue a frame received on one interface on the other one as is, false if it was dropped.
+/// Frames longer than 8 bytes only fit an FD capable interface.
+#[allow(unreachable_patterns, unused_variables)]
+fn bridge_forward<const MTU: usize>(cx: &mut app::can_rx_router::Context, to: Interface, frame: &Frame<MTU>) -> bool {
+    match to {
+        #[cfg(feature = "can-mcp25625")]
+        Interface::Mcp => {
+            let pushed = Frame::<8>::new(frame.id, frame.data())
+                .map(|frame| cx.shared.can_mcp_tx.lock(|tx| tx.push(frame)).is_ok());
+            rtic::pend(config::MCP25625_IRQ_HANDLER);
+            pushed.unwrap_or(false)
+        }
+        #[cfg(feature = "can-mcp2518fd")]
+        Interface::Mcp => {
+            let pushed = config::CanFdFrame::new(frame.id, frame.data())
+                .map(|frame| cx.shared.can_mcp_tx.lock(|tx| tx.push(frame)).is_ok());
+            rtic::pend(config::MCP2518FD_IRQ_HANDLER);
+            pushed.unwrap_or(false)
+        }
+        #[cfg(feature = "can-stm")]
+        Interface::Stm => {
+            let pushed = Frame::<8>::new(frame.id, frame.data())
+                .map(|frame| cx.shared.can_stm_tx.lock(|tx| tx.push(frame)).is_ok());
+            rtic::pend(crate::pac::Interrupt::CEC_CAN);
+            pushed.unwrap_or(false)
+        }
+        _ => false,
     }
 }
 
@@ -114,43 +373,64 @@ pub fn can_mcp25625_init(
         config::MCP25625SPI_FREQ.0 * 1_000_000,
         rcc.clocks.sysclk().0
     );
-    mcp25625_configure(&mut mcp25625)?;
+    mcp25625_configure(&mut mcp25625, McpOperationMode::Normal, crate::nvconfig::node_id())?;
     Ok(mcp25625)
 }
 
 #[cfg(feature = "can-mcp25625")]
-use mcp25625::{McpErrorKind, FiltersConfig, MCP25625Config, McpOperationMode};
+use mcp25625::{McpErrorKind, FiltersConfig, FiltersConfigBuffer0, FiltersConfigBuffer1, FiltersMask, MCP25625Config, McpOperationMode};
 use vhrdcan::{FrameId, Frame};
 
+/// Both masks and all 6 filters
 #[cfg(feature = "can-mcp25625")]
-fn mcp25625_configure(mcp25625: &mut config::Mcp25625Instance) -> Result<(), McpErrorKind> {
-    // let filters_buffer0 = FiltersConfigBuffer0 {
-    //     mask: FiltersMask::AllExtendedIdBits,
-    //     filter0: config::,
-    //     filter1: None
-    // };
-    // let filters_buffer1 = FiltersConfigBuffer1 {
-    //     mask: FiltersMask::OnlyStandardIdBits,
-    //     filter2: config::,
-    //     filter3: None,
-    //     filter4: None,
-    //     filter5: None,
-    // };
-    // let filters_config = FiltersConfig::Filter(filters_buffer0, Some(filters_buffer1));
-    let filters_config = FiltersConfig::ReceiveAll;
+const MCP25625_FILTERS: usize = 6;
+
+#[cfg(feature = "can-mcp25625")]
+fn mcp25625_configure(mcp25625: &mut config::Mcp25625Instance, operation_mode: McpOperationMode, node_id: Option<NodeId>) -> Result<(), McpErrorKind> {
+    let filters: heapless::Vec<Filter, MCP25625_FILTERS> = rx_filters(Interface::Mcp, node_id);
+    mcp25625_apply_config(mcp25625, operation_mode, &filters)
+}
+
+/// Filters can only be changed in configuration mode, so everything is applied at once.
+#[cfg(feature = "can-mcp25625")]
+pub(crate) fn mcp25625_apply_config(mcp25625: &mut config::Mcp25625Instance, operation_mode: McpOperationMode, filters: &[Filter]) -> Result<(), McpErrorKind> {
+    // Two masks, shared by 2 filters of buffer 0 and 4 filters of buffer 1
+    let filters = filter::TwoMaskFilters::new(filters);
+    // Filters are at most 29 bits long
+    let id = |id: u32| FrameId::new_extended(id).unwrap();
+    let filters_buffer0 = FiltersConfigBuffer0 {
+        mask: FiltersMask::Custom(filters.mask0),
+        filter0: id(filters.ids0[0]),
+        filter1: Some(id(filters.ids0[1])),
+    };
+    let filters_buffer1 = FiltersConfigBuffer1 {
+        mask: FiltersMask::Custom(filters.mask1),
+        filter2: id(filters.ids1[0]),
+        filter3: Some(id(filters.ids1[1])),
+        filter4: Some(id(filters.ids1[2])),
+        filter5: Some(id(filters.ids1[3])),
+    };
+    let filters_config = FiltersConfig::Filter(filters_buffer0, Some(filters_buffer1));
+    let bitrate = crate::nvconfig::get().can_bitrate.bps();
+    // Every bitrate is checked against the oscillator at compile time in config
+    let timing = BitTiming::calculate(config::MCP25625_OSC_HZ, bitrate, config::CAN_SAMPLE_POINT_PERMILLE, &bit_timing::MCP25625)
+        .unwrap()
+        .with_sjw(bit_timing::MCP25625_SJW)
+        .mcp25625();
     let mcp_config = MCP25625Config {
-        brp: 0, // Fosc=16MHz
-        prop_seg: 3,
-        ph_seg1: 2,
-        ph_seg2: 2,
-        sync_jump_width: 2,
+        brp: timing.brp,
+        prop_seg: timing.prop_seg,
+        ph_seg1: timing.ph_seg1,
+        ph_seg2: timing.ph_seg2,
+        sync_jump_width: timing.sync_jump_width,
         rollover_to_buffer1: true,
         filters_config,
         // filters_config: FiltersConfig::ReceiveAll,
-        operation_mode: McpOperationMode::Normal
+        operation_mode
     };
     mcp25625.apply_config(mcp_config)?;
-    mcp25625.enable_interrupts(0b0001_1111);
+    // RX, TX, error and message error interrupts
+    mcp25625.enable_interrupts(0b1011_1111);
     mcp25625.clkout_mode(mcp25625::ClkOutMode::SystemClockDiv8); // default is /8 as well = 2MHz
     Ok(())
 }
@@ -159,7 +439,7 @@ macro_rules! log_debug_if_cps {
     ($($arg:tt)*) => {
         cfg_if::cfg_if! {
             if #[cfg(feature = "can-printstat")] {
-                log_debug!(=>1, $($arg)*);
+                log_debug!(target: Canbus, =>1, $($arg)*);
             }
         }
     };
@@ -167,10 +447,21 @@ macro_rules! log_debug_if_cps {
 
 #[cfg(feature = "can-mcp25625")]
 pub fn can_mcp25625_irq(cx: &mut crate::app::exti_4_15::Context) {
-    use mcp25625::{McpReceiveBuffer, McpPriority, };
+    use mcp25625::{McpReceiveBuffer, };
     match cx.local.can_mcp25625 {
         Some(mcp25625) => {
             let mcp25625: &mut config::Mcp25625Instance = mcp25625;
+            // Only possible in configuration mode, which resets the controller
+            if let Some(node_id) = cx.local.can_mcp_filters.outdated() {
+                if !cx.shared.can_mcp_health.lock(|h| h.is_held_off()) {
+                    let mailboxes = &mut cx.local.can_mcp_tx_mailboxes;
+                    cx.shared.can_mcp_tx.lock(|tx| mailboxes.reset(tx));
+                    if let Err(_e) = set_rx_filters::<_, MCP25625_FILTERS>(mcp25625, Interface::Mcp, node_id) {
+                        log_error!(target: Canbus, "Mcp25625 filters update failed: {:?}", crate::logging::Fmt(_e));
+                    }
+                    cx.local.can_mcp_filters.applied(node_id);
+                }
+            }
             let intf = mcp25625.interrupt_flags();
             log_debug_if_cps!("INTF: {:?}", intf);
             let errf = mcp25625.error_flags();
@@ -200,6 +491,7 @@ pub fn can_mcp25625_irq(cx: &mut crate::app::exti_4_15::Context) {
                     },
                     Err(_) => {
                         log_debug_if_cps!("RX overflow");
+                        cx.shared.can_mcp_health.lock(|h| h.on_rx_overflow());
                     }
                 }
             }
@@ -207,35 +499,39 @@ pub fn can_mcp25625_irq(cx: &mut crate::app::exti_4_15::Context) {
                 crate::app::can_rx_router::spawn().ok();
             }
 
-            let _tec = mcp25625.tec();
-            let _rec = mcp25625.rec();
-            log_debug_if_cps!("TEC: {}, REC: {}", _tec, _rec);
-
-            for _ in 0..3 {
-                let maybe_frame = cx.shared.can_mcp_tx.lock(|tx| tx.peek().cloned());
-                match maybe_frame {
-                    Some(frame) => {
-                        // Treat extended id frames as uavcan, use only one buffer for them to avoid priority inversion
-                        // If standard id frame is placed after extended one it will have to wait with this implementation!
-                        let buffer_choice = match frame.id {
-                            FrameId::Standard(_) => { mcp25625::TxBufferChoice::Any }
-                            FrameId::Extended(_) => { mcp25625::TxBufferChoice::OnlyOne(0) }
-                        };
-                        match mcp25625.send(frame.as_frame_ref(), buffer_choice, McpPriority::Highest) {
-                            Ok(_) => {
-                                let _ = cx.shared.can_mcp_tx.lock(|tx| tx.pop());
-                                log_debug_if_cps!("TX: {:?}", frame);
-                            }
-                            Err(_e) => {
-                                log_debug_if_cps!("TX error: {:?}", _e);
-                                break;
-                            }
-                        }
-                    },
-                    None => {
-                        break;
-                    }
+            let status = error_status(mcp25625);
+            log_debug_if_cps!("{:?}", status);
+            let action = cx.shared.can_mcp_health.lock(|h| {
+                if errf.rx0ovr_is_set() || errf.rx1ovr_is_set() {
+                    h.on_rx_overflow();
+                }
+                if intf.merrf_is_set() {
+                    h.on_tx_error();
+                }
+                h.update(status, crate::utils::millis(), RecoveryPolicy::current())
+            });
+            let mode = match action {
+                Action::None => None,
+                Action::HoldOff => Some(McpOperationMode::Configuration),
+                Action::Restart => Some(McpOperationMode::Normal),
+            };
+            if let Some(mode) = mode {
+                let node_id = crate::nvconfig::node_id();
+                let mailboxes = &mut cx.local.can_mcp_tx_mailboxes;
+                cx.shared.can_mcp_tx.lock(|tx| mailboxes.reset(tx));
+                if let Err(_e) = mcp25625_configure(mcp25625, mode, node_id) {
+                    log_error!(target: Canbus, "Mcp25625 {:?} failed: {:?}", action, crate::logging::Fmt(_e));
+                }
+                cx.local.can_mcp_filters.applied(node_id);
+                if action == Action::Restart {
+                    // Send out what was queued meanwhile
+                    rtic::pend(config::MCP25625_IRQ_HANDLER);
                 }
+                return;
+            }
+            // Frames wait in the queue while off the bus
+            if !cx.shared.can_mcp_health.lock(|h| h.is_held_off()) {
+                mcp25625_transmit(mcp25625, cx.local.can_mcp_tx_mailboxes, &mut cx.shared.can_mcp_tx);
             }
 
             if errf.is_err() {
@@ -247,6 +543,219 @@ pub fn can_mcp25625_irq(cx: &mut crate::app::exti_4_15::Context) {
     }
 }
 
+/// Move frames from the queue into the TX buffers, see `uavcan::tx::TxMailboxes`.
+#[cfg(feature = "can-mcp25625")]
+fn mcp25625_transmit(
+    mcp25625: &mut config::Mcp25625Instance,
+    mailboxes: &mut config::Mcp25625TxMailboxes,
+    tx: &mut impl Mutex<T = config::CanTxQueue>
+) {
+    use mcp25625::TxBufferChoice;
+    // Aborted frames raise no interrupt, so every buffer is checked
+    for buffer in 0..config::MCP25625_TX_BUFFERS {
+        if mailboxes.is_loaded(buffer) && !mcp25625.is_tx_pending(buffer as u8) {
+            if mailboxes.is_aborting(buffer) && mcp25625.is_tx_aborted(buffer as u8) {
+                mailboxes.returned(buffer);
+            } else {
+                mailboxes.completed(buffer);
+            }
+        }
+    }
+    loop {
+        match tx.lock(|tx| mailboxes.next(tx)) {
+            MailboxAction::Load { mailbox, frame } => {
+                let choice = TxBufferChoice::OnlyOne(mailbox as u8);
+                match mcp25625.send(frame.as_frame_ref(), choice, mcp25625_priority(frame.id)) {
+                    Ok(_) => {
+                        log_debug_if_cps!("TX: {:?}", frame);
+                    }
+                    Err(_e) => {
+                        log_debug_if_cps!("TX error: {:?}", _e);
+                        // Retried on the next interrupt or health poll
+                        mailboxes.returned(mailbox);
+                        break;
+                    }
+                }
+            }
+            MailboxAction::Abort { mailbox } => {
+                log_debug_if_cps!("TX abort: {}", mailbox);
+                mcp25625.abort_tx(mailbox as u8);
+                rtic::pend(config::MCP25625_IRQ_HANDLER);
+                break;
+            }
+            MailboxAction::Wait => break,
+        }
+    }
+}
+
+/// Buffer priority from the top two bits of the arbitration field, so UAVCAN priorities share
+/// levels in pairs. Within a level the chip sends the highest buffer number first, the only
+/// inversion left.
+#[cfg(feature = "can-mcp25625")]
+fn mcp25625_priority(id: FrameId) -> mcp25625::McpPriority {
+    use mcp25625::McpPriority;
+    let arbitration_field = match id {
+        FrameId::Standard(sid) => (sid.inner() as u32) << 18,
+        FrameId::Extended(eid) => eid.inner(),
+    };
+    match arbitration_field >> 27 {
+        0 => McpPriority::Highest,
+        1 => McpPriority::High,
+        2 => McpPriority::Low,
+        _ => McpPriority::Lowest,
+    }
+}
+
+#[cfg(feature = "can-mcp2518fd")]
+pub fn can_mcp2518fd_init(
+    spi: config::Mcp2518fdSpi,
+    sck: config::Mcp2518fdSck,
+    miso: config::Mcp2518fdMiso,
+    mosi: config::Mcp2518fdMosi,
+    cs: config::Mcp2518fdCs,
+    rcc: &mut hal::rcc::Rcc,
+) -> Result<config::Mcp2518fdInstance, crate::mcp2518fd::Error> {
+    let spi = hal::spi::Spi::spi1(
+        spi,
+        (sck, miso, mosi),
+        embedded_hal::spi::MODE_0,
+        config::MCP2518FD_SPI_FREQ,
+        rcc
+    );
+    let mut mcp2518fd = crate::mcp2518fd::MCP2518FD::new(spi, cs);
+    mcp2518fd.apply_config(&config::mcp2518fd_config(crate::nvconfig::get().can_bitrate))?;
+    mcp2518fd_set_filters(&mut mcp2518fd, crate::nvconfig::node_id())?;
+    Ok(mcp2518fd)
+}
+
+#[cfg(feature = "can-mcp2518fd")]
+fn mcp2518fd_set_filters(mcp2518fd: &mut config::Mcp2518fdInstance, node_id: Option<NodeId>) -> Result<(), crate::mcp2518fd::Error> {
+    set_rx_filters::<_, { crate::mcp2518fd::registers::FILTER_COUNT as usize }>(mcp2518fd, Interface::Mcp, node_id)
+}
+
+#[cfg(feature = "can-mcp2518fd")]
+pub fn can_mcp2518fd_irq(cx: &mut crate::app::exti_4_15::Context) {
+    use crate::mcp2518fd::{Error, registers::{INT_RXIF, INT_TEFIF, BDIAG1_TX_ERRORS, OperationMode}};
+    let mcp2518fd: &mut config::Mcp2518fdInstance = match cx.local.can_mcp2518fd {
+        Some(mcp2518fd) => mcp2518fd,
+        None => {
+            return;
+        }
+    };
+    if let Some(node_id) = cx.local.can_mcp_filters.outdated() {
+        if let Err(_e) = mcp2518fd_set_filters(mcp2518fd, node_id) {
+            log_error!(target: Canbus, "Mcp2518fd filters update failed: {:?}", _e);
+        }
+        cx.local.can_mcp_filters.applied(node_id);
+    }
+    let intf = mcp2518fd.interrupt_flags().unwrap_or(0);
+    log_debug_if_cps!("INTF: {:04x}", intf);
+
+    let mut new_frames = false;
+    loop {
+        match mcp2518fd.receive() {
+            Ok(Some(frame)) => {
+                match cx.shared.can_mcp_rx.lock(|rx| rx.push(frame)) {
+                    Ok(_) => {
+                        log_debug_if_cps!("RX: {:?}", frame);
+                        new_frames = true;
+                    }
+                    Err(_) => {
+                        log_debug_if_cps!("RX overflow");
+                        cx.shared.can_mcp_health.lock(|h| h.on_rx_overflow());
+                    }
+                }
+            }
+            Ok(None) => {
+                break;
+            }
+            Err(_e) => {
+                log_debug_if_cps!("RX error: {:?}", _e);
+                break;
+            }
+        }
+    }
+    if new_frames {
+        crate::app::can_rx_router::spawn().ok();
+    }
+    if let Ok(true) = mcp2518fd.take_rx_overflow() {
+        log_debug_if_cps!("RX FIFO overflow");
+        cx.shared.can_mcp_health.lock(|h| h.on_rx_overflow());
+    }
+
+    let status = error_status(mcp2518fd);
+    let tx_errors = mcp2518fd.take_bus_diagnostics().unwrap_or(0) & BDIAG1_TX_ERRORS;
+    log_debug_if_cps!("{:?}, TX errors: {:08x}", status, tx_errors);
+    let action = cx.shared.can_mcp_health.lock(|h| {
+        if tx_errors != 0 {
+            h.on_tx_error();
+        }
+        h.update(status, crate::utils::millis(), RecoveryPolicy::current())
+    });
+    // Configuration mode resets the FIFOs, frames that were in the TX FIFO are lost
+    match action {
+        Action::None => {}
+        Action::HoldOff => {
+            if let Err(_e) = mcp2518fd.set_operation_mode(OperationMode::Configuration) {
+                log_error!(target: Canbus, "Mcp2518fd hold off failed: {:?}", _e);
+            }
+        }
+        Action::Restart => {
+            let node_id = crate::nvconfig::node_id();
+            let result = mcp2518fd.apply_config(&config::mcp2518fd_config(crate::nvconfig::get().can_bitrate))
+                .and_then(|_| mcp2518fd_set_filters(mcp2518fd, node_id));
+            if let Err(_e) = result {
+                log_error!(target: Canbus, "Mcp2518fd restart failed: {:?}", _e);
+            }
+            cx.local.can_mcp_filters.applied(node_id);
+            // Send out what was queued meanwhile
+            rtic::pend(config::MCP2518FD_IRQ_HANDLER);
+            return;
+        }
+    }
+    if cx.shared.can_mcp_health.lock(|h| h.is_held_off()) {
+        mcp2518fd.clear_interrupt_flags(intf).ok();
+        return;
+    }
+
+    // Transmit events only tell that there is space in the TX FIFO again
+    let _sent = mcp2518fd.drain_tef().unwrap_or(0);
+    log_debug_if_cps!("TEF: {}", _sent);
+    loop {
+        let maybe_frame = cx.shared.can_mcp_tx.lock(|tx| tx.peek().cloned());
+        match maybe_frame {
+            Some(frame) => {
+                match mcp2518fd.transmit(&frame) {
+                    Ok(_) => {
+                        let _ = cx.shared.can_mcp_tx.lock(|tx| tx.pop());
+                        log_debug_if_cps!("TX: {:?}", frame);
+                    }
+                    Err(Error::FrameTooLong) => {
+                        // Would block the queue forever
+                        let _ = cx.shared.can_mcp_tx.lock(|tx| tx.pop());
+                        log_debug_if_cps!("TX dropped: {:?}", frame);
+                    }
+                    Err(_e) => {
+                        log_debug_if_cps!("TX error: {:?}", _e);
+                        break;
+                    }
+                }
+            }
+            None => {
+                break;
+            }
+        }
+    }
+
+    mcp2518fd.clear_interrupt_flags(intf).ok();
+    // nINT is level triggered, but EXTI only sees the falling edge: if a frame came in or went out
+    // while handling, the line never went high, run again
+    let pending = mcp2518fd.interrupt_flags().map(|f| f as u32 & (INT_RXIF | INT_TEFIF) != 0).unwrap_or(false);
+    if pending {
+        rtic::pend(config::MCP2518FD_IRQ_HANDLER);
+    }
+}
+
 #[cfg(feature = "can-stm")]
 pub fn can_stm_init(
     can_peripheral: hal::stm32::CAN,
@@ -254,26 +763,15 @@ pub fn can_stm_init(
     can_rx: config::CanRx,
     rcc: &mut hal::rcc::Rcc
 ) -> config::CanStmInstance {
-    use stm32f0xx_hal::can::bxcan::filter::{BankConfig, Mask32};
-
-    let can = hal::can::CanInstance::new(can_peripheral, can_tx, can_rx, rcc);
-    let mut can = hal::can::bxcan::Can::new(can);
-    #[cfg(feature = "module-button")]
-    let bit_timing = 0x00050000;
-    #[cfg(feature = "module-afe")]
-    let bit_timing = 0x00050000;
-    #[cfg(feature = "module-pi")]
-    let bit_timing = 0x00050000;
-    #[cfg(feature = "module-led")]
-    let bit_timing = 0x001c0002;
+    let mut can = crate::hw::stm32f0::CanStm::new(can_peripheral, can_tx, can_rx, rcc);
+    let bitrate = crate::nvconfig::get().can_bitrate.bps();
+    // Every bitrate is checked against SYS_CLK_HZ at compile time in config
+    let timing = BitTiming::calculate(crate::SYS_CLK_HZ, bitrate, config::CAN_SAMPLE_POINT_PERMILLE, &bit_timing::BXCAN).unwrap();
     can.modify_config()
         .set_loopback(false)
         .set_silent(false)
-        .set_bit_timing(bit_timing);
-    {
-        let mut filters = can.modify_filters();
-        filters.enable_bank(0, BankConfig::Mask32(Mask32::accept_all()));
-    }
+        .set_bit_timing(timing.bxcan_btr());
+    can_stm_set_filters(&mut can, crate::nvconfig::node_id());
     can.enable().ok();
 
     use hal::can::bxcan::Interrupt;
@@ -285,26 +783,30 @@ pub fn can_stm_init(
     can
 }
 
+/// All of them go to FIFO 0, one 32 bit mask filter per bank
 #[cfg(feature = "can-stm")]
-use hal::can::bxcan::Frame as BxFrame;
-use uavcan_llr::types::{CanId, TransferKind, SubjectId};
-use core::convert::TryFrom;
-use embedded_time::duration::Milliseconds;
-// use vhrd_module_nvconfig::NVConfig;
+const CAN_STM_FILTER_BANKS: usize = 14;
 
-pub struct CanStmState {
-    #[cfg(feature = "can-stm")]
-    pushed_out: Option<BxFrame>,
-}
-impl CanStmState {
-    pub const fn new() -> Self {
-        CanStmState {
-            #[cfg(feature = "can-stm")]
-            pushed_out: None
-        }
-    }
+#[cfg(feature = "can-stm")]
+fn can_stm_set_filters(can: &mut config::CanStmInstance, node_id: Option<NodeId>) {
+    // Banks are written directly, cannot fail
+    let _ = set_rx_filters::<_, CAN_STM_FILTER_BANKS>(can, Interface::Stm, node_id);
 }
 
+#[cfg(feature = "can-stm")]
+use hal::can::bxcan::Frame as BxFrame;
+use crate::uavcan::router::{self, Subscription, Source, Endpoint, Dispatch};
+use crate::can_health::{Action, ErrorStatus, Interface, RecoveryPolicy};
+use crate::can_interfaces::{self, BridgeRules};
+use crate::uavcan::filter::{self, Filter};
+use crate::hw::CanInterface;
+#[cfg(feature = "can-mcp25625")]
+use crate::uavcan::tx::MailboxAction;
+#[cfg(any(feature = "can-stm", feature = "can-mcp25625"))]
+use crate::bit_timing::{self, BitTiming};
+use uavcan_llr::types::{CanId, Priority, NodeId};
+// use vhrd_module_nvconfig::NVConfig;
+
 #[cfg(feature = "can-stm")]
 pub fn can_stm_task(mut cx: crate::app::can_stm_task::Context) {
     // log_debug!("can_irq");
@@ -312,6 +814,10 @@ pub fn can_stm_task(mut cx: crate::app::can_stm_task::Context) {
 
     let can: &mut config::CanStmInstance = cx.local.can_stm;
     can.clear_wakeup_interrupt();
+    if let Some(node_id) = cx.local.can_stm_filters.outdated() {
+        can_stm_set_filters(can, node_id);
+        cx.local.can_stm_filters.applied(node_id);
+    }
     // unsafe {
     //     let dp = hal::pac::Peripherals::steal();
     //     log_debug!("msr:{:032b}", dp.CAN.msr.read().bits());
@@ -329,7 +835,7 @@ pub fn can_stm_task(mut cx: crate::app::can_stm_task::Context) {
                             new_frames = true;
                         }
                         Err(_) => {
-
+                            cx.shared.can_stm_health.lock(|h| h.on_rx_overflow());
                         }
                     }
                 }
@@ -343,55 +849,75 @@ pub fn can_stm_task(mut cx: crate::app::can_stm_task::Context) {
         crate::app::can_rx_router::spawn().ok();
     }
 
-    cx.local.state.pushed_out = match &cx.local.state.pushed_out {
-        Some(frame) => {
-            match can.transmit(&frame) {
-                Ok(maybe_frame) => {
-                    log_debug_if_cps!("TXPu -> push");
-                    maybe_frame
-                }
-                Err(_) => {
-                    log_debug_if_cps!("TXPu -> none");
-                    None
-                }
-            }
+    match can_stm_check_errors(can, &mut cx.shared.can_stm_health) {
+        Action::None => {}
+        Action::HoldOff => {
+            can.set_init_mode(true);
         }
-        None => {
-            None
+        Action::Restart => {
+            can.set_init_mode(false);
         }
-    };
-    if cx.local.state.pushed_out.is_some() {
+    }
+    if cx.shared.can_stm_health.lock(|h| h.is_held_off()) {
         return;
     }
 
+    // bxcan only takes a frame more important than all the pending ones, so frames of a session
+    // stay in order. With all mailboxes full the least important pending frame is aborted and
+    // handed back, it goes back into the queue in front of its session.
     loop {
-        match cx.shared.can_stm_tx.lock(|tx: &mut config::CanTxQueue| tx.pop()) {
-            Some(frame) => {
-                match can.transmit(&BxFrame::new_data(vhrdcanid2bxcanid(frame.id), BxData::new(frame.data()).unwrap())) {
-                    Ok(maybe_frame) => {
-                        match maybe_frame {
-                            Some(frame) => {
-                                cx.local.state.pushed_out = Some(frame);
-                                log_debug_if_cps!("TX -> push");
-                                break;
-                            }
-                            None => {
-                                log_debug_if_cps!("TX -> none");
-                            }
-                        }
+        let frame = match cx.shared.can_stm_tx.lock(|tx: &mut config::CanTxQueue| tx.peek().cloned()) {
+            Some(frame) => frame,
+            None => break,
+        };
+        match can.transmit(&BxFrame::new_data(vhrdcanid2bxcanid(frame.id), BxData::new(frame.data()).unwrap())) {
+            Ok(pushed_out) => {
+                log_debug_if_cps!("TX: {:?}", frame);
+                cx.shared.can_stm_tx.lock(|tx: &mut config::CanTxQueue| {
+                    let _ = tx.pop();
+                    if let Some(pushed_out) = pushed_out {
+                        log_debug_if_cps!("TX -> push");
+                        let pushed_out = Frame::<8>::new(bxcanid2vhrdcanid(pushed_out.id()), pushed_out.data().unwrap()).unwrap();
+                        // Cannot fail, pop made room
+                        let _ = tx.push_front(pushed_out);
                     }
-                    Err(_e) => {
-                        log_debug_if_cps!("TX error: {:?}", _e);
-                    }
-                }
+                });
             }
-            None => {
+            Err(_) => {
+                // No mailbox for a frame this important yet, retried on mailbox empty interrupt
                 break;
             }
         }
     }
 }
 
+/// Last error codes only a transmitter sees: ACK, bit recessive and bit dominant errors
+#[cfg(feature = "can-stm")]
+const CAN_LEC_TX_ERRORS: core::ops::RangeInclusive<u8> = 3..=5;
+
+/// Read error state and counters, feed them into the interface health and clear the sticky ones.
+#[cfg(feature = "can-stm")]
+fn can_stm_check_errors(can: &mut config::CanStmInstance, health: &mut impl Mutex<T = crate::can_health::InterfaceHealth>) -> Action {
+    let status = error_status(can);
+    let lec = can.take_last_error_code();
+    let arbitration_lost = can.take_arbitration_lost();
+    let rx_overflow = can.take_rx_overflow();
+    log_debug_if_cps!("{:?}, LEC: {}", status, lec);
+
+    health.lock(|h| {
+        for _ in 0..arbitration_lost {
+            h.on_arbitration_lost();
+        }
+        if rx_overflow {
+            h.on_rx_overflow();
+        }
+        if CAN_LEC_TX_ERRORS.contains(&lec) {
+            h.on_tx_error();
+        }
+        h.update(status, crate::utils::millis(), RecoveryPolicy::current())
+    })
+}
+
 #[cfg(feature = "can-stm")]
 fn vhrdcanid2bxcanid(id: FrameId) -> crate::hal::can::bxcan::Id {
     use hal::can::bxcan::{Id, StandardId, ExtendedId};
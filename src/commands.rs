@@ -0,0 +1,76 @@
+//! uavcan.node.ExecuteCommand handling.
+//!
+//! Commands that end in a reset only prepare for it, the caller resets after the response is sent.
+
+use crate::boot::{BootRequest, ImageDescriptor};
+use crate::nvconfig::{self, key, NvStore, RuntimeConfig};
+use crate::prelude::NodeId;
+use crate::uavcan::execute_command::{self as cmd, Request, Status};
+use crate::uavcan::register::Error;
+
+/// Returns response length and whether to reset afterwards.
+pub fn handle(payload: &[u8], source: NodeId, store: &mut NvStore, response: &mut [u8]) -> Result<(usize, bool), Error> {
+    let request = Request::decode(payload)?;
+    let (status, reset) = match request.command {
+        cmd::COMMAND_RESTART => (Status::Success, true),
+        cmd::COMMAND_BEGIN_SOFTWARE_UPDATE => begin_software_update(source, request.parameter),
+        cmd::COMMAND_FACTORY_RESET => factory_reset(store),
+        cmd::COMMAND_STORE_PERSISTENT_STATES => match nvconfig::get().save(store) {
+            Ok(_) => (Status::Success, false),
+            Err(_e) => {
+                log_error!("Failed to store config: {:?}", _e);
+                (Status::InternalError, false)
+            }
+        },
+        _ => (Status::BadCommand, false),
+    };
+    log_info!("Command {} from {}: {:?}", request.command, source.inner(), status);
+    Ok((cmd::encode_response(status, response)?, reset))
+}
+
+/// Source of the command is the file server, as UAVCAN specification requires.
+fn begin_software_update(source: NodeId, parameter: &[u8]) -> (Status, bool) {
+    if !cfg!(feature = "bootloader") {
+        return (Status::BadState, false);
+    }
+    let (image, path) = match ImageDescriptor::split_update_parameter(parameter) {
+        Some(split) => split,
+        None => return (Status::BadParameter, false),
+    };
+    match heapless::Vec::from_slice(path) {
+        Ok(path) => {
+            BootRequest::Update { server: source.inner(), image, path }.write();
+            (Status::Success, true)
+        }
+        Err(_) => (Status::BadParameter, false),
+    }
+}
+
+/// Wipe configuration but keep the image descriptor, bootloader would not trust the image otherwise.
+fn factory_reset(store: &mut NvStore) -> (Status, bool) {
+    let mut descriptor = [0u8; 8];
+    let descriptor = store.read(key::IMAGE_DESCRIPTOR, key::RECORD_VERSION, &mut descriptor).map(|_| descriptor);
+    let r = store.clear().and_then(|_| match descriptor {
+        Some(d) => store.write(key::IMAGE_DESCRIPTOR, key::RECORD_VERSION, &d),
+        None => Ok(()),
+    });
+    match r {
+        Ok(_) => {
+            nvconfig::set(RuntimeConfig::DEFAULT);
+            (Status::Success, true)
+        }
+        Err(_e) => {
+            log_error!("Factory reset failed: {:?}", _e);
+            (Status::InternalError, false)
+        }
+    }
+}
+
+/// Reboot service payload: empty for a plain reset, [REBOOT_INTO_BOOTLOADER] to stay in bootloader.
+pub const REBOOT_INTO_BOOTLOADER: u8 = 1;
+
+pub fn prepare_reboot(payload: &[u8]) {
+    if payload.first() == Some(&REBOOT_INTO_BOOTLOADER) {
+        BootRequest::Stay.write();
+    }
+}
@@ -1,59 +1,177 @@
-use embedded_time::duration::{Seconds, Milliseconds};
+use embedded_time::duration::Milliseconds;
 
-/// How often to update LED brightness during transitions (Breath, etc)
+#[allow(dead_code)]
+mod shared;
+pub use shared::*;
+
+/// How often to update LED brightness, blink patterns play with this resolution
 pub const BLINKER_UPDATE_PERIOD: Milliseconds = Milliseconds(20);
-pub const BLINKER_BREATH_PERIOD: Seconds = Seconds(8);
+
+/// Default, can be changed in non-volatile config
+pub const BLINKER_BRIGHTNESS_PERCENT: u8 = 15;
 
 pub const HEALTH_CHECK_PERIOD: Milliseconds = Milliseconds(1000);
 
 pub const REBOOT_SERVICE_ID: ServiceId = ServiceId::new(4).unwrap();
-#[cfg(feature = "module-led")]
-pub const RMP_RAMP_TARGET_SUBJECT_ID: SubjectId = SubjectId::new(14).unwrap();
-#[cfg(feature = "module-led")]
-pub const DUTY_RAMP_TARGET_SUBJECT_ID: SubjectId = SubjectId::new(13).unwrap();
 
 #[cfg(feature = "module-afe")]
 pub const ZERO_AFE: SubjectId = SubjectId::new(11).unwrap();
+/// Thrust and torque together with timestamp and validity flags, multi-frame
+#[cfg(feature = "module-afe")]
+pub const AFE_TELEMETRY_SUBJECT: SubjectId = SubjectId::new(22).unwrap();
 
+/// Default node ID of each module, can be changed in non-volatile config. Plug-and-play allocation
+/// is the fallback for a node without one: a build without a module, or one whose ID was cleared
+/// with a register write.
 #[cfg(feature = "module-pi")]
-pub const UAVCAN_NODE_ID: NodeId = NodeId::new(5).unwrap();
+pub const UAVCAN_NODE_ID: Option<NodeId> = Some(NodeId::new(5).unwrap());
 #[cfg(feature = "module-led")]
-pub const UAVCAN_NODE_ID: NodeId = NodeId::new(4).unwrap();
-
-pub const BUTTON_UAVCAN_NODE_ID: NodeId = NodeId::new(3).unwrap();
+pub const UAVCAN_NODE_ID: Option<NodeId> = Some(NodeId::new(4).unwrap());
 #[cfg(feature = "module-button")]
-pub const UAVCAN_NODE_ID: NodeId = BUTTON_UAVCAN_NODE_ID;
-pub const POWER_BUTTON_SUBJECT: SubjectId = SubjectId::new(20).unwrap();
-pub const SAFETY_BUTTON_SUBJECT: SubjectId = SubjectId::new(21).unwrap();
+pub const UAVCAN_NODE_ID: Option<NodeId> = Some(BUTTON_UAVCAN_NODE_ID);
+#[cfg(feature = "module-afe")]
+pub const UAVCAN_NODE_ID: Option<NodeId> = Some(NodeId::new(2).unwrap());
+#[cfg(not(any(feature = "module-pi", feature = "module-led", feature = "module-button", feature = "module-afe")))]
+pub const UAVCAN_NODE_ID: Option<NodeId> = None;
+pub const PNP_ALLOCATION_SUBJECT_ID: SubjectId = SubjectId::new(crate::uavcan::pnp::SUBJECT_ID).unwrap();
+
+pub const VESC_INPUT_TIMEOUT: Milliseconds = Milliseconds(500);
+/// VESC status messages come every few ms, nothing for this long makes node health a warning
+#[cfg(feature = "vesc-ctrl")]
+pub const VESC_FEEDBACK_TIMEOUT: Milliseconds = Milliseconds(1000);
 
+/// Defaults of AFE calibration, can be changed in non-volatile config
+pub const AFE_READING_IS_JUNK_DELTA: i32 = 1000;
+pub const AFE_MAX_NOT_JUNK_THRUST: i32 = i32::MAX;
+pub const AFE_MAX_NOT_JUNK_TORQUE: i32 = i32::MAX;
+/// Conversions take 100 ms at 10 SPS, the ADC is given up on after this
 #[cfg(feature = "module-afe")]
-pub const UAVCAN_NODE_ID: NodeId = NodeId::new(2).unwrap();
-pub const PI_NODE_ID: NodeId = NodeId::new(10).unwrap();
+pub const AFE_CONVERSION_TIMEOUT: Milliseconds = Milliseconds(500);
+
+pub const REGISTER_ACCESS_SERVICE_ID: ServiceId = ServiceId::new(crate::uavcan::register::ACCESS_SERVICE_ID).unwrap();
+pub const REGISTER_LIST_SERVICE_ID: ServiceId = ServiceId::new(crate::uavcan::register::LIST_SERVICE_ID).unwrap();
+pub const GET_INFO_SERVICE_ID: ServiceId = ServiceId::new(crate::uavcan::get_info::SERVICE_ID).unwrap();
+pub const EXECUTE_COMMAND_SERVICE_ID: ServiceId = ServiceId::new(crate::uavcan::execute_command::SERVICE_ID).unwrap();
+/// Time for a service response to leave before reset
+pub const REBOOT_DELAY: Milliseconds = Milliseconds(100);
+
+/// Controllers are polled for error state this often, also bounds bus-off backoff resolution
+pub const CAN_HEALTH_POLL_PERIOD: Milliseconds = Milliseconds(100);
+/// Statistics are published every this many polls
+pub const CAN_STATISTICS_PUBLISH_POLLS: u8 = 50;
+pub const CAN_STATISTICS_SUBJECT_ID: SubjectId = SubjectId::new(23).unwrap();
+/// Conditions behind the heartbeat health, published right after it
+pub const HEALTH_REPORT_SUBJECT_ID: SubjectId = SubjectId::new(crate::uavcan::health_report::SUBJECT_ID).unwrap();
+pub const CRASH_REPORT_SUBJECT_ID: SubjectId = SubjectId::new(crate::uavcan::crash_report::SUBJECT_ID).unwrap();
+pub const CRASH_REPORT_SERVICE_ID: ServiceId = ServiceId::new(crate::uavcan::crash_report::SERVICE_ID).unwrap();
+/// Next attempt while anonymous or after the TX queue was full
+pub const CRASH_REPORT_RETRY_PERIOD: Milliseconds = Milliseconds(1000);
+/// Independent watchdog timeout with the typical LSI frequency
+pub const WATCHDOG_TIMEOUT: Milliseconds = Milliseconds(2000);
+/// How often the supervisor checks the watched tasks and feeds the watchdog
+pub const WATCHDOG_FEED_PERIOD: Milliseconds = Milliseconds(250);
+/// Added to the period of a task to make its check-in deadline, covers scheduling jitter
+pub const WATCHDOG_CHECK_IN_SLACK: Milliseconds = Milliseconds(500);
+/// Deadline of the first check-in of the tasks spawned in init
+pub const WATCHDOG_FIRST_CHECK_IN: Milliseconds = Milliseconds(1000);
+/// Deadline of one pass of the idle loop, AFE zeroing takes 20 conversions at 10 SPS
+pub const WATCHDOG_IDLE_PERIOD: Milliseconds = Milliseconds(5000);
+/// Default of the runtime log filter, can be changed in non-volatile config
+pub const LOG_FILTER: crate::logging::filter::LogFilter = crate::logging::filter::LogFilter::ALL;
+/// Log output with log-defmt-can, see `logging::can`
+#[cfg(feature = "log-defmt-can")]
+pub const LOG_SUBJECT_ID: SubjectId = SubjectId::new(24).unwrap();
+/// Log output with log-text-can, standard diagnostic records
+#[cfg(feature = "log-text-can")]
+pub const LOG_SUBJECT_ID: SubjectId = SubjectId::new(crate::uavcan::diagnostic::SUBJECT_ID).unwrap();
+/// Records waiting to be sent, defmt ones are a few bytes each
+#[cfg(feature = "log-defmt-can")]
+pub const LOG_CAN_QUEUE_SIZE: usize = 256;
+/// Records waiting to be sent, text ones are up to header and [LOG_TEXT_MAX_LEN] each
+#[cfg(feature = "log-text-can")]
+pub const LOG_CAN_QUEUE_SIZE: usize = 512;
+/// Longer messages are cut, every byte costs queue space and bus time
+#[cfg(feature = "log-text-can")]
+pub const LOG_TEXT_MAX_LEN: usize = 64;
+/// Payload of one log transfer, 8 classic frames
+#[cfg(feature = "log-defmt-can")]
+pub const LOG_CAN_CHUNK_SIZE: usize = 48;
+/// Next attempt after the TX queue was full
+#[cfg(any(feature = "log-defmt-can", feature = "log-text-can"))]
+pub const LOG_CAN_RETRY_PERIOD: Milliseconds = Milliseconds(20);
+/// Defaults of bus-off recovery policy, can be changed in non-volatile config
+pub const CAN_AUTO_RECOVERY: bool = true;
+pub const CAN_RECOVERY_BACKOFF_MS: [u16; 2] = [100, 5_000];
+/// Default, can be changed in non-volatile config, applied after reboot
+pub const CAN_BITRATE: Bitrate = Bitrate::Mbps1;
+/// Same for every controller, see `bit_timing`
+pub const CAN_SAMPLE_POINT_PERMILLE: u32 = 875;
+/// Defaults of dual interface mode, can be changed in non-volatile config, see `can_interfaces`
+pub const CAN_REDUNDANT: bool = false;
+pub const CAN_TX_INTERFACES: Interfaces = Interfaces::ALL;
+#[cfg(feature = "module-pi")]
+pub const CAN_BRIDGE_RULES: BridgeRules = crate::module::pi::BRIDGE_RULES;
+#[cfg(not(feature = "module-pi"))]
+pub const CAN_BRIDGE_RULES: BridgeRules = [crate::can_interfaces::BridgeRule::DISABLED; crate::can_interfaces::BRIDGE_RULES];
+
+// Non-volatile config, last 12K of flash are excluded from FLASH region in memory.x
+pub const NVCONFIG_FLASH_ADDRESS: usize = 0x0800_0000 + 64 * 1024 - NVCONFIG_FLASH_SIZE;
+pub const NVCONFIG_FLASH_SIZE: usize = 12 * 1024;
+#[cfg(feature = "f051c8u")]
+pub const FLASH_PAGE_SIZE: usize = 1024;
+#[cfg(feature = "f072c8u")]
+pub const FLASH_PAGE_SIZE: usize = 2048;
+const_assert!(NVCONFIG_FLASH_SIZE % (2 * FLASH_PAGE_SIZE) == 0);
 
 // CAN Bus
 use heapless::binary_heap::{BinaryHeap, Min};
 use vhrdcan::frame::Frame;
-pub type CanTxQueue = BinaryHeap<Frame<8>, Min, 32>;
+pub const CAN_TX_QUEUE_LEN: usize = 32;
+pub type CanTxQueue = crate::uavcan::tx::TxQueue<8, CAN_TX_QUEUE_LEN>;
 pub type CanRxQueue = BinaryHeap<Frame<8>, Min, 32>;
+/// MCP2518FD queues, shorter as frames are 8 times bigger
+#[cfg(feature = "can-mcp2518fd")]
+pub type CanFdFrame = Frame<{ crate::mcp2518fd::MTU }>;
+#[cfg(feature = "can-mcp2518fd")]
+pub type CanFdTxQueue = crate::uavcan::tx::TxQueue<{ crate::mcp2518fd::MTU }, 12>;
+#[cfg(feature = "can-mcp2518fd")]
+pub type CanFdRxQueue = BinaryHeap<CanFdFrame, Min, 12>;
+
+/// How many multi-frame transfers can be received simultaneously
+pub const UAVCAN_RX_SESSIONS: usize = 4;
+/// Biggest multi-frame transfer accepted, including 2 bytes of transfer CRC
+pub const UAVCAN_RX_MAX_TRANSFER_SIZE: usize = 128;
+/// Transfer is dropped if not completed in this time
+pub const UAVCAN_RX_TRANSFER_TIMEOUT: Milliseconds = Milliseconds(1000);
+pub type RxAssembler = crate::uavcan::assembler::Assembler<UAVCAN_RX_SESSIONS, UAVCAN_RX_MAX_TRANSFER_SIZE>;
+/// Redundant interfaces: a session switches over to the other interface after this much silence
+pub const UAVCAN_REDUNDANCY_TIMEOUT: Milliseconds = Milliseconds(1000);
+/// Sessions remembered for deduplication, none needed with a single interface
+pub const UAVCAN_REDUNDANCY_SESSIONS: usize = if crate::can_interfaces::DUAL { 16 } else { 0 };
+pub type RxDeduplicator = crate::uavcan::redundancy::Deduplicator<UAVCAN_REDUNDANCY_SESSIONS>;
 
 /// CAN Bus: MCP2515
 #[cfg(feature = "can-mcp25625")]
 pub mod mcp25625_config {
     use crate::{hal, pac};
-    use hal::gpio::{Alternate, AF0, PushPull, Input, PullUp, Output, gpiob::{PB3, PB4, PB5}, gpioc::{PC14, PC15}};
     use pac::{Interrupt, SPI1};
     use hal::time::MegaHertz;
     use hal::spi::{Spi};
+    use crate::bit_timing::{all_bitrates_supported, MCP25625};
 
-    pub type Mcp25625Sck = PB3<Alternate<AF0>>;
+    pub type Mcp25625Sck = crate::board::McpSck;
     pub const MCP25625SPI_FREQ: MegaHertz = MegaHertz(1);
-    pub type Mcp25625Miso = PB4<Alternate<AF0>>;
-    pub type Mcp25625Mosi = PB5<Alternate<AF0>>;
-    pub type Mcp25625Cs = PC14<Output<PushPull>>;
+    pub type Mcp25625Miso = crate::board::McpMiso;
+    pub type Mcp25625Mosi = crate::board::McpMosi;
+    pub type Mcp25625Cs = crate::board::McpCs;
     pub type Mcp25625Spi = SPI1;
     pub type Mcp25625Instance = mcp25625::MCP25625<Spi<Mcp25625Spi, Mcp25625Sck, Mcp25625Miso, Mcp25625Mosi, hal::spi::EightBit>, Mcp25625Cs>;
-    pub type Mcp25625Irq = PC15<Input<PullUp>>;
+    pub type Mcp25625Irq = crate::board::McpIrq;
     pub const MCP25625_IRQ_HANDLER: Interrupt = Interrupt::EXTI4_15;
+    pub const MCP25625_OSC_HZ: u32 = 16_000_000;
+    pub const MCP25625_TX_BUFFERS: usize = 3;
+    pub type Mcp25625TxMailboxes = crate::uavcan::tx::TxMailboxes<8, MCP25625_TX_BUFFERS>;
+    const_assert!(all_bitrates_supported(MCP25625_OSC_HZ, super::CAN_SAMPLE_POINT_PERMILLE, &MCP25625));
 }
 #[cfg(feature = "can-mcp25625")]
 pub use mcp25625_config::*;
@@ -62,20 +180,68 @@ pub type Mcp25625Instance = ();
 #[cfg(not(feature = "can-mcp25625"))]
 pub type Mcp25625Irq = ();
 
+#[cfg(all(feature = "can-mcp25625", feature = "can-mcp2518fd"))]
+compile_error!("can-mcp25625 and can-mcp2518fd share the same pins, select only one");
+
+/// CAN Bus: MCP2518FD, wired the same way as MCP2515
+#[cfg(feature = "can-mcp2518fd")]
+pub mod mcp2518fd_config {
+    use crate::{hal, pac};
+    use pac::{Interrupt, SPI1};
+    use hal::time::MegaHertz;
+    use hal::spi::{Spi};
+    use crate::mcp2518fd::{Config, BitTiming, registers::PayloadSize};
+    use crate::bit_timing::{self, Bitrate, all_bitrates_supported, MCP2518FD_NOMINAL};
+
+    pub type Mcp2518fdSck = crate::board::McpSck;
+    pub const MCP2518FD_SPI_FREQ: MegaHertz = MegaHertz(4);
+    pub type Mcp2518fdMiso = crate::board::McpMiso;
+    pub type Mcp2518fdMosi = crate::board::McpMosi;
+    pub type Mcp2518fdCs = crate::board::McpCs;
+    pub type Mcp2518fdSpi = SPI1;
+    pub type Mcp2518fdInstance = crate::mcp2518fd::MCP2518FD<Spi<Mcp2518fdSpi, Mcp2518fdSck, Mcp2518fdMiso, Mcp2518fdMosi, hal::spi::EightBit>, Mcp2518fdCs>;
+    pub type Mcp2518fdIrq = crate::board::McpIrq;
+    pub const MCP2518FD_IRQ_HANDLER: Interrupt = Interrupt::EXTI4_15;
+    pub const MCP2518FD_OSC_HZ: u32 = 40_000_000;
+    const_assert!(all_bitrates_supported(MCP2518FD_OSC_HZ, super::CAN_SAMPLE_POINT_PERMILLE, &MCP2518FD_NOMINAL));
+    /// Nominal bitrate as selected, 4Mbit/s data sampled at 80%
+    pub const fn mcp2518fd_config(bitrate: Bitrate) -> Config {
+        let nominal = bit_timing::BitTiming::calculate(MCP2518FD_OSC_HZ, bitrate.bps(), super::CAN_SAMPLE_POINT_PERMILLE, &MCP2518FD_NOMINAL).unwrap();
+        // Widest jump width, as recommended for CAN FD
+        let [brp, tseg1, tseg2, sjw] = nominal.with_sjw(nominal.tseg2).mcp2518fd();
+        Config {
+            nominal: BitTiming { brp, tseg1, tseg2, sjw },
+            data: Some(BitTiming { brp: 0, tseg1: 6, tseg2: 1, sjw: 1 }),
+            tef_depth: 4,
+            tx_fifo_depth: 8,
+            rx_fifo_depth: 12,
+            payload_size: PayloadSize::Bytes64,
+        }
+    }
+    const_assert!(mcp2518fd_config(super::CAN_BITRATE).ram_usage() <= crate::mcp2518fd::registers::RAM_SIZE);
+}
+#[cfg(feature = "can-mcp2518fd")]
+pub use mcp2518fd_config::*;
+#[cfg(not(feature = "can-mcp2518fd"))]
+pub type Mcp2518fdInstance = ();
+
 /// CAN Bus: STM
 #[cfg(feature = "can-stm")]
 pub mod can_stm_config {
-    use crate::hal;
-    use hal::gpio::{Alternate, AF4, gpioa::{PA11, PA12}};
+    use crate::bit_timing::{all_bitrates_supported, BXCAN};
 
-    pub type CanTx = PA12<Alternate<AF4>>;
-    pub type CanRx = PA11<Alternate<AF4>>;
-    pub type CanStmInstance = hal::can::bxcan::Can<hal::can::CanInstance<CanTx, CanRx>>;
+    pub type CanTx = crate::board::CanTx;
+    pub type CanRx = crate::board::CanRx;
+    pub type CanStmInstance = crate::hw::stm32f0::CanStm;
+    // bxCAN runs from PCLK, which is not divided from SYS_CLK_HZ
+    const_assert!(all_bitrates_supported(crate::SYS_CLK_HZ, super::CAN_SAMPLE_POINT_PERMILLE, &BXCAN));
 }
 #[cfg(feature = "can-stm")]
 pub use can_stm_config::*;
 use uavcan_llr::types::{NodeId, ServiceId};
 use crate::prelude::{SubjectId};
+use crate::bit_timing::Bitrate;
+use crate::can_interfaces::{Interfaces, BridgeRules};
 // use heapless::pool::Node;
 
 #[cfg(not(feature = "can-stm"))]
@@ -0,0 +1,21 @@
+//! Part of the config the modules agree on over the bus, also included by tools for the host
+//! simulation.
+
+use uavcan_llr::types::{NodeId, SubjectId};
+
+/// Peers are matched by their default IDs, so the button and the Pi must keep them, an allocator
+/// on the bus has to be configured not to hand these out.
+pub const BUTTON_UAVCAN_NODE_ID: NodeId = NodeId::new(3).unwrap();
+pub const POWER_BUTTON_SUBJECT: SubjectId = SubjectId::new(20).unwrap();
+pub const SAFETY_BUTTON_SUBJECT: SubjectId = SubjectId::new(21).unwrap();
+
+pub const PI_NODE_ID: NodeId = NodeId::new(10).unwrap();
+/// Targets of `ramp_vesc`, from the Pi
+pub const RMP_RAMP_TARGET_SUBJECT_ID: SubjectId = SubjectId::new(14).unwrap();
+pub const DUTY_RAMP_TARGET_SUBJECT_ID: SubjectId = SubjectId::new(13).unwrap();
+
+/// Default VESC CAN ID, can be changed in non-volatile config
+pub const VESC_ID: u8 = 7;
+/// Defaults of ramp_vesc tunables, can be changed in non-volatile config
+pub const VESC_DUTY_MIN: u32 = 4_000;
+pub const VESC_RAMP_RATES: [u32; 2] = [500, 300];
@@ -0,0 +1,147 @@
+//! Crash reports kept across reset in the RAM block reserved by memory.x, see `boot`.
+//!
+//! Panic and HardFault handlers in `error_handlers` store what happened and reset, so does
+//! `supervisor` before the watchdog fires. [init] picks
+//! the report up on the next boot together with the reset cause, it is then published once by
+//! `task::crash_report` and answered by the crash report service until the next reset.
+
+use core::cell::RefCell;
+#[cfg(not(feature = "defmt"))]
+use core::fmt;
+use core::panic::PanicInfo;
+use core::sync::atomic::{compiler_fence, Ordering};
+use cortex_m_rt::ExceptionFrame;
+use heapless::Vec;
+use crate::boot::{CRASH_REPORT_ADDRESS, CRASH_REPORT_SIZE};
+use crate::pac;
+use crate::supervisor::Task;
+use crate::uavcan::crash_report::{self, CrashReport, Fault, HardFaultFrame, ResetCause};
+use crate::uavcan::crc::crc16;
+use crate::uavcan::register::Error;
+
+const MAGIC: u32 = 0x4352_5348; // "CRSH"
+/// magic u32 | len u8 | report | crc u16 at the very end
+const_assert!(4 + 1 + crash_report::MAX_SIZE + 2 <= CRASH_REPORT_SIZE);
+
+/// Report of the last reset, encoded, filled in by [init]
+static LAST: bare_metal::Mutex<RefCell<Vec<u8, { crash_report::MAX_SIZE }>>> = bare_metal::Mutex::new(RefCell::new(Vec::new()));
+
+/// Called from the panic handler. Message is the panic info as core::fmt prints it, so it is
+/// only kept without defmt.
+pub fn store_panic(info: &PanicInfo) {
+    let (file, line) = info.location().map(|l| (l.file(), l.line())).unwrap_or(("", 0));
+    #[cfg(not(feature = "defmt"))]
+    let mut message = crate::utils::TruncatingWriter::<{ crash_report::MAX_MESSAGE_LEN }>::new();
+    // Error only means the message was cut
+    #[cfg(not(feature = "defmt"))]
+    fmt::write(&mut message, format_args!("{}", info)).ok();
+    #[cfg(not(feature = "defmt"))]
+    let message = message.as_bytes();
+    #[cfg(feature = "defmt")]
+    let message: &[u8] = &[];
+    store(&CrashReport {
+        reset_cause: ResetCause::from_bits(0),
+        uptime_ms: crate::utils::millis(),
+        fault: Fault::Panic { line, file: file.as_bytes(), message },
+    });
+}
+
+/// Called from the HardFault handler, touches nothing that could fault again, so uptime is left out.
+pub fn store_hard_fault(ef: &ExceptionFrame) {
+    store(&CrashReport {
+        reset_cause: ResetCause::from_bits(0),
+        uptime_ms: 0,
+        fault: Fault::HardFault(HardFaultFrame {
+            r0: ef.r0,
+            r1: ef.r1,
+            r2: ef.r2,
+            r3: ef.r3,
+            r12: ef.r12,
+            lr: ef.lr,
+            pc: ef.pc,
+            xpsr: ef.xpsr,
+            sp: ef as *const ExceptionFrame as u32,
+        }),
+    });
+}
+
+/// Called by the supervisor when `task` is late, the watchdog reset follows.
+pub fn store_watchdog(task: Task, silent_ms: u32) {
+    store(&CrashReport {
+        reset_cause: ResetCause::from_bits(0),
+        uptime_ms: crate::utils::millis(),
+        fault: Fault::Watchdog { task: task as u8, silent_ms },
+    });
+}
+
+/// Encoded in place, handlers calling this may be short of stack.
+fn store(report: &CrashReport) {
+    // Excluded from the RAM regions by memory.x, nothing else refers to it
+    let buf = unsafe { &mut *(CRASH_REPORT_ADDRESS as *mut [u8; CRASH_REPORT_SIZE]) };
+    buf[0..4].copy_from_slice(&MAGIC.to_le_bytes());
+    let len = report.encode(&mut buf[5..CRASH_REPORT_SIZE - 2]).unwrap_or(0);
+    buf[4] = len as u8;
+    let crc = crc16(&buf[..CRASH_REPORT_SIZE - 2]);
+    buf[CRASH_REPORT_SIZE - 2..].copy_from_slice(&crc.to_le_bytes());
+    // Reset follows, the block has to be written by then
+    compiler_fence(Ordering::SeqCst);
+}
+
+/// Read and invalidate the stored block, RAM content after power-on is random.
+fn take() -> [u8; CRASH_REPORT_SIZE] {
+    let mut buf = [0u8; CRASH_REPORT_SIZE];
+    for (i, b) in buf.iter_mut().enumerate() {
+        unsafe {
+            *b = core::ptr::read_volatile((CRASH_REPORT_ADDRESS + i) as *const u8);
+            core::ptr::write_volatile((CRASH_REPORT_ADDRESS + i) as *mut u8, 0);
+        }
+    }
+    buf
+}
+
+/// Combines the stored report, if any, with the reset cause and clears the reset flags. Has to
+/// run before RCC is configured.
+pub fn init(rcc: &pac::RCC) {
+    let reset_cause = ResetCause::from_csr(rcc.csr.read().bits());
+    rcc.csr.modify(|_, w| w.rmvf().set_bit());
+
+    let buf = take();
+    let crc = u16::from_le_bytes([buf[CRASH_REPORT_SIZE - 2], buf[CRASH_REPORT_SIZE - 1]]);
+    let valid = buf[0..4] == MAGIC.to_le_bytes() && crc16(&buf[..CRASH_REPORT_SIZE - 2]) == crc;
+    let stored = if valid {
+        buf.get(5..5 + buf[4] as usize).and_then(|payload| CrashReport::decode(payload).ok())
+    } else {
+        None
+    };
+    let mut report = stored.unwrap_or(CrashReport { reset_cause, uptime_ms: 0, fault: Fault::None });
+    report.reset_cause = reset_cause;
+
+    log_info!("Reset cause: {:?}", reset_cause.cause());
+    match report.fault {
+        Fault::None => {}
+        Fault::Panic { line: _line, file: _file, .. } => {
+            log_error!("Panicked before reset at {}:{}", core::str::from_utf8(_file).unwrap_or("?"), _line);
+        }
+        Fault::HardFault(_f) => {
+            log_error!("HardFault before reset, PC: {:08x}, LR: {:08x}", _f.pc, _f.lr);
+        }
+        Fault::Watchdog { task: _task, silent_ms: _silent_ms } => {
+            log_error!("Watchdog reset, {:?} did not check in for {} ms", Task::from_u8(_task), _silent_ms);
+        }
+    }
+
+    let mut encoded = [0u8; crash_report::MAX_SIZE];
+    let len = report.encode(&mut encoded).unwrap_or(0);
+    cortex_m::interrupt::free(|cs| {
+        *LAST.borrow(cs).borrow_mut() = Vec::from_slice(&encoded[..len]).unwrap_or_default();
+    });
+}
+
+/// Report of the last reset, as published and answered.
+pub fn encode_last(buf: &mut [u8]) -> Result<usize, Error> {
+    cortex_m::interrupt::free(|cs| {
+        let last = LAST.borrow(cs).borrow();
+        buf.get_mut(..last.len()).ok_or(Error::BufferTooSmall)?.copy_from_slice(&last);
+        Ok(last.len())
+    })
+}
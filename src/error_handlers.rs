@@ -1,9 +1,24 @@
 use core::panic::PanicInfo;
+use cortex_m_rt::{exception, ExceptionFrame};
 
 #[inline(never)]
 #[panic_handler]
 fn panic(_info: &PanicInfo) -> ! {
+    crate::crash::store_panic(_info);
+    // Message would need core::fmt, which defmt is there to leave out
+    #[cfg(not(feature = "defmt"))]
     log_error!("{:#?}", _info);
+    #[cfg(feature = "defmt")]
+    if let Some(_location) = _info.location() {
+        log_error!("Panicked at {}:{}", _location.file(), _location.line());
+    }
     cortex_m::asm::delay(6_000_000);
     cortex_m::peripheral::SCB::sys_reset(); // -> !
-}
\ No newline at end of file
+}
+
+/// Stack pointer might be what faulted, so only the report is stored, nothing is logged.
+#[exception]
+fn HardFault(ef: &ExceptionFrame) -> ! {
+    crate::crash::store_hard_fault(ef);
+    cortex_m::peripheral::SCB::sys_reset();
+}
@@ -0,0 +1,85 @@
+//! Fault and warning conditions raised by subsystems, the worst active one is the node health.
+//!
+//! Also included by tools, nothing in here depends on the hardware.
+
+use crate::uavcan::heartbeat::Health;
+use crate::uavcan::health_report::HealthReport;
+
+/// Value is the bit number in the published masks.
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum Reason {
+    /// Error passive or close to it on any interface, failure while off the bus
+    CanErrors = 0,
+    /// DRV8323 reports faults on nFAULT or did not initialise
+    GateDriverFault = 1,
+    /// HX711 did not finish a conversion in time
+    LoadCellTimeout = 2,
+    /// Nothing heard from the VESC for a while
+    VescFeedbackStale = 3,
+    /// Control input stopped and watchdog_vesc released the motor
+    VescWatchdog = 4,
+    /// Config records failed their CRC on boot, defaults apply to them
+    ConfigCorrupt = 5,
+}
+
+impl Reason {
+    pub const COUNT: usize = 6;
+
+    pub fn from_u8(value: u8) -> Option<Reason> {
+        Some(match value {
+            0 => Reason::CanErrors,
+            1 => Reason::GateDriverFault,
+            2 => Reason::LoadCellTimeout,
+            3 => Reason::VescFeedbackStale,
+            4 => Reason::VescWatchdog,
+            5 => Reason::ConfigCorrupt,
+            _ => return None,
+        })
+    }
+
+    const fn bit(self) -> u16 {
+        1 << self as u8
+    }
+}
+
+#[derive(Copy, Clone, Default, Eq, PartialEq, Debug)]
+pub struct Conditions {
+    failures: u16,
+    warnings: u16,
+}
+
+impl Conditions {
+    pub const fn new() -> Self {
+        Conditions { failures: 0, warnings: 0 }
+    }
+
+    /// Replaces the severity `reason` was raised with before, nominal clears it. True if anything
+    /// changed.
+    pub fn raise(&mut self, reason: Reason, health: Health) -> bool {
+        let before = *self;
+        self.failures &= !reason.bit();
+        self.warnings &= !reason.bit();
+        match health {
+            Health::Norminal => {}
+            Health::Warning => self.warnings |= reason.bit(),
+            Health::Failure => self.failures |= reason.bit(),
+        }
+        *self != before
+    }
+
+    /// Worst of the active conditions
+    pub fn health(&self) -> Health {
+        if self.failures != 0 {
+            Health::Failure
+        } else if self.warnings != 0 {
+            Health::Warning
+        } else {
+            Health::Norminal
+        }
+    }
+
+    pub fn report(&self) -> HealthReport {
+        HealthReport { health: self.health(), failures: self.failures, warnings: self.warnings }
+    }
+}
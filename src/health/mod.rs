@@ -0,0 +1,47 @@
+//! Node health, aggregated from conditions that subsystems raise and clear, see [conditions].
+//!
+//! `task::health_check` puts the worst active condition into the heartbeat and publishes all of
+//! them in the health report next to it.
+
+pub mod conditions;
+
+use core::cell::Cell;
+pub use crate::uavcan::heartbeat::Health;
+use crate::uavcan::health_report::HealthReport;
+pub use conditions::Reason;
+use conditions::Conditions;
+
+static CONDITIONS: bare_metal::Mutex<Cell<Conditions>> = bare_metal::Mutex::new(Cell::new(Conditions::new()));
+
+/// `health` is warning or failure, nominal clears the condition.
+pub fn raise(reason: Reason, health: Health) {
+    let changed = cortex_m::interrupt::free(|cs| {
+        let cell = CONDITIONS.borrow(cs);
+        let mut conditions = cell.get();
+        let changed = conditions.raise(reason, health);
+        cell.set(conditions);
+        changed
+    });
+    if changed {
+        match health {
+            Health::Norminal => {
+                log_info!(target: Health, "{:?} cleared", reason);
+            }
+            _ => {
+                log_warn!(target: Health, "{:?} raised: {:?}", reason, health);
+            }
+        }
+    }
+}
+
+pub fn clear(reason: Reason) {
+    raise(reason, Health::Norminal);
+}
+
+pub fn health() -> Health {
+    cortex_m::interrupt::free(|cs| CONDITIONS.borrow(cs).get().health())
+}
+
+pub fn report() -> HealthReport {
+    cortex_m::interrupt::free(|cs| CONDITIONS.borrow(cs).get().report())
+}
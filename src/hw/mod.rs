@@ -0,0 +1,83 @@
+//! What modules need from the hardware, so that their logic does not depend on particular pins,
+//! timers and chips. Implemented for this board in [stm32f0], by mocks in the host tools.
+//!
+//! Traits touch no hardware and are shared with the host tools, implementations are only built
+//! for the MCU.
+
+use core::fmt::Debug;
+use crate::uavcan::filter::Filter;
+
+#[cfg(target_os = "none")]
+pub mod stm32f0;
+
+/// Single PWM output, duty in timer counts.
+pub trait PwmChannel {
+    fn max_duty(&self) -> u16;
+    fn set_duty(&mut self, duty: u16);
+}
+
+/// Output that is not connected, for boards without a channel.
+pub struct NoPwm;
+
+impl PwmChannel for NoPwm {
+    fn max_duty(&self) -> u16 {
+        u16::MAX
+    }
+
+    fn set_duty(&mut self, _duty: u16) {}
+}
+
+/// Brightness in 1/1000 of the maximum allowed for the LED.
+pub trait RgbLed {
+    fn set_rgb(&mut self, r: u16, g: u16, b: u16);
+
+    fn set_white(&mut self, brightness: u16) {
+        self.set_rgb(brightness, brightness, brightness);
+    }
+}
+
+/// Group of inputs read at once, polarity is applied by the implementation.
+pub trait DigitalInputs {
+    type State;
+
+    fn read(&mut self) -> Self::State;
+}
+
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum LoadCellChannel {
+    Torque,
+    Thrust,
+}
+
+/// Bridge ADC with multiplexed channels. First conversions after switching channels may still
+/// belong to the previous one, skipping them is up to the caller.
+pub trait LoadCellAdc {
+    type Error: Debug;
+
+    fn select(&mut self, channel: LoadCellChannel) -> Result<(), Self::Error>;
+    /// Raw reading, blocks until a conversion is done or the ADC is given up on
+    fn read(&mut self) -> Result<i32, Self::Error>;
+}
+
+/// Three phase gate driver.
+pub trait GateDriver {
+    type Error: Debug;
+
+    /// One PWM input per half bridge
+    fn configure(&mut self) -> Result<(), Self::Error>;
+    /// Fault status registers, all zeros without faults
+    fn faults(&mut self) -> Result<[u16; 2], Self::Error>;
+}
+
+/// CAN controller, as far as filters and error state go. Frames are moved by the driver
+/// specific interrupt handlers in `canbus`.
+pub trait CanInterface {
+    type Error: Debug;
+
+    /// Replace acceptance filters, caller merges them down to what the controller has
+    fn set_filters(&mut self, filters: &[Filter]) -> Result<(), Self::Error>;
+    /// Transmit and receive error counters
+    fn error_counters(&mut self) -> Result<(u8, u8), Self::Error>;
+    fn is_bus_off(&mut self) -> Result<bool, Self::Error>;
+}
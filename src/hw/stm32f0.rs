@@ -0,0 +1,379 @@
+//! [super] traits on STM32F0 timers and the chips modules are built with.
+
+use crate::{hal, pac};
+use crate::uavcan::filter::Filter;
+use super::*;
+
+/// Driver reported an error, drivers are not asked for details.
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct DriverError;
+
+const PWM_FREQ_HZ: u32 = 20_000;
+
+/// Clocks of the PWM timers below, the HAL keeps RCC to itself once frozen, so this is called in
+/// init before that.
+pub fn enable_timer_clocks(rcc: &pac::RCC) {
+    rcc.apb2enr.modify(|_, w| w.tim16en().set_bit());
+    #[cfg(feature = "module-button")]
+    rcc.apb1enr.modify(|_, w| w.tim14en().set_bit());
+    #[cfg(feature = "module-led")]
+    {
+        rcc.apb1enr.modify(|_, w| w.tim3en().enabled());
+        rcc.apb1rstr.modify(|_, w| w.tim3rst().set_bit());
+        rcc.apb1rstr.modify(|_, w| w.tim3rst().clear_bit());
+    }
+}
+
+/// Status LED, clock is enabled by [enable_timer_clocks]
+pub struct Tim16Ch1 {
+    tim: pac::TIM16,
+    _pin: crate::board::StatusLed,
+    max_duty: u16,
+}
+
+impl Tim16Ch1 {
+    pub fn new(tim: pac::TIM16, pin: crate::board::StatusLed, rcc: &hal::rcc::Rcc) -> Self {
+        tim.ccmr1_output_mut().modify(|_, w| unsafe { w.oc1m().bits(0b110) }); // PWM Mode 1
+        tim.ccer.modify(|_, w| w.cc1e().set_bit()); // Output compare enable
+        tim.ccr1.write(|w| unsafe { w.bits(0x0) });
+        tim.bdtr.modify(|_, w| w.moe().set_bit()); // Enable
+        tim.ccer.write(|w| w.cc1e().set_bit());
+        tim.egr.write(|w| w.ug().set_bit());
+        tim.cr1.modify(|_, w| w.arpe().set_bit().cen().set_bit());
+
+        let max_duty = (rcc.clocks.sysclk().0 / PWM_FREQ_HZ) as u16;
+        tim.arr.write(|w| unsafe { w.arr().bits(max_duty) });
+
+        Tim16Ch1 { tim, _pin: pin, max_duty }
+    }
+}
+
+impl PwmChannel for Tim16Ch1 {
+    fn max_duty(&self) -> u16 {
+        self.max_duty
+    }
+
+    fn set_duty(&mut self, duty: u16) {
+        self.tim.ccr1.write(|w| unsafe { w.ccr1().bits(duty) });
+    }
+}
+
+/// Button module LED next to the status one, clock is enabled by [enable_timer_clocks]
+#[cfg(feature = "module-button")]
+pub struct Tim14Ch1 {
+    tim: pac::TIM14,
+    _pin: crate::board::ButtonLed,
+    max_duty: u16,
+}
+
+#[cfg(feature = "module-button")]
+impl Tim14Ch1 {
+    pub fn new(tim: pac::TIM14, pin: crate::board::ButtonLed, rcc: &hal::rcc::Rcc) -> Self {
+        tim.ccmr1_output_mut().modify(|_, w| unsafe { w.oc1m().bits(0b110) }); // PWM Mode 1
+        tim.ccer.modify(|_, w| w.cc1e().set_bit()); // Output compare enable
+        tim.ccr1.write(|w| unsafe { w.bits(0x0) });
+        tim.ccer.write(|w| w.cc1e().set_bit());
+        tim.egr.write(|w| w.ug().set_bit());
+        tim.cr1.modify(|_, w| w.arpe().set_bit().cen().set_bit());
+
+        let max_duty = (rcc.clocks.sysclk().0 / PWM_FREQ_HZ) as u16;
+        tim.arr.write(|w| unsafe { w.arr().bits(max_duty) });
+
+        Tim14Ch1 { tim, _pin: pin, max_duty }
+    }
+}
+
+#[cfg(feature = "module-button")]
+impl PwmChannel for Tim14Ch1 {
+    fn max_duty(&self) -> u16 {
+        self.max_duty
+    }
+
+    fn set_duty(&mut self, duty: u16) {
+        self.tim.ccr1.write(|w| unsafe { w.ccr().bits(duty) });
+    }
+}
+
+/// LED module strip, TIM3 channels 2, 3 and 4 driving red, green and blue. Clock is enabled and
+/// the timer reset by [enable_timer_clocks].
+#[cfg(feature = "module-led")]
+pub struct Tim3Rgb {
+    tim: pac::TIM3,
+    _pins: (crate::board::LedR, crate::board::LedG, crate::board::LedB),
+}
+
+#[cfg(feature = "module-led")]
+impl Tim3Rgb {
+    /// Full brightness, in timer counts. 1800 - 2400 max on 48MHz and 20kHz.
+    const MAX_DUTY: u16 = 700;
+
+    pub fn new(
+        tim: pac::TIM3,
+        r: crate::board::LedR,
+        g: crate::board::LedG,
+        b: crate::board::LedB,
+        rcc: &hal::rcc::Rcc,
+    ) -> Self {
+        tim.cr1.write(|w| w.cms().center_aligned1().ckd().div1());
+        let arr_bits = (rcc.clocks.sysclk().0 / PWM_FREQ_HZ) as u16;
+        tim.arr.write(|w| w.arr().bits(arr_bits));
+        tim.psc.write(|w| w.psc().bits(0));
+        tim.egr.write(|w| w.ug().update());
+
+        // Disable output compare 2,3,4
+        tim.ccer.modify(|_, w| w.cc2e().clear_bit().cc3e().clear_bit().cc4e().clear_bit());
+        // Select output mode
+        tim.ccmr1_output_mut().modify(|_, w| w.oc2m().pwm_mode1());
+        tim.ccmr2_output_mut().modify(|_, w| w.oc3m().pwm_mode1().oc4m().pwm_mode1());
+        tim.ccr2.write(|w| w.ccr().bits(0));
+        tim.ccr3.write(|w| w.ccr().bits(0));
+        tim.ccr4.write(|w| w.ccr().bits(0));
+        // Polarity and enable outputs
+        tim.ccer.modify(|_, w| {
+            w.cc2p().clear_bit().cc3p().clear_bit().cc4p().clear_bit()
+                .cc2e().set_bit().cc3e().set_bit().cc4e().set_bit()
+        });
+        // Enable preload
+        tim.ccmr1_output_mut().modify(|_, w| w.oc2pe().enabled());
+        tim.ccmr2_output_mut().modify(|_, w| w.oc3pe().enabled().oc4pe().enabled());
+        tim.cr1.modify(|_, w| w.arpe().set_bit());
+        tim.cr1.modify(|_, w| w.cen().enabled());
+        log_info!("tim3_max_duty: {}", arr_bits);
+
+        Tim3Rgb { tim, _pins: (r, g, b) }
+    }
+
+    fn map(brightness: u16) -> u32 {
+        brightness.min(1000) as u32 * Self::MAX_DUTY as u32 / 1000
+    }
+}
+
+#[cfg(feature = "module-led")]
+impl RgbLed for Tim3Rgb {
+    fn set_rgb(&mut self, r: u16, g: u16, b: u16) {
+        // All three change on the same update event
+        self.tim.cr1.modify(|_, w| w.udis().disabled());
+        self.tim.ccr2.write(|w| unsafe { w.bits(Self::map(r)) });
+        self.tim.ccr3.write(|w| unsafe { w.bits(Self::map(g)) });
+        self.tim.ccr4.write(|w| unsafe { w.bits(Self::map(b)) });
+        self.tim.cr1.modify(|_, w| w.udis().enabled());
+    }
+}
+
+#[cfg(feature = "module-afe-hx711")]
+impl LoadCellAdc for crate::module::afe::Hx711Instance {
+    type Error = DriverError;
+
+    fn select(&mut self, channel: LoadCellChannel) -> Result<(), DriverError> {
+        let mode = match channel {
+            LoadCellChannel::Torque => hx711::Mode::ChAGain128,
+            LoadCellChannel::Thrust => hx711::Mode::ChBGain32,
+        };
+        self.set_mode(mode).map(|_| ()).map_err(|_| DriverError)
+    }
+
+    /// Sensor that went missing never signals a conversion, waiting is cut at
+    /// [config::AFE_CONVERSION_TIMEOUT](crate::config::AFE_CONVERSION_TIMEOUT).
+    fn read(&mut self) -> Result<i32, DriverError> {
+        let started_ms = crate::utils::millis();
+        loop {
+            match self.retrieve() {
+                Ok(value) => return Ok(value),
+                Err(nb::Error::WouldBlock) => {
+                    if crate::utils::millis().wrapping_sub(started_ms) > crate::config::AFE_CONVERSION_TIMEOUT.0 {
+                        return Err(DriverError);
+                    }
+                }
+                Err(nb::Error::Other(_)) => return Err(DriverError),
+            }
+        }
+    }
+}
+
+#[cfg(feature = "module-led")]
+impl GateDriver for crate::module::led::Drv8323Instance {
+    type Error = DriverError;
+
+    fn configure(&mut self) -> Result<(), DriverError> {
+        self.set_pwm_mode(drv8323::registers::PwmMode::ThreePin).map_err(|_| DriverError)
+    }
+
+    fn faults(&mut self) -> Result<[u16; 2], DriverError> {
+        use drv8323::registers::DrvRegister;
+        let status1 = self.read_register(DrvRegister::FaultStatus1).map_err(|_| DriverError)?;
+        let status2 = self.read_register(DrvRegister::FaultStatus2).map_err(|_| DriverError)?;
+        Ok([status1 as u16, status2 as u16])
+    }
+}
+
+#[cfg(feature = "can-stm")]
+type BxCan = hal::can::bxcan::Can<hal::can::CanInstance<crate::config::CanTx, crate::config::CanRx>>;
+
+// bxCAN register bits the driver has no accessors for
+#[cfg(feature = "can-stm")]
+const CAN_MCR_INRQ: u32 = 1 << 0;
+/// Mailbox 0, others are 8 bits apart. ALST and TERR are cleared together with RQCP.
+#[cfg(feature = "can-stm")]
+const CAN_TSR_RQCP0: u32 = 1 << 0;
+#[cfg(feature = "can-stm")]
+const CAN_TSR_ALST0: u32 = 1 << 2;
+#[cfg(feature = "can-stm")]
+const CAN_RF0R_FOVR0: u32 = 1 << 4;
+#[cfg(feature = "can-stm")]
+const CAN_ESR_BOFF: u32 = 1 << 2;
+#[cfg(feature = "can-stm")]
+const CAN_ESR_LEC_SHIFT: u32 = 4;
+#[cfg(feature = "can-stm")]
+const CAN_ESR_LEC_MASK: u32 = 0b111 << CAN_ESR_LEC_SHIFT;
+/// Never set by hardware, written to tell new errors from the old ones
+#[cfg(feature = "can-stm")]
+const CAN_LEC_SOFTWARE: u32 = 7;
+
+/// bxCAN driver together with the error and status bits it has no accessors for. Everything
+/// outside of the driver goes through the methods below.
+#[cfg(feature = "can-stm")]
+pub struct CanStm {
+    can: BxCan,
+    regs: &'static pac::can::RegisterBlock,
+}
+
+#[cfg(feature = "can-stm")]
+impl CanStm {
+    pub fn new(can: pac::CAN, tx: crate::config::CanTx, rx: crate::config::CanRx, rcc: &mut hal::rcc::Rcc) -> Self {
+        // The bits touched below are either write 1 to clear or left alone by the driver once it
+        // is set up, so going around it does not undo its own writes
+        let regs = unsafe { &*pac::CAN::ptr() };
+        CanStm { can: BxCan::new(hal::can::CanInstance::new(can, tx, rx, rcc)), regs }
+    }
+
+    /// Last error code seen since the previous call, 0 if none.
+    pub fn take_last_error_code(&mut self) -> u8 {
+        let lec = (self.regs.esr.read().bits() & CAN_ESR_LEC_MASK) >> CAN_ESR_LEC_SHIFT;
+        if lec == 0 || lec == CAN_LEC_SOFTWARE {
+            return 0;
+        }
+        self.regs.esr.write(|w| unsafe { w.bits(CAN_LEC_SOFTWARE << CAN_ESR_LEC_SHIFT) });
+        lec as u8
+    }
+
+    /// Clears completed mailboxes, returns how many of them lost arbitration.
+    pub fn take_arbitration_lost(&mut self) -> u8 {
+        let tsr = self.regs.tsr.read().bits();
+        let mut completed = 0;
+        let mut arbitration_lost = 0;
+        for mailbox in 0..3 {
+            if tsr & (CAN_TSR_RQCP0 << (mailbox * 8)) != 0 {
+                completed |= CAN_TSR_RQCP0 << (mailbox * 8);
+                if tsr & (CAN_TSR_ALST0 << (mailbox * 8)) != 0 {
+                    arbitration_lost += 1;
+                }
+            }
+        }
+        if completed != 0 {
+            self.regs.tsr.write(|w| unsafe { w.bits(completed) });
+        }
+        arbitration_lost
+    }
+
+    /// True if FIFO 0 overflowed since the previous call.
+    pub fn take_rx_overflow(&mut self) -> bool {
+        if self.regs.rf0r.read().bits() & CAN_RF0R_FOVR0 == 0 {
+            return false;
+        }
+        self.regs.rf0r.write(|w| unsafe { w.bits(CAN_RF0R_FOVR0) });
+        true
+    }
+
+    /// bxCAN does not take part in bus activity while in init mode, see RM0091 CAN section.
+    /// Leaving it after bus-off starts the recovery sequence of 128 * 11 recessive bits.
+    pub fn set_init_mode(&mut self, init: bool) {
+        self.regs.mcr.modify(|r, w| unsafe {
+            w.bits(if init { r.bits() | CAN_MCR_INRQ } else { r.bits() & !CAN_MCR_INRQ })
+        });
+    }
+}
+
+#[cfg(feature = "can-stm")]
+impl core::ops::Deref for CanStm {
+    type Target = BxCan;
+
+    fn deref(&self) -> &BxCan {
+        &self.can
+    }
+}
+
+#[cfg(feature = "can-stm")]
+impl core::ops::DerefMut for CanStm {
+    fn deref_mut(&mut self) -> &mut BxCan {
+        &mut self.can
+    }
+}
+
+/// Filter banks can be changed at any time, reception is paused meanwhile. All of them go to
+/// FIFO 0, one 32 bit mask filter per bank.
+#[cfg(feature = "can-stm")]
+impl CanInterface for CanStm {
+    type Error = core::convert::Infallible;
+
+    fn set_filters(&mut self, filters: &[Filter]) -> Result<(), Self::Error> {
+        use hal::can::bxcan::{ExtendedId, filter::{BankConfig, Mask32}};
+        let mut banks = self.modify_filters();
+        banks.clear();
+        for (i, f) in filters.iter().enumerate() {
+            // Filters are at most 29 bits long
+            let (id, mask) = (ExtendedId::new(f.id).unwrap(), ExtendedId::new(f.mask).unwrap());
+            banks.enable_bank(i as u8, BankConfig::Mask32(Mask32::frames_with_ext_id(id, mask)));
+        }
+        Ok(())
+    }
+
+    fn error_counters(&mut self) -> Result<(u8, u8), Self::Error> {
+        let esr = self.regs.esr.read().bits();
+        Ok(((esr >> 16) as u8, (esr >> 24) as u8))
+    }
+
+    fn is_bus_off(&mut self) -> Result<bool, Self::Error> {
+        Ok(self.regs.esr.read().bits() & CAN_ESR_BOFF != 0)
+    }
+}
+
+/// Filters can only be changed in configuration mode, which resets the controller. Applies the
+/// whole configuration and goes back to normal mode.
+#[cfg(feature = "can-mcp25625")]
+impl CanInterface for crate::config::Mcp25625Instance {
+    type Error = mcp25625::McpErrorKind;
+
+    fn set_filters(&mut self, filters: &[Filter]) -> Result<(), Self::Error> {
+        crate::canbus::mcp25625_apply_config(self, mcp25625::McpOperationMode::Normal, filters)
+    }
+
+    fn error_counters(&mut self) -> Result<(u8, u8), Self::Error> {
+        Ok((self.tec(), self.rec()))
+    }
+
+    fn is_bus_off(&mut self) -> Result<bool, Self::Error> {
+        Ok(self.error_flags().txbo_is_set())
+    }
+}
+
+#[cfg(feature = "can-mcp2518fd")]
+impl CanInterface for crate::config::Mcp2518fdInstance {
+    type Error = crate::mcp2518fd::Error;
+
+    fn set_filters(&mut self, filters: &[Filter]) -> Result<(), Self::Error> {
+        use crate::mcp2518fd::registers::FILTER_COUNT;
+        for i in 0..FILTER_COUNT {
+            self.set_extended_filter(i, filters.get(i as usize).map(|f| (f.id, f.mask)))?;
+        }
+        Ok(())
+    }
+
+    fn error_counters(&mut self) -> Result<(u8, u8), Self::Error> {
+        crate::mcp2518fd::MCP2518FD::error_counters(self)
+    }
+
+    fn is_bus_off(&mut self) -> Result<bool, Self::Error> {
+        crate::mcp2518fd::MCP2518FD::is_bus_off(self)
+    }
+}
@@ -0,0 +1,92 @@
+//! Log output over CAN: whole records are queued by the logger and published by
+//! `task::log_can`. Records that do not fit are counted and dropped.
+//!
+//! defmt frames are published in chunks, text is formatted into one diagnostic record per message.
+
+use core::cell::{Cell, RefCell};
+#[cfg(feature = "log-text-can")]
+use core::fmt;
+use heapless::spsc::Queue;
+use crate::app;
+use crate::config;
+#[cfg(feature = "log-text-can")]
+use crate::uavcan::diagnostic::{self, Record, Severity};
+#[cfg(feature = "log-text-can")]
+use crate::utils::TruncatingWriter;
+
+/// Biggest record accepted
+#[cfg(feature = "log-defmt-can")]
+pub const MAX_RECORD_SIZE: usize = 128;
+#[cfg(feature = "log-text-can")]
+pub const MAX_RECORD_SIZE: usize = diagnostic::HEADER_SIZE + config::LOG_TEXT_MAX_LEN;
+
+static QUEUE: bare_metal::Mutex<RefCell<Queue<u8, { config::LOG_CAN_QUEUE_SIZE }>>> = bare_metal::Mutex::new(RefCell::new(Queue::new()));
+/// Records dropped since boot, for a debugger
+static DROPPED: bare_metal::Mutex<Cell<u32>> = bare_metal::Mutex::new(Cell::new(0));
+
+/// Enqueue one whole record, it is dropped if there is no room for all of it.
+pub fn push(record: &[u8]) {
+    let queued = cortex_m::interrupt::free(|cs| {
+        let mut queue = QUEUE.borrow(cs).borrow_mut();
+        if queue.capacity() - queue.len() < record.len() {
+            return false;
+        }
+        for &b in record {
+            queue.enqueue(b).ok();
+        }
+        true
+    });
+    if queued {
+        app::log_can_task::spawn().ok();
+    } else {
+        count_dropped();
+    }
+}
+
+pub fn count_dropped() {
+    cortex_m::interrupt::free(|cs| {
+        let dropped = DROPPED.borrow(cs);
+        dropped.set(dropped.get().wrapping_add(1));
+    });
+}
+
+/// Copies as many queued bytes as fit into `buf` without removing them.
+pub fn peek(buf: &mut [u8]) -> usize {
+    cortex_m::interrupt::free(|cs| {
+        let queue = QUEUE.borrow(cs).borrow();
+        let mut len = 0;
+        for (dst, &src) in buf.iter_mut().zip(queue.iter()) {
+            *dst = src;
+            len += 1;
+        }
+        len
+    })
+}
+
+/// Removes `len` bytes, returns how many are left.
+pub fn discard(len: usize) -> usize {
+    cortex_m::interrupt::free(|cs| {
+        let mut queue = QUEUE.borrow(cs).borrow_mut();
+        for _ in 0..len {
+            queue.dequeue();
+        }
+        queue.len()
+    })
+}
+
+/// Formats one message, text beyond [config::LOG_TEXT_MAX_LEN] is cut.
+#[cfg(feature = "log-text-can")]
+pub fn log(severity: Severity, args: fmt::Arguments) {
+    let mut text = TruncatingWriter::<{ config::LOG_TEXT_MAX_LEN }>::new();
+    // Error only means the text was cut
+    fmt::write(&mut text, args).ok();
+    let record = Record {
+        timestamp_us: crate::utils::micros(),
+        severity,
+        text: text.as_bytes(),
+    };
+    let mut buf = [0u8; MAX_RECORD_SIZE];
+    if let Ok(len) = record.encode(&mut buf) {
+        push(&buf[..len]);
+    }
+}
@@ -0,0 +1,101 @@
+//! Global defmt logger. Frames are encoded with rzcobs, so that a decoder can resynchronize on
+//! the next frame after lost bytes, and go to RTT up channel 0 or to [super::can].
+//!
+//! A frame is written between `acquire` and `release` with interrupts disabled, as in defmt-rtt.
+
+use core::sync::atomic::{AtomicBool, Ordering};
+use cortex_m::{interrupt, register};
+
+#[defmt::global_logger]
+struct Logger;
+
+static TAKEN: AtomicBool = AtomicBool::new(false);
+static INTERRUPTS_ACTIVE: AtomicBool = AtomicBool::new(false);
+static mut ENCODER: defmt::Encoder = defmt::Encoder::new();
+
+unsafe impl defmt::Logger for Logger {
+    fn acquire() {
+        let primask = register::primask::read();
+        interrupt::disable();
+        if TAKEN.load(Ordering::Relaxed) {
+            panic!("defmt logger taken reentrantly");
+        }
+        TAKEN.store(true, Ordering::Relaxed);
+        INTERRUPTS_ACTIVE.store(primask.is_active(), Ordering::Relaxed);
+        unsafe { ENCODER.start_frame(backend::write) }
+    }
+
+    unsafe fn flush() {}
+
+    unsafe fn release() {
+        ENCODER.end_frame(backend::write);
+        backend::end_frame();
+        TAKEN.store(false, Ordering::Relaxed);
+        if INTERRUPTS_ACTIVE.load(Ordering::Relaxed) {
+            interrupt::enable();
+        }
+    }
+
+    unsafe fn write(bytes: &[u8]) {
+        ENCODER.write(bytes, backend::write);
+    }
+}
+
+defmt::timestamp!("{=u32:ms}", crate::utils::millis());
+
+#[cfg(feature = "log-defmt-rtt")]
+pub fn rtt_init() {
+    let channels = rtt_target::rtt_init! {
+        up: {
+            0: {
+                size: 1024
+                mode: NoBlockSkip
+                name: "defmt"
+            }
+        }
+    };
+    interrupt::free(|_| unsafe { backend::CHANNEL = Some(channels.up.0) });
+}
+
+/// Writes that do not fit are skipped instead of waiting for the probe, decoder skips to the next frame
+#[cfg(feature = "log-defmt-rtt")]
+mod backend {
+    pub static mut CHANNEL: Option<rtt_target::UpChannel> = None;
+
+    pub fn write(bytes: &[u8]) {
+        if let Some(channel) = unsafe { CHANNEL.as_mut() } {
+            channel.write(bytes);
+        }
+    }
+
+    pub fn end_frame() {}
+}
+
+/// Frame is collected here first, so that the CAN queue only ever holds whole frames
+#[cfg(feature = "log-defmt-can")]
+mod backend {
+    use crate::logging::can;
+
+    static mut FRAME: heapless::Vec<u8, { can::MAX_RECORD_SIZE }> = heapless::Vec::new();
+    static mut TRUNCATED: bool = false;
+
+    pub fn write(bytes: &[u8]) {
+        unsafe {
+            if FRAME.extend_from_slice(bytes).is_err() {
+                TRUNCATED = true;
+            }
+        }
+    }
+
+    pub fn end_frame() {
+        unsafe {
+            if TRUNCATED {
+                can::count_dropped();
+            } else {
+                can::push(&FRAME);
+            }
+            FRAME.clear();
+            TRUNCATED = false;
+        }
+    }
+}
@@ -0,0 +1,89 @@
+//! Runtime log filter, applied below the compile time `log-level-*` ceiling.
+//!
+//! Messages below `level` are dropped. Subsystems tag their messages with a [Target], debug and
+//! trace ones of targets not in `targets` are dropped as well, so that one subsystem can be made
+//! verbose without the rest. Info, warnings and errors only follow `level`.
+//!
+//! Also included by tools, nothing in here depends on the hardware.
+
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Debug)]
+pub enum Level {
+    Trace = 0,
+    Debug = 1,
+    Info = 2,
+    Warn = 3,
+    Error = 4,
+    /// Only as a filter level, silences everything
+    Off = 5,
+}
+
+impl Level {
+    pub fn from_u8(value: u8) -> Option<Level> {
+        Some(match value {
+            0 => Level::Trace,
+            1 => Level::Debug,
+            2 => Level::Info,
+            3 => Level::Warn,
+            4 => Level::Error,
+            5 => Level::Off,
+            _ => return None,
+        })
+    }
+}
+
+/// Subsystems with their own switch, value is the bit number in [Targets].
+#[allow(dead_code)]
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum Target {
+    Canbus = 0,
+    Vesc = 1,
+    Afe = 2,
+    Button = 3,
+    Blinker = 4,
+    Health = 5,
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct Targets(u8);
+
+impl Targets {
+    pub const ALL: Targets = Targets(0b11_1111);
+
+    /// None with unknown bits set
+    pub const fn from_bits(bits: u8) -> Option<Self> {
+        if bits & !Self::ALL.0 != 0 {
+            None
+        } else {
+            Some(Targets(bits))
+        }
+    }
+
+    pub const fn bits(self) -> u8 {
+        self.0
+    }
+
+    pub const fn contains(self, target: Target) -> bool {
+        self.0 & (1 << target as u8) != 0
+    }
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct LogFilter {
+    pub level: Level,
+    pub targets: Targets,
+}
+
+impl LogFilter {
+    /// Everything the compile time level lets through
+    pub const ALL: LogFilter = LogFilter { level: Level::Trace, targets: Targets::ALL };
+
+    pub fn allows(&self, level: Level, target: Option<Target>) -> bool {
+        if level < self.level {
+            return false;
+        }
+        match target {
+            Some(target) if level < Level::Info => self.targets.contains(target),
+            _ => true,
+        }
+    }
+}
@@ -0,0 +1,270 @@
+//! `log_*!` macros and their backends, selected with one of the `log-*` features:
+//! text over RTT or CAN, or defmt binary format over RTT or CAN, see [defmt_logger] and [can].
+//! Level is selected with `log-level-*`.
+
+#[cfg(feature = "defmt")]
+mod defmt_logger;
+#[cfg(any(feature = "log-defmt-can", feature = "log-text-can"))]
+pub mod can;
+pub mod filter;
+
+use core::cell::Cell;
+use core::fmt;
+use filter::{Level, LogFilter, Target};
+
+#[cfg(any(
+    all(feature = "log-text-rtt", any(feature = "log-text-can", feature = "log-defmt-rtt", feature = "log-defmt-can")),
+    all(feature = "log-text-can", any(feature = "log-defmt-rtt", feature = "log-defmt-can")),
+    all(feature = "log-defmt-rtt", feature = "log-defmt-can"),
+))]
+compile_error!("Select only one logging interface");
+
+// Printed to RTT terminal 1, binary formats would also need defmt::Format for driver types
+#[cfg(all(feature = "can-printstat", not(feature = "log-text-rtt")))]
+compile_error!("can-printstat requires log-text-rtt");
+
+pub fn init() {
+    #[cfg(feature = "log-text-rtt")] {
+        rtt_target::rtt_init_print!();
+    }
+    #[cfg(feature = "log-defmt-rtt")]
+    defmt_logger::rtt_init();
+}
+
+static FILTER: bare_metal::Mutex<Cell<LogFilter>> = bare_metal::Mutex::new(Cell::new(LogFilter::ALL));
+
+/// Applied to every following message, kept in sync with the runtime config by `nvconfig::set`.
+pub fn set_filter(filter: LogFilter) {
+    cortex_m::interrupt::free(|cs| FILTER.borrow(cs).set(filter));
+}
+
+/// Whether a message passes the runtime filter, used by `log_*!`.
+#[allow(dead_code)]
+pub fn enabled(level: Level, target: Option<Target>) -> bool {
+    cortex_m::interrupt::free(|cs| FILTER.borrow(cs).get()).allows(level, target)
+}
+
+/// Formats with `core::fmt` in every backend, for types of other crates that do not implement
+/// `defmt::Format`. Pulls formatting code back in with defmt, only use it for rare messages.
+#[allow(dead_code)]
+pub struct Fmt<T>(pub T);
+
+impl<T: fmt::Debug> fmt::Debug for Fmt<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl<T: fmt::Display> fmt::Display for Fmt<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl<T: fmt::Debug> defmt::Format for Fmt<T> {
+    fn format(&self, f: defmt::Formatter<'_>) {
+        defmt::write!(f, "{}", defmt::Debug2Format(&self.0));
+    }
+}
+
+#[macro_export]
+macro_rules! _level_to_color {
+    (trace) => { crate::vt100::CYAN };
+    (debug) => { crate::vt100::DEFAULT };
+    (info) => { crate::vt100::GREEN };
+    (warn) => { crate::vt100::YELLOW };
+    (error) => { crate::vt100::RED };
+}
+
+#[macro_export]
+macro_rules! _level_to_severity {
+    (trace) => { crate::uavcan::diagnostic::Severity::Trace };
+    (debug) => { crate::uavcan::diagnostic::Severity::Debug };
+    (info) => { crate::uavcan::diagnostic::Severity::Info };
+    (warn) => { crate::uavcan::diagnostic::Severity::Warning };
+    (error) => { crate::uavcan::diagnostic::Severity::Error };
+}
+
+/// Text goes to RTT with color, or to CAN where RTT terminals and empty lines are ignored.
+#[cfg(not(feature = "defmt"))]
+#[macro_export]
+macro_rules! _log_internal {
+    ($level: ident, => $terminal:expr) => {
+        #[cfg(feature = "log-text-rtt")]
+        rtt_target::rprintln!(=> $terminal);
+    };
+    ($level: ident, => $terminal:expr, $fmt:expr) => {
+        #[cfg(feature = "log-text-rtt")] {
+            rtt_target::rprint!(=> $terminal, "{}", crate::_level_to_color!($level));
+            rtt_target::rprintln!($fmt);
+            rtt_target::rprint!(crate::vt100::DEFAULT);
+        }
+        #[cfg(feature = "log-text-can")]
+        crate::logging::can::log(crate::_level_to_severity!($level), format_args!($fmt));
+    };
+    ($level: ident, => $terminal:expr, $fmt:expr, $($arg:tt)*) => {
+        #[cfg(feature = "log-text-rtt")] {
+            rtt_target::rprint!(=> $terminal, "{}", crate::_level_to_color!($level));
+            rtt_target::rprintln!($fmt, $($arg)*);
+            rtt_target::rprint!(crate::vt100::DEFAULT);
+        }
+        #[cfg(feature = "log-text-can")]
+        crate::logging::can::log(crate::_level_to_severity!($level), format_args!($fmt, $($arg)*));
+    };
+    ($level: ident) => {
+        #[cfg(feature = "log-text-rtt")]
+        rtt_target::rprintln!();
+    };
+    ($level: ident, $fmt:expr) => {
+        #[cfg(feature = "log-text-rtt")] {
+            rtt_target::rprint!("{}", crate::_level_to_color!($level));
+            rtt_target::rprintln!($fmt);
+            rtt_target::rprint!(crate::vt100::DEFAULT);
+        }
+        #[cfg(feature = "log-text-can")]
+        crate::logging::can::log(crate::_level_to_severity!($level), format_args!($fmt));
+    };
+    ($level: ident, $fmt:expr, $($arg:tt)*) => {
+        #[cfg(feature = "log-text-rtt")] {
+            rtt_target::rprint!("{}", crate::_level_to_color!($level));
+            rtt_target::rprintln!($fmt, $($arg)*);
+            rtt_target::rprint!(crate::vt100::DEFAULT);
+        }
+        #[cfg(feature = "log-text-can")]
+        crate::logging::can::log(crate::_level_to_severity!($level), format_args!($fmt, $($arg)*));
+    };
+}
+
+/// Format strings are interned by defmt, arguments have to implement `defmt::Format`, wrap them
+/// in [Fmt] otherwise. There is only one binary stream, RTT terminals are ignored.
+#[cfg(feature = "defmt")]
+#[macro_export]
+macro_rules! _log_internal {
+    ($level: ident, => $terminal:expr) => {};
+    ($level: ident, => $terminal:expr, $($arg:tt)*) => {
+        crate::_log_internal!($level, $($arg)*)
+    };
+    ($level: ident) => {};
+    ($level: ident, $($arg:tt)*) => {
+        defmt::$level!($($arg)*)
+    };
+}
+
+/// Messages pass the compile time level and then the runtime [filter], `target: Canbus` before
+/// the format string tags a message with a [filter::Target].
+#[cfg(feature = "log-level-trace")]
+#[macro_export]
+macro_rules! log_trace {
+    (target: $target:ident, $($arg:tt)*) => {
+        if crate::logging::enabled(crate::logging::filter::Level::Trace, Some(crate::logging::filter::Target::$target)) {
+            crate::_log_internal!(trace, $($arg)*);
+        }
+    };
+    ($($arg:tt)*) => {
+        if crate::logging::enabled(crate::logging::filter::Level::Trace, None) {
+            crate::_log_internal!(trace, $($arg)*);
+        }
+    };
+}
+#[cfg(not(feature = "log-level-trace"))]
+#[macro_export]
+macro_rules! log_trace {
+    ($($arg:tt)*) => {}
+}
+
+#[cfg(feature = "log-level-debug")]
+#[macro_export]
+macro_rules! log_debug {
+    (target: $target:ident, $($arg:tt)*) => {
+        if crate::logging::enabled(crate::logging::filter::Level::Debug, Some(crate::logging::filter::Target::$target)) {
+            crate::_log_internal!(debug, $($arg)*);
+        }
+    };
+    ($($arg:tt)*) => {
+        if crate::logging::enabled(crate::logging::filter::Level::Debug, None) {
+            crate::_log_internal!(debug, $($arg)*);
+        }
+    };
+}
+#[cfg(not(feature = "log-level-debug"))]
+#[macro_export]
+macro_rules! log_debug {
+    ($($arg:tt)*) => {}
+}
+
+#[cfg(feature = "log-level-info")]
+#[macro_export]
+macro_rules! log_info {
+    (target: $target:ident, $($arg:tt)*) => {
+        if crate::logging::enabled(crate::logging::filter::Level::Info, Some(crate::logging::filter::Target::$target)) {
+            crate::_log_internal!(info, $($arg)*);
+        }
+    };
+    ($($arg:tt)*) => {
+        if crate::logging::enabled(crate::logging::filter::Level::Info, None) {
+            crate::_log_internal!(info, $($arg)*);
+        }
+    };
+}
+#[cfg(not(feature = "log-level-info"))]
+#[macro_export]
+macro_rules! log_info {
+    ($($arg:tt)*) => {}
+}
+
+#[cfg(feature = "log-level-warn")]
+#[macro_export]
+macro_rules! log_warn {
+    (target: $target:ident, $($arg:tt)*) => {
+        if crate::logging::enabled(crate::logging::filter::Level::Warn, Some(crate::logging::filter::Target::$target)) {
+            crate::_log_internal!(warn, $($arg)*);
+        }
+    };
+    ($($arg:tt)*) => {
+        if crate::logging::enabled(crate::logging::filter::Level::Warn, None) {
+            crate::_log_internal!(warn, $($arg)*);
+        }
+    };
+}
+#[cfg(not(feature = "log-level-warn"))]
+#[macro_export]
+macro_rules! log_warn {
+    ($($arg:tt)*) => {}
+}
+
+#[cfg(feature = "log-level-error")]
+#[macro_export]
+macro_rules! log_error {
+    (target: $target:ident, $($arg:tt)*) => {
+        if crate::logging::enabled(crate::logging::filter::Level::Error, Some(crate::logging::filter::Target::$target)) {
+            crate::_log_internal!(error, $($arg)*);
+        }
+    };
+    ($($arg:tt)*) => {
+        if crate::logging::enabled(crate::logging::filter::Level::Error, None) {
+            crate::_log_internal!(error, $($arg)*);
+        }
+    };
+}
+#[cfg(not(feature = "log-level-error"))]
+#[macro_export]
+macro_rules! log_error {
+    ($($arg:tt)*) => {}
+}
+
+macro_rules! count_result {
+    ($expr:expr) => {
+        let file = file!();
+        let line = line!();
+        let column = column!();
+        match $expr {
+            Ok(_) => {
+                // log_debug!("count: ok: {}:{}:{}", file, line, column);
+            },
+            Err(_) => {
+                log_error!("count: err: {}:{}:{}", file, line, column);
+            }
+        }
+    }
+}
\ No newline at end of file
@@ -13,6 +13,13 @@ extern crate static_assertions;
 mod logging;
 #[macro_use]
 mod canbus;
+mod can_health;
+mod can_interfaces;
+mod hw;
+mod board;
+/// Shared with the bootloader and host tools, not everything is used here
+#[allow(dead_code)]
+mod bit_timing;
 mod error_handlers;
 mod vt100;
 pub mod config;
@@ -20,11 +27,27 @@ mod units;
 mod module;
 mod task;
 mod prelude;
+mod uavcan;
+mod nvconfig;
+mod registers;
+mod node_info;
+mod commands;
+mod crash;
+mod supervisor;
+mod health;
+#[cfg(feature = "can-mcp2518fd")]
+mod mcp2518fd;
+/// Shared with the bootloader, not everything is used here
+#[allow(dead_code)]
+mod boot;
 // mod ramp_generator;
 mod utils;
 mod ramp_vesc;
 // mod tf_vesc;
 mod ramp_generator2;
+/// Routing tables are only used with vesc-ctrl, the host simulation always runs them
+#[allow(dead_code)]
+mod vesc_control;
 
 #[cfg(feature = "module-led")]
 pub const SYS_CLK_HZ: u32 = 48_000_000;
@@ -50,8 +73,13 @@ mod app {
     use crate::config;
     use crate::log_info;
     use crate::task::blink::{blink_task, BlinkerEvent, BlinkerState};
-    use crate::task::blink::Blinker;
+    use crate::task::blink::{Blinker, BoardBlinker};
     use crate::task::health_check::health_check_task;
+    use crate::task::pnp::pnp_task;
+    use crate::task::can_health::can_health_task;
+    use crate::task::crash_report::crash_report_task;
+    use crate::task::supervisor::supervisor_task;
+    use crate::supervisor::Task;
     // use crate::module::can_rx_router;
     use crate::canbus::can_rx_router;
 
@@ -69,20 +97,26 @@ mod app {
         can_mcp_tx: config::CanTxQueue,
         #[cfg(feature = "can-mcp25625")]
         can_mcp_rx: config::CanRxQueue,
+        #[cfg(feature = "can-mcp2518fd")]
+        can_mcp_tx: config::CanFdTxQueue,
+        #[cfg(feature = "can-mcp2518fd")]
+        can_mcp_rx: config::CanFdRxQueue,
+        #[cfg(any(feature = "can-mcp25625", feature = "can-mcp2518fd"))]
+        can_mcp_health: crate::can_health::InterfaceHealth,
+        #[cfg(feature = "can-stm")]
+        can_stm_health: crate::can_health::InterfaceHealth,
 
-        blinker: Blinker,
-        uptime: u32,
-        health: crate::task::health_check::Health,
+        blinker: BoardBlinker,
 
         #[cfg(feature = "module-led")]
         drv8323: Option<module::led::Drv8323Instance>,
- //       #[cfg(feature = "module-led")]
-//        stand_state: module::led::StandState,
+        #[cfg(feature = "module-led")]
+        stand_state: module::led::BoardStandState,
 
         #[cfg(feature = "vesc-ctrl")]
-        vesc_feedback: Option<crate::ramp_vesc::VescFeedback>,
+        vesc_feedback: Option<crate::vesc_control::VescFeedback>,
         #[cfg(feature = "vesc-ctrl")]
-        vesc_control_input: Option<crate::ramp_vesc::ControlInput>,
+        vesc_control_input: Option<crate::vesc_control::ControlInput>,
         #[cfg(feature = "vesc-ctrl")]
         vesc_watchdog_input: Option<i32>,
         #[cfg(feature = "vesc-ctrl")]
@@ -91,12 +125,24 @@ mod app {
 
     #[local]
     struct Local {
+        nvstore: crate::nvconfig::NvStore,
+
         #[cfg(feature = "can-mcp25625")]
         can_mcp25625: Option<config::Mcp25625Instance>,
         #[cfg(feature = "can-mcp25625")]
         mcp_irq: config::Mcp25625Irq,
+        #[cfg(feature = "can-mcp2518fd")]
+        can_mcp2518fd: Option<config::Mcp2518fdInstance>,
+        #[cfg(feature = "can-mcp2518fd")]
+        mcp_irq: config::Mcp2518fdIrq,
         #[cfg(feature = "can-stm")]
         can_stm: config::CanStmInstance,
+        #[cfg(any(feature = "can-mcp25625", feature = "can-mcp2518fd"))]
+        can_mcp_filters: crate::canbus::FiltersState,
+        #[cfg(feature = "can-mcp25625")]
+        can_mcp_tx_mailboxes: config::Mcp25625TxMailboxes,
+        #[cfg(feature = "can-stm")]
+        can_stm_filters: crate::canbus::FiltersState,
 
         #[cfg(feature = "module-afe-hx711")]
         hx711_rate: module::afe::Hx711Rate,
@@ -125,6 +171,9 @@ mod app {
         let cp = cx.core;
         let mut dp: super::pac::Peripherals = cx.device;
         let mono = TimSystickMonotonic::new(cp.SYST, dp.TIM15, dp.TIM17, SYS_CLK_HZ);
+        crate::crash::init(&dp.RCC);
+        crate::hw::stm32f0::enable_timer_clocks(&dp.RCC);
+        crate::supervisor::freeze_on_debug_halt(&dp.RCC, &dp.DBGMCU);
 
         // #[cfg(not(feature = "module-pi"))]
         let mut rcc = dp.RCC.configure().sysclk(SYS_CLK_HZ.hz()).freeze(&mut dp.FLASH);
@@ -137,6 +186,21 @@ mod app {
         // }
 
 
+        #[allow(unused_mut)]
+        let mut nvstore = crate::nvconfig::NvStore::new(crate::nvconfig::flash::InternalFlash::nvconfig());
+        let runtime_config = crate::nvconfig::RuntimeConfig::load(&nvstore);
+        if nvstore.is_corrupt() {
+            crate::health::raise(crate::health::Reason::ConfigCorrupt, crate::health::Health::Warning);
+        }
+        crate::node_info::compute_image_crc(&nvstore);
+        // Bootloader takes node ID from the store, make sure it is there
+        #[cfg(feature = "bootloader")]
+        if nvstore.is_empty() {
+            runtime_config.save(&mut nvstore).ok();
+        }
+        crate::nvconfig::set(runtime_config);
+        log_info!("Config: {:?}, store empty: {}", crate::logging::Fmt(runtime_config), nvstore.is_empty());
+
         #[allow(unused_mut, unused_variables)]
         let mut exti = Exti::new(dp.EXTI);
         #[allow(unused_mut, unused_variables)]
@@ -164,78 +228,14 @@ mod app {
         //     log_debug!("loop");
         //     d.delay_us(100_000_00);
         // }
-        let gpioa = dp.GPIOA.split(&mut rcc);
-        let gpiob = dp.GPIOB.split(&mut rcc);
-        let gpioc = dp.GPIOC.split(&mut rcc);
-        #[allow(unused_variables)]
-        let (
-            pa4,
-            pa5,
-            led,
-            pa7,
-
-            pa8, pa9, pa10,
-
-            can_rx,
-            can_tx,
-            mut can_stby,
-
-            pb0, pb1, pb2,
-            mcp25625_sck,
-            mcp25625_miso,
-            mcp25625_mosi,
-            pb6,
-            pb7,
-            pb8,
-            pb9, pb10, pb11, pb12, pb13, pb14, pb15,
-
-            mcp25625_cs,
-            mcp_irq,
-
-        ) = cortex_m::interrupt::free(|cs| {
-            (
-                gpioa.pa4,
-                gpioa.pa5,
-                gpioa.pa6,
-                gpioa.pa7,
-
-                gpioa.pa8, gpioa.pa9, gpioa.pa10,
-
-                gpioa.pa11.into_alternate_af4(cs),
-                gpioa.pa12.into_alternate_af4(cs),
-                gpioa.pa15.into_push_pull_output(cs),
-
-                gpiob.pb0,
-                gpiob.pb1,
-                gpiob.pb2,
-                gpiob.pb3.into_alternate_af0(cs),
-                gpiob.pb4.into_alternate_af0(cs),
-                gpiob.pb5.into_alternate_af0(cs),
-                gpiob.pb6,
-                gpiob.pb7,
-                gpiob.pb8,
-                gpiob.pb9,
-                gpiob.pb10,
-                gpiob.pb11,
-                gpiob.pb12,
-                gpiob.pb13,
-                gpiob.pb14,
-                gpiob.pb15,
-
-                gpioc.pc14.into_push_pull_output(cs),
-                gpioc.pc15.into_pull_up_input(cs),
-
-
-            )
-        });
-        can_stby.set_low().ok();
-        #[cfg(feature = "module-pi")] {
-            let _ = cortex_m::interrupt::free(|cs| pa8.into_alternate_af0(cs));
-        }
-
+        log_info!("Board {}", crate::board::NAME);
+        let mut pins = crate::board::Pins::new(dp.GPIOA.split(&mut rcc), dp.GPIOB.split(&mut rcc), dp.GPIOC.split(&mut rcc));
+        pins.can_stby.set_low().ok();
+        #[cfg(any(feature = "can-mcp25625", feature = "can-mcp2518fd"))]
+        let mcp_irq = pins.mcp_irq;
 
         #[cfg(feature = "can-mcp25625")]
-        let can_mcp25625 = match canbus::can_mcp25625_init(dp.SPI1, mcp25625_sck, mcp25625_miso, mcp25625_mosi, mcp25625_cs, &mut rcc) {
+        let can_mcp25625 = match canbus::can_mcp25625_init(dp.SPI1, pins.mcp_sck, pins.mcp_miso, pins.mcp_mosi, pins.mcp_cs, &mut rcc) {
             Ok(mcp25625) => {
                 log_info!("Mcp25625 init ok");
                 use hal::exti::{GpioLine, TriggerEdge, ExtiLine};
@@ -244,20 +244,46 @@ mod app {
                 Some(mcp25625)
             }
             Err(e) => {
-                log_error!("Mcp25625 init error: {:?}", e);
+                log_error!("Mcp25625 init error: {:?}", crate::logging::Fmt(e));
+                None
+            }
+        };
+
+        #[cfg(feature = "can-mcp2518fd")]
+        let can_mcp2518fd = match canbus::can_mcp2518fd_init(dp.SPI1, pins.mcp_sck, pins.mcp_miso, pins.mcp_mosi, pins.mcp_cs, &mut rcc) {
+            Ok(mcp2518fd) => {
+                log_info!("Mcp2518fd init ok");
+                use hal::exti::{GpioLine, TriggerEdge, ExtiLine};
+                let mcp_irq_line = GpioLine::from_raw_line(mcp_irq.pin_number()).unwrap();
+                exti.listen_gpio(&mut syscfg, mcp_irq.port(), mcp_irq_line, TriggerEdge::Falling);
+                Some(mcp2518fd)
+            }
+            Err(e) => {
+                log_error!("Mcp2518fd init error: {:?}", e);
                 None
             }
         };
 
         #[cfg(feature = "can-stm")]
-        let can_stm = canbus::can_stm_init(dp.CAN, can_tx, can_rx, &mut rcc);
+        let can_stm = canbus::can_stm_init(dp.CAN, pins.can_tx, pins.can_rx, &mut rcc);
 
 
-        let mut blinker = Blinker::new(dp.TIM16, led, &rcc);
-        blinker.set_global_brigthness_percent(15);
-        blink_task::spawn(BlinkerEvent::SetState(BlinkerState::Breath)).ok();
+        #[cfg(feature = "module-button")]
+        let blinker_mirror = crate::hw::stm32f0::Tim14Ch1::new(dp.TIM14, pins.button_led, &rcc);
+        #[cfg(not(feature = "module-button"))]
+        let blinker_mirror = crate::hw::NoPwm;
+        let mut blinker = Blinker::new(crate::hw::stm32f0::Tim16Ch1::new(dp.TIM16, pins.status_led, &rcc), blinker_mirror);
+        blinker.set_global_brigthness_percent(runtime_config.blinker_brightness);
+        blink_task::spawn(BlinkerEvent::SetState(BlinkerState::Auto)).ok();
+        crate::supervisor::check_in(Task::Blink, config::WATCHDOG_FIRST_CHECK_IN);
 
         health_check_task::spawn().ok();
+        crate::supervisor::check_in(Task::HealthCheck, config::WATCHDOG_FIRST_CHECK_IN);
+        can_health_task::spawn().ok();
+        crash_report_task::spawn().ok();
+        if runtime_config.node_id.is_none() {
+            pnp_task::spawn().ok();
+        }
 
         // #[used]
         // #[no_mangle]
@@ -268,19 +294,27 @@ mod app {
         // test_task2::spawn().ok();
 
         #[cfg(feature = "module-button")]
-        let mr = crate::module::button::init(pb0, pb1, pb2, pb12, pa5, pa7, pa8, dp.TIM14, &rcc);
+        let mr = crate::module::button::init(pins.estop_a, pins.estop_b, pins.power_button, pins.spare_button, pins.led0, pins.estop_led);
         #[cfg(feature = "module-button")]
         button_task::spawn().ok();
+        #[cfg(feature = "module-button")]
+        crate::supervisor::check_in(Task::Button, config::WATCHDOG_FIRST_CHECK_IN);
 
         #[cfg(feature = "module-led")]
-        let drv8323 = crate::module::led::init(pb8, pb9, pb13, pb14, pb15, pb7, pb12,  pa8, pb2, pa9, pa4, pa10, pa7, pb0, pb1, dp.SPI2, &mut rcc);
+        let drv8323 = crate::module::led::init(
+            pins.drv_en, pins.drv_cal, pins.drv_sck, pins.drv_miso, pins.drv_mosi, pins.drv_cs, pins.drv_nfault,
+            pins.drv_ha, pins.drv_la_hiz, pins.drv_hb, pins.drv_lb_hiz, pins.drv_hc,
+            dp.SPI2, &mut rcc
+        );
+        #[cfg(feature = "module-led")]
+        let stand_state = module::led::StandState::new(crate::hw::stm32f0::Tim3Rgb::new(dp.TIM3, pins.led_r, pins.led_g, pins.led_b, &rcc));
         #[cfg(feature = "module-led")]
         animation_task::spawn().ok();
 
         #[cfg(feature = "module-pi")]
-        let pi_en = crate::module::pi::init(pb0, pb2);
+        let pi_en = crate::module::pi::init(pins.pi_en, pins.pi_can_stby);
         #[cfg(feature = "module-afe-hx711")]
-        let (hx711_rate, hx711) = crate::module::afe::init_hx711(mono.new_handle(),pa8, pb6, pa10, pb7, pb8);
+        let (hx711_rate, hx711) = crate::module::afe::init_hx711(mono.new_handle(), pins.hx_rate, pins.hx_sck, pins.hx_dout, pins.ib1_en, pins.ib2_en);
         #[cfg(feature = "module-afe-lmp")]
         let _ = crate::module::afe::init_lmp();
 
@@ -288,28 +322,42 @@ mod app {
         ramp_vesc::spawn().ok();
         #[cfg(feature = "vesc-ctrl")]
         watchdog_vesc::spawn().ok();
+        #[cfg(feature = "vesc-ctrl")] {
+            crate::supervisor::check_in(Task::RampVesc, config::WATCHDOG_FIRST_CHECK_IN);
+            crate::supervisor::check_in(Task::WatchdogVesc, config::WATCHDOG_FIRST_CHECK_IN);
+        }
+
+        crate::supervisor::check_in(Task::Idle, config::WATCHDOG_IDLE_PERIOD);
+        crate::supervisor::start(&dp.IWDG);
+        supervisor_task::spawn().ok();
 
         log_info!("Init succeeded, sysclk={}", rcc.clocks.sysclk().0);
 
         (
             Shared {
                 #[cfg(feature = "can-stm")]
-                can_stm_tx: heapless::BinaryHeap::new(),
+                can_stm_tx: config::CanTxQueue::new(),
                 #[cfg(feature = "can-stm")]
                 can_stm_rx: heapless::BinaryHeap::new(),
                 #[cfg(feature = "can-mcp25625")]
-                can_mcp_tx: heapless::BinaryHeap::new(),
+                can_mcp_tx: config::CanTxQueue::new(),
                 #[cfg(feature = "can-mcp25625")]
                 can_mcp_rx: heapless::BinaryHeap::new(),
+                #[cfg(feature = "can-mcp2518fd")]
+                can_mcp_tx: config::CanFdTxQueue::new(),
+                #[cfg(feature = "can-mcp2518fd")]
+                can_mcp_rx: heapless::BinaryHeap::new(),
+                #[cfg(any(feature = "can-mcp25625", feature = "can-mcp2518fd"))]
+                can_mcp_health: crate::can_health::InterfaceHealth::new(crate::can_health::Interface::Mcp),
+                #[cfg(feature = "can-stm")]
+                can_stm_health: crate::can_health::InterfaceHealth::new(crate::can_health::Interface::Stm),
 
                 blinker,
-                uptime: 0,
-                health: crate::task::health_check::Health::Norminal,
 
                 #[cfg(feature = "module-led")]
                 drv8323,
                 #[cfg(feature = "module-led")]
-                stand_state: module::led::StandState::new(),
+                stand_state,
 
                 #[cfg(feature = "vesc-ctrl")]
                 vesc_feedback: None,
@@ -321,12 +369,24 @@ mod app {
                 vesc_watchdog_triggered: None,
             },
             Local {
+                nvstore,
+
                 #[cfg(feature = "can-mcp25625")]
                 can_mcp25625,
                 #[cfg(feature = "can-mcp25625")]
                 mcp_irq,
+                #[cfg(feature = "can-mcp2518fd")]
+                can_mcp2518fd,
+                #[cfg(feature = "can-mcp2518fd")]
+                mcp_irq,
                 #[cfg(feature = "can-stm")]
                 can_stm,
+                #[cfg(any(feature = "can-mcp25625", feature = "can-mcp2518fd"))]
+                can_mcp_filters: crate::canbus::FiltersState::new(),
+                #[cfg(feature = "can-mcp25625")]
+                can_mcp_tx_mailboxes: config::Mcp25625TxMailboxes::new(),
+                #[cfg(feature = "can-stm")]
+                can_stm_filters: crate::canbus::FiltersState::new(),
 
                 #[cfg(feature = "module-afe-hx711")]
                 hx711_rate,
@@ -421,7 +481,7 @@ mod app {
 
 
 
-    #[task(binds = EXTI4_15, shared = [can_mcp_tx, can_mcp_rx], local = [can_mcp25625, mcp_irq])]
+    #[task(binds = EXTI4_15, shared = [can_mcp_tx, can_mcp_rx, can_mcp_health], local = [can_mcp25625, can_mcp2518fd, mcp_irq, can_mcp_filters, can_mcp_tx_mailboxes])]
     #[allow(unused_mut)]
     fn exti_4_15(mut cx: exti_4_15::Context) {
         cfg_if! {
@@ -430,6 +490,11 @@ mod app {
                 let mcp_irq_line = GpioLine::from_raw_line(cx.local.mcp_irq.pin_number()).unwrap();
                 Exti::unpend(mcp_irq_line);
                 crate::canbus::can_mcp25625_irq(&mut cx);
+            } else if #[cfg(feature = "can-mcp2518fd")] {
+                use hal::exti::{GpioLine, ExtiLine};
+                let mcp_irq_line = GpioLine::from_raw_line(cx.local.mcp_irq.pin_number()).unwrap();
+                Exti::unpend(mcp_irq_line);
+                crate::canbus::can_mcp2518fd_irq(&mut cx);
             } else {
                 let _cx = cx;
             }
@@ -438,11 +503,10 @@ mod app {
 
     #[task(
         binds = CEC_CAN,
-        shared = [can_stm_tx, can_stm_rx],
+        shared = [can_stm_tx, can_stm_rx, can_stm_health],
         local = [
             can_stm,
-
-            state: crate::canbus::CanStmState = crate::canbus::CanStmState::new(),
+            can_stm_filters,
         ]
     )]
     #[allow(unused_variables)]
@@ -451,6 +515,21 @@ mod app {
         crate::canbus::can_stm_task(cx);
     }
 
+    /// Deferred reset, so that a service response can be sent first
+    #[task(capacity = 1)]
+    fn reboot_task(_cx: reboot_task::Context) {
+        cortex_m::peripheral::SCB::sys_reset();
+    }
+
+    /// Publishes log output queued by the logger, see `task::log_can`
+    #[task(capacity = 1, shared = [can_mcp_tx, can_stm_tx], local = [
+        transfer_id: uavcan_llr::types::TransferId = uavcan_llr::types::TransferId::new(0).unwrap()
+    ])]
+    fn log_can_task(_cx: log_can_task::Context) {
+        #[cfg(any(feature = "log-defmt-can", feature = "log-text-can"))]
+        crate::task::log_can::log_can_task(_cx);
+    }
+
     #[task(local = [mr], shared = [can_mcp_tx, can_stm_tx, ])]
     fn button_task(_cx: button_task::Context) {
         #[cfg(feature = "module-button")]
@@ -472,7 +551,8 @@ mod app {
     // }
 
     #[task(capacity = 1, shared = [can_mcp_tx, can_stm_tx, vesc_feedback, vesc_control_input, vesc_watchdog_input, vesc_watchdog_triggered], local = [
-        state: crate::ramp_vesc::State = crate::ramp_vesc::State::new()
+        state: crate::vesc_control::VescControl = crate::vesc_control::VescControl::new(),
+        feedback_ms: u32 = 0
     ])]
     fn ramp_vesc(_cx: ramp_vesc::Context) {
         #[cfg(feature = "vesc-ctrl")]
@@ -480,7 +560,7 @@ mod app {
     }
 
     #[task(capacity = 1, shared = [can_mcp_tx, can_stm_tx, vesc_watchdog_input, vesc_watchdog_triggered], local = [
-        state: crate::ramp_vesc::WatchdogVescState = crate::ramp_vesc::WatchdogVescState::new()
+        state: crate::vesc_control::VescWatchdog = crate::vesc_control::VescWatchdog::new()
     ])]
     fn watchdog_vesc(_cx: watchdog_vesc::Context) {
         #[cfg(feature = "vesc-ctrl")]
@@ -500,18 +580,54 @@ mod app {
    // }
 
     extern "Rust" {
-        #[task(shared = [blinker], capacity = 2)]
+        #[task(shared = [blinker, stand_state], capacity = 2)]
         fn blink_task(cx: blink_task::Context, e: crate::task::blink::BlinkerEvent);
 
         #[task(
-            shared = [can_mcp_tx, can_stm_tx, uptime, health, ],
+            shared = [can_mcp_tx, can_stm_tx, can_mcp_health, can_stm_health],
             local = [
                 state: crate::task::health_check::State = crate::task::health_check::State::new()
             ]
         )]
         fn health_check_task(mut cx: health_check_task::Context);
 
-        #[task(shared = [can_mcp_rx, can_stm_rx, vesc_feedback, vesc_control_input])]
+        #[task(
+            shared = [can_mcp_tx, can_stm_tx, can_mcp_health, can_stm_health],
+            local = [
+                state: crate::task::can_health::State = crate::task::can_health::State::new()
+            ]
+        )]
+        fn can_health_task(mut cx: can_health_task::Context);
+
+        #[task(
+            capacity = 1,
+            shared = [can_mcp_tx, can_stm_tx, ],
+            local = [
+                state: crate::task::pnp::State = crate::task::pnp::State::new()
+            ]
+        )]
+        fn pnp_task(mut cx: pnp_task::Context);
+
+        #[task(
+            capacity = 1,
+            shared = [can_mcp_tx, can_stm_tx],
+            local = [
+                transfer_id: uavcan_llr::types::TransferId = uavcan_llr::types::TransferId::new(0).unwrap()
+            ]
+        )]
+        fn crash_report_task(mut cx: crash_report_task::Context);
+
+        #[task(capacity = 1, priority = 2)]
+        fn supervisor_task(_cx: supervisor_task::Context);
+
+        #[task(
+            shared = [can_mcp_rx, can_stm_rx, can_mcp_tx, can_stm_tx, vesc_feedback, vesc_control_input],
+            local = [
+                nvstore,
+                assemblers: crate::canbus::RxAssemblers = crate::canbus::RxAssemblers::new(),
+                deduplicator: crate::config::RxDeduplicator = crate::config::RxDeduplicator::new(crate::config::UAVCAN_REDUNDANCY_TIMEOUT.0)
+            ]
+        )]
         fn can_rx_router(_cx: can_rx_router::Context);
 
     }
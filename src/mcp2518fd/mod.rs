@@ -0,0 +1,305 @@
+//! MCP2518FD external CAN FD controller over SPI.
+//!
+//! Fixed FIFO layout: TEF collects transmit events, FIFO1 transmits and FIFO2 receives everything
+//! accepted by filter 0. TX FIFO keeps frames in order, so transfers are never reordered.
+//! Frames longer than 8 bytes are sent as CAN FD with bit rate switching, shorter ones as classic
+//! frames, so a bus carrying only short transfers stays readable by CAN 2.0 controllers.
+
+pub mod registers;
+pub mod object;
+
+use embedded_hal::blocking::spi::{Transfer, Write};
+use embedded_hal::digital::v2::OutputPin;
+use vhrdcan::Frame;
+use registers::*;
+use object::{MAX_OBJECT_SIZE, extended_id_to_object};
+pub use object::MTU;
+const TX_FIFO: u8 = 1;
+const RX_FIFO: u8 = 2;
+/// Register polls before giving up on a mode change or the oscillator
+const POLL_ATTEMPTS: u32 = 10_000;
+
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum Error {
+    Spi,
+    /// Not in configuration mode after reset, chip is missing or SPI is miswired
+    NotDetected,
+    /// Oscillator did not start or mode change did not complete
+    Timeout,
+    /// FIFOs of the requested depth do not fit into the message RAM
+    RamOverflow,
+    TxFifoFull,
+    /// Frame is bigger than FIFO payload size or is FD while in CAN 2.0 mode
+    FrameTooLong,
+}
+
+/// Register values, i.e. every field is one less than the number of time quanta.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct BitTiming {
+    pub brp: u8,
+    pub tseg1: u8,
+    pub tseg2: u8,
+    pub sjw: u8,
+}
+
+impl BitTiming {
+    const fn register(&self) -> u32 {
+        ((self.brp as u32) << 24) | ((self.tseg1 as u32) << 16) | ((self.tseg2 as u32) << 8) | self.sjw as u32
+    }
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct Config {
+    pub nominal: BitTiming,
+    /// Data phase bit timing, None to run in CAN 2.0 mode
+    pub data: Option<BitTiming>,
+    pub tef_depth: u8,
+    pub tx_fifo_depth: u8,
+    pub rx_fifo_depth: u8,
+    pub payload_size: PayloadSize,
+}
+
+impl Config {
+    /// Message RAM used by this configuration, timestamps are not stored.
+    pub const fn ram_usage(&self) -> usize {
+        let object = OBJ_HEADER_SIZE + self.payload_size.bytes();
+        self.tef_depth as usize * OBJ_HEADER_SIZE
+            + (self.tx_fifo_depth as usize + self.rx_fifo_depth as usize) * object
+    }
+}
+
+pub struct MCP2518FD<SPI, CS> {
+    spi: SPI,
+    cs: CS,
+    payload_size: PayloadSize,
+    fd: bool,
+}
+
+impl<SPI, CS, E> MCP2518FD<SPI, CS>
+    where
+        SPI: Transfer<u8, Error = E> + Write<u8, Error = E>,
+        CS: OutputPin,
+{
+    pub fn new(spi: SPI, mut cs: CS) -> Self {
+        cs.set_high().ok();
+        MCP2518FD {
+            spi,
+            cs,
+            payload_size: PayloadSize::Bytes8,
+            fd: false,
+        }
+    }
+
+    pub fn apply_config(&mut self, config: &Config) -> Result<(), Error> {
+        if config.ram_usage() > RAM_SIZE || config.tef_depth == 0 || config.tx_fifo_depth == 0 || config.rx_fifo_depth == 0 {
+            return Err(Error::RamOverflow);
+        }
+        self.reset()?;
+        if self.operation_mode()? != OperationMode::Configuration {
+            return Err(Error::NotDetected);
+        }
+        self.poll(OSC, OSC_OSCRDY)?;
+
+        self.write_u32(C1NBTCFG, config.nominal.register())?;
+        if let Some(data) = config.data {
+            self.write_u32(C1DBTCFG, data.register())?;
+            let tdco = ((data.tseg1 as u32 + 1) * (data.brp as u32 + 1)).min(63);
+            self.write_u32(C1TDC, TDC_TDCMOD_AUTO | (tdco << TDC_TDCO_SHIFT))?;
+        }
+
+        let plsize = (config.payload_size as u32) << FIFOCON_PLSIZE_SHIFT;
+        self.write_u32(C1TEFCON, (config.tef_depth as u32 - 1) << FIFOCON_FSIZE_SHIFT | TEFCON_TEFNEIE)?;
+        self.write_u32(c1fifocon(TX_FIFO), plsize
+            | (config.tx_fifo_depth as u32 - 1) << FIFOCON_FSIZE_SHIFT
+            | FIFOCON_TXAT_UNLIMITED
+            | FIFOCON_TXEN
+        )?;
+        self.write_u32(c1fifocon(RX_FIFO), plsize
+            | (config.rx_fifo_depth as u32 - 1) << FIFOCON_FSIZE_SHIFT
+            | FIFOCON_RXOVIE
+            | FIFOCON_TFNRFNIE
+        )?;
+
+        // Accept everything into the RX FIFO
+        self.write_u32(c1fltobj(0), 0)?;
+        self.write_u32(c1mask(0), 0)?;
+        self.write_u8(c1fltcon(0), FLTCON_FLTEN | RX_FIFO)?;
+
+        // TEF not empty is what gives an interrupt when there is space in the TX FIFO again,
+        // CERRIF on error state changes
+        self.write_u32(C1INT, (INT_RXIF | INT_TEFIF | INT_RXOVIF | INT_CERRIF) << INT_ENABLE_SHIFT)?;
+
+        let con = self.read_u32(C1CON)?;
+        self.write_u32(C1CON, (con & !CON_TXQEN) | CON_STEF | CON_ISOCRCEN)?;
+        self.payload_size = config.payload_size;
+        self.fd = config.data.is_some();
+        let mode = if self.fd { OperationMode::NormalFd } else { OperationMode::NormalCan20 };
+        self.set_operation_mode(mode)
+    }
+
+    pub fn reset(&mut self) -> Result<(), Error> {
+        self.transaction(INSTRUCTION_RESET, |_| Ok(()))
+    }
+
+    pub fn operation_mode(&mut self) -> Result<OperationMode, Error> {
+        let opmod = (self.read_u32(C1CON)? >> CON_OPMOD_SHIFT) & CON_MODE_MASK;
+        Ok(match opmod {
+            0 => OperationMode::NormalFd,
+            1 => OperationMode::Sleep,
+            2 => OperationMode::InternalLoopback,
+            3 => OperationMode::ListenOnly,
+            4 => OperationMode::Configuration,
+            5 => OperationMode::ExternalLoopback,
+            6 => OperationMode::NormalCan20,
+            _ => OperationMode::Restricted,
+        })
+    }
+
+    pub fn set_operation_mode(&mut self, mode: OperationMode) -> Result<(), Error> {
+        // REQOP is the only writable field in the top byte, besides abort and bandwidth sharing
+        self.write_u8(C1CON + 3, mode as u8)?;
+        for _ in 0..POLL_ATTEMPTS {
+            if self.operation_mode()? == mode {
+                return Ok(());
+            }
+        }
+        Err(Error::Timeout)
+    }
+
+    /// Copy the frame into the TX FIFO and request transmission.
+    pub fn transmit(&mut self, frame: &Frame<MTU>) -> Result<(), Error> {
+        let len = DLC_TO_LEN[len_to_dlc(frame.data().len()) as usize];
+        if len > self.payload_size.bytes() || (len > 8 && !self.fd) {
+            return Err(Error::FrameTooLong);
+        }
+        if self.read_u32(c1fifosta(TX_FIFO))? & FIFOSTA_TFNRFNIF == 0 {
+            return Err(Error::TxFifoFull);
+        }
+        let address = self.read_u32(c1fifoua(TX_FIFO))? as u16;
+        let (object, object_len) = object::tx_object(frame);
+        self.write_bytes(RAM_START + address, &object[..object_len])?;
+        self.write_u8(c1fifocon(TX_FIFO) + 1, FIFOCON_BYTE1_UINC | FIFOCON_BYTE1_TXREQ)
+    }
+
+    /// Next frame from the RX FIFO, None if it is empty. Objects that do not make a frame are
+    /// discarded, so that the caller keeps draining the FIFO.
+    pub fn receive(&mut self) -> Result<Option<Frame<MTU>>, Error> {
+        loop {
+            if self.read_u32(c1fifosta(RX_FIFO))? & FIFOSTA_TFNRFNIF == 0 {
+                return Ok(None);
+            }
+            let address = self.read_u32(c1fifoua(RX_FIFO))? as u16;
+            let mut object = [0u8; MAX_OBJECT_SIZE];
+            let object_len = OBJ_HEADER_SIZE + self.payload_size.bytes();
+            self.read_bytes(RAM_START + address, &mut object[..object_len])?;
+            self.write_u8(c1fifocon(RX_FIFO) + 1, FIFOCON_BYTE1_UINC)?;
+            if let Some(frame) = object::rx_frame(&object, self.payload_size) {
+                return Ok(Some(frame));
+            }
+        }
+    }
+
+    /// Accept extended ID frames matching `id` in `mask` bits into the RX FIFO, None disables the
+    /// filter. Filter 0 accepts everything after [MCP2518FD::apply_config], can be done in any mode.
+    pub fn set_extended_filter(&mut self, filter: u8, id_mask: Option<(u32, u32)>) -> Result<(), Error> {
+        let filter = filter % FILTER_COUNT;
+        // Object and mask can only be changed while the filter is disabled
+        self.write_u8(c1fltcon(filter), 0)?;
+        if let Some((id, mask)) = id_mask {
+            self.write_u32(c1fltobj(filter), extended_id_to_object(id) | FLTOBJ_EXIDE)?;
+            self.write_u32(c1mask(filter), extended_id_to_object(mask) | MASK_MIDE)?;
+            self.write_u8(c1fltcon(filter), FLTCON_FLTEN | RX_FIFO)?;
+        }
+        Ok(())
+    }
+
+    /// Discard transmit events, returns how many frames were sent since the last call.
+    pub fn drain_tef(&mut self) -> Result<u8, Error> {
+        let mut count = 0;
+        while self.read_u32(C1TEFSTA)? & FIFOSTA_TFNRFNIF != 0 {
+            self.write_u8(C1TEFCON + 1, FIFOCON_BYTE1_UINC)?;
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    /// Returns true and clears the flag if a frame was lost because the RX FIFO was full.
+    pub fn take_rx_overflow(&mut self) -> Result<bool, Error> {
+        let status = self.read_u32(c1fifosta(RX_FIFO))?;
+        if status & FIFOSTA_RXOVIF == 0 {
+            return Ok(false);
+        }
+        self.write_u8(c1fifosta(RX_FIFO), (status & !FIFOSTA_RXOVIF) as u8)?;
+        Ok(true)
+    }
+
+    /// C1INT flags, lower half.
+    pub fn interrupt_flags(&mut self) -> Result<u16, Error> {
+        Ok(self.read_u32(C1INT)? as u16)
+    }
+
+    /// Clear flags that are not FIFO status summaries, enables are not touched.
+    pub fn clear_interrupt_flags(&mut self, flags: u16) -> Result<(), Error> {
+        let keep = !(flags as u32 & INT_CLEARABLE) as u16;
+        self.write_bytes(C1INT, &keep.to_le_bytes())
+    }
+
+    /// Transmit and receive error counters.
+    pub fn error_counters(&mut self) -> Result<(u8, u8), Error> {
+        let trec = self.read_u32(C1TREC)?;
+        Ok(((trec >> 8) as u8, trec as u8))
+    }
+
+    pub fn is_bus_off(&mut self) -> Result<bool, Error> {
+        Ok(self.read_u32(C1TREC)? & TREC_TXBO != 0)
+    }
+
+    /// Error flags of C1BDIAG1 seen since the previous call, see `BDIAG1_*`.
+    pub fn take_bus_diagnostics(&mut self) -> Result<u32, Error> {
+        let diag = self.read_u32(C1BDIAG1)?;
+        if diag & 0xFFFF_0000 != 0 {
+            self.write_u32(C1BDIAG1, 0)?;
+        }
+        Ok(diag & 0xFFFF_0000)
+    }
+
+    fn poll(&mut self, address: u16, mask: u32) -> Result<(), Error> {
+        for _ in 0..POLL_ATTEMPTS {
+            if self.read_u32(address)? & mask != 0 {
+                return Ok(());
+            }
+        }
+        Err(Error::Timeout)
+    }
+
+    fn read_u32(&mut self, address: u16) -> Result<u32, Error> {
+        let mut buf = [0u8; 4];
+        self.read_bytes(address, &mut buf)?;
+        Ok(u32::from_le_bytes(buf))
+    }
+
+    fn write_u32(&mut self, address: u16, value: u32) -> Result<(), Error> {
+        self.write_bytes(address, &value.to_le_bytes())
+    }
+
+    fn write_u8(&mut self, address: u16, value: u8) -> Result<(), Error> {
+        self.write_bytes(address, &[value])
+    }
+
+    fn read_bytes(&mut self, address: u16, buf: &mut [u8]) -> Result<(), Error> {
+        self.transaction(INSTRUCTION_READ | address, |spi| spi.transfer(buf).map(|_| ()))
+    }
+
+    fn write_bytes(&mut self, address: u16, bytes: &[u8]) -> Result<(), Error> {
+        self.transaction(INSTRUCTION_WRITE | address, |spi| spi.write(bytes))
+    }
+
+    /// Instruction with address, followed by whatever `f` does, with CS held low.
+    fn transaction<F: FnOnce(&mut SPI) -> Result<(), E>>(&mut self, instruction: u16, f: F) -> Result<(), Error> {
+        self.cs.set_low().ok();
+        let r = self.spi.write(&instruction.to_be_bytes()).and_then(|_| f(&mut self.spi));
+        self.cs.set_high().ok();
+        r.map_err(|_| Error::Spi)
+    }
+}
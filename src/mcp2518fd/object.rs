@@ -0,0 +1,51 @@
+//! Message objects as they are laid out in the message RAM, no SPI in here so that the encoding is
+//! tested on the host.
+
+use vhrdcan::{Frame, FrameId};
+use vhrdcan::id::{StandardId, ExtendedId};
+use super::registers::*;
+
+pub const MTU: usize = 64;
+pub const MAX_OBJECT_SIZE: usize = OBJ_HEADER_SIZE + MTU;
+
+/// TX object and how much of it to write. Payload is padded with zeros up to the length of the
+/// DLC and then to whole words, message RAM is written in words. Frames longer than 8 bytes are
+/// marked as FD with bit rate switching.
+pub fn tx_object(frame: &Frame<MTU>) -> ([u8; MAX_OBJECT_SIZE], usize) {
+    let data = frame.data();
+    let dlc = len_to_dlc(data.len());
+    let len = DLC_TO_LEN[dlc as usize];
+    let mut object = [0u8; MAX_OBJECT_SIZE];
+    let (t0, mut t1) = match frame.id {
+        FrameId::Standard(sid) => (sid.inner() as u32 & OBJ_SID_MASK, 0),
+        FrameId::Extended(eid) => (extended_id_to_object(eid.inner()), OBJ_IDE),
+    };
+    if len > 8 {
+        t1 |= OBJ_FDF | OBJ_BRS;
+    }
+    t1 |= dlc as u32;
+    object[0..4].copy_from_slice(&t0.to_le_bytes());
+    object[4..8].copy_from_slice(&t1.to_le_bytes());
+    object[OBJ_HEADER_SIZE..OBJ_HEADER_SIZE + data.len()].copy_from_slice(data);
+    (object, OBJ_HEADER_SIZE + (len + 3) / 4 * 4)
+}
+
+/// Frame from an RX object, payload is cut to what the FIFO stores. None if the frame could not
+/// be built, the object is to be discarded then.
+pub fn rx_frame(object: &[u8; MAX_OBJECT_SIZE], payload_size: PayloadSize) -> Option<Frame<MTU>> {
+    let r0 = u32::from_le_bytes([object[0], object[1], object[2], object[3]]);
+    let r1 = u32::from_le_bytes([object[4], object[5], object[6], object[7]]);
+    let id = if r1 & OBJ_IDE != 0 {
+        let eid = ((r0 & OBJ_SID_MASK) << 18) | ((r0 >> OBJ_EID_SHIFT) & OBJ_EID_MASK);
+        FrameId::Extended(unsafe { ExtendedId::new_unchecked(eid) })
+    } else {
+        FrameId::Standard(unsafe { StandardId::new_unchecked((r0 & OBJ_SID_MASK) as u16) })
+    };
+    let len = DLC_TO_LEN[(r1 & OBJ_DLC_MASK) as usize].min(payload_size.bytes());
+    Frame::new(id, &object[OBJ_HEADER_SIZE..OBJ_HEADER_SIZE + len])
+}
+
+/// Base ID goes first, extension after it.
+pub fn extended_id_to_object(eid: u32) -> u32 {
+    ((eid >> 18) & OBJ_SID_MASK) | ((eid & OBJ_EID_MASK) << OBJ_EID_SHIFT)
+}
@@ -0,0 +1,183 @@
+//! MCP2518FD register map and bit fields, names follow the datasheet.
+
+#![allow(dead_code)]
+
+// SPI instructions, 4 bit command followed by 12 bit address
+pub const INSTRUCTION_RESET: u16 = 0x0000;
+pub const INSTRUCTION_READ: u16 = 0x3000;
+pub const INSTRUCTION_WRITE: u16 = 0x2000;
+
+// CAN FD controller module
+pub const C1CON: u16 = 0x000;
+pub const C1NBTCFG: u16 = 0x004;
+pub const C1DBTCFG: u16 = 0x008;
+pub const C1TDC: u16 = 0x00C;
+pub const C1INT: u16 = 0x01C;
+pub const C1TREC: u16 = 0x034;
+pub const C1BDIAG1: u16 = 0x03C;
+pub const C1TEFCON: u16 = 0x040;
+pub const C1TEFSTA: u16 = 0x044;
+pub const C1TEFUA: u16 = 0x048;
+pub const C1TXQCON: u16 = 0x050;
+
+pub const fn c1fifocon(fifo: u8) -> u16 {
+    C1TXQCON + 12 * fifo as u16
+}
+
+pub const fn c1fifosta(fifo: u8) -> u16 {
+    c1fifocon(fifo) + 4
+}
+
+pub const fn c1fifoua(fifo: u8) -> u16 {
+    c1fifocon(fifo) + 8
+}
+
+/// One byte per filter
+pub const fn c1fltcon(filter: u8) -> u16 {
+    0x1D0 + filter as u16
+}
+
+pub const fn c1fltobj(filter: u8) -> u16 {
+    0x1F0 + 8 * filter as u16
+}
+
+pub const fn c1mask(filter: u8) -> u16 {
+    c1fltobj(filter) + 4
+}
+
+// Message RAM
+pub const RAM_START: u16 = 0x400;
+pub const RAM_SIZE: usize = 2048;
+
+// MCP2518FD specific
+pub const OSC: u16 = 0xE00;
+pub const IOCON: u16 = 0xE04;
+pub const DEVID: u16 = 0xE14;
+
+// C1CON
+pub const CON_REQOP_SHIFT: u32 = 24;
+pub const CON_OPMOD_SHIFT: u32 = 21;
+pub const CON_MODE_MASK: u32 = 0b111;
+pub const CON_TXQEN: u32 = 1 << 20;
+pub const CON_STEF: u32 = 1 << 19;
+pub const CON_ISOCRCEN: u32 = 1 << 5;
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum OperationMode {
+    NormalFd = 0,
+    Sleep = 1,
+    InternalLoopback = 2,
+    ListenOnly = 3,
+    Configuration = 4,
+    ExternalLoopback = 5,
+    NormalCan20 = 6,
+    Restricted = 7,
+}
+
+// C1TDC
+pub const TDC_TDCMOD_AUTO: u32 = 0b10 << 16;
+pub const TDC_TDCO_SHIFT: u32 = 8;
+
+// C1INT, flags in the lower half, enables in the upper
+pub const INT_TXIF: u32 = 1 << 0;
+pub const INT_RXIF: u32 = 1 << 1;
+pub const INT_TEFIF: u32 = 1 << 4;
+pub const INT_RXOVIF: u32 = 1 << 11;
+pub const INT_SERRIF: u32 = 1 << 12;
+pub const INT_CERRIF: u32 = 1 << 13;
+pub const INT_IVMIF: u32 = 1 << 15;
+pub const INT_ENABLE_SHIFT: u32 = 16;
+/// Flags that are cleared by writing 0, the rest are read-only summaries of FIFO flags
+pub const INT_CLEARABLE: u32 = 0xF00C;
+
+// C1TREC
+pub const TREC_TXBO: u32 = 1 << 21;
+
+// C1BDIAG1, error flags are sticky until cleared by writing 0
+pub const BDIAG1_NBIT0ERR: u32 = 1 << 16;
+pub const BDIAG1_NBIT1ERR: u32 = 1 << 17;
+pub const BDIAG1_NACKERR: u32 = 1 << 18;
+pub const BDIAG1_DBIT0ERR: u32 = 1 << 24;
+pub const BDIAG1_DBIT1ERR: u32 = 1 << 25;
+/// Errors only a transmitter can see
+pub const BDIAG1_TX_ERRORS: u32 = BDIAG1_NBIT0ERR | BDIAG1_NBIT1ERR | BDIAG1_NACKERR | BDIAG1_DBIT0ERR | BDIAG1_DBIT1ERR;
+
+// C1TEFCON and C1FIFOCONm
+pub const FIFOCON_PLSIZE_SHIFT: u32 = 29;
+pub const FIFOCON_FSIZE_SHIFT: u32 = 24;
+pub const FIFOCON_TXAT_UNLIMITED: u32 = 0b11 << 21;
+pub const FIFOCON_FRESET: u32 = 1 << 10;
+pub const FIFOCON_TXEN: u32 = 1 << 7;
+pub const FIFOCON_RXOVIE: u32 = 1 << 3;
+pub const FIFOCON_TFNRFNIE: u32 = 1 << 0;
+/// TEF not empty, C1TEFCON only
+pub const TEFCON_TEFNEIE: u32 = 1 << 0;
+/// Written to the second byte of FIFOCON, to not disturb the configuration
+pub const FIFOCON_BYTE1_UINC: u8 = 1 << 0;
+pub const FIFOCON_BYTE1_TXREQ: u8 = 1 << 1;
+
+// C1TEFSTA and C1FIFOSTAm
+pub const FIFOSTA_RXOVIF: u32 = 1 << 3;
+/// TX FIFO not full, RX FIFO or TEF not empty
+pub const FIFOSTA_TFNRFNIF: u32 = 1 << 0;
+
+// C1FLTCONm
+pub const FLTCON_FLTEN: u8 = 1 << 7;
+pub const FILTER_COUNT: u8 = 32;
+
+// C1FLTOBJm and C1MASKm, ID bits are laid out as in message objects
+pub const FLTOBJ_EXIDE: u32 = 1 << 30;
+pub const MASK_MIDE: u32 = 1 << 30;
+
+// OSC
+pub const OSC_OSCRDY: u32 = 1 << 10;
+
+// Message object header, T0/R0 and T1/R1 words
+pub const OBJ_SID_MASK: u32 = 0x7FF;
+pub const OBJ_EID_SHIFT: u32 = 11;
+pub const OBJ_EID_MASK: u32 = 0x3FFFF;
+pub const OBJ_IDE: u32 = 1 << 4;
+pub const OBJ_BRS: u32 = 1 << 6;
+pub const OBJ_FDF: u32 = 1 << 7;
+pub const OBJ_DLC_MASK: u32 = 0xF;
+pub const OBJ_HEADER_SIZE: usize = 8;
+
+/// Payload size of FIFO objects, in PLSIZE encoding
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum PayloadSize {
+    Bytes8 = 0,
+    Bytes12 = 1,
+    Bytes16 = 2,
+    Bytes20 = 3,
+    Bytes24 = 4,
+    Bytes32 = 5,
+    Bytes48 = 6,
+    Bytes64 = 7,
+}
+
+impl PayloadSize {
+    pub const fn bytes(self) -> usize {
+        DLC_TO_LEN[self as usize + 8]
+    }
+
+    /// Smallest payload size fitting `len` bytes.
+    pub const fn fitting(len: usize) -> Self {
+        match len {
+            0..=8 => PayloadSize::Bytes8,
+            9..=12 => PayloadSize::Bytes12,
+            13..=16 => PayloadSize::Bytes16,
+            17..=20 => PayloadSize::Bytes20,
+            21..=24 => PayloadSize::Bytes24,
+            25..=32 => PayloadSize::Bytes32,
+            33..=48 => PayloadSize::Bytes48,
+            _ => PayloadSize::Bytes64,
+        }
+    }
+}
+
+pub const DLC_TO_LEN: [usize; 16] = [0, 1, 2, 3, 4, 5, 6, 7, 8, 12, 16, 20, 24, 32, 48, 64];
+
+/// DLC of the smallest frame fitting `len` bytes, frame has to be padded up to [DLC_TO_LEN].
+pub fn len_to_dlc(len: usize) -> u8 {
+    DLC_TO_LEN.iter().position(|l| *l >= len).unwrap_or(15) as u8
+}
@@ -0,0 +1,77 @@
+//! Load cell zeroing and readings over any [LoadCellAdc], free of hardware so that the host tools
+//! can run it against a mock ADC.
+
+use crate::hw::{LoadCellAdc, LoadCellChannel};
+
+/// Samples averaged when zeroing
+const ZERO_SAMPLES: usize = 8;
+/// Conversions thrown away after switching channels when zeroing
+const ZERO_SKIP: usize = 2;
+
+pub const TELEMETRY_THRUST_VALID: u8 = 1 << 0;
+pub const TELEMETRY_TORQUE_VALID: u8 = 1 << 1;
+
+/// Raw readings with no load, subtracted from every reading.
+#[derive(Copy, Clone, Default, Eq, PartialEq, Debug)]
+pub struct Zero {
+    pub torque: i32,
+    pub thrust: i32,
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct Reading {
+    pub torque: i32,
+    pub thrust: i32,
+}
+
+pub fn zero<A: LoadCellAdc>(adc: &mut A, junk_delta: i32) -> Result<Zero, A::Error> {
+    let torque = zero_channel(adc, LoadCellChannel::Torque, junk_delta)?;
+    let thrust = zero_channel(adc, LoadCellChannel::Thrust, junk_delta)?;
+    Ok(Zero { torque, thrust })
+}
+
+/// Mean of a few samples, leaving out the ones more than `junk_delta` above the mean of all.
+fn zero_channel<A: LoadCellAdc>(adc: &mut A, channel: LoadCellChannel, junk_delta: i32) -> Result<i32, A::Error> {
+    adc.select(channel)?;
+    for _ in 0..ZERO_SKIP {
+        adc.read()?;
+    }
+    let mut buf = [0i32; ZERO_SAMPLES];
+    for x in buf.iter_mut() {
+        *x = adc.read()?;
+    }
+    let mean_dirty: i32 = buf.iter().sum::<i32>() / buf.len() as i32;
+    log_debug!(target: Afe, "{:?}_0_dirty: {} buf: {:?}", channel, mean_dirty, buf);
+    let mut mean_clean = 0;
+    let mut clean_count = 0;
+    for x in buf {
+        if (x - mean_dirty) < junk_delta {
+            mean_clean += x;
+            clean_count += 1;
+        }
+    }
+    let zero = mean_clean / clean_count;
+    log_debug!(target: Afe, "{:?}_0: {}", channel, zero);
+    Ok(zero)
+}
+
+/// One reading of each channel, the first conversion after switching is thrown away.
+pub fn read<A: LoadCellAdc>(adc: &mut A, zero: Zero) -> Result<Reading, A::Error> {
+    adc.select(LoadCellChannel::Torque)?;
+    adc.read()?;
+    let torque = adc.read()? - zero.torque;
+    adc.select(LoadCellChannel::Thrust)?;
+    adc.read()?;
+    let thrust = adc.read()? - zero.thrust;
+    Ok(Reading { torque, thrust })
+}
+
+/// Combined reading: timestamp in ms, thrust, torque, validity flags. Big endian as other AFE messages.
+pub fn telemetry_payload(timestamp_ms: u32, thrust: i32, torque: i32, flags: u8) -> [u8; 13] {
+    let mut payload = [0u8; 13];
+    payload[0..4].copy_from_slice(&timestamp_ms.to_be_bytes());
+    payload[4..8].copy_from_slice(&thrust.to_be_bytes());
+    payload[8..12].copy_from_slice(&torque.to_be_bytes());
+    payload[12] = flags;
+    payload
+}
@@ -0,0 +1,78 @@
+//! Button module decisions, free of pins and RTIC so that they also run in the host simulation.
+
+/// Polled this often by `button_task`
+pub const CHECK_PERIOD_MS: u32 = 100;
+/// Power button has to be held this long
+pub const PRESS_TIME_MS: u32 = 1000;
+const PRESS_POLLS: u8 = (PRESS_TIME_MS / CHECK_PERIOD_MS) as u8;
+
+const_assert!(PRESS_TIME_MS / CHECK_PERIOD_MS >= 1);
+
+/// VESC is braked with this current while the e-stop is pressed
+pub const BRAKE_CURRENT_MILLIAMPS: i32 = 3_000;
+
+/// Heartbeat status bits
+pub const STATUS_ESTOP: u8 = 1 << 0;
+pub const STATUS_POWER_HELD: u8 = 1 << 1;
+
+/// Pressed or not, pin polarity already applied
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Copy, Clone, Default, Debug)]
+pub struct Inputs {
+    /// Emergency stop has two contacts, any of them stops the motor
+    pub estop_a: bool,
+    pub estop_b: bool,
+    pub power: bool,
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct Outputs {
+    /// Brake the VESC and publish the safety button message, on every poll while pressed
+    pub estop: bool,
+    /// Publish the power button message, once per press
+    pub power_pressed: bool,
+}
+
+pub struct ButtonLogic {
+    power_debounce: u8,
+}
+
+impl ButtonLogic {
+    pub const fn new() -> Self {
+        ButtonLogic { power_debounce: 0 }
+    }
+
+    pub fn update(&mut self, inputs: Inputs) -> Outputs {
+        let mut power_pressed = false;
+        if inputs.power {
+            self.power_debounce = self.power_debounce.saturating_add(1);
+            power_pressed = self.power_debounce == PRESS_POLLS;
+        } else {
+            self.power_debounce = 0;
+        }
+        Outputs {
+            estop: inputs.estop_a || inputs.estop_b,
+            power_pressed,
+        }
+    }
+}
+
+impl Outputs {
+    /// Heartbeat status, power button as it is now, not only when the press was long enough
+    pub fn status(&self, inputs: Inputs) -> u8 {
+        let mut status = 0;
+        if self.estop {
+            status |= STATUS_ESTOP;
+        }
+        if inputs.power {
+            status |= STATUS_POWER_HELD;
+        }
+        status
+    }
+}
+
+impl Default for ButtonLogic {
+    fn default() -> Self {
+        ButtonLogic::new()
+    }
+}
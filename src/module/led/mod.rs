@@ -2,115 +2,76 @@ use crate::hal;
 use crate::prelude::*;
 use crate::pac::SPI2;
 use drv8323::DRV8323;
-use hal::gpio::{
-    gpioa::PA7,
-    gpiob::{PB0, PB1, PB12, PB13, PB14, PB15, PB7, PB8, PB9},
-    Floating, Input,
-};
-use stm32f0xx_hal::gpio::gpioa::{PA10, PA8, PA9, PA4};
-use stm32f0xx_hal::gpio::{Alternate, Output, PushPull, AF0};
+use crate::board::{DrvEn, DrvCal, DrvSck, DrvMiso, DrvMosi, DrvCs, DrvNfault, DrvHa, DrvLaHiz, DrvHb, DrvLbHiz, DrvHc};
 use stm32f0xx_hal::spi::{SixteenBit, Spi};
 use stm32f0xx_hal::time::U32Ext;
 // use drv8323::registers::DrvRegister;
 use embedded_time::duration::Milliseconds;
-use stm32f0xx_hal::gpio::gpiob::PB2;
 use embedded_hal::digital::v2::OutputPin;
 use crate::utils::clone_into_array;
+use crate::uavcan::router::{Subscription, Source, Endpoint};
+use crate::hw::{GateDriver, RgbLed};
+use crate::supervisor::Task;
+use crate::health::{Health, Reason};
 
-pub struct StandState {
+pub type BoardStandState = StandState<crate::hw::stm32f0::Tim3Rgb>;
+
+pub struct StandState<L> {
     pub is_power_enabled: bool,
     pub is_estop_pressed: bool,
+    led: L,
 }
-impl StandState {
-    pub fn new() -> Self {
+impl<L: RgbLed> StandState<L> {
+    pub fn new(mut led: L) -> Self {
+        led.set_white(0);
         StandState {
             is_power_enabled: false,
             is_estop_pressed: false,
+            led,
         }
     }
-    pub fn set_animation_step(&self, percent1000: u16) {
+    pub fn set_animation_step(&mut self, percent1000: u16) {
         if self.is_power_enabled {
             if self.is_estop_pressed {
-                tim3_set_duty(1000, 0, 0);
+                self.led.set_rgb(1000, 0, 0);
             } else {
-                tim3_set_white(1000);
+                self.led.set_white(1000);
             }
         } else {
             // BRG
-            tim3_set_duty( 0, 0, percent1000);
+            self.led.set_rgb( 0, 0, percent1000);
         }
     }
 }
 
 pub type Drv8323Instance = DRV8323<
-    Spi<SPI2, PB13<Alternate<AF0>>, PB14<Alternate<AF0>>, PB15<Alternate<AF0>>, SixteenBit>,
-    PB7<Output<PushPull>>,
-    PB8<Output<PushPull>>,
-    PB9<Output<PushPull>>,
-    PB12<Input<Floating>>,
+    Spi<SPI2, DrvSck, DrvMiso, DrvMosi, SixteenBit>,
+    DrvCs,
+    DrvEn,
+    DrvCal,
+    DrvNfault,
     DummyDelay,
 >;
 
+/// Half bridges A and B are driven as GPIO, C is on TIM1 CH3.
 pub fn init(
-    drv_en: PB8<Input<Floating>>,
-    drv_cal: PB9<Input<Floating>>,
-    drv_sck: PB13<Input<Floating>>,
-    drv_miso: PB14<Input<Floating>>,
-    drv_mosi: PB15<Input<Floating>>,
-    drv_cs: PB7<Input<Floating>>,
-    drv_nfault: PB12<Input<Floating>>,
-
-    ha: PA8<Input<Floating>>,
-    la_hiz: PB2<Input<Floating>>,
-    hb: PA9<Input<Floating>>,
-    lb_hiz: PA4<Input<Floating>>,
-    hc: PA10<Input<Floating>>,
-
-    led0_pwm: PA7<Input<Floating>>,
-    led1_pwm: PB0<Input<Floating>>,
-    led2_pwm: PB1<Input<Floating>>,
+    drv_en: DrvEn,
+    drv_cal: DrvCal,
+    drv_sck: DrvSck,
+    drv_miso: DrvMiso,
+    drv_mosi: DrvMosi,
+    drv_cs: DrvCs,
+    drv_nfault: DrvNfault,
+
+    mut ha: DrvHa,
+    mut la_hiz: DrvLaHiz,
+    _hb: DrvHb,
+    mut lb_hiz: DrvLbHiz,
+    _hc: DrvHc,
 
     spi2: hal::pac::SPI2,
     rcc: &mut hal::rcc::Rcc,
 ) ->Option<Drv8323Instance> {
-    let (
-        drv_sck,
-        drv_miso,
-        drv_mosi,
-        drv_cs,
-        drv_en,
-        drv_cal,
-        drv_nfault,
-        mut ha,
-        mut la_hiz,
-        _hb,
-        mut lb_hiz,
-        _hc,
-        _led0_pwm,
-        _led1_pwm,
-        _led2_pwm,
-    ) =
-        cortex_m::interrupt::free(|cs| {
-            (
-                drv_sck.into_alternate_af0(cs),
-                drv_miso.into_alternate_af0(cs),
-                drv_mosi.into_alternate_af0(cs),
-                drv_cs.into_push_pull_output(cs),
-                drv_en.into_push_pull_output(cs),
-                drv_cal.into_push_pull_output(cs),
-                drv_nfault.into_floating_input(cs),
-                // ha.into_alternate_af2(cs),
-                ha.into_push_pull_output(cs),
-                la_hiz.into_push_pull_output(cs),
-                // hb.into_alternate_af2(cs),
-                hb.into_push_pull_output(cs),
-                lb_hiz.into_push_pull_output(cs),
-                hc.into_alternate_af2(cs),
-                led0_pwm.into_alternate_af1(cs),
-                led1_pwm.into_alternate_af1(cs),
-                led2_pwm.into_alternate_af1(cs),
-            )
-        });
     la_hiz.set_low().ok();
     ha.set_high().ok();
 
@@ -126,12 +87,12 @@ pub fn init(
     let drv8323 = match DRV8323::new(drv_spi, drv_cs, drv_en, drv_cal, drv_nfault, DummyDelay {}) {
         Ok(mut drv8323) => {
             log_info!("DRV8323 create ok");
-            let r = configure_drv8323(&mut drv8323);
+            let r = GateDriver::configure(&mut drv8323);
             log_info!("DRV8323 configure: {:?}", r);
             Some(drv8323)
         }
         Err(e) => {
-            log_error!("DRV8323 init fail: {:?}", e);
+            log_error!("DRV8323 init fail: {:?}", crate::logging::Fmt(e));
             None
         }
     };
@@ -140,52 +101,26 @@ pub fn init(
     // init_tim1(rcc.clocks.sysclk(), 20.khz().into());
     // tim1_set_duty(50);
 
-    init_tim3(rcc.clocks.sysclk(), 20.khz().into());
-    // tim3_set_duty(100); // 1800 - 2400 max on 48mhz+20khz
-    tim3_set_white(0);
-    log_info!("tim3_max_duty: {}", tim3_max_duty());
-
     drv8323
 }
 
-pub fn animation_task(_cx: app::animation_task::Context) {
-    // cx.shared.drv8323.lock(|drv8323| {
-    //     match drv8323 {
-    //         Some(drv8323) => {
-    //             let drv8323: &mut Drv8323Instance = drv8323;
-    //             match drv8323.read_register(DrvRegister::FaultStatus1) {
-    //                 Ok(fault_status_1) => {
-    //                     log_debug!("fault_status_1: {:011b}", fault_status_1);
-    //                 },
-    //                 Err(e) => {
-    //                     log_error!("drv8323 err: {:?}", e);
-    //                 }
-    //             }
-    //             match drv8323.read_register(DrvRegister::FaultStatus2) {
-    //                 Ok(fault_status_2) => {
-    //                     log_debug!("fault_status_2: {:011b}", fault_status_2);
-    //                 },
-    //                 Err(e) => {
-    //                     log_error!("drv8323 err: {:?}", e);
-    //                 }
-    //             }
-    //         },
-    //         None => {}
-    //     }
-    // });
+/// Also polls the gate driver, any bit in its fault status registers means nFAULT is asserted.
+pub fn animation_task(mut cx: app::animation_task::Context) {
+    let health = cx.shared.drv8323.lock(|drv8323: &mut Option<Drv8323Instance>| match drv8323 {
+        Some(drv8323) => match GateDriver::faults(drv8323) {
+            Ok([0, 0]) => Health::Norminal,
+            Ok(_) | Err(_) => Health::Failure,
+        },
+        None => Health::Failure,
+    });
+    crate::health::raise(Reason::GateDriverFault, health);
 
     app::animation_task::spawn_after(Milliseconds::new(1000_u32)).ok();
 }
 
-fn configure_drv8323(drv8323: &mut Drv8323Instance) -> drv8323::DrvResult {
-    use drv8323::registers::PwmMode;
-
-    drv8323.set_pwm_mode(PwmMode::ThreePin)?;
-    Ok(())
-}
-
 pub fn idle(_cx: app::idle::Context) -> ! {
     loop {
+        crate::supervisor::check_in(Task::Idle, config::WATCHDOG_IDLE_PERIOD);
         cortex_m::asm::delay(1_000_000);
     }
 }
@@ -197,8 +132,30 @@ impl embedded_hal::blocking::delay::DelayUs<u32> for DummyDelay {
     }
 }
 
-pub fn handle_message(source: NodeId, message: Message, _payload: &[u8]) {
+const VIRTUAL_POWER_BUTTON_SUBJECT: SubjectId = SubjectId::new(77).unwrap();
+
+pub const SUBSCRIPTIONS: &[Subscription] = &[
+    Subscription::subject(Source::Node(config::BUTTON_UAVCAN_NODE_ID), config::SAFETY_BUTTON_SUBJECT, Endpoint::Module),
+    Subscription::subject(Source::Node(config::BUTTON_UAVCAN_NODE_ID), config::POWER_BUTTON_SUBJECT, Endpoint::Module),
+    Subscription::subject(Source::Node(config::PI_NODE_ID), VIRTUAL_POWER_BUTTON_SUBJECT, Endpoint::Module),
+    Subscription::subject(Source::Node(config::PI_NODE_ID), config::POWER_BUTTON_SUBJECT, Endpoint::Module),
+];
 
+pub fn handle_message(source: NodeId, message: Message, _payload: &[u8]) {
+    if source == config::BUTTON_UAVCAN_NODE_ID && message.subject_id == config::SAFETY_BUTTON_SUBJECT {
+        log_debug!("Estop pressed");
+        //cx.shared.stand_state.lock(|s| s.is_estop_pressed = true);
+        //let _ = app::unpress_estop::spawn_after(Milliseconds::new(500u32));
+    } else if source == config::BUTTON_UAVCAN_NODE_ID && message.subject_id == config::POWER_BUTTON_SUBJECT {
+        log_debug!("Pwr pressed");
+        // cx.shared.stand_state.lock(|s| s.is_power_enabled = !s.is_power_enabled);
+    } else if source == config::PI_NODE_ID && message.subject_id == VIRTUAL_POWER_BUTTON_SUBJECT {
+        log_debug!("Pwr pressed virt");
+        // cx.shared.stand_state.lock(|s| s.is_power_enabled = !s.is_power_enabled);
+    } else if source == config::PI_NODE_ID && message.subject_id == config::POWER_BUTTON_SUBJECT {
+        log_info!("UI Power button pressed");
+        // cx.shared.stand_state.lock(|s| s.is_power_enabled = false);
+    }
 }
 
 pub fn handle_service_request(_source: NodeId, _service: Service, _payload: &[u8]) {
@@ -319,120 +276,3 @@ fn tim1_set_duty(duty: u16) {
     // dp.TIM1.ccr3.write(|w| unsafe { w.bits(self.duty_c) });
     dp.TIM1.cr1.modify(|_, w| w.udis().enabled());
 }
-
-fn init_tim3(core_freq: stm32f0xx_hal::time::Hertz, pwm_freq: stm32f0xx_hal::time::Hertz) {
-    let dp = unsafe { crate::hal::pac::Peripherals::steal() };
-    dp.RCC.apb1enr.modify(|_, w| w.tim3en().enabled());
-    dp.RCC.apb1rstr.modify(|_, w| w.tim3rst().set_bit());
-    dp.RCC.apb1rstr.modify(|_, w| w.tim3rst().clear_bit());
-
-    let tim = dp.TIM3;
-    tim
-        .cr1
-        .write(|w| w.cms().center_aligned1().ckd().div1());
-    let arr_bits = (core_freq.0 / pwm_freq.0) as u16;
-    tim.arr.write(|w| w.arr().bits(arr_bits));
-    tim.psc.write(|w| w.psc().bits(0));
-    // tim.rcr.write(|w| unsafe { w.rep().bits(0) });
-    tim.egr.write(|w| w.ug().update());
-
-    // Disable output compare 1,2,3
-    tim.ccer.modify(|_, w| {
-        w.cc2e()
-            .clear_bit()
-            // .cc1ne()
-            // .clear_bit()
-            .cc3e()
-            .clear_bit()
-            // .cc2ne()
-            // .clear_bit()
-            .cc4e()
-            .clear_bit()
-            // .cc3ne()
-            // .clear_bit()
-    });
-    // Output idle and idle_n state
-    // tim.cr2.modify(
-    //     |_, w| {
-    //         w.ois1()
-    //             .set_bit() //.ois1n().set_bit()
-    //             .ois2()
-    //             .set_bit() //.ois2n().set_bit()
-    //             .ois3()
-    //             .set_bit()
-    //     }, //.ois3n().set_bit()
-    // );
-    // Select output mode
-    tim
-        .ccmr1_output_mut()
-        .modify(|_, w| w.oc2m().pwm_mode1());
-    tim
-        .ccmr2_output_mut()
-        .modify(|_, w| w.oc3m().pwm_mode1().oc4m().pwm_mode1());
-    tim.ccr2.write(|w| w.ccr().bits(0));
-    tim.ccr3.write(|w| w.ccr().bits(0));
-    tim.ccr4.write(|w| w.ccr().bits(0));
-    tim.ccer.modify(
-        |_, w| {
-            w
-                // polarity
-                .cc2p()
-                .clear_bit()
-                .cc3p()
-                .clear_bit()
-                .cc4p()
-                .clear_bit()
-                // enable outputs
-                .cc2e()
-                .set_bit() //.cc1ne().set_bit()
-                .cc3e()
-                .set_bit() //.cc2ne().set_bit()
-                .cc4e()
-                .set_bit()
-        }, //.cc3ne().set_bit()
-    );
-    // Enable preload
-    tim
-        .ccmr1_output_mut()
-        .modify(|_, w| w.oc2pe().enabled());
-    tim
-        .ccmr2_output_mut()
-        .modify(|_, w| w.oc3pe().enabled().oc4pe().enabled());
-    // Preload enable on CCR and ARR
-    // tim.cr2.modify(|_, w| w.ccpc().set_bit());
-    tim.cr1.modify(|_, w| w.arpe().set_bit());
-    // Enable
-    // tim.cnt.write(0)
-    tim.cr1.modify(|_, w| w.cen().enabled());
-    // tim.bdtr.modify(|_, w| w.moe().enabled());
-}
-
-const MAX_DUTY: u16 = 700;
-fn tim3_map(percent1000: u16) -> u32 {
-    if percent1000 > 1000 {
-        MAX_DUTY as u32
-    } else {
-        percent1000 as u32 * MAX_DUTY as u32 / 1000
-    }
-}
-
-pub fn tim3_set_duty(percent1000_r: u16, percent1000_g: u16, percent1000_b: u16) {
-    let dp = unsafe { crate::hal::pac::Peripherals::steal() };
-    // let max_duty = dp.TIM3.arr.read().bits();
-
-    dp.TIM3.cr1.modify(|_, w| w.udis().disabled());
-    dp.TIM3.ccr2.write(|w| unsafe { w.bits(tim3_map(percent1000_r)) });
-    dp.TIM3.ccr3.write(|w| unsafe { w.bits(tim3_map(percent1000_g)) });
-    dp.TIM3.ccr4.write(|w| unsafe { w.bits(tim3_map(percent1000_b)) });
-    dp.TIM3.cr1.modify(|_, w| w.udis().enabled());
-}
-
-pub fn tim3_set_white(percent1000: u16) {
-    tim3_set_duty(percent1000, percent1000, percent1000);
-}
-
-fn tim3_max_duty() -> u32 {
-    let dp = unsafe { crate::hal::pac::Peripherals::steal() };
-    let max_duty = dp.TIM3.arr.read().bits();
-    max_duty
-}
\ No newline at end of file
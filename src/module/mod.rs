@@ -1,9 +1,19 @@
+//! Board specific modules, one or more are enabled with `module-*` features.
+//!
+//! Modules report their state in heartbeats with [set_status], as the vendor specific status
+//! code. Meaning of the bits is up to each module, so is the status LED pattern shown for them,
+//! see [blink_pattern].
+
+use core::cell::Cell;
+
 #[cfg(feature = "module-pi")]
 pub mod pi;
 #[cfg(feature = "module-pi")]
 pub use pi::handle_message;
 #[cfg(feature = "module-pi")]
 pub use pi::handle_service_request;
+#[cfg(feature = "module-pi")]
+pub use pi::SUBSCRIPTIONS;
 #[cfg(not(feature = "module-pi"))]
 pub mod pi {
     pub type Event = ();
@@ -16,6 +26,8 @@ pub mod led;
 pub use led::handle_message;
 #[cfg(feature = "module-led")]
 pub use led::handle_service_request;
+#[cfg(feature = "module-led")]
+pub use led::SUBSCRIPTIONS;
 #[cfg(not(feature = "module-led"))]
 pub mod led {
     pub type Drv8323Instance = ();
@@ -31,6 +43,8 @@ pub mod button {
 pub use button::handle_message;
 #[cfg(feature = "module-button")]
 pub use button::handle_service_request;
+#[cfg(feature = "module-button")]
+pub use button::SUBSCRIPTIONS;
 
 #[cfg(feature = "module-afe")]
 pub mod afe;
@@ -38,3 +52,31 @@ pub mod afe;
 pub use afe::handle_message;
 #[cfg(feature = "module-afe")]
 pub use afe::handle_service_request;
+#[cfg(feature = "module-afe")]
+pub use afe::SUBSCRIPTIONS;
+
+static STATUS: bare_metal::Mutex<Cell<u8>> = bare_metal::Mutex::new(Cell::new(0));
+
+#[allow(dead_code)]
+pub fn set_status(status: u8) {
+    cortex_m::interrupt::free(|cs| STATUS.borrow(cs).set(status));
+}
+
+pub fn status() -> u8 {
+    cortex_m::interrupt::free(|cs| STATUS.borrow(cs).get())
+}
+
+/// Module state worth showing on the status LED, if any
+pub fn blink_pattern() -> Option<crate::task::blink::pattern::Pattern> {
+    #[allow(unused_variables)]
+    let status = status();
+    #[cfg(feature = "module-button")]
+    if let Some(pattern) = button::blink_pattern(status) {
+        return Some(pattern);
+    }
+    #[cfg(feature = "module-pi")]
+    if let Some(pattern) = pi::blink_pattern(status) {
+        return Some(pattern);
+    }
+    None
+}
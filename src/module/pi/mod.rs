@@ -1,23 +1,31 @@
-use stm32f0xx_hal::gpio::{Floating, Input, PushPull, Output};
-use stm32f0xx_hal::gpio::gpiob::{PB0, PB2};
 use embedded_hal::digital::v2::{OutputPin, StatefulOutputPin};
 use crate::prelude::*;
 use crate::app;
+use crate::supervisor::Task;
+use crate::task::blink::pattern::{Pattern, Step, LEVEL_MAX};
 
-pub type PiEn = PB0<Output<PushPull>>;
+pub type PiEn = crate::board::PiEn;
 const PI_SHUTDOWN_TIME: Seconds = Seconds(15);
+/// Heartbeat status bits
+const STATUS_PI_POWERED: u8 = 1 << 0;
+const STATUS_PI_SHUTDOWN: u8 = 1 << 1;
+
+/// Quick breath while waiting for the Pi to shut down
+const SHUTDOWN_PATTERN: Pattern = Pattern::Steps(&[Step::fade(LEVEL_MAX, 250), Step::fade(0, 250)]);
+
+pub fn blink_pattern(status: u8) -> Option<Pattern> {
+    if status & STATUS_PI_SHUTDOWN != 0 {
+        Some(SHUTDOWN_PATTERN)
+    } else {
+        None
+    }
+}
 
 pub struct Resources {
-    pi_en: PB0<Output<PushPull>>,
+    pi_en: PiEn,
 }
 
-pub fn init(pi_en: PB0<Input<Floating>>, pi_can_stby: PB2<Input<Floating>>) -> PiEn {
-    let (pi_en, mut pi_can_stby, ) = cortex_m::interrupt::free(|cs| {
-        (
-            pi_en.into_push_pull_output(cs),
-            pi_can_stby.into_push_pull_output(cs),
-        )
-    });
+pub fn init(pi_en: PiEn, mut pi_can_stby: crate::board::PiCanStby) -> PiEn {
     pi_can_stby.set_low().ok();
     // pi_en.set_high().ok();
 
@@ -33,14 +41,19 @@ pub enum Event {
 pub fn pi_task(cx: app::pi_task::Context, e: Event) {
     let pi_en: &mut PiEn = cx.local.pi_en;
     match e {
-        Event::PowerOff => { pi_en.set_low().ok(); }
+        Event::PowerOff => {
+            pi_en.set_low().ok();
+            crate::module::set_status(0);
+        }
         //Event::PowerOn => { pi_en.set_high().ok(); }
         Event::Toggle => {
             if pi_en.is_set_low().unwrap() {
                 log_info!("Enabling PI");
                 pi_en.set_high();
+                crate::module::set_status(STATUS_PI_POWERED);
             } else {
                 log_info!("Scheduling PI off");
+                crate::module::set_status(STATUS_PI_POWERED | STATUS_PI_SHUTDOWN);
                 app::pi_task::spawn_after(PI_SHUTDOWN_TIME, Event::PowerOff).ok();
             }
         }
@@ -49,6 +62,7 @@ pub fn pi_task(cx: app::pi_task::Context, e: Event) {
 
 pub fn idle(_cx: app::idle::Context) -> ! {
     loop {
+        crate::supervisor::check_in(Task::Idle, config::WATCHDOG_IDLE_PERIOD);
         cortex_m::asm::delay(20_000_000);
         // log_info!("idle");
     }
@@ -56,6 +70,24 @@ pub fn idle(_cx: app::idle::Context) -> ! {
 
 use core::convert::AsMut;
 use rtic::rtic_monotonic::Seconds;
+use crate::uavcan::router::{Subscription, Source, Endpoint};
+use crate::uavcan::filter::Filter;
+use crate::can_health::Interface;
+use crate::can_interfaces::{BridgeRule, BridgeRules};
+
+pub const SUBSCRIPTIONS: &[Subscription] = &[
+    Subscription::subject(Source::Node(config::BUTTON_UAVCAN_NODE_ID), config::POWER_BUTTON_SUBJECT, Endpoint::Module),
+    Subscription::subject(Source::Node(config::PI_NODE_ID), config::POWER_BUTTON_SUBJECT, Endpoint::Module),
+];
+
+/// Default bridge between the Pi CAN on the MCP interface and the robot CAN on the STM one. Pi
+/// talks to the robot with its own node ID and sees all messages on it and requests to itself.
+pub const BRIDGE_RULES: BridgeRules = [
+    BridgeRule::new(Interface::Mcp, Filter::from_node(config::PI_NODE_ID.inner())),
+    BridgeRule::new(Interface::Stm, Filter::messages()),
+    BridgeRule::new(Interface::Stm, Filter::services_to(config::PI_NODE_ID.inner())),
+    BridgeRule::DISABLED,
+];
 
 pub fn handle_message(source: NodeId, message: Message, payload: &[u8]) {
     if source == config::BUTTON_UAVCAN_NODE_ID && message.subject_id == config::POWER_BUTTON_SUBJECT {
@@ -0,0 +1,132 @@
+//! What firmware runs on this node: module kind, chip, CAN driver and build metadata from build.rs.
+//!
+//! Reported via uavcan.node.GetInfo and read only `sys.info.*` registers.
+
+use core::cell::Cell;
+use crate::boot::ImageDescriptor;
+use crate::nvconfig::{key, NvStore};
+use crate::uavcan::crc::crc64we;
+use crate::uavcan::get_info::{NodeInfo, Version};
+
+pub const GIT_HASH: &str = env!("BUILD_GIT_HASH");
+/// Seconds since UNIX epoch
+pub const BUILD_TIMESTAMP: &str = env!("BUILD_TIMESTAMP");
+/// Enabled cargo features, comma separated. Cut by build.rs to fit a register string, ending with
+/// the number of features left out as `,+N` then.
+pub const FEATURES: &str = env!("BUILD_FEATURES");
+const_assert!(FEATURES.len() <= crate::uavcan::register::MAX_STRING_LEN);
+
+macro_rules! module_kind {
+    ($kind:literal) => {
+        pub const MODULE: &str = $kind;
+        /// GetInfo name, reverse domain notation
+        pub const NAME: &str = concat!("tech.vhrd.module.", $kind);
+    };
+}
+
+cfg_if::cfg_if! {
+    if #[cfg(feature = "module-afe-hx711")] {
+        module_kind!("afe.hx711");
+    } else if #[cfg(feature = "module-afe-lmp90080")] {
+        module_kind!("afe.lmp90080");
+    } else if #[cfg(feature = "module-afe-lmp90100")] {
+        module_kind!("afe.lmp90100");
+    } else if #[cfg(feature = "module-afe")] {
+        module_kind!("afe");
+    } else if #[cfg(feature = "module-button")] {
+        module_kind!("button");
+    } else if #[cfg(feature = "module-pi")] {
+        module_kind!("pi");
+    } else if #[cfg(feature = "module-led")] {
+        module_kind!("led");
+    } else {
+        module_kind!("unknown");
+    }
+}
+
+cfg_if::cfg_if! {
+    if #[cfg(feature = "f072c8u")] {
+        pub const CHIP: &str = "f072c8u";
+    } else if #[cfg(feature = "f051c8u")] {
+        pub const CHIP: &str = "f051c8u";
+    } else {
+        pub const CHIP: &str = "unknown";
+    }
+}
+
+cfg_if::cfg_if! {
+    if #[cfg(all(feature = "can-mcp25625", feature = "can-stm"))] {
+        pub const CAN_DRIVER: &str = "mcp25625+stm";
+    } else if #[cfg(feature = "can-mcp25625")] {
+        pub const CAN_DRIVER: &str = "mcp25625";
+    } else if #[cfg(feature = "can-mcp2518fd")] {
+        pub const CAN_DRIVER: &str = "mcp2518fd";
+    } else if #[cfg(feature = "can-stm")] {
+        pub const CAN_DRIVER: &str = "stm";
+    } else {
+        pub const CAN_DRIVER: &str = "none";
+    }
+}
+
+/// 96-bit unique device ID, see RM0091 33.1
+const UID_BASE: usize = 0x1FFF_F7AC;
+
+pub fn unique_id() -> [u8; 12] {
+    let mut uid = [0u8; 12];
+    for (i, b) in uid.iter_mut().enumerate() {
+        *b = unsafe { core::ptr::read_volatile((UID_BASE + i) as *const u8) };
+    }
+    uid
+}
+
+pub fn unique_id_hex() -> heapless::String<24> {
+    const HEX: &[u8; 16] = b"0123456789abcdef";
+    let mut s = heapless::String::new();
+    for b in unique_id().iter() {
+        s.push(HEX[(b >> 4) as usize] as char).ok();
+        s.push(HEX[(b & 0xF) as usize] as char).ok();
+    }
+    s
+}
+
+/// First 16 hex digits of the commit hash, 0 if built outside of git
+pub fn vcs_revision_id() -> u64 {
+    GIT_HASH.get(..16).and_then(|h| u64::from_str_radix(h, 16).ok()).unwrap_or(0)
+}
+
+pub fn build_timestamp() -> u64 {
+    BUILD_TIMESTAMP.parse().unwrap_or(0)
+}
+
+/// CRC-64-WE of the image, set by [compute_image_crc]
+static IMAGE_CRC: bare_metal::Mutex<Cell<Option<u64>>> = bare_metal::Mutex::new(Cell::new(None));
+
+/// Image size is only known when the application was written by the bootloader. Its descriptor
+/// has CRC-32, GetInfo wants CRC-64-WE, which takes tens of milliseconds over a full image, so it
+/// is computed once in init.
+pub fn compute_image_crc(store: &NvStore) {
+    let mut descriptor = [0u8; 8];
+    let crc = store.read(key::IMAGE_DESCRIPTOR, key::RECORD_VERSION, &mut descriptor)
+        .and_then(|_| ImageDescriptor::from_bytes(&descriptor).flash_image())
+        .map(crc64we);
+    cortex_m::interrupt::free(|cs| IMAGE_CRC.borrow(cs).set(crc));
+}
+
+pub fn node_info() -> NodeInfo<'static> {
+    let software_image_crc = cortex_m::interrupt::free(|cs| IMAGE_CRC.borrow(cs).get());
+    // GetInfo has 128 bits for it, rest is zero
+    let mut uid = [0u8; 16];
+    uid[..12].copy_from_slice(&unique_id());
+    NodeInfo {
+        hardware_version: Version { major: 0, minor: 0 },
+        software_version: Version {
+            major: env!("CARGO_PKG_VERSION_MAJOR").parse().unwrap_or(0),
+            minor: env!("CARGO_PKG_VERSION_MINOR").parse().unwrap_or(0),
+        },
+        software_vcs_revision_id: vcs_revision_id(),
+        unique_id: uid,
+        name: NAME,
+        software_image_crc,
+        build_info: &[CHIP, CAN_DRIVER, BUILD_TIMESTAMP, FEATURES],
+    }
+}
@@ -0,0 +1,122 @@
+//! Internal flash of STM32F0 as storage for the configuration store.
+//! Also used by the bootloader to program the application region.
+
+use crate::{config, pac};
+use super::storage::{Flash, FlashError};
+
+const KEY1: u32 = 0x4567_0123;
+const KEY2: u32 = 0xCDEF_89AB;
+
+const SR_BSY: u32 = 1 << 0;
+const SR_PGERR: u32 = 1 << 2;
+const SR_WRPRTERR: u32 = 1 << 4;
+const SR_EOP: u32 = 1 << 5;
+
+const CR_PG: u32 = 1 << 0;
+const CR_PER: u32 = 1 << 1;
+const CR_STRT: u32 = 1 << 6;
+const CR_LOCK: u32 = 1 << 7;
+
+/// Page aligned region of internal flash, offsets are relative to `address`.
+pub struct InternalFlash {
+    address: usize,
+    size: usize,
+}
+
+impl InternalFlash {
+    /// Region reserved in memory.x for the configuration store.
+    pub fn nvconfig() -> Self {
+        InternalFlash::new(config::NVCONFIG_FLASH_ADDRESS, config::NVCONFIG_FLASH_SIZE)
+    }
+
+    pub fn new(address: usize, size: usize) -> Self {
+        InternalFlash { address, size }
+    }
+
+    fn regs(&self) -> &pac::flash::RegisterBlock {
+        unsafe { &*pac::FLASH::ptr() }
+    }
+
+    fn unlock(&self) {
+        let flash = self.regs();
+        if flash.cr.read().bits() & CR_LOCK != 0 {
+            flash.keyr.write(|w| unsafe { w.bits(KEY1) });
+            flash.keyr.write(|w| unsafe { w.bits(KEY2) });
+        }
+    }
+
+    fn lock(&self) {
+        self.regs().cr.modify(|r, w| unsafe { w.bits(r.bits() | CR_LOCK) });
+    }
+
+    fn wait_ready(&self) -> Result<(), FlashError> {
+        let flash = self.regs();
+        while flash.sr.read().bits() & SR_BSY != 0 {}
+        let sr = flash.sr.read().bits();
+        flash.sr.write(|w| unsafe { w.bits(SR_EOP | SR_PGERR | SR_WRPRTERR) });
+        if sr & SR_WRPRTERR != 0 {
+            Err(FlashError::WriteProtected)
+        } else if sr & SR_PGERR != 0 {
+            Err(FlashError::Program)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl Flash for InternalFlash {
+    const PAGE_SIZE: usize = config::FLASH_PAGE_SIZE;
+
+    fn size(&self) -> usize {
+        self.size
+    }
+
+    fn read(&self, offset: usize, buf: &mut [u8]) {
+        let src = (self.address + offset) as *const u8;
+        unsafe { core::ptr::copy_nonoverlapping(src, buf.as_mut_ptr(), buf.len()) };
+    }
+
+    fn erase_page(&mut self, offset: usize) -> Result<(), FlashError> {
+        if offset % Self::PAGE_SIZE != 0 || offset >= self.size() {
+            return Err(FlashError::OutOfBounds);
+        }
+        let flash = self.regs();
+        self.unlock();
+        let r = self.wait_ready().and_then(|_| {
+            flash.cr.modify(|r, w| unsafe { w.bits(r.bits() | CR_PER) });
+            flash.ar.write(|w| unsafe { w.bits((self.address + offset) as u32) });
+            flash.cr.modify(|r, w| unsafe { w.bits(r.bits() | CR_STRT) });
+            let r = self.wait_ready();
+            flash.cr.modify(|r, w| unsafe { w.bits(r.bits() & !CR_PER) });
+            r
+        });
+        self.lock();
+        r
+    }
+
+    fn write(&mut self, offset: usize, data: &[u8]) -> Result<(), FlashError> {
+        if offset % 2 != 0 || data.len() % 2 != 0 {
+            return Err(FlashError::Alignment);
+        }
+        if offset + data.len() > self.size() {
+            return Err(FlashError::OutOfBounds);
+        }
+        let flash = self.regs();
+        self.unlock();
+        let mut r = self.wait_ready();
+        if r.is_ok() {
+            flash.cr.modify(|r, w| unsafe { w.bits(r.bits() | CR_PG) });
+            for (i, half_word) in data.chunks(2).enumerate() {
+                let dst = (self.address + offset + i * 2) as *mut u16;
+                unsafe { core::ptr::write_volatile(dst, u16::from_le_bytes([half_word[0], half_word[1]])) };
+                r = self.wait_ready();
+                if r.is_err() {
+                    break;
+                }
+            }
+            flash.cr.modify(|r, w| unsafe { w.bits(r.bits() & !CR_PG) });
+        }
+        self.lock();
+        r
+    }
+}
@@ -0,0 +1,28 @@
+//! Record keys, never reuse a retired one.
+//!
+//! Also included by the bootloader, which reads the node ID and CAN bitrate and owns the image
+//! descriptor.
+
+pub const NODE_ID: u16 = 1;
+pub const VESC_ID: u16 = 2;
+pub const BLINKER_BRIGHTNESS: u16 = 3;
+pub const HEARTBEAT_PERIOD: u16 = 4;
+pub const AFE_JUNK_DELTA: u16 = 5;
+pub const AFE_MAX_THRUST: u16 = 6;
+pub const AFE_MAX_TORQUE: u16 = 7;
+pub const VESC_DUTY_MIN: u16 = 8;
+pub const VESC_RAMP_RATES: u16 = 9;
+pub const VESC_INPUT_TIMEOUT: u16 = 10;
+pub const CAN_AUTO_RECOVERY: u16 = 11;
+pub const CAN_RECOVERY_BACKOFF: u16 = 12;
+/// Also read by the bootloader
+pub const CAN_BITRATE: u16 = 13;
+pub const CAN_REDUNDANT: u16 = 14;
+pub const CAN_TX_INTERFACES: u16 = 15;
+pub const CAN_BRIDGE: u16 = 16;
+pub const LOG_FILTER: u16 = 17;
+/// Written by the bootloader after an update, see `boot::ImageDescriptor`
+pub const IMAGE_DESCRIPTOR: u16 = 0x100;
+
+/// Bump when encoding of a value changes, old records will read as defaults.
+pub const RECORD_VERSION: u8 = 1;
@@ -0,0 +1,225 @@
+//! Runtime configuration persisted in the flash region reserved in memory.x.
+//!
+//! Compile time constants from `config` are the defaults, used when the store is empty, a record
+//! is corrupt or was written with another version. Read once in init, then accessed via [get].
+
+pub mod storage;
+pub mod flash;
+pub mod key;
+
+use core::cell::Cell;
+use crate::config;
+use crate::prelude::NodeId;
+use crate::bit_timing::Bitrate;
+use crate::can_health::Interface;
+use crate::can_interfaces::{Interfaces, BridgeRule, BridgeRules, Routing, BRIDGE_RULES};
+use crate::logging::filter::{Level, LogFilter, Targets};
+use crate::uavcan::filter::Filter;
+use storage::{Flash, Store, StoreError};
+use key::RECORD_VERSION;
+
+pub type NvStore = Store<flash::InternalFlash>;
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct RuntimeConfig {
+    /// None until allocated with plug-and-play, see `task::pnp`
+    pub node_id: Option<NodeId>,
+    pub vesc_id: u8,
+    pub blinker_brightness: u8,
+    pub heartbeat_period_ms: u16,
+    /// Zeroing readings further than this from the mean are discarded
+    pub afe_junk_delta: i32,
+    /// Readings above these are not published
+    pub afe_max_thrust: i32,
+    pub afe_max_torque: i32,
+    pub vesc_duty_min: u32,
+    /// Ramp generator up and down rates per second
+    pub vesc_ramp_rates: [u32; 2],
+    pub vesc_input_timeout_ms: u16,
+    /// See `can_health::RecoveryPolicy`
+    pub can_auto_recovery: bool,
+    pub can_recovery_backoff_ms: [u16; 2],
+    /// Used from the next boot on
+    pub can_bitrate: Bitrate,
+    /// Dual interface mode, see `can_interfaces`
+    pub can_redundant: bool,
+    pub can_tx_interfaces: Interfaces,
+    pub can_bridge: BridgeRules,
+    pub log_filter: LogFilter,
+}
+
+impl RuntimeConfig {
+    pub const DEFAULT: RuntimeConfig = RuntimeConfig {
+        node_id: config::UAVCAN_NODE_ID,
+        vesc_id: config::VESC_ID,
+        blinker_brightness: config::BLINKER_BRIGHTNESS_PERCENT,
+        heartbeat_period_ms: config::HEALTH_CHECK_PERIOD.0 as u16,
+        afe_junk_delta: config::AFE_READING_IS_JUNK_DELTA,
+        afe_max_thrust: config::AFE_MAX_NOT_JUNK_THRUST,
+        afe_max_torque: config::AFE_MAX_NOT_JUNK_TORQUE,
+        vesc_duty_min: config::VESC_DUTY_MIN,
+        vesc_ramp_rates: config::VESC_RAMP_RATES,
+        vesc_input_timeout_ms: config::VESC_INPUT_TIMEOUT.0 as u16,
+        can_auto_recovery: config::CAN_AUTO_RECOVERY,
+        can_recovery_backoff_ms: config::CAN_RECOVERY_BACKOFF_MS,
+        can_bitrate: config::CAN_BITRATE,
+        can_redundant: config::CAN_REDUNDANT,
+        can_tx_interfaces: config::CAN_TX_INTERFACES,
+        can_bridge: config::CAN_BRIDGE_RULES,
+        log_filter: config::LOG_FILTER,
+    };
+
+    pub fn load<F: Flash>(store: &Store<F>) -> Self {
+        let mut cfg = Self::DEFAULT;
+        if let Some(node_id) = read_u8(store, key::NODE_ID) {
+            cfg.node_id = NodeId::new(node_id);
+        }
+        if let Some(vesc_id) = read_u8(store, key::VESC_ID) {
+            cfg.vesc_id = vesc_id;
+        }
+        if let Some(brightness) = read_u8(store, key::BLINKER_BRIGHTNESS) {
+            cfg.blinker_brightness = brightness;
+        }
+        if let Some(b) = read::<_, 2>(store, key::HEARTBEAT_PERIOD) {
+            cfg.heartbeat_period_ms = u16::from_le_bytes(b);
+        }
+        if let Some(b) = read::<_, 4>(store, key::AFE_JUNK_DELTA) {
+            cfg.afe_junk_delta = i32::from_le_bytes(b);
+        }
+        if let Some(b) = read::<_, 4>(store, key::AFE_MAX_THRUST) {
+            cfg.afe_max_thrust = i32::from_le_bytes(b);
+        }
+        if let Some(b) = read::<_, 4>(store, key::AFE_MAX_TORQUE) {
+            cfg.afe_max_torque = i32::from_le_bytes(b);
+        }
+        if let Some(b) = read::<_, 4>(store, key::VESC_DUTY_MIN) {
+            cfg.vesc_duty_min = u32::from_le_bytes(b);
+        }
+        if let Some(b) = read::<_, 8>(store, key::VESC_RAMP_RATES) {
+            cfg.vesc_ramp_rates = [
+                u32::from_le_bytes([b[0], b[1], b[2], b[3]]),
+                u32::from_le_bytes([b[4], b[5], b[6], b[7]]),
+            ];
+        }
+        if let Some(b) = read::<_, 2>(store, key::VESC_INPUT_TIMEOUT) {
+            cfg.vesc_input_timeout_ms = u16::from_le_bytes(b);
+        }
+        if let Some(auto) = read_u8(store, key::CAN_AUTO_RECOVERY) {
+            cfg.can_auto_recovery = auto != 0;
+        }
+        if let Some(b) = read::<_, 4>(store, key::CAN_RECOVERY_BACKOFF) {
+            cfg.can_recovery_backoff_ms = [u16::from_le_bytes([b[0], b[1]]), u16::from_le_bytes([b[2], b[3]])];
+        }
+        if let Some(bitrate) = read::<_, 4>(store, key::CAN_BITRATE).and_then(|b| Bitrate::from_bps(u32::from_le_bytes(b))) {
+            cfg.can_bitrate = bitrate;
+        }
+        if let Some(redundant) = read_u8(store, key::CAN_REDUNDANT) {
+            cfg.can_redundant = redundant != 0;
+        }
+        if let Some(interfaces) = read_u8(store, key::CAN_TX_INTERFACES).and_then(Interfaces::from_bits) {
+            cfg.can_tx_interfaces = interfaces;
+        }
+        if let Some(b) = read::<_, BRIDGE_RECORD_SIZE>(store, key::CAN_BRIDGE) {
+            for (rule, b) in cfg.can_bridge.iter_mut().zip(b.chunks_exact(BRIDGE_RULE_SIZE)) {
+                *rule = decode_bridge_rule(b);
+            }
+        }
+        if let Some(b) = read::<_, 2>(store, key::LOG_FILTER) {
+            if let (Some(level), Some(targets)) = (Level::from_u8(b[0]), Targets::from_bits(b[1])) {
+                cfg.log_filter = LogFilter { level, targets };
+            }
+        }
+        cfg
+    }
+
+    /// Persist all values, unchanged ones are not rewritten.
+    pub fn save<F: Flash>(&self, store: &mut Store<F>) -> Result<(), StoreError> {
+        store.write(key::NODE_ID, RECORD_VERSION, &[self.node_id.map(|id| id.inner()).unwrap_or(NODE_ID_UNSET)])?;
+        store.write(key::VESC_ID, RECORD_VERSION, &[self.vesc_id])?;
+        store.write(key::BLINKER_BRIGHTNESS, RECORD_VERSION, &[self.blinker_brightness])?;
+        store.write(key::HEARTBEAT_PERIOD, RECORD_VERSION, &self.heartbeat_period_ms.to_le_bytes())?;
+        store.write(key::AFE_JUNK_DELTA, RECORD_VERSION, &self.afe_junk_delta.to_le_bytes())?;
+        store.write(key::AFE_MAX_THRUST, RECORD_VERSION, &self.afe_max_thrust.to_le_bytes())?;
+        store.write(key::AFE_MAX_TORQUE, RECORD_VERSION, &self.afe_max_torque.to_le_bytes())?;
+        store.write(key::VESC_DUTY_MIN, RECORD_VERSION, &self.vesc_duty_min.to_le_bytes())?;
+        let mut rates = [0u8; 8];
+        rates[0..4].copy_from_slice(&self.vesc_ramp_rates[0].to_le_bytes());
+        rates[4..8].copy_from_slice(&self.vesc_ramp_rates[1].to_le_bytes());
+        store.write(key::VESC_RAMP_RATES, RECORD_VERSION, &rates)?;
+        store.write(key::VESC_INPUT_TIMEOUT, RECORD_VERSION, &self.vesc_input_timeout_ms.to_le_bytes())?;
+        store.write(key::CAN_AUTO_RECOVERY, RECORD_VERSION, &[self.can_auto_recovery as u8])?;
+        let mut backoff = [0u8; 4];
+        backoff[0..2].copy_from_slice(&self.can_recovery_backoff_ms[0].to_le_bytes());
+        backoff[2..4].copy_from_slice(&self.can_recovery_backoff_ms[1].to_le_bytes());
+        store.write(key::CAN_RECOVERY_BACKOFF, RECORD_VERSION, &backoff)?;
+        store.write(key::CAN_BITRATE, RECORD_VERSION, &self.can_bitrate.bps().to_le_bytes())?;
+        store.write(key::CAN_REDUNDANT, RECORD_VERSION, &[self.can_redundant as u8])?;
+        store.write(key::CAN_TX_INTERFACES, RECORD_VERSION, &[self.can_tx_interfaces.bits()])?;
+        let mut bridge = [0u8; BRIDGE_RECORD_SIZE];
+        for (rule, b) in self.can_bridge.iter().zip(bridge.chunks_exact_mut(BRIDGE_RULE_SIZE)) {
+            encode_bridge_rule(rule, b);
+        }
+        store.write(key::CAN_BRIDGE, RECORD_VERSION, &bridge)?;
+        store.write(key::LOG_FILTER, RECORD_VERSION, &[self.log_filter.level as u8, self.log_filter.targets.bits()])?;
+        Ok(())
+    }
+}
+
+/// Stored instead of a node ID to forget the allocated one
+const NODE_ID_UNSET: u8 = 0xFF;
+
+/// Source interface, filter ID and mask
+const BRIDGE_RULE_SIZE: usize = 9;
+const BRIDGE_RECORD_SIZE: usize = BRIDGE_RULE_SIZE * BRIDGE_RULES;
+const_assert!(BRIDGE_RECORD_SIZE <= storage::MAX_VALUE_SIZE);
+/// Stored as the source interface of an unused rule
+const BRIDGE_RULE_DISABLED: u8 = 0xFF;
+
+fn encode_bridge_rule(rule: &BridgeRule, b: &mut [u8]) {
+    b[0] = rule.from.map(|i| i as u8).unwrap_or(BRIDGE_RULE_DISABLED);
+    b[1..5].copy_from_slice(&rule.filter.id.to_le_bytes());
+    b[5..9].copy_from_slice(&rule.filter.mask.to_le_bytes());
+}
+
+fn decode_bridge_rule(b: &[u8]) -> BridgeRule {
+    let from = match b[0] {
+        0 => Interface::Mcp,
+        1 => Interface::Stm,
+        _ => return BridgeRule::DISABLED,
+    };
+    let id = u32::from_le_bytes([b[1], b[2], b[3], b[4]]);
+    let mask = u32::from_le_bytes([b[5], b[6], b[7], b[8]]);
+    BridgeRule::new(from, Filter::new(id, mask))
+}
+
+fn read_u8<F: Flash>(store: &Store<F>, key: u16) -> Option<u8> {
+    read::<_, 1>(store, key).map(|b| b[0])
+}
+
+/// Records of unexpected length are treated as missing.
+fn read<F: Flash, const N: usize>(store: &Store<F>, key: u16) -> Option<[u8; N]> {
+    let mut buf = [0u8; N];
+    match store.read(key, RECORD_VERSION, &mut buf) {
+        Some(len) if len == N => Some(buf),
+        _ => None,
+    }
+}
+
+static CURRENT: bare_metal::Mutex<Cell<RuntimeConfig>> = bare_metal::Mutex::new(Cell::new(RuntimeConfig::DEFAULT));
+
+pub fn get() -> RuntimeConfig {
+    cortex_m::interrupt::free(|cs| CURRENT.borrow(cs).get())
+}
+
+/// Log filter and CAN routing are kept separately, log macros and received frames only need that
+/// much of the config.
+pub fn set(cfg: RuntimeConfig) {
+    cortex_m::interrupt::free(|cs| CURRENT.borrow(cs).set(cfg));
+    crate::logging::set_filter(cfg.log_filter);
+    crate::can_interfaces::set_routing(Routing::new(cfg.can_redundant, cfg.can_tx_interfaces, cfg.can_bridge));
+}
+
+/// None while the node is anonymous, it must not publish anything but allocation requests then.
+pub fn node_id() -> Option<NodeId> {
+    get().node_id
+}
@@ -0,0 +1,347 @@
+//! Log structured key/value storage for internal flash.
+//!
+//! Region is split into two banks. Records are appended to the active bank, the latest valid
+//! record for a key wins. When the active bank is full, latest records are copied into the other
+//! bank together with the value being written, which then becomes active (sequence number in the
+//! bank header is incremented). Bank header is written last, so interrupted compaction leaves the
+//! old bank active.
+//!
+//! Bank header: magic u32 | sequence u16 | crc u16
+//! Record: key u16 | len u8 | version u8 | data, padded to half-word | crc u16
+//! All little endian, CRC-16/CCITT-FALSE over everything before it.
+//!
+//! Hardware access is behind the [Flash] trait, so everything here runs on the host as well.
+
+use crate::uavcan::crc::TransferCrc;
+use heapless::Vec;
+
+/// "VNV" and format version in the lowest byte
+pub const BANK_MAGIC: u32 = 0x564E_5601;
+pub const BANK_HEADER_SIZE: usize = 8;
+pub const RECORD_HEADER_SIZE: usize = 4;
+pub const MAX_VALUE_SIZE: usize = 64;
+/// Distinct keys carried over during compaction
+pub const MAX_KEYS: usize = 32;
+const ERASED_KEY: u16 = 0xFFFF;
+
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum FlashError {
+    /// Programming error, flash was not erased
+    Program,
+    WriteProtected,
+    /// Address or length not aligned to half-word
+    Alignment,
+    OutOfBounds,
+}
+
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum StoreError {
+    Flash(FlashError),
+    /// Value is longer than MAX_VALUE_SIZE
+    TooLong,
+    /// Even after compaction there is no room for the record
+    NoSpace,
+    ReservedKey,
+    /// More distinct keys than compaction can handle
+    TooManyKeys,
+}
+
+impl From<FlashError> for StoreError {
+    fn from(e: FlashError) -> Self {
+        StoreError::Flash(e)
+    }
+}
+
+/// Flash region dedicated to the store, offsets are relative to the region start.
+pub trait Flash {
+    /// Smallest erasable unit
+    const PAGE_SIZE: usize;
+    /// Whole region size, must be a multiple of 2 * PAGE_SIZE
+    fn size(&self) -> usize;
+    fn read(&self, offset: usize, buf: &mut [u8]);
+    fn erase_page(&mut self, offset: usize) -> Result<(), FlashError>;
+    /// Offset and data length are multiple of 2
+    fn write(&mut self, offset: usize, data: &[u8]) -> Result<(), FlashError>;
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+struct RecordHeader {
+    key: u16,
+    len: usize,
+    version: u8,
+}
+
+const fn padded(len: usize) -> usize {
+    (len + 1) & !1
+}
+
+const fn record_size(len: usize) -> usize {
+    RECORD_HEADER_SIZE + padded(len) + 2
+}
+
+pub struct Store<F: Flash> {
+    flash: F,
+    active_bank: usize,
+    sequence: u16,
+    write_offset: usize,
+}
+
+impl<F: Flash> Store<F> {
+    /// Find the active bank and where to append next. Empty or corrupt flash results in an empty
+    /// store, bank 0 is formatted on first write.
+    pub fn new(flash: F) -> Self {
+        let mut store = Store {
+            flash,
+            active_bank: 0,
+            sequence: 0,
+            write_offset: 0,
+        };
+        let headers = [store.read_bank_header(0), store.read_bank_header(1)];
+        match headers {
+            [Some(s0), Some(s1)] => {
+                // Wrapping compare, newer bank has the higher sequence
+                if (s1.wrapping_sub(s0) as i16) > 0 {
+                    store.active_bank = 1;
+                    store.sequence = s1;
+                } else {
+                    store.sequence = s0;
+                }
+            }
+            [Some(s0), None] => {
+                store.sequence = s0;
+            }
+            [None, Some(s1)] => {
+                store.active_bank = 1;
+                store.sequence = s1;
+            }
+            [None, None] => {
+                return store;
+            }
+        }
+        store.write_offset = store.scan_end();
+        store
+    }
+
+    pub fn bank_size(&self) -> usize {
+        self.flash.size() / 2
+    }
+
+    /// True if nothing is stored or flash contents were unusable
+    pub fn is_empty(&self) -> bool {
+        self.write_offset <= BANK_HEADER_SIZE
+    }
+
+    /// True if a record in the active bank fails its CRC or garbage follows the last one, the
+    /// values in question read as absent.
+    pub fn is_corrupt(&self) -> bool {
+        if self.write_offset < BANK_HEADER_SIZE {
+            return false;
+        }
+        let mut offset = BANK_HEADER_SIZE;
+        while let Some((header, valid)) = self.read_record_header(offset) {
+            if !valid {
+                return true;
+            }
+            offset += record_size(header.len);
+        }
+        offset != self.write_offset
+    }
+
+    /// Copy the latest value for key into buf, None if absent or stored with other version.
+    pub fn read(&self, key: u16, version: u8, buf: &mut [u8]) -> Option<usize> {
+        let (offset, header) = self.find_latest(key)?;
+        if header.version != version || header.len > buf.len() {
+            return None;
+        }
+        self.flash.read(self.bank_offset(self.active_bank) + offset + RECORD_HEADER_SIZE, &mut buf[..header.len]);
+        Some(header.len)
+    }
+
+    /// Append a new value for key, unless the same value is already stored.
+    pub fn write(&mut self, key: u16, version: u8, value: &[u8]) -> Result<(), StoreError> {
+        if key == ERASED_KEY {
+            return Err(StoreError::ReservedKey);
+        }
+        if value.len() > MAX_VALUE_SIZE {
+            return Err(StoreError::TooLong);
+        }
+        let mut current = [0u8; MAX_VALUE_SIZE];
+        if let Some(len) = self.read(key, version, &mut current) {
+            if &current[..len] == value {
+                return Ok(());
+            }
+        }
+        // Torn or corrupted records are left behind in the old bank, or they would be reported on
+        // every boot
+        if self.write_offset < BANK_HEADER_SIZE
+            || self.write_offset + record_size(value.len()) > self.bank_size()
+            || self.is_corrupt()
+        {
+            return self.compact((key, version, value));
+        }
+        let offset = self.bank_offset(self.active_bank) + self.write_offset;
+        self.write_record(offset, key, version, value)?;
+        self.write_offset += record_size(value.len());
+        Ok(())
+    }
+
+    /// Erase both banks, all keys return to defaults.
+    pub fn clear(&mut self) -> Result<(), StoreError> {
+        let mut offset = 0;
+        while offset < self.flash.size() {
+            self.flash.erase_page(offset)?;
+            offset += F::PAGE_SIZE;
+        }
+        self.active_bank = 0;
+        self.sequence = 0;
+        self.write_offset = 0;
+        Ok(())
+    }
+
+    /// Copy the latest records of other keys and then the `pending` one into the other bank and
+    /// switch to it. Nothing is touched if they would not fit.
+    fn compact(&mut self, pending: (u16, u8, &[u8])) -> Result<(), StoreError> {
+        let (pending_key, pending_version, pending_value) = pending;
+        let mut latest: Vec<(usize, RecordHeader), MAX_KEYS> = Vec::new();
+        let mut new_write_offset = BANK_HEADER_SIZE + record_size(pending_value.len());
+        if self.write_offset != 0 {
+            let mut offset = BANK_HEADER_SIZE;
+            while let Some((header, valid)) = self.read_record_header(offset) {
+                if valid && header.key != pending_key && latest.iter().all(|(_, h)| h.key != header.key) {
+                    if let Some(record) = self.find_latest(header.key) {
+                        new_write_offset += record_size(record.1.len);
+                        latest.push(record).map_err(|_| StoreError::TooManyKeys)?;
+                    }
+                }
+                offset += record_size(header.len);
+            }
+        }
+        if new_write_offset > self.bank_size() {
+            return Err(StoreError::NoSpace);
+        }
+
+        let new_bank = if self.write_offset == 0 { self.active_bank } else { self.active_bank ^ 1 };
+        let new_base = self.bank_offset(new_bank);
+        let mut offset = 0;
+        while offset < self.bank_size() {
+            self.flash.erase_page(new_base + offset)?;
+            offset += F::PAGE_SIZE;
+        }
+
+        let mut offset = BANK_HEADER_SIZE;
+        for (old_offset, header) in latest.iter() {
+            let mut value = [0u8; MAX_VALUE_SIZE];
+            self.flash.read(self.bank_offset(self.active_bank) + old_offset + RECORD_HEADER_SIZE, &mut value[..header.len]);
+            self.write_record(new_base + offset, header.key, header.version, &value[..header.len])?;
+            offset += record_size(header.len);
+        }
+        self.write_record(new_base + offset, pending_key, pending_version, pending_value)?;
+
+        let sequence = self.sequence.wrapping_add(1);
+        let mut bank_header = [0u8; BANK_HEADER_SIZE];
+        bank_header[0..4].copy_from_slice(&BANK_MAGIC.to_le_bytes());
+        bank_header[4..6].copy_from_slice(&sequence.to_le_bytes());
+        let mut crc = TransferCrc::new();
+        crc.add(&bank_header[0..6]);
+        bank_header[6..8].copy_from_slice(&crc.get().to_le_bytes());
+        self.flash.write(new_base, &bank_header)?;
+
+        self.active_bank = new_bank;
+        self.sequence = sequence;
+        self.write_offset = new_write_offset;
+        Ok(())
+    }
+
+    fn write_record(&mut self, offset: usize, key: u16, version: u8, value: &[u8]) -> Result<(), FlashError> {
+        let mut record = [0xFFu8; RECORD_HEADER_SIZE + MAX_VALUE_SIZE + 2];
+        record[0..2].copy_from_slice(&key.to_le_bytes());
+        record[2] = value.len() as u8;
+        record[3] = version;
+        record[RECORD_HEADER_SIZE..RECORD_HEADER_SIZE + value.len()].copy_from_slice(value);
+        let crc_at = RECORD_HEADER_SIZE + padded(value.len());
+        let mut crc = TransferCrc::new();
+        crc.add(&record[..crc_at]);
+        record[crc_at..crc_at + 2].copy_from_slice(&crc.get().to_le_bytes());
+        self.flash.write(offset, &record[..crc_at + 2])
+    }
+
+    fn bank_offset(&self, bank: usize) -> usize {
+        bank * self.bank_size()
+    }
+
+    fn read_bank_header(&self, bank: usize) -> Option<u16> {
+        let mut header = [0u8; BANK_HEADER_SIZE];
+        self.flash.read(self.bank_offset(bank), &mut header);
+        let mut crc = TransferCrc::new();
+        crc.add(&header[0..6]);
+        let magic = u32::from_le_bytes([header[0], header[1], header[2], header[3]]);
+        if magic != BANK_MAGIC || crc.get() != u16::from_le_bytes([header[6], header[7]]) {
+            return None;
+        }
+        Some(u16::from_le_bytes([header[4], header[5]]))
+    }
+
+    /// Header of a record at offset in the active bank and whether its CRC is valid.
+    /// None at the end of the log or if the header itself is garbage.
+    fn read_record_header(&self, offset: usize) -> Option<(RecordHeader, bool)> {
+        if offset + RECORD_HEADER_SIZE > self.bank_size() {
+            return None;
+        }
+        let base = self.bank_offset(self.active_bank);
+        let mut raw = [0u8; RECORD_HEADER_SIZE];
+        self.flash.read(base + offset, &mut raw);
+        let header = RecordHeader {
+            key: u16::from_le_bytes([raw[0], raw[1]]),
+            len: raw[2] as usize,
+            version: raw[3],
+        };
+        if header.key == ERASED_KEY || header.len > MAX_VALUE_SIZE || offset + record_size(header.len) > self.bank_size() {
+            return None;
+        }
+        let mut crc = TransferCrc::new();
+        crc.add(&raw);
+        let mut chunk = [0u8; 2];
+        let mut pos = 0;
+        while pos < padded(header.len) {
+            self.flash.read(base + offset + RECORD_HEADER_SIZE + pos, &mut chunk);
+            crc.add(&chunk);
+            pos += 2;
+        }
+        self.flash.read(base + offset + RECORD_HEADER_SIZE + pos, &mut chunk);
+        Some((header, crc.get() == u16::from_le_bytes(chunk)))
+    }
+
+    fn find_latest(&self, key: u16) -> Option<(usize, RecordHeader)> {
+        if self.write_offset < BANK_HEADER_SIZE {
+            return None;
+        }
+        let mut latest = None;
+        let mut offset = BANK_HEADER_SIZE;
+        while let Some((header, valid)) = self.read_record_header(offset) {
+            if valid && header.key == key {
+                latest = Some((offset, header));
+            }
+            offset += record_size(header.len);
+        }
+        latest
+    }
+
+    /// Offset after the last record. If the log ends with garbage instead of erased flash, the
+    /// bank is reported as full, so that the next write compacts it.
+    fn scan_end(&self) -> usize {
+        let mut offset = BANK_HEADER_SIZE;
+        while let Some((header, _)) = self.read_record_header(offset) {
+            offset += record_size(header.len);
+        }
+        if offset + RECORD_HEADER_SIZE <= self.bank_size() {
+            let mut raw = [0u8; RECORD_HEADER_SIZE];
+            self.flash.read(self.bank_offset(self.active_bank) + offset, &mut raw);
+            if raw != [0xFF; RECORD_HEADER_SIZE] {
+                return self.bank_size();
+            }
+        }
+        offset
+    }
+}
@@ -47,7 +47,7 @@ impl State {
 pub fn ramp_generator(cx: app::ramp_generator::Context, e: Event) {
     let state: &mut State = cx.local.state;
     let now: Instant<crate::TimMono> = app::monotonics::TimMono::now();
-    log_debug!("ramp_generator e: {:?} s: {:?}", e, state);
+    log_debug!(target: Vesc, "ramp_generator e: {:?} s: {:?}", e, state);
     if let Event::Reset { initial, target, rate_per_s } = e {
         if target == initial {
             *state = State::Hold {
@@ -139,6 +139,7 @@ pub fn ramp_generator(cx: app::ramp_generator::Context, e: Event) {
                 }
             };
             log_info!(
+                target: Vesc,
                 "dt: {} dv: {} new_cur: {} input_dt: {}",
                 dt,
                 dv,
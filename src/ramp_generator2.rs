@@ -1,10 +1,8 @@
-use crate::app;
-use embedded_time::Instant;
-use embedded_time::duration::Milliseconds;
-use core::convert::TryFrom;
+//! Ramps a value towards a target with separate up and down rates. Time is passed in by the
+//! caller, so that it also runs in the host simulation.
 
 pub struct RampGenerator {
-    prev_t: Option<Instant<crate::TimMono>>,
+    prev_ms: Option<u32>,
     state: State,
     current: i32,
     target: i32,
@@ -12,6 +10,7 @@ pub struct RampGenerator {
     rate_down: u32,
 }
 
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 #[derive(Copy, Clone, Eq, PartialEq, Debug)]
 pub enum State {
     RampUp,
@@ -22,7 +21,7 @@ pub enum State {
 impl RampGenerator {
     pub const fn new() -> Self {
         RampGenerator {
-            prev_t: None,
+            prev_ms: None,
             state: State::Hold,
             current: 0,
             target: 0,
@@ -41,37 +40,27 @@ impl RampGenerator {
             State::RampUp
         };
 
-        log_debug!("update_state prev:{:?} -> {:?} cur:{} tgt:{}", prev, self.state, self.current, self.target);
+        log_debug!(target: Vesc, "update_state prev:{:?} -> {:?} cur:{} tgt:{}", prev, self.state, self.current, self.target);
     }
 
     pub fn state(&self) -> State {
         self.state
     }
 
-    pub fn get_output(&mut self) -> i32 {
-        let now: Instant<crate::TimMono> = app::monotonics::TimMono::now();
+    pub fn get_output(&mut self, now_ms: u32) -> i32 {
         if self.state == State::Hold {
-            self.prev_t = Some(now);
+            self.prev_ms = Some(now_ms);
             self.current
         } else {
-            let dt = match self.prev_t {
-                Some(prev_t) => {
-                    let r = now
-                        .checked_duration_since(&prev_t)
-                        .map(|dt| Milliseconds::<u32>::try_from(dt).unwrap_or(Milliseconds(0)))
-                        .unwrap_or(Milliseconds(0));
-                    self.prev_t = Some(now);
-                    r
-                },
-                None => {
-                    self.prev_t = Some(now);
-                    Milliseconds(0)
-                }
+            let dt = match self.prev_ms {
+                Some(prev_ms) => now_ms.wrapping_sub(prev_ms),
+                None => 0,
             };
+            self.prev_ms = Some(now_ms);
             let dv = if self.state == State::RampUp {
-                self.rate_up * dt.0 / 1000
+                self.rate_up * dt / 1000
             } else {
-                self.rate_down * dt.0 / 1000
+                self.rate_down * dt / 1000
             };
             let dv = dv as i32;
             self.current = if self.state == State::RampUp {
@@ -88,7 +77,7 @@ impl RampGenerator {
                 }
             };
 
-            log_debug!("dt = {} dv = {}, new_current = {}", dt.0, dv, self.current);
+            log_debug!(target: Vesc, "dt = {} dv = {}, new_current = {}", dt, dv, self.current);
 
             self.update_state();
             self.current
@@ -100,10 +89,9 @@ impl RampGenerator {
         self.rate_down = down_per_s;
     }
 
-    pub fn set_current(&mut self, current: i32) {
+    pub fn set_current(&mut self, current: i32, now_ms: u32) {
         // log_warn!("set_current {}", current);
-        let now: Instant<crate::TimMono> = app::monotonics::TimMono::now();
-        self.prev_t = Some(now);
+        self.prev_ms = Some(now_ms);
         self.current = current;
         self.update_state();
     }
@@ -118,4 +106,10 @@ impl RampGenerator {
         self.target = target;
         self.update_state();
     }
-}
\ No newline at end of file
+}
+
+impl Default for RampGenerator {
+    fn default() -> Self {
+        RampGenerator::new()
+    }
+}
@@ -0,0 +1,314 @@
+//! Registers exposed over uavcan.register.Access and List, backed by [RuntimeConfig].
+//!
+//! Writes are applied immediately, persistent ones are also saved to flash. Adding a register
+//! means adding a line to [REGISTERS], index in the table is what List returns.
+
+use core::convert::TryFrom;
+use crate::node_info;
+use crate::bit_timing::Bitrate;
+use crate::logging::filter::{Level, Targets};
+use crate::nvconfig::{self, NvStore, RuntimeConfig};
+use crate::prelude::NodeId;
+use crate::uavcan::register::{self, AccessRequest, NumKind, Value};
+
+pub struct Register {
+    pub name: &'static str,
+    pub persistent: bool,
+    get: fn(&RuntimeConfig) -> Value,
+    /// None for read only registers. Returns false if value is of wrong type or out of range.
+    set: Option<fn(&mut RuntimeConfig, &Value) -> bool>,
+}
+
+impl Register {
+    pub fn is_mutable(&self) -> bool {
+        self.set.is_some()
+    }
+}
+
+/// uavcan.node.id value of a node without an ID, as the standard defines it
+const NODE_ID_UNSET: i64 = 0xFFFF;
+
+/// Source interface (0 is MCP, 1 is STM, 255 for an unused rule), filter ID and mask.
+#[allow(unused_macros)]
+macro_rules! bridge_rule_register {
+    ($name:literal, $i:literal) => {
+        Register {
+            name: $name,
+            persistent: true,
+            get: |c| bridge_rule::value(&c.can_bridge[$i]),
+            set: Some(|c, v| match bridge_rule::from_value(v) {
+                Some(rule) => { c.can_bridge[$i] = rule; true }
+                None => false,
+            }),
+        }
+    };
+}
+
+pub const REGISTERS: &[Register] = &[
+    Register {
+        name: "uavcan.node.id",
+        persistent: true,
+        get: |c| Value::number(NumKind::Natural16, &[c.node_id.map(|id| id.inner() as i64).unwrap_or(NODE_ID_UNSET)]),
+        // Unset ID is allocated with plug-and-play after reboot
+        set: Some(|c, v| match v.integer() {
+            Some(NODE_ID_UNSET) => { c.node_id = None; true }
+            Some(id) => match u8::try_from(id).ok().and_then(NodeId::new) {
+                Some(id) => { c.node_id = Some(id); true }
+                None => false,
+            },
+            None => false,
+        }),
+    },
+    Register {
+        name: "node.heartbeat.period_ms",
+        persistent: true,
+        get: |c| Value::number(NumKind::Natural16, &[c.heartbeat_period_ms as i64]),
+        set: Some(|c, v| set_in_range(&mut c.heartbeat_period_ms, v, 100, 10_000)),
+    },
+    Register {
+        name: "blinker.brightness_percent",
+        persistent: true,
+        get: |c| Value::number(NumKind::Natural8, &[c.blinker_brightness as i64]),
+        set: Some(|c, v| set_in_range(&mut c.blinker_brightness, v, 0, 100)),
+    },
+    #[cfg(feature = "module-afe")]
+    Register {
+        name: "afe.junk_delta",
+        persistent: true,
+        get: |c| Value::number(NumKind::Integer32, &[c.afe_junk_delta as i64]),
+        set: Some(|c, v| set_in_range(&mut c.afe_junk_delta, v, 1, i32::MAX)),
+    },
+    #[cfg(feature = "module-afe")]
+    Register {
+        name: "afe.max_thrust",
+        persistent: true,
+        get: |c| Value::number(NumKind::Integer32, &[c.afe_max_thrust as i64]),
+        set: Some(|c, v| set_in_range(&mut c.afe_max_thrust, v, 0, i32::MAX)),
+    },
+    #[cfg(feature = "module-afe")]
+    Register {
+        name: "afe.max_torque",
+        persistent: true,
+        get: |c| Value::number(NumKind::Integer32, &[c.afe_max_torque as i64]),
+        set: Some(|c, v| set_in_range(&mut c.afe_max_torque, v, 0, i32::MAX)),
+    },
+    Register {
+        name: "vesc.id",
+        persistent: true,
+        get: |c| Value::number(NumKind::Natural8, &[c.vesc_id as i64]),
+        set: Some(|c, v| set_in_range(&mut c.vesc_id, v, 0, 254)),
+    },
+    #[cfg(feature = "vesc-ctrl")]
+    Register {
+        name: "vesc.duty_min",
+        persistent: true,
+        get: |c| Value::number(NumKind::Natural32, &[c.vesc_duty_min as i64]),
+        set: Some(|c, v| set_in_range(&mut c.vesc_duty_min, v, 0, 100_000)),
+    },
+    #[cfg(feature = "vesc-ctrl")]
+    Register {
+        name: "vesc.ramp_rates",
+        persistent: true,
+        get: |c| Value::number(NumKind::Natural32, &[c.vesc_ramp_rates[0] as i64, c.vesc_ramp_rates[1] as i64]),
+        set: Some(|c, v| match v.integers() {
+            Some(rates) if rates.len() == 2 && rates.iter().all(|r| (1..=100_000).contains(r)) => {
+                c.vesc_ramp_rates = [rates[0] as u32, rates[1] as u32];
+                true
+            }
+            _ => false,
+        }),
+    },
+    #[cfg(feature = "vesc-ctrl")]
+    Register {
+        name: "vesc.input_timeout_ms",
+        persistent: true,
+        get: |c| Value::number(NumKind::Natural16, &[c.vesc_input_timeout_ms as i64]),
+        set: Some(|c, v| set_in_range(&mut c.vesc_input_timeout_ms, v, 100, 10_000)),
+    },
+    Register {
+        name: "can.auto_recovery",
+        persistent: true,
+        get: |c| Value::bit(c.can_auto_recovery),
+        set: Some(|c, v| match v.as_bool() {
+            Some(auto) => { c.can_auto_recovery = auto; true }
+            None => false,
+        }),
+    },
+    Register {
+        name: "can.recovery_backoff_ms",
+        persistent: true,
+        get: |c| Value::number(NumKind::Natural16, &[c.can_recovery_backoff_ms[0] as i64, c.can_recovery_backoff_ms[1] as i64]),
+        // Initial and maximum
+        set: Some(|c, v| match v.integers() {
+            Some(b) if b.len() == 2 && (10..=60_000).contains(&b[0]) && (b[0]..=60_000).contains(&b[1]) => {
+                c.can_recovery_backoff_ms = [b[0] as u16, b[1] as u16];
+                true
+            }
+            _ => false,
+        }),
+    },
+    Register {
+        name: "can.bitrate",
+        persistent: true,
+        get: |c| Value::number(NumKind::Natural32, &[c.can_bitrate.bps() as i64]),
+        // Applied after reboot, every node on the bus has to be switched
+        set: Some(|c, v| match v.integer().and_then(|bps| u32::try_from(bps).ok()).and_then(Bitrate::from_bps) {
+            Some(bitrate) => { c.can_bitrate = bitrate; true }
+            None => false,
+        }),
+    },
+    #[cfg(all(feature = "can-stm", any(feature = "can-mcp25625", feature = "can-mcp2518fd")))]
+    Register {
+        name: "can.redundant",
+        persistent: true,
+        get: |c| Value::bit(c.can_redundant),
+        set: Some(|c, v| match v.as_bool() {
+            Some(redundant) => { c.can_redundant = redundant; true }
+            None => false,
+        }),
+    },
+    #[cfg(all(feature = "can-stm", any(feature = "can-mcp25625", feature = "can-mcp2518fd")))]
+    Register {
+        name: "can.tx_interfaces",
+        persistent: true,
+        // Bit 0 is MCP, bit 1 is STM, both are used in redundant mode regardless
+        get: |c| Value::number(NumKind::Natural8, &[c.can_tx_interfaces.bits() as i64]),
+        set: Some(|c, v| match v.integer().and_then(|b| u8::try_from(b).ok()).and_then(crate::can_interfaces::Interfaces::from_bits) {
+            Some(interfaces) => { c.can_tx_interfaces = interfaces; true }
+            None => false,
+        }),
+    },
+    #[cfg(all(feature = "can-stm", any(feature = "can-mcp25625", feature = "can-mcp2518fd")))]
+    bridge_rule_register!("can.bridge.0", 0),
+    #[cfg(all(feature = "can-stm", any(feature = "can-mcp25625", feature = "can-mcp2518fd")))]
+    bridge_rule_register!("can.bridge.1", 1),
+    #[cfg(all(feature = "can-stm", any(feature = "can-mcp25625", feature = "can-mcp2518fd")))]
+    bridge_rule_register!("can.bridge.2", 2),
+    #[cfg(all(feature = "can-stm", any(feature = "can-mcp25625", feature = "can-mcp2518fd")))]
+    bridge_rule_register!("can.bridge.3", 3),
+    Register {
+        name: "log.level",
+        persistent: true,
+        // 0 trace, 1 debug, 2 info, 3 warn, 4 error, 5 off, compile time level stays the limit
+        get: |c| Value::number(NumKind::Natural8, &[c.log_filter.level as i64]),
+        set: Some(|c, v| match v.integer().and_then(|l| u8::try_from(l).ok()).and_then(Level::from_u8) {
+            Some(level) => { c.log_filter.level = level; true }
+            None => false,
+        }),
+    },
+    Register {
+        name: "log.targets",
+        persistent: true,
+        // Debug and trace of these are let through, bit number is `logging::filter::Target`:
+        // canbus, vesc, afe, button, blinker, health
+        get: |c| Value::number(NumKind::Natural8, &[c.log_filter.targets.bits() as i64]),
+        set: Some(|c, v| match v.integer().and_then(|b| u8::try_from(b).ok()).and_then(Targets::from_bits) {
+            Some(targets) => { c.log_filter.targets = targets; true }
+            None => false,
+        }),
+    },
+    Register { name: "sys.info.module", persistent: false, get: |_| Value::string(node_info::MODULE), set: None },
+    Register { name: "sys.info.chip", persistent: false, get: |_| Value::string(node_info::CHIP), set: None },
+    Register { name: "sys.info.can_driver", persistent: false, get: |_| Value::string(node_info::CAN_DRIVER), set: None },
+    Register { name: "sys.info.git_hash", persistent: false, get: |_| Value::string(node_info::GIT_HASH), set: None },
+    Register {
+        name: "sys.info.build_timestamp",
+        persistent: false,
+        get: |_| Value::number(NumKind::Natural64, &[node_info::build_timestamp() as i64]),
+        set: None,
+    },
+    Register { name: "sys.info.features", persistent: false, get: |_| Value::string(node_info::FEATURES), set: None },
+    Register { name: "sys.info.unique_id", persistent: false, get: |_| Value::string(&node_info::unique_id_hex()), set: None },
+];
+
+fn set_in_range<T: TryFrom<i64>>(field: &mut T, value: &Value, min: i64, max: i64) -> bool {
+    match value.integer() {
+        Some(x) if x >= min && x <= max => match T::try_from(x) {
+            Ok(x) => { *field = x; true }
+            Err(_) => false,
+        },
+        _ => false,
+    }
+}
+
+#[cfg(all(feature = "can-stm", any(feature = "can-mcp25625", feature = "can-mcp2518fd")))]
+mod bridge_rule {
+    use core::convert::TryFrom;
+    use crate::can_health::Interface;
+    use crate::can_interfaces::BridgeRule;
+    use crate::uavcan::filter::Filter;
+    use crate::uavcan::register::{NumKind, Value};
+
+    const UNUSED: i64 = 0xFF;
+    const EXTENDED_ID_MAX: u32 = 0x1FFF_FFFF;
+
+    pub fn value(rule: &BridgeRule) -> Value {
+        let from = rule.from.map(|i| i as i64).unwrap_or(UNUSED);
+        Value::number(NumKind::Natural32, &[from, rule.filter.id as i64, rule.filter.mask as i64])
+    }
+
+    pub fn from_value(value: &Value) -> Option<BridgeRule> {
+        let v = value.integers().filter(|v| v.len() == 3)?;
+        let from = match v[0] {
+            UNUSED => return Some(BridgeRule::DISABLED),
+            0 => Interface::Mcp,
+            1 => Interface::Stm,
+            _ => return None,
+        };
+        let id = u32::try_from(v[1]).ok().filter(|id| *id <= EXTENDED_ID_MAX)?;
+        let mask = u32::try_from(v[2]).ok().filter(|mask| *mask <= EXTENDED_ID_MAX)?;
+        Some(BridgeRule::new(from, Filter::new(id, mask)))
+    }
+}
+
+const fn names_fit(registers: &[Register]) -> bool {
+    let mut i = 0;
+    while i < registers.len() {
+        if registers[i].name.len() > register::MAX_NAME_LEN {
+            return false;
+        }
+        i += 1;
+    }
+    true
+}
+const_assert!(names_fit(REGISTERS));
+
+pub fn find(name: &str) -> Option<&'static Register> {
+    REGISTERS.iter().find(|r| r.name == name)
+}
+
+/// Handle uavcan.register.Access request, response is written into `response`.
+/// Unknown registers are answered with an empty value, writes of wrong type are ignored and
+/// current value is returned, as the standard requires.
+pub fn handle_access(payload: &[u8], store: &mut NvStore, response: &mut [u8]) -> Result<usize, register::Error> {
+    let request = AccessRequest::decode(payload)?;
+    let reg = match find(request.name) {
+        Some(reg) => reg,
+        None => {
+            log_debug!("Unknown register: {}", request.name);
+            return register::encode_access_response(false, false, &Value::Empty, response);
+        }
+    };
+    if let (Some(set), false) = (reg.set, request.value.is_empty()) {
+        let mut cfg = nvconfig::get();
+        if set(&mut cfg, &request.value) {
+            nvconfig::set(cfg);
+            if reg.persistent {
+                if let Err(_e) = cfg.save(store) {
+                    log_error!("Failed to save {}: {:?}", reg.name, _e);
+                }
+            }
+            log_info!("Register {} written", reg.name);
+        } else {
+            log_warn!("Register {}: rejected value {:?}", reg.name, crate::logging::Fmt(&request.value));
+        }
+    }
+    register::encode_access_response(reg.is_mutable(), reg.persistent, &(reg.get)(&nvconfig::get()), response)
+}
+
+/// Handle uavcan.register.List request, index past the end results in an empty name.
+pub fn handle_list(payload: &[u8], response: &mut [u8]) -> Result<usize, register::Error> {
+    let index = register::decode_list_request(payload)? as usize;
+    let name = REGISTERS.get(index).map(|r| r.name).unwrap_or("");
+    register::encode_list_response(name, response)
+}
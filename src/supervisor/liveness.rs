@@ -0,0 +1,75 @@
+//! Check-ins of periodic tasks, the watchdog is fed only while all of them are on time.
+//!
+//! Every check-in tells when the next one is due at the latest, so that tasks with a period
+//! changeable at runtime carry their deadline along. Tasks that never checked in or were
+//! forgotten are not watched.
+//!
+//! Also included by tools, nothing in here depends on the hardware.
+
+/// Watched tasks, value goes into the crash report when one of them is late.
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum Task {
+    HealthCheck = 0,
+    Blink = 1,
+    Button = 2,
+    RampVesc = 3,
+    WatchdogVesc = 4,
+    Idle = 5,
+}
+
+impl Task {
+    pub const COUNT: usize = 6;
+
+    pub fn from_u8(value: u8) -> Option<Task> {
+        Some(match value {
+            0 => Task::HealthCheck,
+            1 => Task::Blink,
+            2 => Task::Button,
+            3 => Task::RampVesc,
+            4 => Task::WatchdogVesc,
+            5 => Task::Idle,
+            _ => return None,
+        })
+    }
+}
+
+#[derive(Copy, Clone, Default, Debug)]
+struct Entry {
+    last_ms: u32,
+    due_ms: u32,
+}
+
+#[derive(Default)]
+pub struct Liveness {
+    entries: [Option<Entry>; Task::COUNT],
+}
+
+impl Liveness {
+    pub const fn new() -> Self {
+        Liveness { entries: [None; Task::COUNT] }
+    }
+
+    /// Next check-in of `task` is due within `within_ms` from `now_ms`, starts watching it.
+    pub fn check_in(&mut self, task: Task, now_ms: u32, within_ms: u32) {
+        self.entries[task as usize] = Some(Entry { last_ms: now_ms, due_ms: now_ms.wrapping_add(within_ms) });
+    }
+
+    /// Stops watching `task`, until it checks in again.
+    pub fn forget(&mut self, task: Task) {
+        self.entries[task as usize] = None;
+    }
+
+    /// First of the watched tasks past its deadline, with the time since its last check-in.
+    pub fn overdue(&self, now_ms: u32) -> Option<(Task, u32)> {
+        self.entries.iter().enumerate().find_map(|(i, e)| {
+            let e = e.as_ref()?;
+            // Wrapping difference, so that the u32 millisecond counter can roll over
+            if (now_ms.wrapping_sub(e.due_ms) as i32) > 0 {
+                Some((Task::from_u8(i as u8)?, now_ms.wrapping_sub(e.last_ms)))
+            } else {
+                None
+            }
+        })
+    }
+}
@@ -0,0 +1,73 @@
+//! Independent watchdog, fed by `task::supervisor` only while every watched task checks in on
+//! time, see [liveness].
+//!
+//! A late task is logged and stored as the crash report, feeding then stops and the IWDG resets
+//! the node. A task hogging the CPU starves the supervisor as well, the reset cause tells that
+//! apart from a regular reset, only without naming the task.
+
+pub mod liveness;
+
+use core::cell::RefCell;
+use embedded_time::duration::Milliseconds;
+use crate::config;
+use crate::pac;
+pub use liveness::Task;
+use liveness::Liveness;
+
+static LIVENESS: bare_metal::Mutex<RefCell<Liveness>> = bare_metal::Mutex::new(RefCell::new(Liveness::new()));
+
+const KEY_ENABLE: u32 = 0xCCCC;
+const KEY_UNLOCK: u32 = 0x5555;
+const KEY_RELOAD: u32 = 0xAAAA;
+/// Typical, it is anywhere from 30 to 50 kHz
+const LSI_HZ: u32 = 40_000;
+/// Divider 64 is 4 in PR
+const PRESCALER: u32 = 64;
+const PRESCALER_BITS: u32 = 4;
+const RELOAD: u32 = config::WATCHDOG_TIMEOUT.0 * (LSI_HZ / PRESCALER) / 1000;
+const_assert!(RELOAD <= 0xFFF);
+// LSI at 50 kHz makes the timeout a fifth shorter, feeding has to leave room for that
+const_assert!(config::WATCHDOG_FEED_PERIOD.0 * 2 < config::WATCHDOG_TIMEOUT.0);
+
+/// Watchdog is stopped while a debugger halts the core in debug builds, so that stepping through
+/// code does not reset the node. Runs before RCC is configured, the HAL keeps it afterwards.
+pub fn freeze_on_debug_halt(rcc: &pac::RCC, dbgmcu: &pac::DBGMCU) {
+    if cfg!(debug_assertions) {
+        // DBGMCU registers are only writable with its clock on
+        rcc.apb2enr.modify(|_, w| w.dbgmcuen().set_bit());
+        dbgmcu.apb1_fz.modify(|_, w| w.dbg_iwdg_stop().set_bit());
+    }
+}
+
+/// Starts the watchdog, it can not be stopped until reset.
+pub fn start(iwdg: &pac::IWDG) {
+    iwdg.kr.write(|w| unsafe { w.bits(KEY_ENABLE) });
+    iwdg.kr.write(|w| unsafe { w.bits(KEY_UNLOCK) });
+    iwdg.pr.write(|w| unsafe { w.bits(PRESCALER_BITS) });
+    iwdg.rlr.write(|w| unsafe { w.bits(RELOAD) });
+    while iwdg.sr.read().bits() != 0 {}
+    feed();
+}
+
+pub fn feed() {
+    let iwdg = unsafe { &*pac::IWDG::ptr() };
+    iwdg.kr.write(|w| unsafe { w.bits(KEY_RELOAD) });
+}
+
+/// Next check-in of `task` is due within `within`, plus [config::WATCHDOG_CHECK_IN_SLACK].
+pub fn check_in(task: Task, within: Milliseconds) {
+    let within_ms = within.0 + config::WATCHDOG_CHECK_IN_SLACK.0;
+    let now = crate::utils::millis();
+    cortex_m::interrupt::free(|cs| LIVENESS.borrow(cs).borrow_mut().check_in(task, now, within_ms));
+}
+
+/// For tasks that stop on purpose.
+pub fn forget(task: Task) {
+    cortex_m::interrupt::free(|cs| LIVENESS.borrow(cs).borrow_mut().forget(task));
+}
+
+/// First late task and how long it has been silent.
+pub fn overdue() -> Option<(Task, u32)> {
+    let now = crate::utils::millis();
+    cortex_m::interrupt::free(|cs| LIVENESS.borrow(cs).borrow().overdue(now))
+}
@@ -0,0 +1,121 @@
+pub mod pattern;
+
+use crate::app;
+use crate::config;
+use crate::hw::PwmChannel;
+use crate::hw::stm32f0::Tim16Ch1;
+use pattern::{NodeState, Player, LEVEL_MAX};
+
+/// Status LED, mirrored to the button LED on the button module
+#[cfg(feature = "module-button")]
+pub type BoardBlinker = Blinker<Tim16Ch1, crate::hw::stm32f0::Tim14Ch1>;
+#[cfg(not(feature = "module-button"))]
+pub type BoardBlinker = Blinker<Tim16Ch1, crate::hw::NoPwm>;
+
+pub enum BlinkerEvent {
+    SetState(BlinkerState),
+    Internal
+}
+
+#[derive(Copy, Clone)]
+pub enum BlinkerState {
+    Off,
+    /// Pattern follows node state, see [pattern::select]
+    Auto,
+}
+
+/// Plays blink patterns on `led`, `mirror` follows at full brightness.
+pub struct Blinker<P, M> {
+    led: P,
+    mirror: M,
+    max_duty: u16,
+    player: Player,
+    state: BlinkerState,
+    global_brightess: u8,
+}
+
+impl<P: PwmChannel, M: PwmChannel> Blinker<P, M> {
+    pub fn new(led: P, mirror: M) -> Self {
+        let max_duty = led.max_duty();
+        Blinker {
+            led,
+            mirror,
+            max_duty,
+            player: Player::new(),
+            state: BlinkerState::Off,
+            global_brightess: 100
+        }
+    }
+
+    fn set_level(&mut self, level: u16) {
+        let duty = self.max_duty as u32 * level as u32 / LEVEL_MAX as u32;
+        self.set_duty_raw(duty as u16);
+    }
+
+    fn set_duty_raw(&mut self, duty: u16) {
+        let duty_scaled = self.global_brightess as u32 * duty as u32 / 100;
+        self.led.set_duty(duty_scaled as u16);
+        self.mirror.set_duty(duty.min(self.mirror.max_duty()));
+    }
+
+    pub fn set_global_brigthness_percent(&mut self, brightness: u8) {
+        let brightness = if brightness > 100 {
+            100
+        } else {
+            brightness
+        };
+        self.global_brightess = brightness;
+    }
+}
+
+fn node_state() -> NodeState {
+    NodeState::new(&crate::health::report(), crate::nvconfig::node_id().is_none(), crate::module::blink_pattern())
+}
+
+use rtic::Mutex;
+use crate::supervisor::Task;
+
+pub fn blink_task(mut cx: app::blink_task::Context, e: BlinkerEvent) {
+    let mut blinker = cx.shared.blinker;
+    #[cfg(feature = "module-led")]
+    let mut stand_state = cx.shared.stand_state;
+    blinker.lock(|b: &mut BoardBlinker| {
+        match e {
+            BlinkerEvent::SetState(state) => {
+                b.state = state;
+                match state {
+                    BlinkerState::Off => {
+                        b.player.set(pattern::OFF);
+                        b.set_duty_raw(0);
+                        crate::supervisor::forget(Task::Blink);
+                    },
+                    BlinkerState::Auto => {
+                        app::blink_task::spawn_after(config::BLINKER_UPDATE_PERIOD, BlinkerEvent::Internal).ok();
+                        crate::supervisor::check_in(Task::Blink, config::BLINKER_UPDATE_PERIOD);
+                    }
+                }
+            }
+            BlinkerEvent::Internal => {
+                match b.state {
+                    BlinkerState::Auto => {
+                        let pattern = pattern::select(&node_state());
+                        if pattern != b.player.pattern() {
+                            log_debug!(target: Blinker, "pattern: {:?}", pattern);
+                        }
+                        b.player.set(pattern);
+                        // Brightness can be changed via registers at any time
+                        b.set_global_brigthness_percent(crate::nvconfig::get().blinker_brightness);
+                        let level = b.player.advance(config::BLINKER_UPDATE_PERIOD.0);
+                        b.set_level(level);
+                        #[cfg(feature = "module-led")]
+                        stand_state.lock(|s| s.set_animation_step(level));
+
+                        app::blink_task::spawn_after(config::BLINKER_UPDATE_PERIOD, BlinkerEvent::Internal).ok();
+                        crate::supervisor::check_in(Task::Blink, config::BLINKER_UPDATE_PERIOD);
+                    },
+                    _ => {}
+                }
+            }
+        }
+    });
+}
@@ -0,0 +1,212 @@
+//! Blink patterns of the status LED, as data: a pattern is a repeating list of steps, [Player]
+//! turns it into brightness over time. [select] picks the pattern from node state, so that it can
+//! be read without connecting to the node:
+//!
+//! | Pattern                        | Meaning                                          |
+//! |--------------------------------|--------------------------------------------------|
+//! | N blinks, pause                | Failure, reason number N - 1 in the health report |
+//! | Fast blink                     | Off the CAN bus                                  |
+//! | Module specific                | See the module, e.g. solid while e-stop pressed  |
+//! | Slow blink                     | No node id, waiting for plug-and-play allocation |
+//! | Heartbeat double pulse         | Warning, see the health report                   |
+//! | Breath                         | All good                                         |
+//!
+//! Also included by tools, nothing in here depends on the hardware.
+
+use crate::health::Reason;
+use crate::uavcan::heartbeat::Health;
+use crate::uavcan::health_report::HealthReport;
+
+/// Brightness in the steps and from [Player::advance]
+pub const LEVEL_MAX: u16 = 1000;
+
+pub const CODE_ON_MS: u16 = 200;
+pub const CODE_OFF_MS: u16 = 300;
+/// After the last blink of a code, so that the count can be told
+pub const CODE_PAUSE_MS: u16 = 1500;
+
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct Step {
+    /// Up to [LEVEL_MAX]
+    pub level: u16,
+    pub ms: u16,
+    /// Linear transition from the level of the previous step, jump to `level` otherwise
+    pub fade: bool,
+}
+
+impl Step {
+    pub const fn hold(level: u16, ms: u16) -> Self {
+        Step { level, ms, fade: false }
+    }
+
+    pub const fn fade(level: u16, ms: u16) -> Self {
+        Step { level, ms, fade: true }
+    }
+}
+
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum Pattern {
+    /// Repeated in order, empty is off
+    Steps(&'static [Step]),
+    /// Number of blinks followed by a pause
+    Code(u8),
+}
+
+pub const OFF: Pattern = Pattern::Steps(&[]);
+pub const SOLID: Pattern = Pattern::Steps(&[Step::hold(LEVEL_MAX, 1000)]);
+pub const FAST_BLINK: Pattern = Pattern::Steps(&[Step::hold(LEVEL_MAX, 100), Step::hold(0, 100)]);
+pub const SLOW_BLINK: Pattern = Pattern::Steps(&[Step::hold(LEVEL_MAX, 1000), Step::hold(0, 1000)]);
+pub const HEARTBEAT: Pattern = Pattern::Steps(&[
+    Step::hold(LEVEL_MAX, 100),
+    Step::hold(0, 150),
+    Step::hold(LEVEL_MAX, 100),
+    Step::hold(0, 1150),
+]);
+pub const BREATH: Pattern = Pattern::Steps(&[Step::fade(LEVEL_MAX, 4000), Step::fade(0, 4000)]);
+
+impl Pattern {
+    fn len(&self) -> usize {
+        match self {
+            Pattern::Steps(steps) => steps.len(),
+            Pattern::Code(count) => *count as usize * 2,
+        }
+    }
+
+    pub fn step(&self, index: usize) -> Option<Step> {
+        match self {
+            Pattern::Steps(steps) => steps.get(index).copied(),
+            Pattern::Code(_) if index >= self.len() => None,
+            Pattern::Code(_) if index % 2 == 0 => Some(Step::hold(LEVEL_MAX, CODE_ON_MS)),
+            Pattern::Code(_) if index == self.len() - 1 => Some(Step::hold(0, CODE_PAUSE_MS)),
+            Pattern::Code(_) => Some(Step::hold(0, CODE_OFF_MS)),
+        }
+    }
+}
+
+pub struct Player {
+    pattern: Pattern,
+    index: usize,
+    elapsed_ms: u32,
+    /// Where a fading step starts from
+    from: u16,
+    level: u16,
+}
+
+impl Default for Player {
+    fn default() -> Self {
+        Player::new()
+    }
+}
+
+impl Player {
+    pub const fn new() -> Self {
+        Player { pattern: OFF, index: 0, elapsed_ms: 0, from: 0, level: 0 }
+    }
+
+    pub fn pattern(&self) -> Pattern {
+        self.pattern
+    }
+
+    /// Starts over if `pattern` is different from the current one, fades continue from the
+    /// current level.
+    pub fn set(&mut self, pattern: Pattern) {
+        if pattern != self.pattern {
+            self.pattern = pattern;
+            self.index = 0;
+            self.elapsed_ms = 0;
+            self.from = self.level;
+        }
+    }
+
+    /// Level to show for the next `dt_ms`, then moves that far forward. The first call after
+    /// [Player::set] gives the start of the pattern.
+    pub fn advance(&mut self, dt_ms: u32) -> u16 {
+        let level = self.level();
+        self.elapsed_ms = self.elapsed_ms.saturating_add(dt_ms);
+        level
+    }
+
+    /// Level at `elapsed_ms` into the current step, steps already over are skipped.
+    fn level(&mut self) -> u16 {
+        let len = self.pattern.len();
+        let mut skipped = 0;
+        let step = loop {
+            let step = match self.pattern.step(self.index) {
+                Some(step) => step,
+                None => {
+                    self.level = 0;
+                    return 0;
+                }
+            };
+            // Zero length steps only, nothing to wait for
+            if self.elapsed_ms < step.ms as u32 || skipped > len {
+                break step;
+            }
+            self.elapsed_ms -= step.ms as u32;
+            self.from = step.level;
+            self.index = (self.index + 1) % len;
+            skipped += 1;
+        };
+        self.level = if step.fade && step.ms != 0 {
+            let from = self.from as i32;
+            let span = step.level as i32 - from;
+            (from + span * self.elapsed_ms.min(step.ms as u32) as i32 / step.ms as i32) as u16
+        } else {
+            step.level
+        };
+        self.level.min(LEVEL_MAX)
+    }
+}
+
+/// What the status LED shows, see the table at the top
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct NodeState {
+    /// Failure mask from the health report, without the CAN interfaces
+    pub failures: u16,
+    /// Health of the CAN interfaces, failure while off the bus
+    pub can: Health,
+    pub warning: bool,
+    pub anonymous: bool,
+    /// Module state worth showing, takes over everything but failures
+    pub module: Option<Pattern>,
+}
+
+impl NodeState {
+    /// CAN errors have a pattern of their own, so they are taken out of the report masks. Bits
+    /// stay where they are, failure codes keep matching reason numbers.
+    pub fn new(report: &HealthReport, anonymous: bool, module: Option<Pattern>) -> Self {
+        let can_bit = 1 << Reason::CanErrors as u16;
+        let can = if report.failures & can_bit != 0 {
+            Health::Failure
+        } else if report.warnings & can_bit != 0 {
+            Health::Warning
+        } else {
+            Health::Norminal
+        };
+        NodeState {
+            failures: report.failures & !can_bit,
+            can,
+            warning: report.warnings & !can_bit != 0,
+            anonymous,
+            module,
+        }
+    }
+}
+
+pub fn select(state: &NodeState) -> Pattern {
+    if state.failures != 0 {
+        Pattern::Code(state.failures.trailing_zeros() as u8 + 1)
+    } else if state.can == Health::Failure {
+        FAST_BLINK
+    } else if let Some(pattern) = state.module {
+        pattern
+    } else if state.anonymous {
+        SLOW_BLINK
+    } else if state.warning || state.can == Health::Warning {
+        HEARTBEAT
+    } else {
+        BREATH
+    }
+}
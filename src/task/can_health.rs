@@ -0,0 +1,64 @@
+//! Periodic poll of CAN controllers and statistics publishing.
+//!
+//! Controllers are driven from their interrupt handlers, which are only pended from here: that is
+//! where error state is read and bus-off recovery happens, see `can_health`. Interrupts alone are
+//! not enough, a controller off the bus produces none.
+
+use crate::{app, config};
+use rtic::Mutex;
+use uavcan_llr::types::{TransferId, CanId, Priority};
+
+pub struct State {
+    polls: u8,
+    #[cfg(any(feature = "can-mcp25625", feature = "can-mcp2518fd"))]
+    mcp_transfer_id: TransferId,
+    #[cfg(feature = "can-stm")]
+    stm_transfer_id: TransferId,
+}
+
+impl State {
+    pub const fn new() -> Self {
+        State {
+            polls: 0,
+            #[cfg(any(feature = "can-mcp25625", feature = "can-mcp2518fd"))]
+            mcp_transfer_id: TransferId::new(0).unwrap(),
+            #[cfg(feature = "can-stm")]
+            stm_transfer_id: TransferId::new(0).unwrap(),
+        }
+    }
+}
+
+/// Statistics of an interface are published on that interface only, so every bus sees one
+/// session with consecutive transfer IDs.
+macro_rules! publish_statistics {
+    ($cx:expr, $interface:expr, $health:ident, $transfer_id:ident) => {
+        // Anonymous nodes do not publish anything
+        if let Some(node_id) = crate::nvconfig::node_id() {
+            let id = CanId::new_message_kind(node_id, config::CAN_STATISTICS_SUBJECT_ID, false, Priority::Low);
+            let mut payload = [0u8; crate::can_health::STATISTICS_SIZE];
+            let len = $cx.shared.$health.lock(|h| h.encode(&mut payload));
+            let transfer_id = crate::uavcan::tx::next_transfer_id(&mut $cx.local.state.$transfer_id);
+            let interfaces = crate::can_interfaces::Interfaces::only($interface);
+            can_send_transfer_on!($cx, interfaces, id, &payload[..len], transfer_id).ok();
+        }
+    };
+}
+
+pub fn can_health_task(mut cx: app::can_health_task::Context) {
+    #[cfg(feature = "can-mcp25625")]
+    rtic::pend(config::MCP25625_IRQ_HANDLER);
+    #[cfg(feature = "can-mcp2518fd")]
+    rtic::pend(config::MCP2518FD_IRQ_HANDLER);
+    #[cfg(feature = "can-stm")]
+    rtic::pend(crate::pac::Interrupt::CEC_CAN);
+
+    cx.local.state.polls += 1;
+    if cx.local.state.polls >= config::CAN_STATISTICS_PUBLISH_POLLS {
+        cx.local.state.polls = 0;
+        #[cfg(any(feature = "can-mcp25625", feature = "can-mcp2518fd"))]
+        publish_statistics!(cx, crate::can_health::Interface::Mcp, can_mcp_health, mcp_transfer_id);
+        #[cfg(feature = "can-stm")]
+        publish_statistics!(cx, crate::can_health::Interface::Stm, can_stm_health, stm_transfer_id);
+    }
+    app::can_health_task::spawn_after(config::CAN_HEALTH_POLL_PERIOD).ok();
+}
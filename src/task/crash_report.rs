@@ -0,0 +1,24 @@
+//! Publishes the report of the last reset once, see `crash`. Waits for a node ID and retries
+//! while the TX queue is full.
+
+use rtic::Mutex;
+use uavcan_llr::types::{CanId, Priority};
+use crate::app;
+use crate::config;
+use crate::uavcan::crash_report;
+
+pub fn crash_report_task(mut cx: app::crash_report_task::Context) {
+    let node_id = match crate::nvconfig::node_id() {
+        Some(node_id) => node_id,
+        None => {
+            app::crash_report_task::spawn_after(config::CRASH_REPORT_RETRY_PERIOD).ok();
+            return;
+        }
+    };
+    let mut payload = [0u8; crash_report::MAX_SIZE];
+    let len = crate::crash::encode_last(&mut payload).unwrap_or(0);
+    let id = CanId::new_message_kind(node_id, config::CRASH_REPORT_SUBJECT_ID, false, Priority::Slow);
+    if can_send_transfer!(cx, id, &payload[..len], cx.local.transfer_id).is_err() {
+        app::crash_report_task::spawn_after(config::CRASH_REPORT_RETRY_PERIOD).ok();
+    }
+}
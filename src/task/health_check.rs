@@ -1,58 +1,55 @@
-use crate::{app, config, };
+use crate::app;
+use crate::config;
 use rtic::Mutex;
 use rtic::rtic_monotonic::Milliseconds;
 use uavcan_llr::types::{TransferId, CanId, SubjectId, Priority};
-use uavcan_llr::slicer::{Slicer, OwnedSlice};
+use crate::uavcan::heartbeat;
+use crate::health::Reason;
+use crate::supervisor::Task;
 
-#[derive(Copy, Clone, Eq, PartialEq, Debug)]
-pub enum Health {
-    /// not a typo, fully functioning node
-    Norminal = 0b000,
-    /// node can perform it's task, but is experiencing troubles
-    Warning = 0b001,
-    /// node cannot perform it's task
-    Failure = 0b010,
-}
-
-#[derive(Copy, Clone, Eq, PartialEq, Debug)]
-#[allow(dead_code)]
-pub enum Mode {
-    Bootloader = 0b00,
-    Firmware = 0b01,
-}
+pub use crate::uavcan::heartbeat::{Health, Mode};
 
 #[derive(Default)]
 pub struct State {
     transfer_id: TransferId,
+    report_transfer_id: TransferId,
 }
 
 impl State {
     pub const fn new() -> Self {
         State {
             transfer_id: TransferId::new(0).unwrap(),
+            report_transfer_id: TransferId::new(0).unwrap(),
         }
     }
 }
 
 pub fn health_check_task(mut cx: crate::app::health_check_task::Context) {
-    let uptime = if config::HEALTH_CHECK_PERIOD == Milliseconds::new(1000u32) {
-        cx.shared.uptime.lock(|uptime: &mut u32| {
-            *uptime = uptime.saturating_add(1);
-            *uptime
-        })
-    } else {
-        0u32 // TODO: impl if health check period is not 1s
-    };
-
-    let mut payload = [0u8; 7];
-    payload[0..=3].copy_from_slice(&uptime.to_le_bytes());
-    let mode = Mode::Firmware;
-    payload[4] = cx.shared.health.lock(|h| (*h as u8) | ((mode as u8) << 3));
-
-    let id = CanId::new_message_kind(config::UAVCAN_NODE_ID, SubjectId::new(10).unwrap(), false, Priority::Nominal);
-    let frame = Slicer::<8>::new_single(OwnedSlice::new(payload, 5), id, &mut cx.local.state.transfer_id);
-    can_send!(cx, frame);
+    // Period can be changed by a register write at any time, uptime does not depend on it
+    let period = Milliseconds::new(crate::nvconfig::get().heartbeat_period_ms as u32);
+    let uptime = (crate::utils::micros() / 1_000_000) as u32;
+
+    #[allow(unused_mut)]
+    let mut can = Health::Norminal;
+    #[cfg(any(feature = "can-mcp25625", feature = "can-mcp2518fd"))] {
+        can = can.worst(cx.shared.can_mcp_health.lock(|h| h.health()));
+    }
+    #[cfg(feature = "can-stm")] {
+        can = can.worst(cx.shared.can_stm_health.lock(|h| h.health()));
+    }
+    crate::health::raise(Reason::CanErrors, can);
+    let report = crate::health::report();
+    let payload = heartbeat::encode(uptime, report.health, Mode::Firmware, crate::module::status());
+
+    // Anonymous nodes do not publish heartbeats
+    if let Some(node_id) = crate::nvconfig::node_id() {
+        let id = CanId::new_message_kind(node_id, SubjectId::new(heartbeat::SUBJECT_ID).unwrap(), false, Priority::Nominal);
+        can_send_transfer!(cx, id, &payload, &mut cx.local.state.transfer_id).ok();
+        let id = CanId::new_message_kind(node_id, config::HEALTH_REPORT_SUBJECT_ID, false, Priority::Nominal);
+        can_send_transfer!(cx, id, &report.encode(), &mut cx.local.state.report_transfer_id).ok();
+    }
 
     //log_info!("uptime: {}s", uptime);
-    app::health_check_task::spawn_after(config::HEALTH_CHECK_PERIOD).ok();
+    app::health_check_task::spawn_after(period).ok();
+    crate::supervisor::check_in(Task::HealthCheck, period);
 }
\ No newline at end of file
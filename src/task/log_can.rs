@@ -0,0 +1,45 @@
+//! Publishes log output queued by `logging::can` as message transfers on
+//! [config::LOG_SUBJECT_ID]. defmt chunks do not follow record boundaries, a receiver
+//! concatenates the payloads of one node in transfer order. Text records are sent one per transfer.
+//!
+//! Anonymous nodes do not publish, records are kept until a node ID is allocated or the queue
+//! is full.
+
+use rtic::Mutex;
+use uavcan_llr::types::{CanId, Priority};
+use crate::app;
+use crate::config;
+use crate::logging::can::{discard, peek};
+
+#[cfg(feature = "log-defmt-can")]
+const CHUNK_SIZE: usize = config::LOG_CAN_CHUNK_SIZE;
+#[cfg(feature = "log-text-can")]
+const CHUNK_SIZE: usize = crate::logging::can::MAX_RECORD_SIZE;
+
+/// Publishes one chunk and respawns itself while there is more. Chunk stays queued if the TX
+/// queue is full and is retried later.
+pub fn log_can_task(mut cx: app::log_can_task::Context) {
+    let node_id = match crate::nvconfig::node_id() {
+        Some(node_id) => node_id,
+        None => return,
+    };
+    let mut chunk = [0u8; CHUNK_SIZE];
+    let len = peek(&mut chunk);
+    if len == 0 {
+        return;
+    }
+    // Queue only holds whole records, one starts at the front
+    #[cfg(feature = "log-text-can")]
+    let len = crate::uavcan::diagnostic::Record::encoded_len(&chunk);
+    let id = CanId::new_message_kind(node_id, config::LOG_SUBJECT_ID, false, Priority::Slow);
+    match can_send_transfer!(cx, id, &chunk[..len], cx.local.transfer_id) {
+        Ok(()) => {
+            if discard(len) > 0 {
+                app::log_can_task::spawn().ok();
+            }
+        }
+        Err(_) => {
+            app::log_can_task::spawn_after(config::LOG_CAN_RETRY_PERIOD).ok();
+        }
+    }
+}
@@ -1,2 +1,8 @@
 pub mod health_check;
 pub mod blink;
+pub mod pnp;
+pub mod can_health;
+pub mod crash_report;
+pub mod supervisor;
+#[cfg(any(feature = "log-defmt-can", feature = "log-text-can"))]
+pub mod log_can;
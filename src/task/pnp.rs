@@ -0,0 +1,58 @@
+//! Plug-and-play node ID allocation, runs while the node has no ID.
+//!
+//! Requests are published anonymously by [pnp_task], responses are handed over by the router to
+//! [handle_allocation], which applies and persists the ID. Task stops rescheduling itself then.
+
+use crate::{app, config, node_info};
+use crate::nvconfig::{self, NvStore};
+use crate::uavcan::pnp::{self, Allocatee};
+use rtic::Mutex;
+use rtic::rtic_monotonic::Milliseconds;
+use uavcan_llr::types::{TransferId, CanId, Priority};
+
+pub struct State {
+    allocatee: Option<Allocatee>,
+    transfer_id: TransferId,
+}
+
+impl State {
+    pub const fn new() -> Self {
+        State {
+            allocatee: None,
+            transfer_id: TransferId::new(0).unwrap(),
+        }
+    }
+}
+
+pub fn pnp_task(mut cx: app::pnp_task::Context) {
+    if nvconfig::node_id().is_some() {
+        return;
+    }
+    let allocatee = cx.local.state.allocatee.get_or_insert_with(|| Allocatee::new(&node_info::unique_id()));
+    let mut payload = [0u8; pnp::REQUEST_SIZE];
+    if let Ok(len) = allocatee.request(&mut payload) {
+        let id = CanId::new_message_kind(allocatee.pseudo_source_node_id(), config::PNP_ALLOCATION_SUBJECT_ID, true, Priority::Slow);
+        can_send_transfer!(cx, id, &payload[..len], &mut cx.local.state.transfer_id).ok();
+    }
+    app::pnp_task::spawn_after(Milliseconds::new(allocatee.next_request_delay_ms())).ok();
+}
+
+/// Allocation responses to other nodes and all of them after the ID is set are ignored.
+pub fn handle_allocation(payload: &[u8], store: &mut NvStore) {
+    if nvconfig::node_id().is_some() {
+        return;
+    }
+    let node_id = match Allocatee::new(&node_info::unique_id()).accept(payload) {
+        Some(node_id) => node_id,
+        None => {
+            return;
+        }
+    };
+    let mut cfg = nvconfig::get();
+    cfg.node_id = Some(node_id);
+    nvconfig::set(cfg);
+    log_info!("Node ID allocated: {}", node_id.inner());
+    if let Err(_e) = cfg.save(store) {
+        log_error!("Failed to store allocated node ID: {:?}", _e);
+    }
+}
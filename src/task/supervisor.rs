@@ -0,0 +1,19 @@
+//! Feeds the watchdog while all watched tasks are on time, see `supervisor`. Runs above the other
+//! software tasks, so that one of them stuck in a loop is still caught and named.
+
+use crate::app;
+use crate::config;
+
+pub fn supervisor_task(_cx: app::supervisor_task::Context) {
+    match crate::supervisor::overdue() {
+        None => {
+            crate::supervisor::feed();
+            app::supervisor_task::spawn_after(config::WATCHDOG_FEED_PERIOD).ok();
+        }
+        Some((task, silent_ms)) => {
+            log_error!("{:?} did not check in for {} ms, waiting for watchdog reset", task, silent_ms);
+            crate::crash::store_watchdog(task, silent_ms);
+            // Not fed anymore, the watchdog resets the node
+        }
+    }
+}
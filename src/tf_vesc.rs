@@ -88,7 +88,7 @@ pub fn tf_vesc(mut cx: app::tf_vesc::Context, e: Event) {
                                 .unwrap_or(Milliseconds(0));
                             if dt > INPUT_TIMEOUT {
                                 state.mode = Mode::Off;
-                                log_debug!("tf_vesc: timeout");
+                                log_debug!(target: Vesc, "tf_vesc: timeout");
                                 let current: i32 = 0;
                                 let frame = Frame::new(VESC_SET_CURRENT_ID, &current.to_be_bytes()).unwrap();
                                 can_send!(cx, frame);
@@ -123,7 +123,7 @@ pub fn tf_vesc(mut cx: app::tf_vesc::Context, e: Event) {
             } else {
                 duty_p5
             };
-            log_debug!("erpm = {}, duty = {}, target = {}, e = {}, p = {}, i = {}, o = {}, oc = {}", state.last_erpm, state.last_duty,  erpm, e, p, state.i, duty_p5, duty_p5_clamp);
+            log_debug!(target: Vesc, "erpm = {}, duty = {}, target = {}, e = {}, p = {}, i = {}, o = {}, oc = {}", state.last_erpm, state.last_duty,  erpm, e, p, state.i, duty_p5, duty_p5_clamp);
 
             let frame = Frame::new(VESC_SET_DUTY_ID, &duty_p5_clamp.to_be_bytes()).unwrap();
             can_send!(cx, frame);
@@ -133,7 +133,7 @@ pub fn tf_vesc(mut cx: app::tf_vesc::Context, e: Event) {
                 can_send!(cx, Frame::new(VESC_SET_DUTY_ID, &state.last_duty.to_be_bytes()).unwrap());
             } else {
                 let e = erpm_target - state.last_erpm;
-                log_info!("erpm_search: e: {}", e);
+                log_info!(target: Vesc, "erpm_search: e: {}", e);
                 if e.abs() < 100 {
                     state.found = true;
                     can_send!(cx, Frame::new(VESC_SET_DUTY_ID, &state.last_duty.to_be_bytes()).unwrap());
@@ -0,0 +1,205 @@
+//! Multi-frame transfer reassembly based on the UAVCAN/CAN tail byte.
+//!
+//! Sessions are keyed by the CAN ID with priority bits masked out, so every (source, subject) or
+//! (source, destination, service) pair gets its own session. Number of sessions is bounded, stale
+//! ones are evicted after a timeout.
+
+use super::crc::TransferCrc;
+
+pub const TAIL_START_OF_TRANSFER: u8 = 1 << 7;
+pub const TAIL_END_OF_TRANSFER: u8 = 1 << 6;
+pub const TAIL_TOGGLE: u8 = 1 << 5;
+pub const TAIL_TRANSFER_ID_MASK: u8 = 0b0001_1111;
+/// Everything except for the 3 priority bits
+pub const SESSION_KEY_MASK: u32 = 0x03FF_FFFF;
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct TailByte(pub u8);
+
+impl TailByte {
+    pub const fn new(start: bool, end: bool, toggle: bool, transfer_id: u8) -> Self {
+        TailByte(
+            ((start as u8) << 7) | ((end as u8) << 6) | ((toggle as u8) << 5) | (transfer_id & TAIL_TRANSFER_ID_MASK)
+        )
+    }
+
+    pub const fn is_start(&self) -> bool {
+        self.0 & TAIL_START_OF_TRANSFER != 0
+    }
+
+    pub const fn is_end(&self) -> bool {
+        self.0 & TAIL_END_OF_TRANSFER != 0
+    }
+
+    pub const fn toggle(&self) -> bool {
+        self.0 & TAIL_TOGGLE != 0
+    }
+
+    pub const fn transfer_id(&self) -> u8 {
+        self.0 & TAIL_TRANSFER_ID_MASK
+    }
+}
+
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum Error {
+    /// Frame without payload and tail byte
+    Empty,
+    /// Single frame transfer with toggle bit cleared
+    WrongToggle,
+    /// Continuation frame without preceding start frame
+    NoSession,
+    /// Continuation frame belongs to another transfer
+    TransferIdMismatch,
+    /// All sessions are busy and none of them timed out yet
+    NoFreeSession,
+    /// Transfer does not fit into the session buffer
+    TooLong,
+    /// Last frame came too late, transfer is dropped
+    Timeout,
+    /// Multi-frame transfer shorter than its CRC
+    TooShort,
+    CrcMismatch,
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct Transfer<'a> {
+    pub payload: &'a [u8],
+    pub transfer_id: u8,
+}
+
+#[derive(Copy, Clone)]
+struct Session<const MAX_SIZE: usize> {
+    key: Option<u32>,
+    transfer_id: u8,
+    expected_toggle: bool,
+    started_at_ms: u32,
+    len: usize,
+    buf: [u8; MAX_SIZE],
+}
+
+impl<const MAX_SIZE: usize> Session<MAX_SIZE> {
+    const fn new() -> Self {
+        Session {
+            key: None,
+            transfer_id: 0,
+            expected_toggle: true,
+            started_at_ms: 0,
+            len: 0,
+            buf: [0u8; MAX_SIZE],
+        }
+    }
+
+    fn is_expired(&self, now_ms: u32, timeout_ms: u32) -> bool {
+        now_ms.wrapping_sub(self.started_at_ms) > timeout_ms
+    }
+}
+
+/// `SESSIONS` concurrent transfers of up to `MAX_SIZE` bytes each (including transfer CRC).
+pub struct Assembler<const SESSIONS: usize, const MAX_SIZE: usize> {
+    sessions: [Session<MAX_SIZE>; SESSIONS],
+    timeout_ms: u32,
+}
+
+impl<const SESSIONS: usize, const MAX_SIZE: usize> Assembler<SESSIONS, MAX_SIZE> {
+    pub const fn new(timeout_ms: u32) -> Self {
+        Assembler {
+            sessions: [Session::new(); SESSIONS],
+            timeout_ms,
+        }
+    }
+
+    /// Feed one frame worth of data (payload and tail byte) received with the given CAN ID.
+    /// Returns complete transfer payload without tail bytes, padding is left in place as it is
+    /// indistinguishable from data and DSDL decoders are expected to ignore it.
+    pub fn push<'a>(&'a mut self, can_id: u32, data: &'a [u8], now_ms: u32) -> Result<Option<Transfer<'a>>, Error> {
+        let (tail, payload) = match data.split_last() {
+            Some((tail, payload)) => (TailByte(*tail), payload),
+            None => {
+                return Err(Error::Empty);
+            }
+        };
+        let key = can_id & SESSION_KEY_MASK;
+        let timeout_ms = self.timeout_ms;
+
+        if tail.is_start() && tail.is_end() {
+            if !tail.toggle() {
+                return Err(Error::WrongToggle);
+            }
+            return Ok(Some(Transfer { payload, transfer_id: tail.transfer_id() }));
+        }
+
+        if tail.is_start() {
+            if !tail.toggle() {
+                return Err(Error::WrongToggle);
+            }
+            let idx = match self.find(key) {
+                Some(idx) => idx,
+                None => self.allocate(now_ms).ok_or(Error::NoFreeSession)?,
+            };
+            let session = &mut self.sessions[idx];
+            session.key = Some(key);
+            session.transfer_id = tail.transfer_id();
+            session.started_at_ms = now_ms;
+            session.len = 0;
+            session.expected_toggle = true;
+        }
+
+        let idx = self.find(key).ok_or(Error::NoSession)?;
+        let session = &mut self.sessions[idx];
+        if session.transfer_id != tail.transfer_id() {
+            if session.is_expired(now_ms, timeout_ms) {
+                session.key = None;
+            }
+            return Err(Error::TransferIdMismatch);
+        }
+        if session.expected_toggle != tail.toggle() {
+            // Duplicate frame (redundant interface or retransmission), ignore it
+            return Ok(None);
+        }
+        if session.is_expired(now_ms, timeout_ms) {
+            session.key = None;
+            return Err(Error::Timeout);
+        }
+        if session.len + payload.len() > MAX_SIZE {
+            session.key = None;
+            return Err(Error::TooLong);
+        }
+        session.buf[session.len..session.len + payload.len()].copy_from_slice(payload);
+        session.len += payload.len();
+        session.expected_toggle = !session.expected_toggle;
+
+        if !tail.is_end() {
+            return Ok(None);
+        }
+        session.key = None;
+        if session.len < 2 {
+            return Err(Error::TooShort);
+        }
+        let mut crc = TransferCrc::new();
+        crc.add(&session.buf[..session.len]);
+        if !crc.is_residue_ok() {
+            return Err(Error::CrcMismatch);
+        }
+        Ok(Some(Transfer { payload: &session.buf[..session.len - 2], transfer_id: session.transfer_id }))
+    }
+
+    /// Number of transfers currently in progress
+    pub fn active_sessions(&self) -> usize {
+        self.sessions.iter().filter(|s| s.key.is_some()).count()
+    }
+
+    fn find(&self, key: u32) -> Option<usize> {
+        self.sessions.iter().position(|s| s.key == Some(key))
+    }
+
+    fn allocate(&mut self, now_ms: u32) -> Option<usize> {
+        let timeout_ms = self.timeout_ms;
+        for s in self.sessions.iter_mut() {
+            if s.key.is_some() && s.is_expired(now_ms, timeout_ms) {
+                s.key = None;
+            }
+        }
+        self.sessions.iter().position(|s| s.key.is_none())
+    }
+}
@@ -0,0 +1,180 @@
+//! Vendor specific crash report: why the node was reset and, if it crashed, where.
+//!
+//! Published once after boot on [SUBJECT_ID] and returned by the [SERVICE_ID] service with an
+//! empty request, see `crash` in the firmware.
+//!
+//! reset cause u8 | uptime ms u32 | kind u8 | fault
+//! panic: line u32 | file len u8 | file | message len u8 | message
+//! hard fault: r0, r1, r2, r3, r12, lr, pc, xpsr, sp as u32
+//! watchdog: task u8 | silent ms u32
+
+use super::register::{Error, Reader, Writer};
+
+pub const SUBJECT_ID: u16 = 25;
+pub const SERVICE_ID: u16 = 5;
+/// End of the path is kept, that is where the file name is
+pub const MAX_FILE_LEN: usize = 24;
+pub const MAX_MESSAGE_LEN: usize = 80;
+pub const MAX_SIZE: usize = 1 + 4 + 1 + 4 + 1 + MAX_FILE_LEN + 1 + MAX_MESSAGE_LEN;
+
+const KIND_NONE: u8 = 0;
+const KIND_PANIC: u8 = 1;
+const KIND_HARD_FAULT: u8 = 2;
+const KIND_WATCHDOG: u8 = 3;
+
+/// Reset flags of RCC_CSR, several can be set at once, the pin flag is set on every reset.
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct ResetCause(u8);
+
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum Cause {
+    LowPower,
+    WindowWatchdog,
+    IndependentWatchdog,
+    Software,
+    /// Power-on or brown-out, the power supervisor does not tell them apart
+    PowerOn,
+    OptionByteLoader,
+    Pin,
+    Unknown,
+}
+
+impl ResetCause {
+    const OBL: u8 = 1 << 0;
+    const PIN: u8 = 1 << 1;
+    const POR: u8 = 1 << 2;
+    const SOFTWARE: u8 = 1 << 3;
+    const IWDG: u8 = 1 << 4;
+    const WWDG: u8 = 1 << 5;
+    const LOW_POWER: u8 = 1 << 6;
+
+    /// From RCC_CSR bits 25 to 31
+    pub const fn from_csr(csr: u32) -> Self {
+        ResetCause((csr >> 25) as u8 & 0x7F)
+    }
+
+    pub const fn from_bits(bits: u8) -> Self {
+        ResetCause(bits & 0x7F)
+    }
+
+    pub const fn bits(self) -> u8 {
+        self.0
+    }
+
+    /// Most specific of the flags set
+    pub fn cause(self) -> Cause {
+        let order = [
+            (Self::LOW_POWER, Cause::LowPower),
+            (Self::WWDG, Cause::WindowWatchdog),
+            (Self::IWDG, Cause::IndependentWatchdog),
+            (Self::SOFTWARE, Cause::Software),
+            (Self::POR, Cause::PowerOn),
+            (Self::OBL, Cause::OptionByteLoader),
+            (Self::PIN, Cause::Pin),
+        ];
+        order.iter().find(|(bit, _)| self.0 & bit != 0).map(|(_, cause)| *cause).unwrap_or(Cause::Unknown)
+    }
+}
+
+/// Stacked by the exception entry, `sp` is where. Cortex-M0 has no fault status registers, the
+/// instruction at `pc` and the exception number in `xpsr` are all there is.
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct HardFaultFrame {
+    pub r0: u32,
+    pub r1: u32,
+    pub r2: u32,
+    pub r3: u32,
+    pub r12: u32,
+    pub lr: u32,
+    pub pc: u32,
+    pub xpsr: u32,
+    pub sp: u32,
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum Fault<'a> {
+    /// Reset without a crash
+    None,
+    /// Message is the panic info as printed by core::fmt, empty with defmt logging, which leaves
+    /// core::fmt out
+    Panic { line: u32, file: &'a [u8], message: &'a [u8] },
+    HardFault(HardFaultFrame),
+    /// Watched task that missed its check-in, numbered as `supervisor::liveness::Task`, and how
+    /// long it had been silent
+    Watchdog { task: u8, silent_ms: u32 },
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct CrashReport<'a> {
+    pub reset_cause: ResetCause,
+    /// When the fault happened, 0 if unknown
+    pub uptime_ms: u32,
+    pub fault: Fault<'a>,
+}
+
+impl<'a> CrashReport<'a> {
+    pub fn decode(payload: &'a [u8]) -> Result<Self, Error> {
+        let mut r = Reader::new(payload);
+        let reset_cause = ResetCause::from_bits(r.u8()?);
+        let uptime_ms = r.u32()?;
+        let fault = match r.u8()? {
+            KIND_NONE => Fault::None,
+            KIND_PANIC => {
+                let line = r.u32()?;
+                let len = r.u8()? as usize;
+                let file = r.bytes(len)?;
+                let len = r.u8()? as usize;
+                Fault::Panic { line, file, message: r.bytes(len)? }
+            }
+            KIND_HARD_FAULT => Fault::HardFault(HardFaultFrame {
+                r0: r.u32()?,
+                r1: r.u32()?,
+                r2: r.u32()?,
+                r3: r.u32()?,
+                r12: r.u32()?,
+                lr: r.u32()?,
+                pc: r.u32()?,
+                xpsr: r.u32()?,
+                sp: r.u32()?,
+            }),
+            KIND_WATCHDOG => Fault::Watchdog { task: r.u8()?, silent_ms: r.u32()? },
+            _ => return Err(Error::Unsupported),
+        };
+        Ok(CrashReport { reset_cause, uptime_ms, fault })
+    }
+
+    /// File and message are truncated to [MAX_FILE_LEN] and [MAX_MESSAGE_LEN].
+    pub fn encode(&self, buf: &mut [u8]) -> Result<usize, Error> {
+        let mut w = Writer::new(buf);
+        w.u8(self.reset_cause.bits())?;
+        w.u32(self.uptime_ms)?;
+        match self.fault {
+            Fault::None => w.u8(KIND_NONE)?,
+            Fault::Panic { line, file, message } => {
+                w.u8(KIND_PANIC)?;
+                w.u32(line)?;
+                let file = &file[file.len().saturating_sub(MAX_FILE_LEN)..];
+                w.u8(file.len() as u8)?;
+                w.bytes(file)?;
+                let message = &message[..core::cmp::min(message.len(), MAX_MESSAGE_LEN)];
+                w.u8(message.len() as u8)?;
+                w.bytes(message)?;
+            }
+            Fault::HardFault(f) => {
+                w.u8(KIND_HARD_FAULT)?;
+                for v in [f.r0, f.r1, f.r2, f.r3, f.r12, f.lr, f.pc, f.xpsr, f.sp].iter() {
+                    w.u32(*v)?;
+                }
+            }
+            Fault::Watchdog { task, silent_ms } => {
+                w.u8(KIND_WATCHDOG)?;
+                w.u8(task)?;
+                w.u32(silent_ms)?;
+            }
+        }
+        Ok(w.position())
+    }
+}
@@ -0,0 +1,89 @@
+//! CRC-16/CCITT-FALSE used by UAVCAN/CAN for multi-frame transfers, and CRC-64-WE that
+//! uavcan.node.GetInfo reports for the software image.
+//! Bitwise implementations, lookup tables are not worth the flash on F051.
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct TransferCrc(u16);
+
+impl TransferCrc {
+    pub const fn new() -> Self {
+        TransferCrc(0xFFFF)
+    }
+
+    pub fn add(&mut self, bytes: &[u8]) {
+        for b in bytes {
+            self.add_byte(*b);
+        }
+    }
+
+    pub fn add_byte(&mut self, byte: u8) {
+        self.0 ^= (byte as u16) << 8;
+        for _ in 0..8 {
+            if self.0 & 0x8000 != 0 {
+                self.0 = (self.0 << 1) ^ 0x1021;
+            } else {
+                self.0 <<= 1;
+            }
+        }
+    }
+
+    pub const fn get(&self) -> u16 {
+        self.0
+    }
+
+    /// Running the CRC over data followed by its own big endian CRC yields zero.
+    pub const fn is_residue_ok(&self) -> bool {
+        self.0 == 0
+    }
+}
+
+impl Default for TransferCrc {
+    fn default() -> Self {
+        TransferCrc::new()
+    }
+}
+
+pub fn crc16(bytes: &[u8]) -> u16 {
+    let mut crc = TransferCrc::new();
+    crc.add(bytes);
+    crc.get()
+}
+
+/// CRC-64-WE, as in the software_image_crc field of uavcan.node.GetInfo.1.0.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct ImageCrc(u64);
+
+impl ImageCrc {
+    pub const fn new() -> Self {
+        ImageCrc(0xFFFF_FFFF_FFFF_FFFF)
+    }
+
+    pub fn add(&mut self, bytes: &[u8]) {
+        for b in bytes {
+            self.0 ^= (*b as u64) << 56;
+            for _ in 0..8 {
+                if self.0 & 0x8000_0000_0000_0000 != 0 {
+                    self.0 = (self.0 << 1) ^ 0x42F0_E1EB_A9EA_3693;
+                } else {
+                    self.0 <<= 1;
+                }
+            }
+        }
+    }
+
+    pub const fn get(&self) -> u64 {
+        !self.0
+    }
+}
+
+impl Default for ImageCrc {
+    fn default() -> Self {
+        ImageCrc::new()
+    }
+}
+
+pub fn crc64we(bytes: &[u8]) -> u64 {
+    let mut crc = ImageCrc::new();
+    crc.add(bytes);
+    crc.get()
+}
@@ -0,0 +1,78 @@
+//! uavcan.diagnostic.Record.1.1, text log messages.
+
+use super::register::{Error, Reader, Writer};
+
+pub const SUBJECT_ID: u16 = 8184;
+/// Longest text allowed by the standard
+pub const MAX_TEXT_LEN: usize = 255;
+/// Timestamp and severity before the text
+pub const HEADER_SIZE: usize = 7 + 1 + 1;
+pub const MAX_SIZE: usize = HEADER_SIZE + MAX_TEXT_LEN;
+const TIMESTAMP_MASK: u64 = (1 << 56) - 1;
+
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Debug)]
+pub enum Severity {
+    Trace = 0,
+    Debug = 1,
+    Info = 2,
+    Notice = 3,
+    Warning = 4,
+    Error = 5,
+    Critical = 6,
+    Alert = 7,
+}
+
+impl Severity {
+    pub fn from_u8(value: u8) -> Option<Severity> {
+        Some(match value {
+            0 => Severity::Trace,
+            1 => Severity::Debug,
+            2 => Severity::Info,
+            3 => Severity::Notice,
+            4 => Severity::Warning,
+            5 => Severity::Error,
+            6 => Severity::Critical,
+            7 => Severity::Alert,
+            _ => return None,
+        })
+    }
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct Record<'a> {
+    /// Microseconds, since boot of the sending node instead of synchronized network time
+    pub timestamp_us: u64,
+    pub severity: Severity,
+    /// Truncated to [MAX_TEXT_LEN] bytes, possibly in the middle of a character
+    pub text: &'a [u8],
+}
+
+impl<'a> Record<'a> {
+    pub fn decode(payload: &'a [u8]) -> Result<Self, Error> {
+        let mut r = Reader::new(payload);
+        let mut timestamp = [0u8; 8];
+        timestamp[..7].copy_from_slice(r.bytes(7)?);
+        let severity = Severity::from_u8(r.u8()? & 0b111).ok_or(Error::Unsupported)?;
+        let len = r.u8()? as usize;
+        Ok(Record {
+            timestamp_us: u64::from_le_bytes(timestamp),
+            severity,
+            text: r.bytes(len)?,
+        })
+    }
+
+    pub fn encode(&self, buf: &mut [u8]) -> Result<usize, Error> {
+        let mut w = Writer::new(buf);
+        w.bytes(&(self.timestamp_us & TIMESTAMP_MASK).to_le_bytes()[..7])?;
+        w.u8(self.severity as u8)?;
+        let text = &self.text[..core::cmp::min(self.text.len(), MAX_TEXT_LEN)];
+        w.u8(text.len() as u8)?;
+        w.bytes(text)?;
+        Ok(w.position())
+    }
+
+    /// Size of the encoded record starting at `header`, which has to be at least [HEADER_SIZE] long.
+    pub fn encoded_len(header: &[u8]) -> usize {
+        HEADER_SIZE + header[HEADER_SIZE - 1] as usize
+    }
+}
@@ -0,0 +1,51 @@
+//! uavcan.node.ExecuteCommand.1.1 serialization.
+
+use super::register::{Error, Reader};
+
+pub const SERVICE_ID: u16 = 435;
+
+pub const COMMAND_RESTART: u16 = 65535;
+pub const COMMAND_POWER_OFF: u16 = 65534;
+pub const COMMAND_BEGIN_SOFTWARE_UPDATE: u16 = 65533;
+pub const COMMAND_FACTORY_RESET: u16 = 65532;
+pub const COMMAND_EMERGENCY_STOP: u16 = 65531;
+pub const COMMAND_STORE_PERSISTENT_STATES: u16 = 65530;
+
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum Status {
+    Success = 0,
+    Failure = 1,
+    NotAuthorized = 2,
+    BadCommand = 3,
+    BadParameter = 4,
+    BadState = 5,
+    InternalError = 6,
+}
+
+pub struct Request<'a> {
+    pub command: u16,
+    /// Expected image size, CRC and file path for BEGIN_SOFTWARE_UPDATE, see
+    /// `boot::ImageDescriptor::split_update_parameter`, vendor specific otherwise
+    pub parameter: &'a [u8],
+}
+
+impl<'a> Request<'a> {
+    pub fn decode(payload: &'a [u8]) -> Result<Self, Error> {
+        let mut r = Reader::new(payload);
+        let command = r.u16()?;
+        let len = r.u8()? as usize;
+        let parameter = r.bytes(len)?;
+        Ok(Request { command, parameter })
+    }
+}
+
+pub fn encode_response(status: Status, buf: &mut [u8]) -> Result<usize, Error> {
+    match buf.first_mut() {
+        Some(b) => {
+            *b = status as u8;
+            Ok(1)
+        }
+        None => Err(Error::BufferTooSmall),
+    }
+}
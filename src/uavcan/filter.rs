@@ -0,0 +1,194 @@
+//! Acceptance filters generated from subscription tables.
+//!
+//! Every subscription becomes an extended ID filter with a mask, filters closest to each other
+//! are then merged until they fit the hardware. Controllers let through a superset of what the
+//! router accepts, instead of everything.
+
+use heapless::Vec;
+use uavcan_llr::types::NodeId;
+use super::router::{Subscription, Source, Port};
+
+// UAVCAN/CAN ID layout
+const SERVICE_NOT_MESSAGE: u32 = 1 << 25;
+const SUBJECT_ID_SHIFT: u32 = 8;
+const SUBJECT_ID_MASK: u32 = 0x1FFF;
+const SERVICE_ID_SHIFT: u32 = 14;
+const SERVICE_ID_MASK: u32 = 0x1FF;
+const DESTINATION_SHIFT: u32 = 7;
+const NODE_ID_MASK: u32 = 0x7F;
+const EXTENDED_ID_MASK: u32 = 0x1FFF_FFFF;
+
+/// Room for all subscriptions before merging, more than that are merged on the go
+pub const MAX_FILTERS: usize = 32;
+
+/// Extended ID filter, frame is accepted if `frame_id & mask == id & mask`.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct Filter {
+    pub id: u32,
+    pub mask: u32,
+}
+
+impl Filter {
+    pub const ACCEPT_ALL: Filter = Filter { id: 0, mask: 0 };
+
+    pub const fn new(id: u32, mask: u32) -> Self {
+        let mask = mask & EXTENDED_ID_MASK;
+        Filter { id: id & mask, mask }
+    }
+
+    /// Everything sent by a node, messages and services.
+    pub const fn from_node(node_id: u8) -> Self {
+        Filter::new(node_id as u32, NODE_ID_MASK)
+    }
+
+    /// All messages, anonymous ones included.
+    pub const fn messages() -> Self {
+        Filter::new(0, SERVICE_NOT_MESSAGE)
+    }
+
+    /// Requests and responses destined to a node.
+    pub const fn services_to(node_id: u8) -> Self {
+        Filter::new(
+            SERVICE_NOT_MESSAGE | (node_id as u32) << DESTINATION_SHIFT,
+            SERVICE_NOT_MESSAGE | NODE_ID_MASK << DESTINATION_SHIFT,
+        )
+    }
+
+    pub fn matches(&self, id: u32) -> bool {
+        id & self.mask == self.id
+    }
+
+    /// Whether everything accepted by `other` is accepted by this one.
+    pub fn covers(&self, other: &Filter) -> bool {
+        self.mask & other.mask == self.mask && other.id & self.mask == self.id
+    }
+
+    /// Smallest filter accepting everything both of them do.
+    pub fn merge(&self, other: &Filter) -> Filter {
+        Filter::new(self.id, self.mask & other.mask & !(self.id ^ other.id))
+    }
+
+    /// Number of bits compared, more is better
+    pub fn selectivity(&self) -> u32 {
+        self.mask.count_ones()
+    }
+
+    /// None for services while the node has no ID, they are dropped by the router anyway.
+    pub fn from_subscription(subscription: &Subscription, local_node_id: Option<NodeId>) -> Option<Filter> {
+        let (source_id, source_mask) = match subscription.source {
+            Source::Any => (0, 0),
+            Source::Node(node_id) => (node_id.inner() as u32, NODE_ID_MASK),
+        };
+        match subscription.port {
+            Port::Subject(subject_id) => Some(Filter::new(
+                (subject_id.inner() as u32) << SUBJECT_ID_SHIFT | source_id,
+                SERVICE_NOT_MESSAGE | SUBJECT_ID_MASK << SUBJECT_ID_SHIFT | source_mask,
+            )),
+            Port::Service(service_id) => local_node_id.map(|local| Filter::new(
+                SERVICE_NOT_MESSAGE
+                    | (service_id.inner() as u32) << SERVICE_ID_SHIFT
+                    | (local.inner() as u32) << DESTINATION_SHIFT
+                    | source_id,
+                SERVICE_NOT_MESSAGE
+                    | SERVICE_ID_MASK << SERVICE_ID_SHIFT
+                    | NODE_ID_MASK << DESTINATION_SHIFT
+                    | source_mask,
+            )),
+            Port::RawExtended { id, mask } => Some(Filter::new(id, mask)),
+        }
+    }
+}
+
+/// Filters for all subscriptions in `tables`, merged down to at most `N`.
+pub fn generate<const N: usize>(tables: &[&[Subscription]], local_node_id: Option<NodeId>) -> Vec<Filter, N> {
+    let mut filters: Vec<Filter, MAX_FILTERS> = Vec::new();
+    let subscriptions = tables.iter().flat_map(|t| t.iter());
+    for filter in subscriptions.filter_map(|s| Filter::from_subscription(s, local_node_id)) {
+        if filters.iter().any(|f| f.covers(&filter)) {
+            continue;
+        }
+        filters.retain(|f| !filter.covers(f));
+        if filters.is_full() {
+            merge_closest(&mut filters);
+        }
+        let _ = filters.push(filter);
+    }
+    while filters.len() > N {
+        merge_closest(&mut filters);
+    }
+    filters.iter().copied().collect()
+}
+
+/// Replace the two filters that lose the least bits when merged with their merge.
+pub fn merge_closest<const M: usize>(filters: &mut Vec<Filter, M>) {
+    let mut best: Option<(usize, usize, Filter)> = None;
+    for i in 0..filters.len() {
+        for j in i + 1..filters.len() {
+            let merged = filters[i].merge(&filters[j]);
+            if best.map(|(_, _, b)| merged.selectivity() > b.selectivity()).unwrap_or(true) {
+                best = Some((i, j, merged));
+            }
+        }
+    }
+    if let Some((i, j, merged)) = best {
+        filters[i] = merged;
+        filters.swap_remove(j);
+        // Merged filter might now cover others
+        filters.retain(|f| *f == merged || !merged.covers(f));
+    }
+}
+
+/// Filters of hardware with two masks shared by a group of 2 and a group of 4 filters
+/// (MCP2515 family, receive buffer 0 and 1).
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct TwoMaskFilters {
+    pub mask0: u32,
+    pub ids0: [u32; 2],
+    pub mask1: u32,
+    pub ids1: [u32; 4],
+}
+
+impl TwoMaskFilters {
+    /// Split filters between the groups so that shared masks lose the least bits, unused filter
+    /// slots repeat used ones. Pass at most 6 filters, see [generate].
+    pub fn new(filters: &[Filter]) -> Self {
+        let filters = &filters[..filters.len().min(6)];
+        let n = filters.len();
+        if n < 2 {
+            let f = filters.first().copied().unwrap_or(Filter::ACCEPT_ALL);
+            return TwoMaskFilters { mask0: f.mask, ids0: [f.id; 2], mask1: f.mask, ids1: [f.id; 4] };
+        }
+        let group_mask = |select: u32, in_group0: bool| {
+            filters.iter().enumerate()
+                .filter(|(i, _)| (select & (1 << i) != 0) == in_group0)
+                .fold(EXTENDED_ID_MASK, |mask, (_, f)| mask & f.mask)
+        };
+        let mut best: Option<(u32, u32)> = None;
+        for select in 1u32..(1 << n) {
+            let in_group0 = select.count_ones() as usize;
+            if in_group0 > 2 || n - in_group0 > 4 || n - in_group0 == 0 {
+                continue;
+            }
+            let masks = [group_mask(select, true), group_mask(select, false)];
+            let score: u32 = (0..n).map(|i| masks[(select & (1 << i) == 0) as usize].count_ones()).sum();
+            if best.map(|(_, s)| score > s).unwrap_or(true) {
+                best = Some((select, score));
+            }
+        }
+        let select = best.map(|(s, _)| s).unwrap_or(1);
+        let (mask0, mask1) = (group_mask(select, true), group_mask(select, false));
+        let mut ids0 = [0u32; 2];
+        let mut ids1 = [0u32; 4];
+        let group0 = filters.iter().enumerate().filter(|(i, _)| select & (1 << i) != 0).map(|(_, f)| f.id & mask0);
+        let group1 = filters.iter().enumerate().filter(|(i, _)| select & (1 << i) == 0).map(|(_, f)| f.id & mask1);
+        fill_cycled(&mut ids0, group0);
+        fill_cycled(&mut ids1, group1);
+        TwoMaskFilters { mask0, ids0, mask1, ids1 }
+    }
+}
+
+fn fill_cycled<I: Iterator<Item = u32> + Clone>(slots: &mut [u32], ids: I) {
+    for (slot, id) in slots.iter_mut().zip(ids.cycle()) {
+        *slot = id;
+    }
+}
@@ -0,0 +1,71 @@
+//! uavcan.node.GetInfo.1.0 response serialization, request is empty.
+
+use super::register::{Error, Writer};
+
+pub const SERVICE_ID: u16 = 430;
+
+/// Longest name allowed by the standard
+pub const MAX_NAME_LEN: usize = 50;
+/// Build description in place of the certificate of authenticity, which is not used otherwise.
+/// Kept short so that the response fits into the TX queue with room to spare.
+pub const MAX_BUILD_INFO_LEN: usize = 96;
+/// Last byte of a build description that was cut
+pub const TRUNCATED: u8 = b'~';
+/// Largest response this encoder produces: versions, revision, unique id, name, image CRC and
+/// build description.
+pub const MAX_RESPONSE_SIZE: usize = 2 * 3 + 8 + 16 + 1 + MAX_NAME_LEN + 1 + 8 + 1 + MAX_BUILD_INFO_LEN;
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct Version {
+    pub major: u8,
+    pub minor: u8,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct NodeInfo<'a> {
+    pub hardware_version: Version,
+    pub software_version: Version,
+    pub software_vcs_revision_id: u64,
+    pub unique_id: [u8; 16],
+    /// Reverse domain notation, truncated to [MAX_NAME_LEN]
+    pub name: &'a str,
+    /// CRC-64-WE of the image
+    pub software_image_crc: Option<u64>,
+    /// Joined with spaces into the certificate of authenticity field: chip, CAN driver, build
+    /// timestamp and features in this firmware. Cut to [MAX_BUILD_INFO_LEN], ending with
+    /// [TRUNCATED] then.
+    pub build_info: &'a [&'a str],
+}
+
+impl<'a> NodeInfo<'a> {
+    pub fn encode(&self, buf: &mut [u8]) -> Result<usize, Error> {
+        let mut w = Writer::new(buf);
+        w.bytes(&[1, 0])?; // protocol version
+        w.bytes(&[self.hardware_version.major, self.hardware_version.minor])?;
+        w.bytes(&[self.software_version.major, self.software_version.minor])?;
+        w.bytes(&self.software_vcs_revision_id.to_le_bytes())?;
+        w.bytes(&self.unique_id)?;
+        let name = &self.name.as_bytes()[..core::cmp::min(self.name.len(), MAX_NAME_LEN)];
+        w.u8(name.len() as u8)?;
+        w.bytes(name)?;
+        match self.software_image_crc {
+            Some(crc) => {
+                w.u8(1)?;
+                w.bytes(&crc.to_le_bytes())?;
+            }
+            None => w.u8(0)?,
+        }
+        // certificate_of_authenticity
+        let separators = self.build_info.len().saturating_sub(1);
+        let full_len = self.build_info.iter().map(|s| s.len()).sum::<usize>() + separators;
+        let len = core::cmp::min(full_len, MAX_BUILD_INFO_LEN);
+        w.u8(len as u8)?;
+        let bytes = self.build_info.iter().enumerate()
+            .flat_map(|(i, s)| (if i == 0 { "" } else { " " }).bytes().chain(s.bytes()))
+            .take(len);
+        for (i, b) in bytes.enumerate() {
+            w.u8(if full_len > len && i == len - 1 { TRUNCATED } else { b })?;
+        }
+        Ok(w.position())
+    }
+}
@@ -0,0 +1,34 @@
+//! Vendor specific health report, published next to every heartbeat: the conditions behind the
+//! health in it, see `health` in the firmware.
+//!
+//! health u8 | failures u16 | warnings u16
+//! Every reason code is a bit in one of the masks, little endian.
+
+use super::heartbeat::Health;
+use super::register::{Error, Reader};
+
+pub const SUBJECT_ID: u16 = 26;
+pub const SIZE: usize = 5;
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct HealthReport {
+    pub health: Health,
+    pub failures: u16,
+    pub warnings: u16,
+}
+
+impl HealthReport {
+    pub fn encode(&self) -> [u8; SIZE] {
+        let mut payload = [0u8; SIZE];
+        payload[0] = self.health as u8;
+        payload[1..3].copy_from_slice(&self.failures.to_le_bytes());
+        payload[3..5].copy_from_slice(&self.warnings.to_le_bytes());
+        payload
+    }
+
+    pub fn decode(payload: &[u8]) -> Result<Self, Error> {
+        let mut r = Reader::new(payload);
+        let health = Health::from_u8(r.u8()?).ok_or(Error::Unsupported)?;
+        Ok(HealthReport { health, failures: r.u16()?, warnings: r.u16()? })
+    }
+}
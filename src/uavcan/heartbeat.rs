@@ -0,0 +1,49 @@
+//! uavcan.node.Heartbeat encoding.
+
+/// Published on this subject instead of the fixed one, every node on the bus expects it
+pub const SUBJECT_ID: u16 = 10;
+/// Uptime, health and mode in one byte, a zero byte, vendor specific status code. Standard layout
+/// has mode in the zero byte, these nodes pack it with health, standard parsers then read mode 0.
+pub const SIZE: usize = 7;
+
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum Health {
+    /// not a typo, fully functioning node
+    Norminal = 0b000,
+    /// node can perform it's task, but is experiencing troubles
+    Warning = 0b001,
+    /// node cannot perform it's task
+    Failure = 0b010,
+}
+
+impl Health {
+    pub fn from_u8(value: u8) -> Option<Health> {
+        Some(match value {
+            0b000 => Health::Norminal,
+            0b001 => Health::Warning,
+            0b010 => Health::Failure,
+            _ => return None,
+        })
+    }
+
+    pub fn worst(self, other: Health) -> Health {
+        if (other as u8) > (self as u8) { other } else { self }
+    }
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[allow(dead_code)]
+pub enum Mode {
+    Bootloader = 0b00,
+    Firmware = 0b01,
+}
+
+/// `status` is up to the module, see `module::status` in the firmware.
+pub fn encode(uptime_s: u32, health: Health, mode: Mode, status: u8) -> [u8; SIZE] {
+    let mut payload = [0u8; SIZE];
+    payload[0..=3].copy_from_slice(&uptime_s.to_le_bytes());
+    payload[4] = (health as u8) | ((mode as u8) << 3);
+    payload[6] = status;
+    payload
+}
@@ -0,0 +1,18 @@
+//! UAVCAN transport layer on top of uavcan-llr: receive routing, multi-frame reassembly and
+//! transmit slicing.
+//! Nothing in here touches the hardware or RTIC resources, so it can be reused on the host.
+
+pub mod router;
+pub mod assembler;
+pub mod crc;
+pub mod tx;
+pub mod register;
+pub mod get_info;
+pub mod execute_command;
+pub mod pnp;
+pub mod filter;
+pub mod redundancy;
+pub mod heartbeat;
+pub mod diagnostic;
+pub mod crash_report;
+pub mod health_report;
@@ -0,0 +1,120 @@
+//! uavcan.pnp.NodeIDAllocationData.1.0, plug-and-play node ID allocation on CAN classic.
+//!
+//! Node without an ID publishes anonymous requests carrying a hash of its unique ID, allocator
+//! answers on the same subject with the hash and the allocated ID. Allocatee side is
+//! [Allocatee], allocator side lives in tools/pnp-allocator.
+
+use core::convert::TryFrom;
+use uavcan_llr::types::NodeId;
+use super::register::{Error, Reader, Writer};
+
+pub const SUBJECT_ID: u16 = 8166;
+/// Request: hash and an empty node ID array, fits into a single frame as anonymous transfers must
+pub const REQUEST_SIZE: usize = 6 + 1;
+/// Response: hash and one node ID
+pub const MAX_SIZE: usize = 6 + 1 + 2;
+const UNIQUE_ID_HASH_MASK: u64 = (1 << 48) - 1;
+
+/// 48 bit FNV-1a of the unique ID, any hash works as long as the allocator only compares them.
+pub fn unique_id_hash(unique_id: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for b in unique_id {
+        hash ^= *b as u64;
+        hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+    }
+    hash & UNIQUE_ID_HASH_MASK
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct NodeIdAllocationData {
+    pub unique_id_hash: u64,
+    /// None in requests, allocated ID in responses
+    pub allocated_node_id: Option<NodeId>,
+}
+
+impl NodeIdAllocationData {
+    pub fn decode(payload: &[u8]) -> Result<Self, Error> {
+        let mut r = Reader::new(payload);
+        let mut hash = [0u8; 8];
+        hash[..6].copy_from_slice(r.bytes(6)?);
+        let allocated_node_id = match r.u8()? {
+            0 => None,
+            1 => Some(u8::try_from(r.u16()?).ok().and_then(NodeId::new).ok_or(Error::Unsupported)?),
+            _ => return Err(Error::TooLong),
+        };
+        Ok(NodeIdAllocationData {
+            unique_id_hash: u64::from_le_bytes(hash),
+            allocated_node_id,
+        })
+    }
+
+    pub fn encode(&self, buf: &mut [u8]) -> Result<usize, Error> {
+        let mut w = Writer::new(buf);
+        w.bytes(&(self.unique_id_hash & UNIQUE_ID_HASH_MASK).to_le_bytes()[..6])?;
+        match self.allocated_node_id {
+            Some(id) => {
+                w.u8(1)?;
+                w.u16(id.inner() as u16)?;
+            }
+            None => w.u8(0)?,
+        }
+        Ok(w.position())
+    }
+}
+
+/// Allocatee side: when to send the next request and whether a response is ours.
+pub struct Allocatee {
+    unique_id_hash: u64,
+    rng: u32,
+}
+
+impl Allocatee {
+    /// Requests are sent with a random period up to this, so that nodes started together do not
+    /// keep colliding
+    pub const MAX_REQUEST_PERIOD_MS: u32 = 1000;
+
+    pub fn new(unique_id: &[u8]) -> Self {
+        let unique_id_hash = unique_id_hash(unique_id);
+        Allocatee {
+            unique_id_hash,
+            rng: ((unique_id_hash as u32) ^ ((unique_id_hash >> 32) as u32)) | 1,
+        }
+    }
+
+    pub fn unique_id_hash(&self) -> u64 {
+        self.unique_id_hash
+    }
+
+    /// xorshift32, seeded from the unique ID
+    fn random(&mut self) -> u32 {
+        let mut x = self.rng;
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.rng = x;
+        x
+    }
+
+    /// Delay before the next request.
+    pub fn next_request_delay_ms(&mut self) -> u32 {
+        self.random() % Self::MAX_REQUEST_PERIOD_MS + 1
+    }
+
+    /// Anonymous frames carry a pseudo-random source node ID to avoid CAN ID collisions.
+    pub fn pseudo_source_node_id(&mut self) -> NodeId {
+        NodeId::new((self.random() % 128) as u8).unwrap_or_default()
+    }
+
+    /// Request payload, `buf` has to be at least [REQUEST_SIZE] long.
+    pub fn request(&self, buf: &mut [u8]) -> Result<usize, Error> {
+        NodeIdAllocationData { unique_id_hash: self.unique_id_hash, allocated_node_id: None }.encode(buf)
+    }
+
+    /// Node ID allocated to this node, if the message is a response addressed to it.
+    pub fn accept(&self, payload: &[u8]) -> Option<NodeId> {
+        match NodeIdAllocationData::decode(payload) {
+            Ok(data) if data.unique_id_hash == self.unique_id_hash => data.allocated_node_id,
+            _ => None,
+        }
+    }
+}
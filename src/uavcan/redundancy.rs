@@ -0,0 +1,58 @@
+//! Deduplication of transfers received over redundant interfaces.
+//!
+//! Every transfer arrives once per interface. A session follows the interface it was first heard
+//! on, the same transfers coming over the other one are dropped. If the followed interface stays
+//! silent for the timeout, the session switches to whichever interface delivers next. Comparing
+//! transfer IDs alone would not do, late copies of already handled transfers would pass.
+
+use super::assembler::SESSION_KEY_MASK;
+
+#[derive(Copy, Clone, Debug)]
+struct Session {
+    key: u32,
+    interface: u8,
+    transfer_id: u8,
+    last_ms: u32,
+}
+
+/// Remembers the last `N` sessions, least recently heard one is forgotten first.
+pub struct Deduplicator<const N: usize> {
+    sessions: [Option<Session>; N],
+    timeout_ms: u32,
+}
+
+impl<const N: usize> Deduplicator<N> {
+    pub const fn new(timeout_ms: u32) -> Self {
+        Deduplicator { sessions: [None; N], timeout_ms }
+    }
+
+    /// Whether a complete transfer received on `interface` should be handled. `can_id` is the ID
+    /// of any of its frames, priority is ignored.
+    pub fn accept(&mut self, can_id: u32, transfer_id: u8, interface: u8, now_ms: u32) -> bool {
+        let key = can_id & SESSION_KEY_MASK;
+        let timeout_ms = self.timeout_ms;
+        if let Some(session) = self.sessions.iter_mut().flatten().find(|s| s.key == key) {
+            let timed_out = now_ms.wrapping_sub(session.last_ms) > timeout_ms;
+            if session.interface != interface && !timed_out {
+                return false;
+            }
+            if session.interface == interface && session.transfer_id == transfer_id && !timed_out {
+                // Repeated by the sender, for example after a lost acknowledgement
+                return false;
+            }
+            *session = Session { key, interface, transfer_id, last_ms: now_ms };
+            return true;
+        }
+        let slot = match self.sessions.iter().position(|s| s.is_none()) {
+            Some(free) => Some(free),
+            None => self.sessions.iter()
+                .enumerate()
+                .max_by_key(|(_, s)| s.map(|s| now_ms.wrapping_sub(s.last_ms)).unwrap_or(0))
+                .map(|(i, _)| i),
+        };
+        if let Some(slot) = slot {
+            self.sessions[slot] = Some(Session { key, interface, transfer_id, last_ms: now_ms });
+        }
+        true
+    }
+}
@@ -0,0 +1,318 @@
+//! uavcan.register.Access.1.0 and uavcan.register.List.1.0 serialization.
+//!
+//! Only what registers of this firmware need is supported: empty, string, bit and integer/natural
+//! arrays. Real and unstructured values are rejected on decode.
+
+use heapless::Vec;
+
+pub const ACCESS_SERVICE_ID: u16 = 384;
+pub const LIST_SERVICE_ID: u16 = 385;
+
+/// Longest register name of this firmware. Requests for longer names are decoded all the same and
+/// answered as for any unknown register.
+pub const MAX_NAME_LEN: usize = 48;
+pub const MAX_STRING_LEN: usize = 128;
+pub const MAX_ARRAY_LEN: usize = 4;
+
+const TAG_EMPTY: u8 = 0;
+const TAG_STRING: u8 = 1;
+const TAG_BIT: u8 = 3;
+
+/// Numeric array kinds, discriminant is the union tag in uavcan.register.Value.1.0
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum NumKind {
+    Integer64 = 4,
+    Integer32 = 5,
+    Integer16 = 6,
+    Integer8 = 7,
+    Natural64 = 8,
+    Natural32 = 9,
+    Natural16 = 10,
+    Natural8 = 11,
+}
+
+impl NumKind {
+    fn from_tag(tag: u8) -> Option<Self> {
+        use NumKind::*;
+        Some(match tag {
+            4 => Integer64,
+            5 => Integer32,
+            6 => Integer16,
+            7 => Integer8,
+            8 => Natural64,
+            9 => Natural32,
+            10 => Natural16,
+            11 => Natural8,
+            _ => return None,
+        })
+    }
+
+    fn width(&self) -> usize {
+        use NumKind::*;
+        match self {
+            Integer64 | Natural64 => 8,
+            Integer32 | Natural32 => 4,
+            Integer16 | Natural16 => 2,
+            Integer8 | Natural8 => 1,
+        }
+    }
+
+    /// Arrays of up to 256 elements have 16 bit length prefix, shorter ones 8 bit
+    fn wide_length_prefix(&self) -> bool {
+        matches!(self, NumKind::Integer8 | NumKind::Natural8)
+    }
+
+    fn is_signed(&self) -> bool {
+        (*self as u8) < NumKind::Natural64 as u8
+    }
+}
+
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub enum Value {
+    Empty,
+    String(Vec<u8, MAX_STRING_LEN>),
+    Bit(Vec<bool, MAX_ARRAY_LEN>),
+    /// Both signed and unsigned arrays, unsigned 64 bit values above i64::MAX are not supported
+    Number(NumKind, Vec<i64, MAX_ARRAY_LEN>),
+}
+
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum Error {
+    /// Payload ended prematurely
+    Truncated,
+    /// Name, string or array does not fit into local buffers
+    TooLong,
+    /// Real or unstructured value
+    Unsupported,
+    /// Response does not fit into the provided buffer
+    BufferTooSmall,
+}
+
+impl Value {
+    pub fn string(s: &str) -> Self {
+        let bytes = &s.as_bytes()[..core::cmp::min(s.len(), MAX_STRING_LEN)];
+        Value::String(Vec::from_slice(bytes).unwrap_or_default())
+    }
+
+    pub fn bit(b: bool) -> Self {
+        let mut v = Vec::new();
+        let _ = v.push(b);
+        Value::Bit(v)
+    }
+
+    pub fn number(kind: NumKind, values: &[i64]) -> Self {
+        let len = core::cmp::min(values.len(), MAX_ARRAY_LEN);
+        Value::Number(kind, Vec::from_slice(&values[..len]).unwrap_or_default())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        matches!(self, Value::Empty)
+    }
+
+    /// Numeric values regardless of their kind, bits are converted to 0 and 1
+    pub fn integers(&self) -> Option<Vec<i64, MAX_ARRAY_LEN>> {
+        match self {
+            Value::Number(_, values) => Some(values.clone()),
+            Value::Bit(bits) => Some(bits.iter().map(|b| *b as i64).collect()),
+            _ => None,
+        }
+    }
+
+    pub fn integer(&self) -> Option<i64> {
+        self.integers().and_then(|v| if v.len() == 1 { Some(v[0]) } else { None })
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        self.integer().map(|i| i != 0)
+    }
+
+    pub fn decode(r: &mut Reader) -> Result<Self, Error> {
+        let tag = r.u8()?;
+        match tag {
+            TAG_EMPTY => Ok(Value::Empty),
+            TAG_STRING => {
+                let len = r.u16()? as usize;
+                let bytes = r.bytes(len)?;
+                Ok(Value::String(Vec::from_slice(bytes).map_err(|_| Error::TooLong)?))
+            }
+            TAG_BIT => {
+                let len = r.u16()? as usize;
+                let bytes = r.bytes((len + 7) / 8)?;
+                let mut bits = Vec::new();
+                for i in 0..len {
+                    bits.push(bytes[i / 8] & (1 << (i % 8)) != 0).map_err(|_| Error::TooLong)?;
+                }
+                Ok(Value::Bit(bits))
+            }
+            tag => {
+                let kind = NumKind::from_tag(tag).ok_or(Error::Unsupported)?;
+                let len = if kind.wide_length_prefix() { r.u16()? as usize } else { r.u8()? as usize };
+                let mut values = Vec::new();
+                for _ in 0..len {
+                    let raw = r.bytes(kind.width())?;
+                    let mut le = [0u8; 8];
+                    le[..raw.len()].copy_from_slice(raw);
+                    let mut value = i64::from_le_bytes(le);
+                    if kind.is_signed() && kind.width() < 8 {
+                        let shift = 64 - kind.width() * 8;
+                        value = (value << shift) >> shift;
+                    }
+                    values.push(value).map_err(|_| Error::TooLong)?;
+                }
+                Ok(Value::Number(kind, values))
+            }
+        }
+    }
+
+    pub fn encode(&self, w: &mut Writer) -> Result<(), Error> {
+        match self {
+            Value::Empty => w.u8(TAG_EMPTY),
+            Value::String(s) => {
+                w.u8(TAG_STRING)?;
+                w.u16(s.len() as u16)?;
+                w.bytes(s)
+            }
+            Value::Bit(bits) => {
+                w.u8(TAG_BIT)?;
+                w.u16(bits.len() as u16)?;
+                let mut byte = 0u8;
+                for (i, b) in bits.iter().enumerate() {
+                    byte |= (*b as u8) << (i % 8);
+                    if i % 8 == 7 {
+                        w.u8(byte)?;
+                        byte = 0;
+                    }
+                }
+                if bits.len() % 8 != 0 {
+                    w.u8(byte)?;
+                }
+                Ok(())
+            }
+            Value::Number(kind, values) => {
+                w.u8(*kind as u8)?;
+                if kind.wide_length_prefix() {
+                    w.u16(values.len() as u16)?;
+                } else {
+                    w.u8(values.len() as u8)?;
+                }
+                for v in values {
+                    w.bytes(&v.to_le_bytes()[..kind.width()])?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+pub struct AccessRequest<'a> {
+    pub name: &'a str,
+    pub value: Value,
+}
+
+impl<'a> AccessRequest<'a> {
+    pub fn decode(payload: &'a [u8]) -> Result<Self, Error> {
+        let mut r = Reader::new(payload);
+        let name = decode_name(&mut r)?;
+        let value = Value::decode(&mut r)?;
+        Ok(AccessRequest { name, value })
+    }
+}
+
+/// Timestamp is not known (zero), then mutable and persistent flags and the value
+pub fn encode_access_response(mutable: bool, persistent: bool, value: &Value, buf: &mut [u8]) -> Result<usize, Error> {
+    let mut w = Writer::new(buf);
+    w.bytes(&[0u8; 7])?;
+    w.u8((mutable as u8) | ((persistent as u8) << 1))?;
+    value.encode(&mut w)?;
+    Ok(w.position())
+}
+
+pub fn decode_list_request(payload: &[u8]) -> Result<u16, Error> {
+    Reader::new(payload).u16()
+}
+
+/// Empty name means the index is out of range
+pub fn encode_list_response(name: &str, buf: &mut [u8]) -> Result<usize, Error> {
+    let mut w = Writer::new(buf);
+    w.u8(name.len() as u8)?;
+    w.bytes(name.as_bytes())?;
+    Ok(w.position())
+}
+
+fn decode_name<'a>(r: &mut Reader<'a>) -> Result<&'a str, Error> {
+    let len = r.u8()? as usize;
+    core::str::from_utf8(r.bytes(len)?).map_err(|_| Error::Unsupported)
+}
+
+/// Reading past the end is an error instead of DSDL implicit zero extension, so that a truncated
+/// request is not mistaken for a write.
+pub struct Reader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(buf: &'a [u8]) -> Self {
+        Reader { buf, pos: 0 }
+    }
+
+    pub fn bytes(&mut self, len: usize) -> Result<&'a [u8], Error> {
+        if self.pos + len > self.buf.len() {
+            return Err(Error::Truncated);
+        }
+        let bytes = &self.buf[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    pub fn u8(&mut self) -> Result<u8, Error> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    pub fn u16(&mut self) -> Result<u16, Error> {
+        let b = self.bytes(2)?;
+        Ok(u16::from_le_bytes([b[0], b[1]]))
+    }
+
+    pub fn u32(&mut self) -> Result<u32, Error> {
+        let b = self.bytes(4)?;
+        Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    }
+}
+
+pub struct Writer<'a> {
+    buf: &'a mut [u8],
+    pos: usize,
+}
+
+impl<'a> Writer<'a> {
+    pub fn new(buf: &'a mut [u8]) -> Self {
+        Writer { buf, pos: 0 }
+    }
+
+    pub fn position(&self) -> usize {
+        self.pos
+    }
+
+    pub fn bytes(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        if self.pos + bytes.len() > self.buf.len() {
+            return Err(Error::BufferTooSmall);
+        }
+        self.buf[self.pos..self.pos + bytes.len()].copy_from_slice(bytes);
+        self.pos += bytes.len();
+        Ok(())
+    }
+
+    pub fn u8(&mut self, v: u8) -> Result<(), Error> {
+        self.bytes(&[v])
+    }
+
+    pub fn u16(&mut self, v: u16) -> Result<(), Error> {
+        self.bytes(&v.to_le_bytes())
+    }
+
+    pub fn u32(&mut self, v: u32) -> Result<(), Error> {
+        self.bytes(&v.to_le_bytes())
+    }
+}
@@ -0,0 +1,215 @@
+//! Table driven dispatch of received CAN frames.
+//!
+//! Each module declares what it consumes as a const slice of [Subscription]s, router walks the
+//! tables in order and the first match wins. Adding a subscriber means adding a line to a table,
+//! not another branch in `canbus::can_rx_router`.
+
+use core::convert::TryFrom;
+use uavcan_llr::types::{CanId, TransferKind, TransferId, NodeId, SubjectId, ServiceId, Message, Service};
+use vhrdcan::{Frame, FrameId};
+use super::assembler::{self, Assembler};
+
+/// Which source nodes a subscription accepts transfers from.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum Source {
+    Any,
+    Node(NodeId),
+}
+
+impl Source {
+    /// Source ID of anonymous transfers is a pseudo ID, they only match [Source::Any].
+    pub fn matches(&self, node_id: NodeId, anonymous: bool) -> bool {
+        match self {
+            Source::Any => true,
+            Source::Node(id) => !anonymous && *id == node_id,
+        }
+    }
+}
+
+/// Set in the CAN ID of anonymous messages
+const ANONYMOUS_BIT: u32 = 1 << 24;
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum Port {
+    /// UAVCAN message with a particular subject id
+    Subject(SubjectId),
+    /// UAVCAN service request addressed to this node
+    Service(ServiceId),
+    /// Non UAVCAN frame with extended id matching `id` in `mask` bits (VESC status for example)
+    RawExtended { id: u32, mask: u32 },
+}
+
+/// Where an accepted transfer is delivered to.
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum Endpoint {
+    /// `module::handle_message` or `module::handle_service_request` of the selected module
+    Module,
+    /// Node reboot, handled by the router task itself
+    Reboot,
+    /// VESC status frames, stored into `vesc_feedback`
+    VescFeedback,
+    /// Duty / rpm targets, stored into `vesc_control_input`
+    VescControl,
+    /// uavcan.register.Access, answered by the router task from `registers`
+    RegisterAccess,
+    /// uavcan.register.List
+    RegisterList,
+    /// uavcan.node.GetInfo, answered from `node_info`
+    GetInfo,
+    /// uavcan.node.ExecuteCommand, restart, software update and factory reset
+    ExecuteCommand,
+    /// Report of the last reset, see `crash`
+    CrashReport,
+    /// uavcan.pnp.NodeIDAllocationData responses, while this node has no ID
+    PnpAllocation,
+    /// Never routed, only lets frames forwarded by the bridge through hardware filters, see
+    /// `can_interfaces`
+    Bridge,
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct Subscription {
+    pub source: Source,
+    pub port: Port,
+    pub endpoint: Endpoint,
+}
+
+impl Subscription {
+    pub const fn subject(source: Source, subject_id: SubjectId, endpoint: Endpoint) -> Self {
+        Subscription { source, port: Port::Subject(subject_id), endpoint }
+    }
+
+    pub const fn service(source: Source, service_id: ServiceId, endpoint: Endpoint) -> Self {
+        Subscription { source, port: Port::Service(service_id), endpoint }
+    }
+
+    pub const fn raw_extended(id: u32, endpoint: Endpoint) -> Self {
+        Subscription::raw_extended_masked(id, 0x1FFF_FFFF, endpoint)
+    }
+
+    pub const fn raw_extended_masked(id: u32, mask: u32, endpoint: Endpoint) -> Self {
+        Subscription { source: Source::Any, port: Port::RawExtended { id, mask }, endpoint }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub enum Dispatch<'a> {
+    Message {
+        endpoint: Endpoint,
+        source: NodeId,
+        message: Message,
+        payload: &'a [u8],
+    },
+    Service {
+        endpoint: Endpoint,
+        source: NodeId,
+        service: Service,
+        payload: &'a [u8],
+    },
+    Raw {
+        endpoint: Endpoint,
+        id: u32,
+        payload: &'a [u8],
+    }
+}
+
+impl<'a> Dispatch<'a> {
+    pub fn payload(&self) -> &'a [u8] {
+        match self {
+            Dispatch::Message { payload, .. } => payload,
+            Dispatch::Service { payload, .. } => payload,
+            Dispatch::Raw { payload, .. } => payload,
+        }
+    }
+
+    /// Same dispatch with payload substituted, used to hand over reassembled transfers.
+    pub fn with_payload<'b>(self, payload: &'b [u8]) -> Dispatch<'b> {
+        match self {
+            Dispatch::Message { endpoint, source, message, .. } => Dispatch::Message { endpoint, source, message, payload },
+            Dispatch::Service { endpoint, source, service, .. } => Dispatch::Service { endpoint, source, service, payload },
+            Dispatch::Raw { endpoint, id, .. } => Dispatch::Raw { endpoint, id, payload },
+        }
+    }
+}
+
+/// Find who is interested in the frame. `local_node_id` is used to drop service transfers
+/// addressed to other nodes, all of them are dropped until the node has an ID.
+/// Payload is handed out as is, with the tail byte for UAVCAN frames.
+pub fn route<'a, const MTU: usize>(
+    tables: &[&[Subscription]],
+    local_node_id: Option<NodeId>,
+    frame: &'a Frame<MTU>
+) -> Option<Dispatch<'a>> {
+    let payload = frame.data();
+    if let FrameId::Extended(eid) = frame.id {
+        let raw = find(tables, |s| match s.port {
+            Port::RawExtended { id, mask } => eid.inner() & mask == id & mask,
+            _ => false,
+        });
+        if let Some(s) = raw {
+            return Some(Dispatch::Raw { endpoint: s.endpoint, id: eid.inner(), payload });
+        }
+    }
+    let uavcan_id = match CanId::try_from(frame.id) {
+        Ok(id) => id,
+        Err(_) => {
+            return None;
+        }
+    };
+    let source = uavcan_id.source_node_id;
+    match uavcan_id.transfer_kind {
+        TransferKind::Message(message) => {
+            let anonymous = matches!(frame.id, FrameId::Extended(eid) if eid.inner() & ANONYMOUS_BIT != 0);
+            find(tables, |s| s.port == Port::Subject(message.subject_id) && s.source.matches(source, anonymous))
+                .map(|s| Dispatch::Message { endpoint: s.endpoint, source, message, payload })
+        }
+        TransferKind::Service(service) => {
+            if Some(service.destination_node_id) != local_node_id {
+                return None;
+            }
+            find(tables, |s| s.port == Port::Service(service.service_id) && s.source.matches(source, false))
+                .map(|s| Dispatch::Service { endpoint: s.endpoint, source, service, payload })
+        }
+    }
+}
+
+/// [route] a frame and reassemble the transfer it belongs to. Raw frames are handed out right away,
+/// transfers once complete and if `accept`, called with the CAN ID and transfer ID, lets them
+/// through. None for frames nobody subscribed to and incomplete transfers.
+pub fn receive<'a, const MTU: usize, const SESSIONS: usize, const MAX_SIZE: usize, F: FnOnce(u32, u8) -> bool>(
+    tables: &[&[Subscription]],
+    local_node_id: Option<NodeId>,
+    frame: &'a Frame<MTU>,
+    assembler: &'a mut Assembler<SESSIONS, MAX_SIZE>,
+    now_ms: u32,
+    accept: F,
+) -> Result<Option<(Dispatch<'a>, TransferId)>, assembler::Error> {
+    let dispatch = match route(tables, local_node_id, frame) {
+        Some(dispatch) => dispatch,
+        None => {
+            return Ok(None);
+        }
+    };
+    let eid = match (dispatch, frame.id) {
+        // Raw frames carry no transfer ID
+        (Dispatch::Raw { .. }, _) => {
+            return Ok(Some((dispatch, TransferId::default())));
+        }
+        (_, FrameId::Extended(eid)) => eid.inner(),
+        (_, FrameId::Standard(_)) => {
+            return Ok(None);
+        }
+    };
+    match assembler.push(eid, dispatch.payload(), now_ms)? {
+        Some(transfer) if accept(eid, transfer.transfer_id) => {
+            let transfer_id = TransferId::new(transfer.transfer_id).unwrap_or_default();
+            Ok(Some((dispatch.with_payload(transfer.payload), transfer_id)))
+        }
+        _ => Ok(None),
+    }
+}
+
+fn find<F: Fn(&Subscription) -> bool>(tables: &[&[Subscription]], f: F) -> Option<Subscription> {
+    tables.iter().flat_map(|t| t.iter()).find(|s| f(s)).copied()
+}
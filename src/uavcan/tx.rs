@@ -0,0 +1,381 @@
+//! Multi-frame transmit: transfer slicing, TX queue that keeps frames of one transfer in order and
+//! scheduling of the queue into controller TX mailboxes.
+
+use core::cmp::Ordering;
+use heapless::binary_heap::{BinaryHeap, Min};
+use vhrdcan::{Frame, FrameId};
+use uavcan_llr::types::TransferId;
+use super::assembler::TailByte;
+use super::crc::TransferCrc;
+
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum TxError {
+    /// Not enough room in the TX queue for the whole transfer, nothing was enqueued
+    QueueFull,
+}
+
+/// Number of frames needed to send `payload_len` bytes, transfer CRC included for multi-frame.
+pub const fn frame_count(payload_len: usize, mtu: usize) -> usize {
+    if payload_len < mtu {
+        1
+    } else {
+        let per_frame = mtu - 1;
+        (payload_len + 2 + per_frame - 1) / per_frame
+    }
+}
+
+/// Smallest valid CAN FD frame length that fits `len` bytes, same as `len` for classic frames.
+pub const fn padded_frame_len(len: usize) -> usize {
+    match len {
+        0..=8 => len,
+        9..=12 => 12,
+        13..=16 => 16,
+        17..=20 => 20,
+        21..=24 => 24,
+        25..=32 => 32,
+        33..=48 => 48,
+        _ => 64,
+    }
+}
+
+/// Splits a payload into frames of at most MTU bytes each with proper tail bytes.
+/// Multi-frame transfers get the CRC appended after the payload. With CAN FD MTU the last frame
+/// is padded with zeros up to a valid length, padding goes before the CRC and is covered by it.
+pub struct TransferSlicer<'a, const MTU: usize> {
+    id: FrameId,
+    payload: &'a [u8],
+    /// Payload and padding
+    padded_len: usize,
+    crc: [u8; 2],
+    offset: usize,
+    total: usize,
+    toggle: bool,
+    transfer_id: u8,
+}
+
+impl<'a, const MTU: usize> TransferSlicer<'a, MTU> {
+    pub fn new(id: FrameId, payload: &'a [u8], transfer_id: u8) -> Self {
+        let is_single = payload.len() < MTU;
+        let (padded_len, crc, total) = if is_single {
+            let padded_len = padded_frame_len(payload.len() + 1) - 1;
+            (padded_len, [0, 0], padded_len)
+        } else {
+            // Frames before the last one are full, CRC never straddles into a padded frame
+            let per_frame = MTU - 1;
+            let last = (payload.len() + 2) % per_frame;
+            let padding = if last == 0 { 0 } else { padded_frame_len(last + 1) - (last + 1) };
+            let mut crc = TransferCrc::new();
+            crc.add(payload);
+            for _ in 0..padding {
+                crc.add(&[0]);
+            }
+            (payload.len() + padding, crc.get().to_be_bytes(), payload.len() + padding + 2)
+        };
+        TransferSlicer {
+            id,
+            payload,
+            padded_len,
+            crc,
+            offset: 0,
+            total,
+            toggle: true,
+            transfer_id,
+        }
+    }
+
+    pub fn frame_count(&self) -> usize {
+        frame_count(self.payload.len(), MTU)
+    }
+}
+
+impl<'a, const MTU: usize> Iterator for TransferSlicer<'a, MTU> {
+    type Item = Frame<MTU>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let is_first = self.offset == 0;
+        if !is_first && self.offset >= self.total {
+            return None;
+        }
+        let chunk_len = core::cmp::min(self.total - self.offset, MTU - 1);
+        let mut buf = [0u8; MTU];
+        for (i, b) in buf[..chunk_len].iter_mut().enumerate() {
+            let pos = self.offset + i;
+            *b = if pos < self.payload.len() {
+                self.payload[pos]
+            } else if pos < self.padded_len {
+                0
+            } else {
+                self.crc[pos - self.padded_len]
+            };
+        }
+        self.offset += chunk_len;
+        let is_last = self.offset >= self.total;
+        buf[chunk_len] = TailByte::new(is_first, is_last, self.toggle, self.transfer_id).0;
+        self.toggle = !self.toggle;
+        if is_last && chunk_len == 0 {
+            // Empty single frame transfer, make sure iteration stops
+            self.offset = 1;
+            self.total = 0;
+        }
+        Frame::new(self.id, &buf[..=chunk_len])
+    }
+}
+
+/// Frame with enqueue sequence number. Frames with the same ID (one transfer or one session)
+/// are popped in the same order they were pushed, heap alone does not guarantee that.
+#[derive(Copy, Clone, Debug)]
+pub struct TxFrame<const MTU: usize> {
+    pub frame: Frame<MTU>,
+    seq: u16,
+}
+
+impl<const MTU: usize> PartialEq for TxFrame<MTU> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+impl<const MTU: usize> Eq for TxFrame<MTU> {}
+
+impl<const MTU: usize> PartialOrd for TxFrame<MTU> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<const MTU: usize> Ord for TxFrame<MTU> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.frame.id.cmp(&other.frame.id).then_with(|| {
+            (self.seq.wrapping_sub(other.seq) as i16).cmp(&0)
+        })
+    }
+}
+
+/// Priority queue of outgoing frames, lowest ID first, FIFO among equal IDs.
+pub struct TxQueue<const MTU: usize, const N: usize> {
+    heap: BinaryHeap<TxFrame<MTU>, Min, N>,
+    seq: u16,
+}
+
+impl<const MTU: usize, const N: usize> TxQueue<MTU, N> {
+    pub fn new() -> Self {
+        TxQueue {
+            heap: BinaryHeap::new(),
+            seq: 0,
+        }
+    }
+
+    pub fn push(&mut self, frame: Frame<MTU>) -> Result<(), Frame<MTU>> {
+        let tx_frame = TxFrame { frame, seq: self.seq };
+        match self.heap.push(tx_frame) {
+            Ok(_) => {
+                self.seq = self.seq.wrapping_add(1);
+                Ok(())
+            }
+            Err(tx_frame) => Err(tx_frame.frame)
+        }
+    }
+
+    pub fn peek(&self) -> Option<&Frame<MTU>> {
+        self.heap.peek().map(|f| &f.frame)
+    }
+
+    pub fn pop(&mut self) -> Option<Frame<MTU>> {
+        self.heap.pop().map(|f| f.frame)
+    }
+
+    /// Put back a frame that was taken out for transmission but did not leave, it goes before the
+    /// frames of its session that are still queued.
+    pub fn push_front(&mut self, frame: Frame<MTU>) -> Result<(), Frame<MTU>> {
+        let next_seq = self.seq;
+        let oldest = self.heap.iter()
+            .filter(|f| f.frame.id == frame.id)
+            .map(|f| f.seq)
+            .min_by_key(|seq| seq.wrapping_sub(next_seq) as i16);
+        let seq = oldest.unwrap_or(next_seq).wrapping_sub(1);
+        self.heap.push(TxFrame { frame, seq }).map_err(|f| f.frame)
+    }
+
+    pub fn len(&self) -> usize {
+        self.heap.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.heap.is_empty()
+    }
+
+    pub fn free(&self) -> usize {
+        self.heap.capacity() - self.heap.len()
+    }
+
+    /// Enqueue the whole transfer or nothing at all.
+    pub fn push_transfer(&mut self, id: FrameId, payload: &[u8], transfer_id: TransferId) -> Result<(), TxError> {
+        let slicer = TransferSlicer::<MTU>::new(id, payload, transfer_id.inner());
+        if slicer.frame_count() > self.free() {
+            return Err(TxError::QueueFull);
+        }
+        for frame in slicer {
+            let _ = self.push(frame);
+        }
+        Ok(())
+    }
+}
+
+impl<const MTU: usize, const N: usize> Default for TxQueue<MTU, N> {
+    fn default() -> Self {
+        TxQueue::new()
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+enum Mailbox<const MTU: usize> {
+    Empty,
+    /// Loaded into the controller, waiting for the bus
+    Pending(Frame<MTU>),
+    /// Abort requested, frame might still leave
+    Aborting(Frame<MTU>),
+    /// Did not leave, controller mailbox is empty
+    Returned(Frame<MTU>),
+}
+
+impl<const MTU: usize> Mailbox<MTU> {
+    fn frame(&self) -> Option<&Frame<MTU>> {
+        match self {
+            Mailbox::Empty => None,
+            Mailbox::Pending(f) | Mailbox::Aborting(f) | Mailbox::Returned(f) => Some(f),
+        }
+    }
+}
+
+/// What the driver has to do next, see [TxMailboxes::next]
+#[derive(Copy, Clone, Debug)]
+pub enum MailboxAction<const MTU: usize> {
+    /// Load the frame into the empty controller mailbox and request transmission
+    Load { mailbox: usize, frame: Frame<MTU> },
+    /// Request abort, then report the outcome with [TxMailboxes::completed] or
+    /// [TxMailboxes::returned]
+    Abort { mailbox: usize },
+    /// Nothing to do until a frame leaves or an abort completes
+    Wait,
+}
+
+/// Moves frames from a [TxQueue] into `M` controller TX mailboxes without priority inversion.
+///
+/// A more important frame at the head of the queue takes a free mailbox or has the least
+/// important pending frame aborted, which then goes back into the queue. Only one frame of a
+/// session is in the controller at a time, so that a controller arbitrating between its mailboxes
+/// cannot reorder frames of a transfer.
+pub struct TxMailboxes<const MTU: usize, const M: usize> {
+    mailboxes: [Mailbox<MTU>; M],
+}
+
+impl<const MTU: usize, const M: usize> TxMailboxes<MTU, M> {
+    pub const fn new() -> Self {
+        TxMailboxes { mailboxes: [Mailbox::Empty; M] }
+    }
+
+    /// Frame in the mailbox left or is being transmitted.
+    pub fn is_loaded(&self, mailbox: usize) -> bool {
+        matches!(self.mailboxes[mailbox], Mailbox::Pending(_) | Mailbox::Aborting(_))
+    }
+
+    pub fn is_aborting(&self, mailbox: usize) -> bool {
+        matches!(self.mailboxes[mailbox], Mailbox::Aborting(_))
+    }
+
+    /// Frame in the mailbox was transmitted.
+    pub fn completed(&mut self, mailbox: usize) {
+        self.mailboxes[mailbox] = Mailbox::Empty;
+    }
+
+    /// Frame in the mailbox did not leave: aborted or could not be loaded.
+    pub fn returned(&mut self, mailbox: usize) {
+        if let Mailbox::Pending(f) | Mailbox::Aborting(f) = self.mailboxes[mailbox] {
+            self.mailboxes[mailbox] = Mailbox::Returned(f);
+        }
+    }
+
+    /// Controller was reset, everything not known to have left goes back into the queue. Might
+    /// duplicate a frame, receivers drop it by the toggle bit. Frames that do not fit are lost.
+    pub fn reset<const N: usize>(&mut self, queue: &mut TxQueue<MTU, N>) {
+        for mailbox in self.mailboxes.iter_mut() {
+            if let Some(frame) = mailbox.frame() {
+                let _ = queue.push_front(*frame);
+            }
+            *mailbox = Mailbox::Empty;
+        }
+    }
+
+    /// Call until [MailboxAction::Wait] is returned, with the queue locked for each call only.
+    pub fn next<const N: usize>(&mut self, queue: &mut TxQueue<MTU, N>) -> MailboxAction<MTU> {
+        let head = queue.peek().map(|f| f.id);
+        let returned = self.mailboxes.iter().enumerate()
+            .filter_map(|(i, m)| match m {
+                Mailbox::Returned(f) => Some((i, *f)),
+                _ => None,
+            })
+            .min_by_key(|(_, f)| f.id);
+        // Returned frame was taken out before any queued frame of its session
+        if let Some((mailbox, frame)) = returned {
+            if head.map(|id| frame.id <= id).unwrap_or(true) {
+                self.mailboxes[mailbox] = Mailbox::Pending(frame);
+                return MailboxAction::Load { mailbox, frame };
+            }
+        }
+        let head = match head {
+            Some(id) => id,
+            None => return MailboxAction::Wait,
+        };
+        if self.mailboxes.iter().any(|m| m.frame().map(|f| f.id == head).unwrap_or(false)) {
+            return MailboxAction::Wait;
+        }
+        let free = self.mailboxes.iter().position(|m| matches!(m, Mailbox::Empty));
+        if let Some(mailbox) = free {
+            return self.load_head(mailbox, queue);
+        }
+        if let Some((mailbox, frame)) = returned {
+            // Less important than the head, popping the head first makes room for it
+            let action = self.load_head(mailbox, queue);
+            let _ = queue.push_front(frame);
+            return action;
+        }
+        if self.mailboxes.iter().any(|m| matches!(m, Mailbox::Aborting(_))) {
+            return MailboxAction::Wait;
+        }
+        let least_important = self.mailboxes.iter().enumerate()
+            .filter_map(|(i, m)| m.frame().map(|f| (i, f.id)))
+            .max_by_key(|(_, id)| *id);
+        match least_important {
+            Some((mailbox, id)) if head < id => {
+                if let Mailbox::Pending(f) = self.mailboxes[mailbox] {
+                    self.mailboxes[mailbox] = Mailbox::Aborting(f);
+                }
+                MailboxAction::Abort { mailbox }
+            }
+            _ => MailboxAction::Wait,
+        }
+    }
+
+    fn load_head<const N: usize>(&mut self, mailbox: usize, queue: &mut TxQueue<MTU, N>) -> MailboxAction<MTU> {
+        match queue.pop() {
+            Some(frame) => {
+                self.mailboxes[mailbox] = Mailbox::Pending(frame);
+                MailboxAction::Load { mailbox, frame }
+            }
+            None => MailboxAction::Wait,
+        }
+    }
+}
+
+impl<const MTU: usize, const M: usize> Default for TxMailboxes<MTU, M> {
+    fn default() -> Self {
+        TxMailboxes::new()
+    }
+}
+
+/// Transfer ID to use for the next transfer on the same session.
+pub fn next_transfer_id(transfer_id: &mut TransferId) -> TransferId {
+    let current = *transfer_id;
+    *transfer_id = TransferId::new((current.inner() + 1) & 0b0001_1111).unwrap_or_default();
+    current
+}
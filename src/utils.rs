@@ -1,3 +1,8 @@
+use core::cell::Cell;
+#[cfg(not(feature = "defmt"))]
+use core::fmt;
+use embedded_time::{Clock, Instant};
+
 pub fn clone_into_array<A, T>(slice: &[T]) -> A
     where A: Sized + Default + AsMut<[T]>,
           T: Clone
@@ -5,4 +10,65 @@ pub fn clone_into_array<A, T>(slice: &[T]) -> A
     let mut a = Default::default();
     <A as AsMut<[T]>>::as_mut(&mut a).clone_from_slice(slice);
     a
-}
\ No newline at end of file
+}
+
+/// Timer ticks before the last wrap of the 32 bit counter, plus the counter at the last read
+static TICKS: bare_metal::Mutex<Cell<u64>> = bare_metal::Mutex::new(Cell::new(0));
+
+/// Monotonic timer ticks since boot. Counter is 32 bits wide, wraps are counted here so that time
+/// since boot does not depend on its rate. Has to be called at least once per wrap, which the
+/// heartbeat and the watchdog supervisor alone do.
+pub fn ticks() -> u64 {
+    // Counter is read inside, so that a preempting read can not record a wrap before this one
+    cortex_m::interrupt::free(|cs| {
+        let now: Instant<crate::TimMono> = crate::app::monotonics::TimMono::now();
+        let counter = *now.duration_since_epoch().integer();
+        let last = TICKS.borrow(cs);
+        let mut ticks = (last.get() & !0xFFFF_FFFF) | counter as u64;
+        if ticks < last.get() {
+            ticks += 1 << 32;
+        }
+        last.set(ticks);
+        ticks
+    })
+}
+
+/// Microseconds since boot, does not wrap
+pub fn micros() -> u64 {
+    let tick = <crate::TimMono as Clock>::SCALING_FACTOR;
+    (ticks() as u128 * *tick.numerator() as u128 * 1_000_000 / *tick.denominator() as u128) as u64
+}
+
+/// Milliseconds since boot, wraps around in ~49 days
+pub fn millis() -> u32 {
+    (micros() / 1000) as u32
+}
+
+/// Formatted text up to `N` bytes, the rest is cut, possibly in the middle of a character.
+/// Formatting stops with an error once full.
+#[cfg(not(feature = "defmt"))]
+pub struct TruncatingWriter<const N: usize> {
+    buf: [u8; N],
+    len: usize,
+}
+
+#[cfg(not(feature = "defmt"))]
+impl<const N: usize> TruncatingWriter<N> {
+    pub const fn new() -> Self {
+        TruncatingWriter { buf: [0u8; N], len: 0 }
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+}
+
+#[cfg(not(feature = "defmt"))]
+impl<const N: usize> fmt::Write for TruncatingWriter<N> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let len = core::cmp::min(s.len(), N - self.len);
+        self.buf[self.len..self.len + len].copy_from_slice(&s.as_bytes()[..len]);
+        self.len += len;
+        if len < s.len() { Err(fmt::Error) } else { Ok(()) }
+    }
+}
@@ -0,0 +1,273 @@
+//! VESC control state machines, free of RTIC and hardware so that they also run in the host
+//! simulation. `ramp_vesc` drives them from tasks.
+//!
+//! [VescControl] turns duty and rpm targets into a ramped duty cycle, an rpm target is reached by
+//! searching for the duty giving it. [VescWatchdog] keeps repeating the last duty to the VESC and
+//! releases the motor if targets stop coming.
+
+use uavcan_llr::types::{Message, NodeId};
+use vhrdcan::FrameId;
+use crate::config;
+use crate::ramp_generator2::{self, RampGenerator};
+use crate::uavcan::router::{Endpoint, Source, Subscription};
+
+// VESC CAN commands, upper bits of an extended ID with the VESC ID in the lower 8
+pub const SET_DUTY: u32 = 0;
+pub const SET_CURRENT: u32 = 1;
+pub const SET_CURRENT_BRAKE: u32 = 2;
+pub const CAN_PACKET_STATUS: u32 = 9;
+pub const RESET_ESTOP_TIMEOUT: u32 = 46;
+
+/// Status frames of any VESC
+pub const STATUS_ID: u32 = CAN_PACKET_STATUS << 8;
+pub const STATUS_MASK: u32 = 0x1FFF_FF00;
+
+pub fn frame_id(command: u32, vesc_id: u8) -> FrameId {
+    FrameId::new_extended((command << 8) | vesc_id as u32).unwrap()
+}
+
+/// Status of the VESC and targets from the Pi, routed to `ramp_vesc` with `vesc-ctrl`
+pub const SUBSCRIPTIONS: &[Subscription] = &[
+    Subscription::raw_extended_masked(STATUS_ID, STATUS_MASK, Endpoint::VescFeedback),
+    Subscription::subject(Source::Node(config::PI_NODE_ID), config::RMP_RAMP_TARGET_SUBJECT_ID, Endpoint::VescControl),
+    Subscription::subject(Source::Node(config::PI_NODE_ID), config::DUTY_RAMP_TARGET_SUBJECT_ID, Endpoint::VescControl),
+];
+
+/// Targets from the Pi
+pub fn control_input(source: NodeId, message: Message, payload: &[u8]) -> Option<ControlInput> {
+    if source != config::PI_NODE_ID {
+        None
+    } else if message.subject_id == config::RMP_RAMP_TARGET_SUBJECT_ID {
+        ControlInput::rpm(payload)
+    } else if message.subject_id == config::DUTY_RAMP_TARGET_SUBJECT_ID {
+        ControlInput::duty(payload)
+    } else {
+        None
+    }
+}
+
+/// Below these the motor is not started
+const ERPM_MIN: i32 = 800;
+/// Duty the rpm search ramps towards
+const ERPM_SEARCH_DUTY_MAX: i32 = 25_000;
+
+#[derive(Copy, Clone, Debug)]
+pub enum ControlInput {
+    SetDutyTarget(i32),
+    SetRpmTarget(i32),
+}
+
+impl ControlInput {
+    /// Duty in 1e-5 units, little endian i32
+    pub fn duty(payload: &[u8]) -> Option<Self> {
+        read_i32_le(payload).map(ControlInput::SetDutyTarget)
+    }
+
+    /// Electrical rpm, little endian i32
+    pub fn rpm(payload: &[u8]) -> Option<Self> {
+        read_i32_le(payload).map(ControlInput::SetRpmTarget)
+    }
+}
+
+fn read_i32_le(payload: &[u8]) -> Option<i32> {
+    if payload.len() < 4 {
+        return None;
+    }
+    Some(i32::from_le_bytes([payload[0], payload[1], payload[2], payload[3]]))
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct VescFeedback {
+    erpm: i32,
+    duty_p5: i32,
+}
+
+impl VescFeedback {
+    /// From a CAN_PACKET_STATUS frame of the VESC with `vesc_id`.
+    pub fn new(id: u32, payload: &[u8], vesc_id: u8) -> Option<Self> {
+        if id & 0xFF != vesc_id as u32 || payload.len() != 8 {
+            return None;
+        }
+        Some(VescFeedback {
+            erpm: i32::from_be_bytes([payload[0], payload[1], payload[2], payload[3]]),
+            duty_p5: (i16::from_be_bytes([payload[6], payload[7]]) as i32) * 100,
+        })
+    }
+
+    pub fn erpm(&self) -> i32 {
+        self.erpm
+    }
+
+    pub fn duty_p5(&self) -> i32 {
+        self.duty_p5
+    }
+}
+
+/// Tunables from non-volatile config
+#[derive(Copy, Clone, Debug)]
+pub struct Params {
+    pub duty_min: i32,
+    /// Up and down per second
+    pub ramp_rates: [u32; 2],
+}
+
+#[derive(Debug)]
+enum Mode {
+    Off,
+    Duty,
+    Erpm(i32),
+}
+
+pub struct VescControl {
+    ramp_generator: RampGenerator,
+    mode: Mode,
+    feedback: VescFeedback,
+}
+
+impl VescControl {
+    pub const fn new() -> Self {
+        VescControl {
+            ramp_generator: RampGenerator::new(),
+            mode: Mode::Off,
+            feedback: VescFeedback { erpm: 0, duty_p5: 0 },
+        }
+    }
+
+    /// After the watchdog released the motor, next target starts from the minimum duty again.
+    pub fn stop(&mut self) {
+        self.mode = Mode::Off;
+        log_error!(target: Vesc, "ramp_vesc -> Mode::Off");
+    }
+
+    pub fn on_feedback(&mut self, feedback: VescFeedback) {
+        self.feedback = feedback;
+    }
+
+    /// Duty to hand over to the watchdog, if there is a new one.
+    pub fn on_input(&mut self, input: ControlInput, params: &Params, now_ms: u32) -> Option<i32> {
+        let duty_min = params.duty_min;
+        let ramp = &mut self.ramp_generator;
+        match input {
+            ControlInput::SetDutyTarget(duty_p5) => {
+                match self.mode {
+                    Mode::Off => {
+                        if duty_p5.abs() < duty_min {
+                            return None;
+                        }
+                        self.mode = Mode::Duty;
+                        ramp.set_rates(params.ramp_rates[0], params.ramp_rates[1]);
+                        ramp.set_current(if duty_p5 > 0 { duty_min } else { -duty_min }, now_ms);
+                        ramp.set_target(duty_p5);
+                        None
+                    }
+                    Mode::Duty => {
+                        ramp.set_target(duty_p5);
+                        Some(ramp.get_output(now_ms))
+                    }
+                    Mode::Erpm(_) => None,
+                }
+            }
+            ControlInput::SetRpmTarget(erpm) => {
+                match self.mode {
+                    Mode::Off => {
+                        if erpm.abs() < ERPM_MIN {
+                            return None;
+                        }
+                        self.mode = Mode::Erpm(erpm);
+                        ramp.set_rates(params.ramp_rates[0], params.ramp_rates[1]);
+                        if erpm > 0 {
+                            ramp.set_current(duty_min, now_ms);
+                            ramp.set_target(ERPM_SEARCH_DUTY_MAX);
+                        } else {
+                            ramp.set_current(-duty_min, now_ms);
+                            ramp.set_target(-ERPM_SEARCH_DUTY_MAX);
+                        }
+                        None
+                    }
+                    Mode::Duty => None,
+                    Mode::Erpm(erpm) => {
+                        if ramp.state() == ramp_generator2::State::Hold {
+                            return Some(ramp.get_output(now_ms));
+                        }
+                        let err = erpm - self.feedback.erpm;
+                        log_debug!(target: Vesc, "err: {}", err);
+                        if err.abs() < 1500 {
+                            log_info!(target: Vesc, "Slowing search down");
+                            ramp.set_rates(50, 300);
+                        }
+                        if err.abs() < 200 {
+                            log_info!(target: Vesc, "Duty found!");
+                            ramp.hold_current();
+                        }
+                        Some(ramp.get_output(now_ms))
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Default for VescControl {
+    fn default() -> Self {
+        VescControl::new()
+    }
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum WatchdogAction {
+    None,
+    /// Send SET_DUTY
+    Duty(i32),
+    /// Targets timed out, send zero SET_CURRENT and stop [VescControl]
+    Release,
+}
+
+enum WatchdogMode {
+    Off,
+    On(i32),
+}
+
+pub struct VescWatchdog {
+    mode: WatchdogMode,
+    last_ms: Option<u32>,
+}
+
+impl VescWatchdog {
+    pub const fn new() -> Self {
+        VescWatchdog {
+            mode: WatchdogMode::Off,
+            last_ms: None,
+        }
+    }
+
+    /// Called periodically with a new duty from [VescControl] if there is one.
+    pub fn update(&mut self, input: Option<i32>, now_ms: u32, timeout_ms: u32) -> WatchdogAction {
+        if let Some(duty_p5) = input {
+            self.mode = WatchdogMode::On(duty_p5);
+            self.last_ms = Some(now_ms);
+            return WatchdogAction::Duty(duty_p5);
+        }
+        match (&self.mode, self.last_ms) {
+            (WatchdogMode::Off, _) => WatchdogAction::None,
+            (WatchdogMode::On(duty_p5), Some(last_ms)) => {
+                if now_ms.wrapping_sub(last_ms) > timeout_ms {
+                    self.mode = WatchdogMode::Off;
+                    log_debug!(target: Vesc, "tf_vesc: timeout");
+                    WatchdogAction::Release
+                } else {
+                    WatchdogAction::Duty(*duty_p5)
+                }
+            }
+            (WatchdogMode::On(_), None) => {
+                self.last_ms = Some(now_ms);
+                WatchdogAction::None
+            }
+        }
+    }
+}
+
+impl Default for VescWatchdog {
+    fn default() -> Self {
+        VescWatchdog::new()
+    }
+}
@@ -0,0 +1,124 @@
+//! Plug-and-play node ID allocator, the counterpart of `task::pnp` in the firmware.
+//!
+//! Allocations are kept in a table keyed by unique ID hash, so that a node gets the same ID every
+//! time it asks. Table can be preloaded to pin IDs of well-known nodes and saved to a text file
+//! with one `<hash in hex> <node id>` line per node. New IDs are taken from the top down, skipping
+//! the ones seen as a source of any transfer on the bus.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::convert::TryFrom;
+use std::fs;
+use std::io;
+use std::path::Path;
+use uavcan_llr::types::{CanId, NodeId, SubjectId, TransferId, TransferKind, Priority};
+use vhrdcan::{Frame, FrameId};
+use crate::uavcan::assembler::Assembler;
+use crate::uavcan::pnp::{self, NodeIdAllocationData};
+use crate::uavcan::tx::{self, TransferSlicer};
+
+/// 126 and 127 are reserved for diagnostic and debugging tools
+pub const MAX_ALLOCATED_NODE_ID: u8 = 125;
+
+pub struct Allocator {
+    node_id: NodeId,
+    table: BTreeMap<u64, NodeId>,
+    /// Raw IDs, to not require Ord from NodeId
+    seen: BTreeSet<u8>,
+    assembler: Assembler<16, { pnp::MAX_SIZE + 2 }>,
+    transfer_id: TransferId,
+}
+
+impl Allocator {
+    pub fn new(node_id: NodeId) -> Self {
+        let mut seen = BTreeSet::new();
+        seen.insert(node_id.inner());
+        Allocator {
+            node_id,
+            table: BTreeMap::new(),
+            seen,
+            assembler: Assembler::new(1000),
+            transfer_id: TransferId::default(),
+        }
+    }
+
+    /// Pin an ID for a particular unique ID hash.
+    pub fn reserve(&mut self, unique_id_hash: u64, node_id: NodeId) {
+        self.table.insert(unique_id_hash, node_id);
+    }
+
+    pub fn allocations(&self) -> &BTreeMap<u64, NodeId> {
+        &self.table
+    }
+
+    pub fn load(&mut self, path: &Path) -> io::Result<()> {
+        let invalid = |line: &str| io::Error::new(io::ErrorKind::InvalidData, format!("bad allocation line: {}", line));
+        for line in fs::read_to_string(path)?.lines().map(str::trim).filter(|l| !l.is_empty() && !l.starts_with('#')) {
+            let mut parts = line.split_whitespace();
+            let hash = parts.next().and_then(|h| u64::from_str_radix(h, 16).ok());
+            let node_id = parts.next().and_then(|id| id.parse::<u8>().ok()).and_then(NodeId::new);
+            match (hash, node_id) {
+                (Some(hash), Some(node_id)) => self.reserve(hash, node_id),
+                _ => return Err(invalid(line)),
+            }
+        }
+        Ok(())
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let mut s = String::new();
+        for (hash, node_id) in &self.table {
+            s += &format!("{:012x} {}\n", hash, node_id.inner());
+        }
+        fs::write(path, s)
+    }
+
+    /// Feed every received frame, returns frames of the allocation response if there is one.
+    pub fn on_frame(&mut self, frame: &Frame<8>, now_ms: u32) -> Vec<Frame<8>> {
+        let (eid, id) = match (frame.id, CanId::try_from(frame.id)) {
+            (FrameId::Extended(eid), Ok(id)) => (eid.inner(), id),
+            _ => return Vec::new(),
+        };
+        let is_allocation = matches!(id.transfer_kind, TransferKind::Message(m) if m.subject_id.inner() == pnp::SUBJECT_ID);
+        if !is_allocation {
+            // Anonymous transfers are only allowed on the allocation subject, this one is real
+            self.seen.insert(id.source_node_id.inner());
+            return Vec::new();
+        }
+        let request = match self.assembler.push(eid, frame.data(), now_ms) {
+            Ok(Some(transfer)) => NodeIdAllocationData::decode(transfer.payload),
+            _ => return Vec::new(),
+        };
+        match request {
+            // Responses from other allocators are ignored
+            Ok(NodeIdAllocationData { unique_id_hash, allocated_node_id: None }) => {
+                self.allocate(unique_id_hash).map(|node_id| self.response(unique_id_hash, node_id)).unwrap_or_default()
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    fn allocate(&mut self, unique_id_hash: u64) -> Option<NodeId> {
+        if let Some(node_id) = self.table.get(&unique_id_hash) {
+            return Some(*node_id);
+        }
+        let reserved: BTreeSet<u8> = self.table.values().map(|id| id.inner()).collect();
+        let node_id = (1..=MAX_ALLOCATED_NODE_ID).rev()
+            .find(|id| !reserved.contains(id) && !self.seen.contains(id))
+            .and_then(NodeId::new)?;
+        self.table.insert(unique_id_hash, node_id);
+        Some(node_id)
+    }
+
+    fn response(&mut self, unique_id_hash: u64, node_id: NodeId) -> Vec<Frame<8>> {
+        let mut payload = [0u8; pnp::MAX_SIZE];
+        let data = NodeIdAllocationData { unique_id_hash, allocated_node_id: Some(node_id) };
+        let len = match data.encode(&mut payload) {
+            Ok(len) => len,
+            Err(_) => return Vec::new(),
+        };
+        let subject_id = SubjectId::new(pnp::SUBJECT_ID).unwrap();
+        let id = CanId::new_message_kind(self.node_id, subject_id, false, Priority::Slow);
+        let transfer_id = tx::next_transfer_id(&mut self.transfer_id);
+        TransferSlicer::<8>::new(id.into(), &payload[..len], transfer_id.inner()).collect()
+    }
+}
@@ -0,0 +1,43 @@
+//! Prints text log output that modules built with `log-text-can` publish on a SocketCAN
+//! interface. Records of all nodes are interleaved in the order they arrive.
+//!
+//! Usage: can_log [interface] [node id]
+
+use std::thread;
+use std::time::{Duration, Instant};
+use vhrd_module_tools::log_stream::{format_record, LogCollector, TEXT_LOG_SUBJECT_ID};
+use vhrd_module_tools::socketcan::SocketCanPort;
+use vhrd_module_tools::uavcan::diagnostic::Record;
+
+fn main() {
+    let mut args = std::env::args().skip(1);
+    let interface = args.next().unwrap_or_else(|| "can0".to_string());
+    let only: Option<u8> = args.next().map(|id| id.parse().expect("node id 0..=127"));
+
+    let mut port = SocketCanPort::open(&interface).unwrap_or_else(|e| panic!("{}: {}", interface, e));
+    let mut collector = LogCollector::new(TEXT_LOG_SUBJECT_ID);
+
+    let start = Instant::now();
+    loop {
+        let frame = match port.try_receive() {
+            Ok(Some(frame)) => frame,
+            Ok(None) => {
+                thread::sleep(Duration::from_millis(1));
+                continue;
+            }
+            Err(e) => panic!("{}: {}", interface, e),
+        };
+        let now_ms = start.elapsed().as_millis() as u32;
+        let (node_id, payload) = match collector.on_frame(&frame, now_ms) {
+            Some(log) => log,
+            None => continue,
+        };
+        if matches!(only, Some(id) if id != node_id.inner()) {
+            continue;
+        }
+        match Record::decode(&payload) {
+            Ok(record) => println!("{}", format_record(node_id, &record)),
+            Err(e) => eprintln!("[{:3}] bad record: {:?}", node_id.inner(), e),
+        }
+    }
+}
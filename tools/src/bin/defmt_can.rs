@@ -0,0 +1,58 @@
+//! Prints defmt log output that modules built with `log-defmt-can` publish on a SocketCAN
+//! interface, decoded with the table from the firmware ELF. Every node gets its own decoder, all
+//! of them are expected to run that firmware.
+//!
+//! Usage: defmt_can <firmware ELF> [interface] [node id]
+
+use std::collections::HashMap;
+use std::thread;
+use std::time::{Duration, Instant};
+use defmt_decoder::{DecodeError, StreamDecoder, Table};
+use vhrd_module_tools::log_stream::{LogCollector, LOG_SUBJECT_ID};
+use vhrd_module_tools::socketcan::SocketCanPort;
+
+fn main() {
+    let mut args = std::env::args().skip(1);
+    let elf_path = args.next().expect("firmware ELF");
+    let interface = args.next().unwrap_or_else(|| "can0".to_string());
+    let only: Option<u8> = args.next().map(|id| id.parse().expect("node id 0..=127"));
+
+    let elf = std::fs::read(&elf_path).unwrap_or_else(|e| panic!("{}: {}", elf_path, e));
+    let table = Table::parse(&elf)
+        .unwrap_or_else(|e| panic!("{}: {}", elf_path, e))
+        .unwrap_or_else(|| panic!("{}: no defmt data, was it built with log-defmt-can?", elf_path));
+    let mut port = SocketCanPort::open(&interface).unwrap_or_else(|e| panic!("{}: {}", interface, e));
+    let mut collector = LogCollector::new(LOG_SUBJECT_ID);
+    let mut decoders: HashMap<u8, Box<dyn StreamDecoder + '_>> = HashMap::new();
+
+    let start = Instant::now();
+    loop {
+        let frame = match port.try_receive() {
+            Ok(Some(frame)) => frame,
+            Ok(None) => {
+                thread::sleep(Duration::from_millis(1));
+                continue;
+            }
+            Err(e) => panic!("{}: {}", interface, e),
+        };
+        let now_ms = start.elapsed().as_millis() as u32;
+        let (node_id, chunk) = match collector.on_frame(&frame, now_ms) {
+            Some(log) => log,
+            None => continue,
+        };
+        let node_id = node_id.inner();
+        if matches!(only, Some(id) if id != node_id) {
+            continue;
+        }
+        let decoder = decoders.entry(node_id).or_insert_with(|| table.new_stream_decoder());
+        decoder.received(&chunk);
+        loop {
+            match decoder.decode() {
+                Ok(frame) => println!("[{}] {}", node_id, frame.display(true)),
+                Err(DecodeError::UnexpectedEof) => break,
+                // A transfer was lost, decoding goes on from the next whole frame
+                Err(DecodeError::Malformed) => eprintln!("[{}] malformed frame skipped", node_id),
+            }
+        }
+    }
+}
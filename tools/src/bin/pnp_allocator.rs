@@ -0,0 +1,115 @@
+//! Runs the allocator against a simulated bus with a number of anonymous nodes, each running the
+//! same allocatee code as the firmware.
+//!
+//! Usage: pnp_allocator [node count] [allocation table file]
+//! Table is loaded first if it exists and saved back after all the nodes got their IDs.
+
+use std::convert::TryFrom;
+use std::path::PathBuf;
+use uavcan_llr::types::{CanId, NodeId, SubjectId, TransferId, Priority};
+use vhrdcan::FrameId;
+use vhrd_module_tools::allocator::Allocator;
+use vhrd_module_tools::sim::{SimBus, SimPort};
+use vhrd_module_tools::uavcan::assembler::Assembler;
+use vhrd_module_tools::uavcan::pnp::{self, Allocatee};
+use vhrd_module_tools::uavcan::tx::{self, TransferSlicer};
+
+const ALLOCATOR_NODE_ID: u8 = 127;
+const SIMULATION_TIMEOUT_MS: u32 = 60_000;
+
+struct SimNode {
+    port: SimPort,
+    allocatee: Allocatee,
+    assembler: Assembler<4, { pnp::MAX_SIZE + 2 }>,
+    transfer_id: TransferId,
+    next_request_ms: u32,
+    node_id: Option<NodeId>,
+}
+
+impl SimNode {
+    fn new(port: SimPort, unique_id: [u8; 12]) -> Self {
+        let mut allocatee = Allocatee::new(&unique_id);
+        let next_request_ms = allocatee.next_request_delay_ms();
+        SimNode {
+            port,
+            allocatee,
+            assembler: Assembler::new(1000),
+            transfer_id: TransferId::default(),
+            next_request_ms,
+            node_id: None,
+        }
+    }
+
+    fn poll(&mut self, now_ms: u32) {
+        while let Some(frame) = self.port.receive() {
+            let eid = match frame.id {
+                FrameId::Extended(eid) => eid.inner(),
+                FrameId::Standard(_) => continue,
+            };
+            if CanId::try_from(frame.id).is_err() {
+                continue;
+            }
+            if let Ok(Some(transfer)) = self.assembler.push(eid, frame.data(), now_ms) {
+                if self.node_id.is_none() {
+                    self.node_id = self.allocatee.accept(transfer.payload);
+                }
+            }
+        }
+        if self.node_id.is_some() || now_ms < self.next_request_ms {
+            return;
+        }
+        self.next_request_ms = now_ms + self.allocatee.next_request_delay_ms();
+        let mut payload = [0u8; pnp::REQUEST_SIZE];
+        let len = self.allocatee.request(&mut payload).unwrap();
+        let subject_id = SubjectId::new(pnp::SUBJECT_ID).unwrap();
+        let id = CanId::new_message_kind(self.allocatee.pseudo_source_node_id(), subject_id, true, Priority::Slow);
+        let transfer_id = tx::next_transfer_id(&mut self.transfer_id);
+        for frame in TransferSlicer::<8>::new(id.into(), &payload[..len], transfer_id.inner()) {
+            self.port.send(frame);
+        }
+    }
+}
+
+fn main() {
+    let mut args = std::env::args().skip(1);
+    let node_count: usize = args.next().map(|n| n.parse().expect("node count")).unwrap_or(8);
+    let table: Option<PathBuf> = args.next().map(PathBuf::from);
+
+    let bus = SimBus::new();
+    let mut allocator = Allocator::new(NodeId::new(ALLOCATOR_NODE_ID).unwrap());
+    if let Some(path) = table.as_ref().filter(|p| p.exists()) {
+        allocator.load(path).expect("allocation table");
+    }
+    let mut allocator_port = bus.port();
+    let mut nodes: Vec<SimNode> = (0..node_count)
+        .map(|i| {
+            let mut unique_id = [0u8; 12];
+            unique_id[..8].copy_from_slice(&(i as u64).to_le_bytes());
+            SimNode::new(bus.port(), unique_id)
+        })
+        .collect();
+
+    let mut now_ms = 0;
+    while nodes.iter().any(|n| n.node_id.is_none()) && now_ms < SIMULATION_TIMEOUT_MS {
+        for node in nodes.iter_mut() {
+            node.poll(now_ms);
+        }
+        while let Some(frame) = allocator_port.receive() {
+            for response in allocator.on_frame(&frame, now_ms) {
+                allocator_port.send(response);
+            }
+        }
+        now_ms += 1;
+    }
+
+    for (i, node) in nodes.iter().enumerate() {
+        match node.node_id {
+            Some(id) => println!("node {}: hash {:012x} -> {}", i, node.allocatee.unique_id_hash(), id.inner()),
+            None => println!("node {}: hash {:012x} not allocated", i, node.allocatee.unique_id_hash()),
+        }
+    }
+    println!("{} frames in {} ms", bus.frames_sent(), now_ms);
+    if let Some(path) = table {
+        allocator.save(&path).expect("allocation table");
+    }
+}
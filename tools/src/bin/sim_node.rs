@@ -0,0 +1,36 @@
+//! Runs a simulated module with VESC control on a SocketCAN interface in real time, for trying it
+//! out against the Pi software or other tools without hardware.
+//!
+//! Usage: sim_node [interface] [node id] [--mock-vesc]
+//! With `--mock-vesc` a VESC stand-in is run on the same interface as well.
+
+use std::thread;
+use std::time::{Duration, Instant};
+use uavcan_llr::types::NodeId;
+use vhrd_module_tools::node::{self, MockVesc, Node, NodeConfig, VescNode};
+use vhrd_module_tools::socketcan::SocketCanPort;
+
+fn main() {
+    let mut args = std::env::args().skip(1);
+    let interface = args.next().unwrap_or_else(|| "vcan0".to_string());
+    let node_id = args.next()
+        .map(|id| id.parse::<u8>().ok().and_then(NodeId::new).expect("node id 0..=127"))
+        .unwrap_or_else(|| NodeId::new(20).unwrap());
+    let mock_vesc = args.any(|a| a == "--mock-vesc");
+
+    let open = || SocketCanPort::open(&interface).unwrap_or_else(|e| panic!("{}: {}", interface, e));
+    let config = NodeConfig::new(node_id);
+    let mut module = VescNode::new(open(), config);
+    let mut vesc = if mock_vesc { Some(MockVesc::new(open(), config.vesc_id)) } else { None };
+    println!("Node {} on {}{}", node_id.inner(), interface, if mock_vesc { " with mock VESC" } else { "" });
+
+    let start = Instant::now();
+    loop {
+        let now_ms = start.elapsed().as_millis() as u32;
+        match vesc.as_mut() {
+            Some(vesc) => node::run(&mut [&mut module, vesc], now_ms, now_ms + 1, 1),
+            None => module.poll(now_ms),
+        }
+        thread::sleep(Duration::from_millis(1));
+    }
+}
@@ -0,0 +1,108 @@
+//! Flash region in memory for the non-volatile config store, with power loss injection.
+//!
+//! Clones share the contents, so that a test can keep one while a `Store` owns the other, and
+//! "reboot" by opening a new store on it.
+
+use crate::nv_storage::{Flash, FlashError};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+/// Same as STM32F051
+pub const PAGE_SIZE: usize = 1024;
+
+struct Inner {
+    data: Vec<u8>,
+    /// Half-words that can still be programmed before power is lost, None for no limit
+    write_budget: Option<usize>,
+    erase_count: usize,
+}
+
+#[derive(Clone)]
+pub struct MemFlash {
+    inner: Rc<RefCell<Inner>>,
+}
+
+impl MemFlash {
+    /// Erased region of `pages`, two banks of `pages / 2`
+    pub fn new(pages: usize) -> Self {
+        MemFlash {
+            inner: Rc::new(RefCell::new(Inner {
+                data: vec![0xFF; pages * PAGE_SIZE],
+                write_budget: None,
+                erase_count: 0,
+            })),
+        }
+    }
+
+    /// Power goes away after `half_words` more are programmed, the write in progress is torn
+    /// there and every following write and erase fails.
+    pub fn cut_power_after(&self, half_words: usize) {
+        self.inner.borrow_mut().write_budget = Some(half_words);
+    }
+
+    pub fn restore_power(&self) {
+        self.inner.borrow_mut().write_budget = None;
+    }
+
+    pub fn contents(&self) -> Vec<u8> {
+        self.inner.borrow().data.clone()
+    }
+
+    /// Overwrite bytes regardless of their state, to simulate bit rot or a foreign image
+    pub fn poke(&self, offset: usize, bytes: &[u8]) {
+        self.inner.borrow_mut().data[offset..offset + bytes.len()].copy_from_slice(bytes);
+    }
+
+    pub fn erase_count(&self) -> usize {
+        self.inner.borrow().erase_count
+    }
+}
+
+impl Flash for MemFlash {
+    const PAGE_SIZE: usize = PAGE_SIZE;
+
+    fn size(&self) -> usize {
+        self.inner.borrow().data.len()
+    }
+
+    fn read(&self, offset: usize, buf: &mut [u8]) {
+        buf.copy_from_slice(&self.inner.borrow().data[offset..offset + buf.len()]);
+    }
+
+    fn erase_page(&mut self, offset: usize) -> Result<(), FlashError> {
+        let mut inner = self.inner.borrow_mut();
+        if offset % PAGE_SIZE != 0 || offset >= inner.data.len() {
+            return Err(FlashError::OutOfBounds);
+        }
+        if inner.write_budget == Some(0) {
+            return Err(FlashError::Program);
+        }
+        inner.data[offset..offset + PAGE_SIZE].fill(0xFF);
+        inner.erase_count += 1;
+        Ok(())
+    }
+
+    fn write(&mut self, offset: usize, data: &[u8]) -> Result<(), FlashError> {
+        let mut inner = self.inner.borrow_mut();
+        if offset % 2 != 0 || data.len() % 2 != 0 {
+            return Err(FlashError::Alignment);
+        }
+        if offset + data.len() > inner.data.len() {
+            return Err(FlashError::OutOfBounds);
+        }
+        for (i, half_word) in data.chunks(2).enumerate() {
+            match inner.write_budget {
+                Some(0) => return Err(FlashError::Program),
+                Some(ref mut budget) => *budget -= 1,
+                None => {}
+            }
+            let at = offset + i * 2;
+            // Like the F0 flash controller: only erased half-words can be programmed
+            if inner.data[at..at + 2] != [0xFF, 0xFF] {
+                return Err(FlashError::Program);
+            }
+            inner.data[at..at + 2].copy_from_slice(half_word);
+        }
+        Ok(())
+    }
+}
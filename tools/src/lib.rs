@@ -0,0 +1,90 @@
+//! Host side counterparts of the firmware: simulated CAN bus and UAVCAN services that are
+//! expected to be provided by some other node on a real bus.
+//!
+//! Module logic free of hardware and RTIC is included as well, `node` runs it against the
+//! simulated bus or SocketCAN.
+
+#[macro_use]
+extern crate static_assertions;
+
+// Shared code logs through these, host builds stay quiet
+#[allow(unused_macros)]
+macro_rules! log_trace {
+    (target: $target:ident, $($arg:tt)*) => {{ let _ = format_args!($($arg)*); }};
+    ($($arg:tt)*) => {{ let _ = format_args!($($arg)*); }};
+}
+#[allow(unused_macros)]
+macro_rules! log_debug {
+    (target: $target:ident, $($arg:tt)*) => {{ let _ = format_args!($($arg)*); }};
+    ($($arg:tt)*) => {{ let _ = format_args!($($arg)*); }};
+}
+#[allow(unused_macros)]
+macro_rules! log_info {
+    (target: $target:ident, $($arg:tt)*) => {{ let _ = format_args!($($arg)*); }};
+    ($($arg:tt)*) => {{ let _ = format_args!($($arg)*); }};
+}
+#[allow(unused_macros)]
+macro_rules! log_warn {
+    (target: $target:ident, $($arg:tt)*) => {{ let _ = format_args!($($arg)*); }};
+    ($($arg:tt)*) => {{ let _ = format_args!($($arg)*); }};
+}
+#[allow(unused_macros)]
+macro_rules! log_error {
+    (target: $target:ident, $($arg:tt)*) => {{ let _ = format_args!($($arg)*); }};
+    ($($arg:tt)*) => {{ let _ = format_args!($($arg)*); }};
+}
+
+/// Only the part of `config` the modules agree on over the bus
+#[path = "../../src/config/shared.rs"]
+pub mod config;
+#[allow(dead_code)]
+#[path = "../../src/uavcan/mod.rs"]
+pub mod uavcan;
+#[path = "../../src/bit_timing.rs"]
+pub mod bit_timing;
+#[path = "../../src/ramp_generator2.rs"]
+pub mod ramp_generator2;
+#[path = "../../src/vesc_control.rs"]
+pub mod vesc_control;
+#[path = "../../src/hw/mod.rs"]
+pub mod hw;
+#[path = "../../src/module/button/logic.rs"]
+pub mod button_logic;
+#[path = "../../src/module/afe/logic.rs"]
+pub mod afe_logic;
+#[path = "../../boards/gen.rs"]
+pub mod board_gen;
+#[path = "../../src/logging/filter.rs"]
+pub mod log_filter;
+#[path = "../../src/supervisor/liveness.rs"]
+pub mod liveness;
+/// Conditions behind the node health, the rest of `health` keeps them in a static
+#[path = "../../src/health"]
+pub mod health {
+    pub mod conditions;
+    pub use conditions::Reason;
+}
+#[path = "../../src/task/blink/pattern.rs"]
+pub mod blink_pattern;
+#[path = "../../src/nvconfig/storage.rs"]
+pub mod nv_storage;
+#[path = "../../src/boot.rs"]
+pub mod boot;
+#[path = "../../src/can_health.rs"]
+pub mod can_health;
+#[allow(dead_code)]
+#[path = "../../src/can_interfaces.rs"]
+pub mod can_interfaces;
+/// Message RAM layout of the MCP2518FD driver, the driver itself needs embedded-hal
+#[path = "../../src/mcp2518fd"]
+pub mod mcp2518fd {
+    pub mod registers;
+    pub mod object;
+}
+pub mod sim;
+pub mod allocator;
+pub mod node;
+pub mod log_stream;
+pub mod flash;
+#[cfg(feature = "socketcan")]
+pub mod socketcan;
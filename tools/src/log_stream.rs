@@ -0,0 +1,60 @@
+//! Log output of modules built with `log-defmt-can` or `log-text-can`, see `task::log_can` in
+//! the firmware. Transfers on one log subject are reassembled per node.
+//!
+//! defmt payloads of one node make up a continuous byte stream, chunks do not follow record
+//! boundaries. Text is sent as one diagnostic record per transfer.
+
+use std::convert::TryFrom;
+use uavcan_llr::types::{CanId, NodeId, TransferKind};
+use vhrdcan::{Frame, FrameId};
+use crate::uavcan::assembler::Assembler;
+use crate::uavcan::diagnostic;
+
+/// As `config::LOG_SUBJECT_ID` in the firmware with `log-defmt-can`
+pub const LOG_SUBJECT_ID: u16 = 24;
+/// Text records of `log-text-can` are on the standard subject
+pub const TEXT_LOG_SUBJECT_ID: u16 = diagnostic::SUBJECT_ID;
+/// Biggest transfer a node sends, firmware uses less
+pub const MAX_TRANSFER_SIZE: usize = diagnostic::MAX_SIZE;
+/// Unfinished transfers are dropped after this long
+const TRANSFER_TIMEOUT_MS: u32 = 1000;
+
+pub struct LogCollector {
+    subject_id: u16,
+    /// One session per node, up to the whole bus
+    assembler: Assembler<128, { MAX_TRANSFER_SIZE + 2 }>,
+}
+
+impl LogCollector {
+    pub fn new(subject_id: u16) -> Self {
+        LogCollector { subject_id, assembler: Assembler::new(TRANSFER_TIMEOUT_MS) }
+    }
+
+    /// Source node and the payload, if `frame` completes a transfer on the log subject.
+    pub fn on_frame(&mut self, frame: &Frame<8>, now_ms: u32) -> Option<(NodeId, Vec<u8>)> {
+        let (eid, id) = match (frame.id, CanId::try_from(frame.id)) {
+            (FrameId::Extended(eid), Ok(id)) => (eid.inner(), id),
+            _ => return None,
+        };
+        if !matches!(id.transfer_kind, TransferKind::Message(m) if m.subject_id.inner() == self.subject_id) {
+            return None;
+        }
+        match self.assembler.push(eid, frame.data(), now_ms) {
+            Ok(Some(transfer)) => Some((id.source_node_id, transfer.payload.to_vec())),
+            _ => None,
+        }
+    }
+}
+
+/// One line per record: node, its uptime, severity and text, invalid UTF-8 is replaced.
+pub fn format_record(node_id: NodeId, record: &diagnostic::Record) -> String {
+    let ms = record.timestamp_us / 1000;
+    format!(
+        "[{:3}] {:6}.{:03} {:<8} {}",
+        node_id.inner(),
+        ms / 1000,
+        ms % 1000,
+        format!("{:?}", record.severity).to_uppercase(),
+        String::from_utf8_lossy(record.text)
+    )
+}
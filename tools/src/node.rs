@@ -0,0 +1,361 @@
+//! Firmware modules as host nodes: same control logic and UAVCAN transport, with RTIC tasks turned
+//! into periodic calls from `poll` and pins into plain fields. Attached to a [SimBus] port or
+//! SocketCAN through [CanPort].
+//!
+//! Time is passed in, tests step it as fast as they like and `sim_node` uses the wall clock.
+//!
+//! [SimBus]: crate::sim::SimBus
+
+use std::convert::TryFrom;
+use uavcan_llr::types::{CanId, NodeId, SubjectId, TransferId, Priority};
+use vhrdcan::{Frame, FrameId};
+use crate::button_logic::{self, ButtonLogic, Inputs};
+use crate::config;
+use crate::sim::CanPort;
+use crate::uavcan::assembler::Assembler;
+use crate::uavcan::heartbeat::{self, Health, Mode};
+use crate::uavcan::router::{self, Dispatch, Endpoint};
+use crate::uavcan::tx::{self, TransferSlicer};
+use crate::vesc_control::{self, ControlInput, VescControl, VescFeedback, VescWatchdog, WatchdogAction};
+
+/// Anything that runs on the bus.
+pub trait Node {
+    fn poll(&mut self, now_ms: u32);
+}
+
+/// Poll all nodes every `step_ms` from `from_ms` until `to_ms` (exclusive), in the given order.
+pub fn run(nodes: &mut [&mut dyn Node], from_ms: u32, to_ms: u32, step_ms: u32) {
+    let mut now_ms = from_ms;
+    while now_ms < to_ms {
+        for node in nodes.iter_mut() {
+            node.poll(now_ms);
+        }
+        now_ms += step_ms;
+    }
+}
+
+/// What `nvconfig` holds on the firmware, defaults are the same.
+#[derive(Copy, Clone, Debug)]
+pub struct NodeConfig {
+    pub node_id: NodeId,
+    pub heartbeat_period_ms: u32,
+    pub vesc_id: u8,
+    pub vesc_params: vesc_control::Params,
+    pub vesc_input_timeout_ms: u32,
+}
+
+impl NodeConfig {
+    pub fn new(node_id: NodeId) -> Self {
+        NodeConfig {
+            node_id,
+            heartbeat_period_ms: 1000,
+            vesc_id: config::VESC_ID,
+            vesc_params: vesc_control::Params {
+                duty_min: config::VESC_DUTY_MIN as i32,
+                ramp_rates: config::VESC_RAMP_RATES,
+            },
+            vesc_input_timeout_ms: 500,
+        }
+    }
+}
+
+/// Task spawned every `period_ms`, first run on the first poll.
+struct Periodic {
+    period_ms: u32,
+    next_ms: Option<u32>,
+}
+
+impl Periodic {
+    fn new(period_ms: u32) -> Self {
+        Periodic { period_ms, next_ms: None }
+    }
+
+    fn due(&mut self, now_ms: u32) -> bool {
+        match self.next_ms {
+            Some(next_ms) if now_ms.wrapping_sub(next_ms) as i32 >= 0 => {
+                self.next_ms = Some(next_ms.wrapping_add(self.period_ms));
+                true
+            }
+            Some(_) => false,
+            None => {
+                self.next_ms = Some(now_ms.wrapping_add(self.period_ms));
+                true
+            }
+        }
+    }
+}
+
+/// Slice a transfer into frames and send them, like `can_send_transfer!`.
+pub fn send_transfer<P: CanPort>(port: &mut P, id: CanId, payload: &[u8], transfer_id: &mut TransferId) {
+    let transfer_id = tx::next_transfer_id(transfer_id);
+    for frame in TransferSlicer::<8>::new(id.into(), payload, transfer_id.inner()) {
+        port.send(frame);
+    }
+}
+
+/// Publish a message with nominal priority.
+pub fn publish<P: CanPort>(port: &mut P, source: NodeId, subject_id: SubjectId, payload: &[u8], transfer_id: &mut TransferId) {
+    let id = CanId::new_message_kind(source, subject_id, false, Priority::Nominal);
+    send_transfer(port, id, payload, transfer_id);
+}
+
+/// Module with `vesc-ctrl`: heartbeat, `ramp_vesc` and `watchdog_vesc`, frames go through the
+/// firmware routing table and `router::receive` like in `can_rx_router`. Fields between the tasks
+/// are the RTIC shared resources.
+pub struct VescNode<P: CanPort> {
+    pub port: P,
+    pub config: NodeConfig,
+    assembler: Assembler<4, 16>,
+    control: VescControl,
+    watchdog: VescWatchdog,
+    vesc_control_input: Option<ControlInput>,
+    vesc_feedback: Option<VescFeedback>,
+    vesc_watchdog_input: Option<i32>,
+    vesc_watchdog_triggered: Option<()>,
+    /// Vendor specific status code in heartbeats
+    pub status: u8,
+    pub health: Health,
+    heartbeat_transfer_id: TransferId,
+    heartbeat_task: Periodic,
+    ramp_task: Periodic,
+    watchdog_task: Periodic,
+}
+
+impl<P: CanPort> VescNode<P> {
+    pub fn new(port: P, config: NodeConfig) -> Self {
+        VescNode {
+            port,
+            config,
+            assembler: Assembler::new(1000),
+            control: VescControl::new(),
+            watchdog: VescWatchdog::new(),
+            vesc_control_input: None,
+            vesc_feedback: None,
+            vesc_watchdog_input: None,
+            vesc_watchdog_triggered: None,
+            status: 0,
+            health: Health::Norminal,
+            heartbeat_transfer_id: TransferId::default(),
+            heartbeat_task: Periodic::new(config.heartbeat_period_ms),
+            ramp_task: Periodic::new(100),
+            watchdog_task: Periodic::new(100),
+        }
+    }
+
+    fn receive(&mut self, now_ms: u32) {
+        while let Some(frame) = self.port.receive() {
+            let tables = [vesc_control::SUBSCRIPTIONS];
+            let dispatch = match router::receive(&tables, Some(self.config.node_id), &frame, &mut self.assembler, now_ms, |_, _| true) {
+                Ok(Some((dispatch, _))) => dispatch,
+                _ => continue,
+            };
+            match dispatch {
+                Dispatch::Raw { endpoint: Endpoint::VescFeedback, id, payload } => {
+                    if let Some(feedback) = VescFeedback::new(id, payload, self.config.vesc_id) {
+                        self.vesc_feedback = Some(feedback);
+                    }
+                }
+                Dispatch::Message { endpoint: Endpoint::VescControl, source, message, payload } => {
+                    if let Some(input) = vesc_control::control_input(source, message, payload) {
+                        self.vesc_control_input = Some(input);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Simulation starts at boot, so uptime is simulated time
+    fn health_check_task(&mut self, now_ms: u32) {
+        let payload = heartbeat::encode(now_ms / 1000, self.health, Mode::Firmware, self.status);
+        let subject_id = SubjectId::new(heartbeat::SUBJECT_ID).unwrap();
+        publish(&mut self.port, self.config.node_id, subject_id, &payload, &mut self.heartbeat_transfer_id);
+    }
+
+    fn ramp_vesc(&mut self, now_ms: u32) {
+        if self.vesc_watchdog_triggered.take().is_some() {
+            self.control.stop();
+        }
+        if let Some(feedback) = self.vesc_feedback.take() {
+            self.control.on_feedback(feedback);
+        }
+        if let Some(input) = self.vesc_control_input.take() {
+            if let Some(duty_p5) = self.control.on_input(input, &self.config.vesc_params, now_ms) {
+                self.vesc_watchdog_input = Some(duty_p5);
+            }
+        }
+    }
+
+    fn watchdog_vesc(&mut self, now_ms: u32) {
+        let vesc_id = self.config.vesc_id;
+        match self.watchdog.update(self.vesc_watchdog_input.take(), now_ms, self.config.vesc_input_timeout_ms) {
+            WatchdogAction::None => {}
+            WatchdogAction::Duty(duty_p5) => {
+                self.port.send(Frame::new(vesc_control::frame_id(vesc_control::SET_DUTY, vesc_id), &duty_p5.to_be_bytes()).unwrap());
+            }
+            WatchdogAction::Release => {
+                self.vesc_watchdog_triggered = Some(());
+                let current: i32 = 0;
+                self.port.send(Frame::new(vesc_control::frame_id(vesc_control::SET_CURRENT, vesc_id), &current.to_be_bytes()).unwrap());
+            }
+        }
+    }
+}
+
+impl<P: CanPort> Node for VescNode<P> {
+    fn poll(&mut self, now_ms: u32) {
+        self.receive(now_ms);
+        if self.heartbeat_task.due(now_ms) {
+            self.health_check_task(now_ms);
+        }
+        if self.ramp_task.due(now_ms) {
+            self.ramp_vesc(now_ms);
+        }
+        if self.watchdog_task.due(now_ms) {
+            self.watchdog_vesc(now_ms);
+        }
+    }
+}
+
+/// Button module, `inputs` stand in for the pins.
+pub struct ButtonNode<P: CanPort> {
+    pub port: P,
+    pub inputs: Inputs,
+    node_id: NodeId,
+    vesc_id: u8,
+    logic: ButtonLogic,
+    power_transfer_id: TransferId,
+    safety_transfer_id: TransferId,
+    button_task: Periodic,
+}
+
+impl<P: CanPort> ButtonNode<P> {
+    pub fn new(port: P, vesc_id: u8) -> Self {
+        ButtonNode {
+            port,
+            inputs: Inputs::default(),
+            node_id: config::BUTTON_UAVCAN_NODE_ID,
+            vesc_id,
+            logic: ButtonLogic::new(),
+            power_transfer_id: TransferId::default(),
+            safety_transfer_id: TransferId::default(),
+            button_task: Periodic::new(button_logic::CHECK_PERIOD_MS),
+        }
+    }
+
+    fn button_task(&mut self) {
+        let outputs = self.logic.update(self.inputs);
+        if outputs.power_pressed {
+            publish(&mut self.port, self.node_id, config::POWER_BUTTON_SUBJECT, &[], &mut self.power_transfer_id);
+        }
+        if !outputs.estop {
+            let frame = Frame::new(vesc_control::frame_id(vesc_control::RESET_ESTOP_TIMEOUT, self.vesc_id), &[]).unwrap();
+            self.port.send(frame);
+        } else {
+            let frame = Frame::new(vesc_control::frame_id(vesc_control::SET_CURRENT_BRAKE, self.vesc_id), &button_logic::BRAKE_CURRENT_MILLIAMPS.to_be_bytes()).unwrap();
+            self.port.send(frame);
+            publish(&mut self.port, self.node_id, config::SAFETY_BUTTON_SUBJECT, &[], &mut self.safety_transfer_id);
+        }
+    }
+}
+
+impl<P: CanPort> Node for ButtonNode<P> {
+    fn poll(&mut self, now_ms: u32) {
+        // Nothing is received, drain the port to not accumulate frames
+        while self.port.receive().is_some() {}
+        if self.button_task.due(now_ms) {
+            self.button_task();
+        }
+    }
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum VescCommand {
+    Duty(i32),
+    Current(i32),
+    CurrentBrake(i32),
+    ResetEstopTimeout,
+    Other(u32),
+}
+
+/// VESC stand-in: records commands addressed to it and reports status every 20ms.
+/// Motor speed follows duty with a first order lag, `ERPM_PER_DUTY_P5` at steady state.
+pub struct MockVesc<P: CanPort> {
+    pub port: P,
+    pub vesc_id: u8,
+    pub commands: Vec<(u32, VescCommand)>,
+    duty_p5: i32,
+    erpm: i32,
+    status_task: Periodic,
+}
+
+impl<P: CanPort> MockVesc<P> {
+    pub const ERPM_PER_DUTY_P5: i32 = 2;
+    const STATUS_PERIOD_MS: u32 = 20;
+
+    pub fn new(port: P, vesc_id: u8) -> Self {
+        MockVesc {
+            port,
+            vesc_id,
+            commands: Vec::new(),
+            duty_p5: 0,
+            erpm: 0,
+            status_task: Periodic::new(Self::STATUS_PERIOD_MS),
+        }
+    }
+
+    pub fn erpm(&self) -> i32 {
+        self.erpm
+    }
+
+    pub fn duty_p5(&self) -> i32 {
+        self.duty_p5
+    }
+
+    fn handle(&mut self, frame: &Frame<8>, now_ms: u32) {
+        let eid = match frame.id {
+            FrameId::Extended(eid) => eid.inner(),
+            FrameId::Standard(_) => return,
+        };
+        // Command is one byte, anything above is UAVCAN
+        if eid & 0xFF != self.vesc_id as u32 || eid >> 8 > 0xFF {
+            return;
+        }
+        let data = frame.data();
+        let value = || <[u8; 4]>::try_from(data.get(..4)?).ok().map(i32::from_be_bytes);
+        let command = match (eid >> 8, value()) {
+            (vesc_control::SET_DUTY, Some(duty)) => VescCommand::Duty(duty),
+            (vesc_control::SET_CURRENT, Some(current)) => VescCommand::Current(current),
+            (vesc_control::SET_CURRENT_BRAKE, Some(current)) => VescCommand::CurrentBrake(current),
+            (vesc_control::RESET_ESTOP_TIMEOUT, _) => VescCommand::ResetEstopTimeout,
+            (command, _) => VescCommand::Other(command),
+        };
+        match command {
+            VescCommand::Duty(duty_p5) => self.duty_p5 = duty_p5,
+            VescCommand::Current(_) | VescCommand::CurrentBrake(_) => self.duty_p5 = 0,
+            _ => {}
+        }
+        self.commands.push((now_ms, command));
+    }
+
+    fn send_status(&mut self) {
+        let target = self.duty_p5 * Self::ERPM_PER_DUTY_P5;
+        self.erpm += (target - self.erpm) / 4;
+        let mut payload = [0u8; 8];
+        payload[0..4].copy_from_slice(&self.erpm.to_be_bytes());
+        payload[6..8].copy_from_slice(&((self.duty_p5 / 100) as i16).to_be_bytes());
+        let id = FrameId::new_extended(vesc_control::STATUS_ID | self.vesc_id as u32).unwrap();
+        self.port.send(Frame::new(id, &payload).unwrap());
+    }
+}
+
+impl<P: CanPort> Node for MockVesc<P> {
+    fn poll(&mut self, now_ms: u32) {
+        while let Some(frame) = self.port.receive() {
+            self.handle(&frame, now_ms);
+        }
+        if self.status_task.due(now_ms) {
+            self.send_status();
+        }
+    }
+}
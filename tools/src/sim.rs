@@ -0,0 +1,75 @@
+//! In-process CAN bus, every frame sent from one port is received by all the others.
+//! No arbitration, frames are delivered in the order they were sent.
+
+use std::cell::RefCell;
+use std::rc::Rc;
+use vhrdcan::Frame;
+
+/// Anything simulated nodes can be attached to, [SimPort] or a SocketCAN interface.
+pub trait CanPort {
+    fn send(&mut self, frame: Frame<8>);
+    fn receive(&mut self) -> Option<Frame<8>>;
+}
+
+#[derive(Clone, Default)]
+pub struct SimBus {
+    /// Sender port index and frame
+    log: Rc<RefCell<Vec<(usize, Frame<8>)>>>,
+    ports: Rc<RefCell<usize>>,
+}
+
+impl SimBus {
+    pub fn new() -> Self {
+        SimBus::default()
+    }
+
+    /// New node attached to the bus, receives only frames sent after this call.
+    pub fn port(&self) -> SimPort {
+        let mut ports = self.ports.borrow_mut();
+        *ports += 1;
+        SimPort {
+            log: self.log.clone(),
+            index: *ports,
+            cursor: self.log.borrow().len(),
+        }
+    }
+
+    /// Total number of frames sent so far.
+    pub fn frames_sent(&self) -> usize {
+        self.log.borrow().len()
+    }
+}
+
+pub struct SimPort {
+    log: Rc<RefCell<Vec<(usize, Frame<8>)>>>,
+    index: usize,
+    cursor: usize,
+}
+
+impl SimPort {
+    pub fn send(&mut self, frame: Frame<8>) {
+        self.log.borrow_mut().push((self.index, frame));
+    }
+
+    pub fn receive(&mut self) -> Option<Frame<8>> {
+        let log = self.log.borrow();
+        while self.cursor < log.len() {
+            let (sender, frame) = &log[self.cursor];
+            self.cursor += 1;
+            if *sender != self.index {
+                return Some(*frame);
+            }
+        }
+        None
+    }
+}
+
+impl CanPort for SimPort {
+    fn send(&mut self, frame: Frame<8>) {
+        SimPort::send(self, frame)
+    }
+
+    fn receive(&mut self) -> Option<Frame<8>> {
+        SimPort::receive(self)
+    }
+}
@@ -0,0 +1,110 @@
+//! Linux SocketCAN port, for running simulated nodes on a `vcan` interface next to real tools or
+//! on a real bus:
+//!
+//! ```text
+//! ip link add dev vcan0 type vcan && ip link set up vcan0
+//! ```
+//!
+//! Raw socket in non-blocking mode, classic frames only. Own frames are not looped back.
+
+use std::ffi::CString;
+use std::io;
+use std::mem;
+use vhrdcan::{Frame, FrameId};
+use crate::sim::CanPort;
+
+pub struct SocketCanPort {
+    fd: libc::c_int,
+}
+
+impl SocketCanPort {
+    pub fn open(interface: &str) -> io::Result<Self> {
+        let name = CString::new(interface).map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "interface name"))?;
+        let ifindex = unsafe { libc::if_nametoindex(name.as_ptr()) };
+        if ifindex == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let fd = unsafe { libc::socket(libc::PF_CAN, libc::SOCK_RAW | libc::SOCK_NONBLOCK, libc::CAN_RAW) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let port = SocketCanPort { fd };
+        let mut addr: libc::sockaddr_can = unsafe { mem::zeroed() };
+        addr.can_family = libc::AF_CAN as libc::sa_family_t;
+        addr.can_ifindex = ifindex as libc::c_int;
+        let r = unsafe {
+            libc::bind(fd, &addr as *const libc::sockaddr_can as *const libc::sockaddr, mem::size_of::<libc::sockaddr_can>() as libc::socklen_t)
+        };
+        if r < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(port)
+    }
+
+    /// Ok(None) if nothing was received yet. RTR and error frames are skipped.
+    pub fn try_receive(&mut self) -> io::Result<Option<Frame<8>>> {
+        loop {
+            let mut raw: libc::can_frame = unsafe { mem::zeroed() };
+            let n = unsafe {
+                libc::read(self.fd, &mut raw as *mut libc::can_frame as *mut libc::c_void, mem::size_of::<libc::can_frame>())
+            };
+            if n < 0 {
+                let e = io::Error::last_os_error();
+                return if e.kind() == io::ErrorKind::WouldBlock { Ok(None) } else { Err(e) };
+            }
+            if raw.can_id & (libc::CAN_RTR_FLAG | libc::CAN_ERR_FLAG) != 0 {
+                continue;
+            }
+            let id = if raw.can_id & libc::CAN_EFF_FLAG != 0 {
+                FrameId::new_extended(raw.can_id & libc::CAN_EFF_MASK)
+            } else {
+                FrameId::new_standard((raw.can_id & libc::CAN_SFF_MASK) as u16)
+            };
+            let len = (raw.can_dlc as usize).min(8);
+            if let Some(frame) = id.and_then(|id| Frame::new(id, &raw.data[..len])) {
+                return Ok(Some(frame));
+            }
+        }
+    }
+
+    pub fn try_send(&mut self, frame: &Frame<8>) -> io::Result<()> {
+        let mut raw: libc::can_frame = unsafe { mem::zeroed() };
+        raw.can_id = match frame.id {
+            FrameId::Extended(eid) => eid.inner() | libc::CAN_EFF_FLAG,
+            FrameId::Standard(sid) => sid.inner() as u32,
+        };
+        let data = frame.data();
+        raw.can_dlc = data.len() as u8;
+        raw.data[..data.len()].copy_from_slice(data);
+        let n = unsafe {
+            libc::write(self.fd, &raw as *const libc::can_frame as *const libc::c_void, mem::size_of::<libc::can_frame>())
+        };
+        if n < 0 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl CanPort for SocketCanPort {
+    /// Frames that do not fit into the socket buffer are dropped, as a full TX queue would.
+    fn send(&mut self, frame: Frame<8>) {
+        if let Err(e) = self.try_send(&frame) {
+            eprintln!("socketcan send: {}", e);
+        }
+    }
+
+    fn receive(&mut self) -> Option<Frame<8>> {
+        self.try_receive().unwrap_or_else(|e| {
+            eprintln!("socketcan receive: {}", e);
+            None
+        })
+    }
+}
+
+impl Drop for SocketCanPort {
+    fn drop(&mut self) {
+        unsafe { libc::close(self.fd) };
+    }
+}
@@ -0,0 +1,101 @@
+//! AFE zeroing and readings against a mock load cell ADC.
+
+use std::collections::VecDeque;
+use vhrd_module_tools::afe_logic::{self, Reading, Zero};
+use vhrd_module_tools::hw::{LoadCellAdc, LoadCellChannel};
+
+/// Conversions after switching channels still belong to the previous one, as on the HX711.
+const SETTLING: usize = 1;
+/// What the ADC reads while settling, far enough from anything else to show up in results.
+const STALE: i32 = 1_000_000;
+
+#[derive(Debug, Eq, PartialEq)]
+struct Failed;
+
+struct MockAdc {
+    channel: LoadCellChannel,
+    settling: usize,
+    torque: VecDeque<i32>,
+    thrust: VecDeque<i32>,
+    /// Read once the queue of a channel is empty
+    torque_idle: i32,
+    thrust_idle: i32,
+    fail: bool,
+}
+
+impl MockAdc {
+    fn new(torque_idle: i32, thrust_idle: i32) -> Self {
+        MockAdc {
+            channel: LoadCellChannel::Torque,
+            settling: 0,
+            torque: VecDeque::new(),
+            thrust: VecDeque::new(),
+            torque_idle,
+            thrust_idle,
+            fail: false,
+        }
+    }
+}
+
+impl LoadCellAdc for MockAdc {
+    type Error = Failed;
+
+    fn select(&mut self, channel: LoadCellChannel) -> Result<(), Failed> {
+        if self.fail {
+            return Err(Failed);
+        }
+        self.channel = channel;
+        self.settling = SETTLING;
+        Ok(())
+    }
+
+    fn read(&mut self) -> Result<i32, Failed> {
+        if self.fail {
+            return Err(Failed);
+        }
+        if self.settling > 0 {
+            self.settling -= 1;
+            return Ok(STALE);
+        }
+        Ok(match self.channel {
+            LoadCellChannel::Torque => self.torque.pop_front().unwrap_or(self.torque_idle),
+            LoadCellChannel::Thrust => self.thrust.pop_front().unwrap_or(self.thrust_idle),
+        })
+    }
+}
+
+#[test]
+fn zero_is_mean_without_junk() {
+    let mut adc = MockAdc::new(100, -200);
+    // One more conversion skipped than the ADC needs, then one spike among the samples
+    adc.torque.extend([5_000, 100, 100, 100, 100, 100, 10_100, 100, 100]);
+    adc.thrust.extend([5_000, -190, -210, -190, -210, -190, -210, -190, -210]);
+    let zero = afe_logic::zero(&mut adc, 500).unwrap();
+    assert_eq!(zero, Zero { torque: 100, thrust: -200 });
+}
+
+#[test]
+fn reading_skips_settling_and_subtracts_zero() {
+    let mut adc = MockAdc::new(1_100, 300);
+    let zero = Zero { torque: 100, thrust: -200 };
+    assert_eq!(afe_logic::read(&mut adc, zero).unwrap(), Reading { torque: 1_000, thrust: 500 });
+
+    adc.torque.push_back(90);
+    adc.thrust.push_back(-200);
+    assert_eq!(afe_logic::read(&mut adc, zero).unwrap(), Reading { torque: -10, thrust: 0 });
+}
+
+#[test]
+fn adc_errors_are_returned() {
+    let mut adc = MockAdc::new(0, 0);
+    adc.fail = true;
+    assert_eq!(afe_logic::zero(&mut adc, 500), Err(Failed));
+    assert_eq!(afe_logic::read(&mut adc, Zero::default()), Err(Failed));
+}
+
+#[test]
+fn telemetry_layout() {
+    let flags = afe_logic::TELEMETRY_THRUST_VALID;
+    let payload = afe_logic::telemetry_payload(0x0102_0304, -2, 0x1122_3344, flags);
+    assert_eq!(payload, [1, 2, 3, 4, 0xff, 0xff, 0xff, 0xfe, 0x11, 0x22, 0x33, 0x44, flags]);
+}
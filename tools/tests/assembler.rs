@@ -0,0 +1,162 @@
+//! Reassembly of multi-frame transfers: tail byte handling, transfer ID checks and transfer CRC.
+
+use uavcan_llr::types::{CanId, NodeId, Priority, SubjectId};
+use vhrdcan::{Frame, FrameId};
+use vhrd_module_tools::uavcan::assembler::{Assembler, Error, TailByte};
+use vhrd_module_tools::uavcan::tx::TransferSlicer;
+
+const TIMEOUT_MS: u32 = 100;
+
+fn id(source: u8) -> FrameId {
+    CanId::new_message_kind(NodeId::new(source).unwrap(), SubjectId::new(1234).unwrap(), false, Priority::Nominal).into()
+}
+
+fn raw(id: FrameId) -> u32 {
+    match id {
+        FrameId::Extended(eid) => eid.inner(),
+        FrameId::Standard(_) => unreachable!(),
+    }
+}
+
+fn frames(source: u8, payload: &[u8], transfer_id: u8) -> Vec<Frame<8>> {
+    TransferSlicer::<8>::new(id(source), payload, transfer_id).collect()
+}
+
+/// Feeds all frames, result of the last one
+fn feed(assembler: &mut Assembler<2, 64>, frames: &[Frame<8>], now_ms: u32) -> Result<Option<(Vec<u8>, u8)>, Error> {
+    let (last, rest) = frames.split_last().unwrap();
+    for frame in rest {
+        assert_eq!(assembler.push(raw(frame.id), frame.data(), now_ms), Ok(None));
+    }
+    assembler.push(raw(last.id), last.data(), now_ms).map(|t| t.map(|t| (t.payload.to_vec(), t.transfer_id)))
+}
+
+#[test]
+fn single_frame() {
+    let mut assembler = Assembler::<2, 64>::new(TIMEOUT_MS);
+    let frames = frames(5, &[1, 2, 3], 7);
+    assert_eq!(frames.len(), 1);
+    assert_eq!(feed(&mut assembler, &frames, 0), Ok(Some((vec![1, 2, 3], 7))));
+    assert_eq!(assembler.active_sessions(), 0);
+}
+
+#[test]
+fn multi_frame() {
+    let mut assembler = Assembler::<2, 64>::new(TIMEOUT_MS);
+    let payload: Vec<u8> = (0..20).collect();
+    let frames = frames(5, &payload, 3);
+    assert_eq!(frames.len(), 4);
+    let (assembled, transfer_id) = feed(&mut assembler, &frames, 0).unwrap().unwrap();
+    // Padding up to the last frame stays in place
+    assert_eq!(&assembled[..payload.len()], &payload[..]);
+    assert!(assembled[payload.len()..].iter().all(|&b| b == 0));
+    assert_eq!(transfer_id, 3);
+    assert_eq!(assembler.active_sessions(), 0);
+}
+
+#[test]
+fn interleaved_sources() {
+    let mut assembler = Assembler::<2, 64>::new(TIMEOUT_MS);
+    let a: Vec<u8> = (0..14).collect();
+    let b: Vec<u8> = (100..114).collect();
+    let (fa, fb) = (frames(5, &a, 1), frames(6, &b, 1));
+    assert_eq!(fa.len(), 3);
+    for i in 0..2 {
+        assert_eq!(assembler.push(raw(fa[i].id), fa[i].data(), 0), Ok(None));
+        assert_eq!(assembler.push(raw(fb[i].id), fb[i].data(), 0), Ok(None));
+    }
+    assert_eq!(assembler.active_sessions(), 2);
+    assert_eq!(assembler.push(raw(fa[2].id), fa[2].data(), 0).unwrap().unwrap().payload, &a[..]);
+    assert_eq!(assembler.push(raw(fb[2].id), fb[2].data(), 0).unwrap().unwrap().payload, &b[..]);
+}
+
+#[test]
+fn single_frame_with_toggle_cleared() {
+    let mut assembler = Assembler::<2, 64>::new(TIMEOUT_MS);
+    let tail = TailByte::new(true, true, false, 0).0;
+    assert_eq!(assembler.push(raw(id(5)), &[1, tail], 0), Err(Error::WrongToggle));
+}
+
+#[test]
+fn start_with_toggle_cleared() {
+    let mut assembler = Assembler::<2, 64>::new(TIMEOUT_MS);
+    let tail = TailByte::new(true, false, false, 0).0;
+    assert_eq!(assembler.push(raw(id(5)), &[1, 2, 3, 4, 5, 6, 7, tail], 0), Err(Error::WrongToggle));
+    assert_eq!(assembler.active_sessions(), 0);
+}
+
+#[test]
+fn repeated_frame_is_ignored() {
+    let mut assembler = Assembler::<2, 64>::new(TIMEOUT_MS);
+    let payload: Vec<u8> = (0..14).collect();
+    let frames = frames(5, &payload, 2);
+    let with_duplicate = [frames[0], frames[1], frames[1], frames[2]];
+    assert_eq!(feed(&mut assembler, &with_duplicate, 0).unwrap().unwrap().0, payload);
+}
+
+#[test]
+fn continuation_without_start() {
+    let mut assembler = Assembler::<2, 64>::new(TIMEOUT_MS);
+    let frames = frames(5, &[0; 14], 2);
+    assert_eq!(assembler.push(raw(frames[1].id), frames[1].data(), 0), Err(Error::NoSession));
+}
+
+#[test]
+fn transfer_id_mismatch() {
+    let mut assembler = Assembler::<2, 64>::new(TIMEOUT_MS);
+    let first = frames(5, &[0; 14], 2);
+    let other = frames(5, &[0; 14], 3);
+    assert_eq!(assembler.push(raw(first[0].id), first[0].data(), 0), Ok(None));
+    assert_eq!(assembler.push(raw(other[1].id), other[1].data(), 0), Err(Error::TransferIdMismatch));
+    // Session of the first transfer is still there
+    assert_eq!(assembler.push(raw(first[1].id), first[1].data(), 0), Ok(None));
+}
+
+#[test]
+fn crc_mismatch() {
+    let mut assembler = Assembler::<2, 64>::new(TIMEOUT_MS);
+    let mut frames = frames(5, &(0..14).collect::<Vec<u8>>(), 4);
+    let data = frames[1].data();
+    let mut corrupted = data.to_vec();
+    corrupted[0] ^= 0x01;
+    frames[1] = Frame::new(frames[1].id, &corrupted).unwrap();
+    assert_eq!(feed(&mut assembler, &frames, 0), Err(Error::CrcMismatch));
+    assert_eq!(assembler.active_sessions(), 0);
+}
+
+#[test]
+fn timeout() {
+    let mut assembler = Assembler::<2, 64>::new(TIMEOUT_MS);
+    let frames = frames(5, &[0; 14], 2);
+    assert_eq!(assembler.push(raw(frames[0].id), frames[0].data(), 0), Ok(None));
+    assert_eq!(assembler.push(raw(frames[1].id), frames[1].data(), TIMEOUT_MS + 1), Err(Error::Timeout));
+    assert_eq!(assembler.active_sessions(), 0);
+}
+
+#[test]
+fn too_long() {
+    let mut assembler = Assembler::<2, 16>::new(TIMEOUT_MS);
+    let frames: Vec<Frame<8>> = TransferSlicer::<8>::new(id(5), &[0; 20], 0).collect();
+    assert_eq!(assembler.push(raw(frames[0].id), frames[0].data(), 0), Ok(None));
+    assert_eq!(assembler.push(raw(frames[1].id), frames[1].data(), 0), Ok(None));
+    assert_eq!(assembler.push(raw(frames[2].id), frames[2].data(), 0), Err(Error::TooLong));
+}
+
+#[test]
+fn sessions_are_bounded_until_timeout() {
+    let mut assembler = Assembler::<2, 64>::new(TIMEOUT_MS);
+    for source in 5..7 {
+        let f = frames(source, &[0; 14], 0);
+        assert_eq!(assembler.push(raw(f[0].id), f[0].data(), 0), Ok(None));
+    }
+    let f = frames(7, &[0; 14], 0);
+    assert_eq!(assembler.push(raw(f[0].id), f[0].data(), 0), Err(Error::NoFreeSession));
+    assert_eq!(assembler.push(raw(f[0].id), f[0].data(), TIMEOUT_MS + 1), Ok(None));
+    assert_eq!(assembler.active_sessions(), 1);
+}
+
+#[test]
+fn empty_frame() {
+    let mut assembler = Assembler::<2, 64>::new(TIMEOUT_MS);
+    assert_eq!(assembler.push(raw(id(5)), &[], 0), Err(Error::Empty));
+}
@@ -0,0 +1,112 @@
+//! Bit timing calculator against register values known to work on the bus.
+//! bxCAN tables match bittiming.can-wiki.info at 87.5%, 1Mbit/s entries are the values modules
+//! shipped with before the calculator.
+
+use vhrd_module_tools::bit_timing::{Bitrate, BitTiming, Limits, BXCAN, MCP25625, MCP25625_SJW, MCP2518FD_NOMINAL, all_bitrates_supported};
+
+const SAMPLE_POINT: u32 = 875;
+
+fn timing(clock_hz: u32, bitrate: Bitrate, limits: &Limits) -> BitTiming {
+    BitTiming::calculate(clock_hz, bitrate.bps(), SAMPLE_POINT, limits)
+        .unwrap_or_else(|| panic!("{:?} at {}Hz", bitrate, clock_hz))
+}
+
+#[test]
+fn bxcan_8mhz() {
+    let table = [
+        (Bitrate::Kbps125, 0x001c_0003),
+        (Bitrate::Kbps250, 0x001c_0001),
+        (Bitrate::Kbps500, 0x001c_0000),
+        (Bitrate::Mbps1, 0x0005_0000),
+    ];
+    for (bitrate, btr) in table {
+        assert_eq!(timing(8_000_000, bitrate, &BXCAN).bxcan_btr(), btr, "{:?}", bitrate);
+    }
+}
+
+#[test]
+fn bxcan_48mhz() {
+    let table = [
+        (Bitrate::Kbps125, 0x001c_0017),
+        (Bitrate::Kbps250, 0x001c_000b),
+        (Bitrate::Kbps500, 0x001c_0005),
+        (Bitrate::Mbps1, 0x001c_0002),
+    ];
+    for (bitrate, btr) in table {
+        assert_eq!(timing(48_000_000, bitrate, &BXCAN).bxcan_btr(), btr, "{:?}", bitrate);
+    }
+}
+
+#[test]
+fn mcp25625_16mhz() {
+    // CNF1, CNF2, CNF3 with the jump width of 2. 1Mbit/s has only 8 quanta and phase 2 is at least
+    // 2, hence 75%.
+    let table = [
+        (Bitrate::Kbps125, [0x43, 0xae, 0x01]),
+        (Bitrate::Kbps250, [0x41, 0xae, 0x01]),
+        (Bitrate::Kbps500, [0x40, 0xae, 0x01]),
+        (Bitrate::Mbps1, [0x40, 0x8a, 0x01]),
+    ];
+    for (bitrate, cnf) in table {
+        assert_eq!(timing(16_000_000, bitrate, &MCP25625).with_sjw(MCP25625_SJW).mcp25625().cnf(), cnf, "{:?}", bitrate);
+    }
+    let m = timing(16_000_000, Bitrate::Mbps1, &MCP25625).with_sjw(MCP25625_SJW).mcp25625();
+    assert_eq!((m.brp, m.prop_seg, m.ph_seg1, m.ph_seg2, m.sync_jump_width), (0, 3, 2, 2, 2));
+}
+
+#[test]
+fn mcp2518fd_40mhz() {
+    // NBTCFG fields: BRP, TSEG1, TSEG2, SJW
+    let table = [
+        (Bitrate::Kbps125, [1, 138, 19, 19]),
+        (Bitrate::Kbps250, [0, 138, 19, 19]),
+        (Bitrate::Kbps500, [0, 68, 9, 9]),
+        (Bitrate::Mbps1, [0, 33, 4, 4]),
+    ];
+    for (bitrate, nbtcfg) in table {
+        let t = timing(40_000_000, bitrate, &MCP2518FD_NOMINAL);
+        assert_eq!(t.with_sjw(t.tseg2).mcp2518fd(), nbtcfg, "{:?}", bitrate);
+    }
+}
+
+#[test]
+fn exact_bitrate_and_valid_segments() {
+    let clocks = [8_000_000, 16_000_000, 24_000_000, 40_000_000, 48_000_000];
+    for limits in [BXCAN, MCP25625, MCP2518FD_NOMINAL] {
+        for clock_hz in clocks {
+            for bitrate in Bitrate::ALL {
+                let t = match BitTiming::calculate(clock_hz, bitrate.bps(), SAMPLE_POINT, &limits) {
+                    Some(t) => t,
+                    None => continue,
+                };
+                assert_eq!(t.bitrate(clock_hz, &limits), bitrate.bps());
+                assert!((limits.brp[0]..=limits.brp[1]).contains(&t.brp));
+                assert!((limits.tseg1[0]..=limits.tseg1[1]).contains(&t.tseg1));
+                assert!((limits.tseg2[0]..=limits.tseg2[1]).contains(&t.tseg2));
+                assert!(t.quanta() >= limits.min_quanta);
+                assert!((700..=900).contains(&t.sample_point_permille()), "{:?} {} {:?}", t, clock_hz, bitrate);
+            }
+        }
+    }
+}
+
+#[test]
+fn unsupported_clocks() {
+    // Not a multiple of the bitrate
+    assert_eq!(BitTiming::calculate(7_000_000, 1_000_000, SAMPLE_POINT, &BXCAN), None);
+    // Too few quanta per bit
+    assert_eq!(BitTiming::calculate(8_000_000, 1_000_000, SAMPLE_POINT, &MCP25625), None);
+    assert!(!all_bitrates_supported(8_000_000, SAMPLE_POINT, &MCP25625));
+    assert!(all_bitrates_supported(8_000_000, SAMPLE_POINT, &BXCAN));
+    assert!(all_bitrates_supported(48_000_000, SAMPLE_POINT, &BXCAN));
+    assert!(all_bitrates_supported(16_000_000, SAMPLE_POINT, &MCP25625));
+    assert!(all_bitrates_supported(40_000_000, SAMPLE_POINT, &MCP2518FD_NOMINAL));
+}
+
+#[test]
+fn bitrate_from_bps() {
+    for bitrate in Bitrate::ALL {
+        assert_eq!(Bitrate::from_bps(bitrate.bps()), Some(bitrate));
+    }
+    assert_eq!(Bitrate::from_bps(100_000), None);
+}
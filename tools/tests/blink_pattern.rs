@@ -0,0 +1,174 @@
+//! Status LED patterns: playback of steps and codes, selection from node state.
+
+use vhrd_module_tools::blink_pattern::{
+    select, NodeState, Pattern, Player, Step, BREATH, CODE_OFF_MS, CODE_ON_MS, CODE_PAUSE_MS, FAST_BLINK,
+    HEARTBEAT, LEVEL_MAX, OFF, SLOW_BLINK, SOLID,
+};
+use vhrd_module_tools::health::Reason;
+use vhrd_module_tools::uavcan::health_report::HealthReport;
+use vhrd_module_tools::uavcan::heartbeat::Health;
+
+const TICK_MS: u32 = 20;
+
+/// Level shown in every tick for `ms`
+fn play(player: &mut Player, ms: u32) -> Vec<u16> {
+    (0..ms / TICK_MS).map(|_| player.advance(TICK_MS)).collect()
+}
+
+/// Lengths of on and off runs
+fn runs(levels: &[u16]) -> Vec<(bool, u32)> {
+    let mut runs: Vec<(bool, u32)> = Vec::new();
+    for &level in levels {
+        let on = level > 0;
+        match runs.last_mut() {
+            Some((last, ms)) if *last == on => *ms += TICK_MS,
+            _ => runs.push((on, TICK_MS)),
+        }
+    }
+    runs
+}
+
+fn nominal() -> NodeState {
+    NodeState { failures: 0, can: Health::Norminal, warning: false, anonymous: false, module: None }
+}
+
+#[test]
+fn off_and_solid() {
+    let mut player = Player::new();
+    assert!(play(&mut player, 1000).iter().all(|&l| l == 0));
+    player.set(SOLID);
+    assert!(play(&mut player, 3000).iter().all(|&l| l == LEVEL_MAX));
+    player.set(OFF);
+    assert_eq!(player.advance(TICK_MS), 0);
+}
+
+#[test]
+fn code_blinks_count_then_pauses() {
+    let mut player = Player::new();
+    player.set(Pattern::Code(3));
+    let period = 3 * (CODE_ON_MS + CODE_OFF_MS) as u32 - CODE_OFF_MS as u32 + CODE_PAUSE_MS as u32;
+    let levels = play(&mut player, 2 * period);
+    let on = CODE_ON_MS as u32;
+    let off = CODE_OFF_MS as u32;
+    let pause = CODE_PAUSE_MS as u32;
+    assert_eq!(
+        runs(&levels),
+        [(true, on), (false, off), (true, on), (false, off), (true, on), (false, pause)].repeat(2)
+    );
+}
+
+#[test]
+fn blink_periods() {
+    let mut player = Player::new();
+    player.set(FAST_BLINK);
+    let levels = play(&mut player, 1000);
+    assert_eq!(runs(&levels), [(true, 100), (false, 100)].repeat(5));
+
+    player.set(SLOW_BLINK);
+    let levels = play(&mut player, 4000);
+    assert_eq!(runs(&levels), [(true, 1000), (false, 1000)].repeat(2));
+}
+
+#[test]
+fn heartbeat_is_double_pulse() {
+    let mut player = Player::new();
+    player.set(HEARTBEAT);
+    let levels = play(&mut player, 1500);
+    // Gap of 150ms is rounded to ticks
+    let pulses: Vec<_> = runs(&levels).into_iter().filter(|&(on, _)| on).collect();
+    assert_eq!(pulses, [(true, 100), (true, 100)]);
+    assert!(levels[0] > 0);
+}
+
+#[test]
+fn breath_fades_up_and_down() {
+    let mut player = Player::new();
+    player.set(BREATH);
+    let up = play(&mut player, 4000);
+    assert!(up.windows(2).all(|w| w[1] > w[0]));
+    assert_eq!(up[0], 0);
+    assert_eq!(up[up.len() / 2], LEVEL_MAX / 2);
+    let down = play(&mut player, 4000);
+    assert!(down.windows(2).all(|w| w[1] < w[0]));
+    assert_eq!(down[0], LEVEL_MAX);
+    assert_eq!(player.advance(TICK_MS), 0);
+}
+
+#[test]
+fn fade_continues_from_current_level() {
+    static DIM: [Step; 1] = [Step::fade(0, 1000)];
+    let mut player = Player::new();
+    player.set(SOLID);
+    player.advance(TICK_MS);
+    player.set(Pattern::Steps(&DIM));
+    assert_eq!(player.advance(500), LEVEL_MAX);
+    assert_eq!(player.advance(TICK_MS), LEVEL_MAX / 2);
+}
+
+#[test]
+fn same_pattern_does_not_restart() {
+    let mut player = Player::new();
+    player.set(SLOW_BLINK);
+    play(&mut player, 1200);
+    player.set(SLOW_BLINK);
+    assert_eq!(player.advance(TICK_MS), 0);
+}
+
+#[test]
+fn custom_steps_and_zero_length() {
+    static STEPS: [Step; 3] = [Step::hold(300, 40), Step::hold(700, 0), Step::hold(0, 20)];
+    let mut player = Player::new();
+    player.set(Pattern::Steps(&STEPS));
+    assert_eq!(play(&mut player, 120), [300, 300, 0, 300, 300, 0]);
+
+    static ZERO: [Step; 2] = [Step::hold(500, 0), Step::hold(0, 0)];
+    player.set(Pattern::Steps(&ZERO));
+    player.advance(TICK_MS);
+    player.set(Pattern::Code(0));
+    assert_eq!(player.advance(TICK_MS), 0);
+}
+
+#[test]
+fn selection_priority() {
+    assert_eq!(select(&nominal()), BREATH);
+    assert_eq!(select(&NodeState { warning: true, ..nominal() }), HEARTBEAT);
+    assert_eq!(select(&NodeState { can: Health::Warning, ..nominal() }), HEARTBEAT);
+    assert_eq!(select(&NodeState { anonymous: true, warning: true, ..nominal() }), SLOW_BLINK);
+    assert_eq!(select(&NodeState { module: Some(SOLID), anonymous: true, ..nominal() }), SOLID);
+    assert_eq!(select(&NodeState { can: Health::Failure, module: Some(SOLID), ..nominal() }), FAST_BLINK);
+    assert_eq!(
+        select(&NodeState { failures: 1 << 2 | 1 << 4, can: Health::Failure, ..nominal() }),
+        Pattern::Code(3)
+    );
+}
+
+fn report(failures: u16, warnings: u16) -> HealthReport {
+    HealthReport { health: Health::Norminal, failures, warnings }
+}
+
+#[test]
+fn can_errors_are_taken_out_of_the_report() {
+    let can_bit = 1 << Reason::CanErrors as u16;
+    let state = NodeState::new(&report(0, 0), false, None);
+    assert_eq!(state, nominal());
+
+    let state = NodeState::new(&report(can_bit, can_bit), false, None);
+    assert_eq!(state, NodeState { can: Health::Failure, ..nominal() });
+    assert_eq!(select(&state), FAST_BLINK);
+
+    let state = NodeState::new(&report(0, can_bit), true, Some(SOLID));
+    assert_eq!(state, NodeState { can: Health::Warning, anonymous: true, module: Some(SOLID), ..nominal() });
+}
+
+#[test]
+fn failure_codes_keep_reason_numbers() {
+    let can_bit = 1 << Reason::CanErrors as u16;
+    let vesc_bit = 1 << Reason::VescWatchdog as u16;
+    let state = NodeState::new(&report(can_bit | vesc_bit, 0), false, None);
+    assert_eq!(state.failures, vesc_bit);
+    assert_eq!(state.can, Health::Failure);
+    assert_eq!(select(&state), Pattern::Code(Reason::VescWatchdog as u8 + 1));
+
+    let state = NodeState::new(&report(0, vesc_bit), false, None);
+    assert_eq!(state, NodeState { warning: true, ..nominal() });
+}
@@ -0,0 +1,61 @@
+//! Board descriptions: every shipped feature set fits the board, conflicts fail the build.
+
+use vhrd_module_tools::board_gen::{self, Mode};
+
+const V1: &str = include_str!("../../boards/v1.toml");
+
+/// Module and CAN features of the builds in build_all.sh
+const BUILDS: &[&[&str]] = &[
+    &["module-button", "can-mcp25625"],
+    &["module-afe", "module-afe-hx711", "can-stm"],
+    &["module-led", "can-stm"],
+    &["module-pi", "can-stm"],
+];
+
+#[test]
+fn v1_builds_have_no_conflicts() {
+    let board = board_gen::parse(V1).unwrap();
+    assert_eq!(board.name, "v1");
+    for features in BUILDS {
+        let signals = board.select(&|f| features.contains(&f)).unwrap_or_else(|e| panic!("{:?}: {}", features, e));
+        assert!(signals.iter().any(|s| s.name == "status_led"));
+        assert!(signals.iter().all(|s| s.features.is_empty() || s.features.iter().any(|f| features.contains(&f.as_str()))));
+    }
+}
+
+#[test]
+fn modules_sharing_pins_conflict() {
+    let board = board_gen::parse(V1).unwrap();
+    let e = board.select(&|f| f == "module-button" || f == "module-led").unwrap_err();
+    assert!(e.contains("PB0 claimed by both"), "{}", e);
+    assert!(e.contains("estop_a") && e.contains("led_g"), "{}", e);
+}
+
+#[test]
+fn invalid_descriptions() {
+    let board = |signal: &str| board_gen::parse(&format!("name = \"test\"\n[signals]\n{}\n", signal));
+    let led = board("led = { pin = \"PA6\", mode = \"af5\", features = [\"module-button\"] }").unwrap();
+    assert_eq!(led.signals[0].mode, Mode::Alternate(5));
+    assert_eq!((led.signals[0].port, led.signals[0].pin), ('a', 6));
+    assert!(board("led = { pin = \"PD6\", mode = \"af5\" }").is_err());
+    assert!(board("led = { pin = \"PA16\", mode = \"af5\" }").is_err());
+    assert!(board("led = { pin = \"PA6\", mode = \"af8\" }").is_err());
+    assert!(board("led = { pin = \"PA6\" }").is_err());
+    assert!(board("Led = { pin = \"PA6\", mode = \"af5\" }").is_err());
+    assert!(board("led = { pin = \"PA6\", mode = \"af5\", features = \"module-button\" }").is_err());
+}
+
+#[test]
+fn generated_pin_setup() {
+    let board = board_gen::parse(V1).unwrap();
+    let signals = board.select(&|f| f == "module-button" || f == "can-mcp25625").unwrap();
+    let code = board_gen::generate(&board, &signals, "boards/v1.toml");
+    assert!(code.contains("pub const NAME: &str = \"v1\";"));
+    assert!(code.contains("pub type CanStby = gpio::gpioa::PA15<Output<PushPull>>;"));
+    assert!(code.contains("pub type EstopA = gpio::gpiob::PB0<Input<PullUp>>;"));
+    assert!(code.contains("pub type McpSck = gpio::gpiob::PB3<Alternate<AF0>>;"));
+    assert!(code.contains("    pub estop_a: EstopA,"));
+    assert!(code.contains("            estop_a: gpiob.pb0.into_pull_up_input(cs),"));
+    assert!(code.contains("            button_led: gpioa.pa7.into_alternate_af4(cs),"));
+    assert!(!code.contains("led_g"));
+}
@@ -0,0 +1,50 @@
+use heapless::Vec;
+use vhrd_module_tools::boot::{crc32, BootRequest, ImageDescriptor, APP_SIZE, BOOT_REQUEST_SIZE, MAX_PATH_LEN};
+
+fn parameter(size: u32, crc: u32, path: &[u8]) -> std::vec::Vec<u8> {
+    let mut p = ImageDescriptor { size, crc }.to_bytes().to_vec();
+    p.extend_from_slice(path);
+    p
+}
+
+#[test]
+fn crc32_is_zlib() {
+    assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    assert_eq!(crc32(b""), 0);
+}
+
+#[test]
+fn update_parameter_carries_size_and_crc() {
+    let p = parameter(1234, 0xDEAD_BEEF, b"fw/button.bin");
+    let (image, path) = ImageDescriptor::split_update_parameter(&p).unwrap();
+    assert_eq!(image, ImageDescriptor { size: 1234, crc: 0xDEAD_BEEF });
+    assert_eq!(path, b"fw/button.bin");
+}
+
+#[test]
+fn update_parameter_rejects_missing_path_or_bad_size() {
+    assert_eq!(ImageDescriptor::split_update_parameter(b"fw/button.bin"), None);
+    assert_eq!(ImageDescriptor::split_update_parameter(&parameter(1234, 1, b"")), None);
+    assert_eq!(ImageDescriptor::split_update_parameter(&parameter(0, 1, b"a")), None);
+    assert_eq!(ImageDescriptor::split_update_parameter(&parameter(APP_SIZE as u32 + 1, 1, b"a")), None);
+    assert!(ImageDescriptor::split_update_parameter(&parameter(APP_SIZE as u32, 1, b"a")).is_some());
+}
+
+#[test]
+fn update_request_round_trip() {
+    let path: Vec<u8, MAX_PATH_LEN> = Vec::from_slice(&[b'x'; MAX_PATH_LEN]).unwrap();
+    let request = BootRequest::Update { server: 42, image: ImageDescriptor { size: 40_000, crc: 0x0102_0304 }, path };
+    assert_eq!(BootRequest::decode(&request.encode()), Some(request));
+    assert_eq!(BootRequest::decode(&BootRequest::Stay.encode()), Some(BootRequest::Stay));
+}
+
+#[test]
+fn corrupted_request_is_ignored() {
+    let path = Vec::from_slice(b"fw.bin").unwrap();
+    let request = BootRequest::Update { server: 42, image: ImageDescriptor { size: 100, crc: 7 }, path };
+    for i in 0..BOOT_REQUEST_SIZE {
+        let mut buf = request.encode();
+        buf[i] ^= 0x10;
+        assert_eq!(BootRequest::decode(&buf), None, "byte {}", i);
+    }
+}
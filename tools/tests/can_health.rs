@@ -0,0 +1,138 @@
+//! CAN error state tracking: bus-off hold-off with backoff, recovery and published statistics.
+
+use vhrd_module_tools::can_health::{Action, ErrorStatus, Interface, InterfaceHealth, RecoveryPolicy, STATISTICS_SIZE};
+use vhrd_module_tools::uavcan::heartbeat::Health;
+
+const POLICY: RecoveryPolicy = RecoveryPolicy { auto: true, backoff_ms: [100, 1000] };
+const BUS_OFF: ErrorStatus = ErrorStatus { tec: 255, rec: 0, bus_off: true };
+const ACTIVE: ErrorStatus = ErrorStatus { tec: 0, rec: 0, bus_off: false };
+
+fn statistics(health: &InterfaceHealth) -> [u8; STATISTICS_SIZE] {
+    let mut buf = [0u8; STATISTICS_SIZE];
+    assert_eq!(health.encode(&mut buf), STATISTICS_SIZE);
+    buf
+}
+
+fn counter(health: &InterfaceHealth, index: usize) -> u32 {
+    let buf = statistics(health);
+    u32::from_le_bytes([buf[4 + index * 4], buf[5 + index * 4], buf[6 + index * 4], buf[7 + index * 4]])
+}
+
+/// Goes bus-off at `now_ms`, returns how long the controller was held off
+fn bus_off_time(health: &mut InterfaceHealth, now_ms: u32) -> u32 {
+    assert_eq!(health.update(BUS_OFF, now_ms, POLICY), Action::HoldOff);
+    assert!(health.is_held_off());
+    for t in now_ms..now_ms + 10_000 {
+        if health.update(BUS_OFF, t, POLICY) == Action::Restart {
+            assert!(!health.is_held_off());
+            return t - now_ms;
+        }
+    }
+    panic!("no restart");
+}
+
+#[test]
+fn error_passive_is_a_warning() {
+    let mut health = InterfaceHealth::new(Interface::Mcp);
+    assert_eq!(health.health(), Health::Norminal);
+    assert_eq!(health.update(ErrorStatus { tec: 130, rec: 0, bus_off: false }, 0, POLICY), Action::None);
+    assert_eq!(health.health(), Health::Warning);
+    assert_eq!(counter(&health, 1), 1);
+    // Back to active, the transition was counted once
+    assert_eq!(health.update(ACTIVE, 1, POLICY), Action::None);
+    assert_eq!(health.health(), Health::Norminal);
+    assert_eq!(counter(&health, 1), 1);
+}
+
+#[test]
+fn new_errors_warn_once() {
+    let mut health = InterfaceHealth::new(Interface::Stm);
+    health.on_tx_error();
+    assert_eq!(health.health(), Health::Warning);
+    assert_eq!(health.health(), Health::Norminal);
+    // Arbitration is normal on a busy bus
+    health.on_arbitration_lost();
+    assert_eq!(health.health(), Health::Norminal);
+}
+
+#[test]
+fn bus_off_is_a_failure_until_restart() {
+    let mut health = InterfaceHealth::new(Interface::Mcp);
+    assert_eq!(health.update(BUS_OFF, 0, POLICY), Action::HoldOff);
+    assert_eq!(health.health(), Health::Failure);
+    // Status is ignored while held off
+    assert_eq!(health.update(ACTIVE, 1, POLICY), Action::None);
+    assert!(health.is_held_off());
+    assert_eq!(health.update(ACTIVE, 100, POLICY), Action::None);
+    assert_eq!(health.update(ACTIVE, 101, POLICY), Action::Restart);
+    assert_eq!(counter(&health, 0), 1);
+    assert_eq!(counter(&health, 5), 1);
+}
+
+#[test]
+fn backoff_doubles_while_unstable() {
+    let mut health = InterfaceHealth::new(Interface::Mcp);
+    let mut now = 0;
+    let mut times = Vec::new();
+    for _ in 0..6 {
+        let t = bus_off_time(&mut health, now);
+        times.push(t);
+        // Fails again right after the restart
+        now += t + 10;
+    }
+    assert_eq!(times, [100, 200, 400, 800, 1000, 1000]);
+}
+
+#[test]
+fn backoff_resets_after_stable_operation() {
+    let mut health = InterfaceHealth::new(Interface::Mcp);
+    let t = bus_off_time(&mut health, 0);
+    assert_eq!(bus_off_time(&mut health, t + 10), 200);
+    // Longer than the maximum backoff after the restart
+    assert_eq!(bus_off_time(&mut health, 5000), 100);
+}
+
+#[test]
+fn no_auto_recovery_stays_off() {
+    let mut health = InterfaceHealth::new(Interface::Mcp);
+    let policy = RecoveryPolicy { auto: false, ..POLICY };
+    assert_eq!(health.update(BUS_OFF, 0, policy), Action::HoldOff);
+    for t in (0..100_000).step_by(100) {
+        assert_eq!(health.update(ACTIVE, t, policy), Action::None);
+    }
+    assert!(health.is_held_off());
+    // Enabled at runtime, counted from then on
+    assert_eq!(health.update(ACTIVE, 200_000, POLICY), Action::None);
+    assert_eq!(health.update(ACTIVE, 200_101, POLICY), Action::Restart);
+}
+
+#[test]
+fn restart_time_wraps() {
+    let mut health = InterfaceHealth::new(Interface::Mcp);
+    let start = u32::MAX - 50;
+    assert_eq!(health.update(BUS_OFF, start, POLICY), Action::HoldOff);
+    assert_eq!(health.update(BUS_OFF, start, POLICY), Action::None);
+    assert_eq!(health.update(BUS_OFF, 10, POLICY), Action::None);
+    assert_eq!(health.update(BUS_OFF, 49, POLICY), Action::Restart);
+}
+
+#[test]
+fn statistics_layout() {
+    let mut health = InterfaceHealth::new(Interface::Stm);
+    health.update(ErrorStatus { tec: 140, rec: 7, bus_off: false }, 0, POLICY);
+    health.on_rx_overflow();
+    health.on_tx_error();
+    health.on_tx_error();
+    health.on_arbitration_lost();
+    let buf = statistics(&health);
+    assert_eq!(&buf[..4], &[Interface::Stm as u8, 1, 140, 7]);
+    let counters: Vec<u32> = (0..6).map(|i| counter(&health, i)).collect();
+    assert_eq!(counters, [0, 1, 1, 2, 1, 0]);
+
+    assert_eq!(health.update(BUS_OFF, 1, POLICY), Action::HoldOff);
+    let buf = statistics(&health);
+    // Held off, last status before it
+    assert_eq!(buf[1], 3);
+    assert_eq!(&buf[2..4], &[255, 0]);
+    assert_eq!(counter(&health, 0), 1);
+}
@@ -0,0 +1,126 @@
+//! Interface sets, bridge rules and deduplication of transfers from redundant interfaces.
+
+use vhrd_module_tools::can_health::Interface;
+use vhrd_module_tools::can_interfaces::{bridge_target, BridgeRule, BridgeRules, Interfaces, BRIDGE_RULES};
+use vhrd_module_tools::uavcan::filter::Filter;
+use vhrd_module_tools::uavcan::redundancy::Deduplicator;
+use vhrdcan::FrameId;
+
+const TIMEOUT_MS: u32 = 1000;
+
+fn extended(id: u32) -> FrameId {
+    FrameId::new_extended(id).unwrap()
+}
+
+fn rules(rules: &[BridgeRule]) -> BridgeRules {
+    let mut all = [BridgeRule::DISABLED; BRIDGE_RULES];
+    all[..rules.len()].copy_from_slice(rules);
+    all
+}
+
+#[test]
+fn interfaces_from_bits() {
+    assert_eq!(Interfaces::from_bits(0), None);
+    assert_eq!(Interfaces::from_bits(0b100), None);
+    assert_eq!(Interfaces::from_bits(0b111), None);
+    assert_eq!(Interfaces::from_bits(0b11), Some(Interfaces::ALL));
+    for interface in [Interface::Mcp, Interface::Stm] {
+        let only = Interfaces::only(interface);
+        assert_eq!(Interfaces::from_bits(only.bits()), Some(only));
+        assert!(only.contains(interface));
+        assert!(Interfaces::ALL.contains(interface));
+    }
+    assert!(!Interfaces::only(Interface::Mcp).contains(Interface::Stm));
+}
+
+#[test]
+fn bridge_disabled_rules_forward_nothing() {
+    let rules = [BridgeRule::DISABLED; BRIDGE_RULES];
+    assert_eq!(bridge_target(&rules, Interface::Mcp, extended(0x1234)), None);
+    assert_eq!(bridge_target(&rules, Interface::Stm, extended(0x1234)), None);
+}
+
+#[test]
+fn bridge_forwards_matching_frames_to_the_other_interface() {
+    let rules = rules(&[BridgeRule::new(Interface::Mcp, Filter::new(0x1200, 0xff00))]);
+    assert_eq!(bridge_target(&rules, Interface::Mcp, extended(0x1234)), Some(Interface::Stm));
+    assert_eq!(bridge_target(&rules, Interface::Mcp, extended(0x1334)), None);
+    // Rules only apply to the interface they forward from
+    assert_eq!(bridge_target(&rules, Interface::Stm, extended(0x1234)), None);
+}
+
+#[test]
+fn bridge_rules_in_both_directions() {
+    let rules = rules(&[
+        BridgeRule::new(Interface::Mcp, Filter::new(0x100, 0xf00)),
+        BridgeRule::new(Interface::Stm, Filter::ACCEPT_ALL),
+    ]);
+    assert_eq!(bridge_target(&rules, Interface::Mcp, extended(0x1ff)), Some(Interface::Stm));
+    assert_eq!(bridge_target(&rules, Interface::Mcp, extended(0x2ff)), None);
+    assert_eq!(bridge_target(&rules, Interface::Stm, extended(0x2ff)), Some(Interface::Mcp));
+}
+
+#[test]
+fn bridge_ignores_standard_frames() {
+    let rules = rules(&[BridgeRule::new(Interface::Mcp, Filter::ACCEPT_ALL)]);
+    let id = FrameId::new_standard(0x123).unwrap();
+    assert_eq!(bridge_target(&rules, Interface::Mcp, id), None);
+}
+
+#[test]
+fn dedup_drops_copies_from_the_other_interface() {
+    let mut dedup: Deduplicator<4> = Deduplicator::new(TIMEOUT_MS);
+    assert!(dedup.accept(0x1234, 0, 0, 0));
+    assert!(!dedup.accept(0x1234, 0, 1, 1));
+    assert!(dedup.accept(0x1234, 1, 0, 10));
+    assert!(!dedup.accept(0x1234, 1, 1, 11));
+}
+
+#[test]
+fn dedup_drops_repeats_on_the_same_interface() {
+    let mut dedup: Deduplicator<4> = Deduplicator::new(TIMEOUT_MS);
+    assert!(dedup.accept(0x1234, 5, 0, 0));
+    assert!(!dedup.accept(0x1234, 5, 0, 1));
+    // After the timeout the same transfer ID is a new transfer
+    assert!(dedup.accept(0x1234, 5, 0, TIMEOUT_MS + 2));
+}
+
+#[test]
+fn dedup_ignores_priority() {
+    let mut dedup: Deduplicator<4> = Deduplicator::new(TIMEOUT_MS);
+    assert!(dedup.accept(0x1234, 0, 0, 0));
+    assert!(!dedup.accept((7 << 26) | 0x1234, 0, 1, 1));
+}
+
+#[test]
+fn dedup_switches_interface_after_timeout() {
+    let mut dedup: Deduplicator<4> = Deduplicator::new(TIMEOUT_MS);
+    assert!(dedup.accept(0x1234, 0, 0, 0));
+    assert!(!dedup.accept(0x1234, 1, 1, TIMEOUT_MS));
+    assert!(dedup.accept(0x1234, 1, 1, TIMEOUT_MS + 1));
+    // Now following the second interface
+    assert!(!dedup.accept(0x1234, 2, 0, TIMEOUT_MS + 2));
+    assert!(dedup.accept(0x1234, 2, 1, TIMEOUT_MS + 3));
+}
+
+#[test]
+fn dedup_sessions_are_independent() {
+    let mut dedup: Deduplicator<4> = Deduplicator::new(TIMEOUT_MS);
+    assert!(dedup.accept(0x1234, 0, 0, 0));
+    assert!(dedup.accept(0x1235, 0, 1, 1));
+    assert!(!dedup.accept(0x1234, 0, 1, 2));
+    assert!(!dedup.accept(0x1235, 0, 0, 3));
+}
+
+#[test]
+fn dedup_forgets_least_recently_heard_session() {
+    let mut dedup: Deduplicator<2> = Deduplicator::new(TIMEOUT_MS);
+    assert!(dedup.accept(0x1001, 0, 0, 0));
+    assert!(dedup.accept(0x1002, 0, 0, 10));
+    assert!(dedup.accept(0x1002, 1, 0, 20));
+    // 0x1001 is evicted, a copy from the other interface is taken as new
+    assert!(dedup.accept(0x1003, 0, 0, 30));
+    assert!(dedup.accept(0x1001, 0, 1, 40));
+    // Evicted 0x1002, kept 0x1003
+    assert!(!dedup.accept(0x1003, 0, 1, 41));
+}
@@ -0,0 +1,77 @@
+//! Crash report encoding and reset cause decoding from RCC_CSR.
+
+use vhrd_module_tools::liveness::Task;
+use vhrd_module_tools::uavcan::crash_report::{Cause, CrashReport, Fault, HardFaultFrame, ResetCause, MAX_FILE_LEN, MAX_SIZE};
+
+fn roundtrip(report: &CrashReport) -> Vec<u8> {
+    let mut buf = [0u8; MAX_SIZE];
+    let len = report.encode(&mut buf).unwrap();
+    buf[..len].to_vec()
+}
+
+#[test]
+fn panic_keeps_end_of_path() {
+    let file = b"/home/user/.cargo/registry/src/heapless-0.7.16/src/vec.rs";
+    let report = CrashReport {
+        reset_cause: ResetCause::from_csr(0x1C00_0000),
+        uptime_ms: 12_345,
+        fault: Fault::Panic { line: 42, file, message: &[b'm'; 200] },
+    };
+    let encoded = roundtrip(&report);
+    assert_eq!(encoded.len(), MAX_SIZE);
+    let decoded = CrashReport::decode(&encoded).unwrap();
+    assert_eq!(decoded.uptime_ms, 12_345);
+    match decoded.fault {
+        Fault::Panic { line, file: f, message } => {
+            assert_eq!(line, 42);
+            assert_eq!(f, &file[file.len() - MAX_FILE_LEN..]);
+            assert!(f.ends_with(b"src/vec.rs"));
+            assert_eq!(message.len(), 80);
+        }
+        f => panic!("{:?}", f),
+    }
+}
+
+#[test]
+fn hard_fault_registers_survive() {
+    let frame = HardFaultFrame { r0: 1, r1: 2, r2: 3, r3: 4, r12: 12, lr: 0x0800_3001, pc: 0x0800_4000, xpsr: 0x6100_0003, sp: 0x2000_1f00 };
+    let report = CrashReport { reset_cause: ResetCause::from_bits(0), uptime_ms: 0, fault: Fault::HardFault(frame) };
+    assert_eq!(CrashReport::decode(&roundtrip(&report)).unwrap(), report);
+}
+
+#[test]
+fn watchdog_names_late_task() {
+    let report = CrashReport {
+        reset_cause: ResetCause::from_csr(0x2400_0000),
+        uptime_ms: 60_000,
+        fault: Fault::Watchdog { task: Task::Idle as u8, silent_ms: 5_700 },
+    };
+    let encoded = roundtrip(&report);
+    let decoded = CrashReport::decode(&encoded).unwrap();
+    assert_eq!(decoded, report);
+    match decoded.fault {
+        Fault::Watchdog { task, .. } => assert_eq!(Task::from_u8(task), Some(Task::Idle)),
+        f => panic!("{:?}", f),
+    }
+}
+
+#[test]
+fn reset_without_crash() {
+    let report = CrashReport { reset_cause: ResetCause::from_csr(0x0C00_0000), uptime_ms: 0, fault: Fault::None };
+    let encoded = roundtrip(&report);
+    assert_eq!(encoded.len(), 6);
+    assert_eq!(CrashReport::decode(&encoded).unwrap(), report);
+    assert_eq!(CrashReport::decode(&[0, 0, 0, 0, 0, 9]).err(), Some(vhrd_module_tools::uavcan::register::Error::Unsupported));
+}
+
+#[test]
+fn most_specific_reset_cause() {
+    // Pin flag comes along with every internal reset
+    assert_eq!(ResetCause::from_csr(0x0C00_0000).cause(), Cause::PowerOn);
+    assert_eq!(ResetCause::from_csr(0x1400_0000).cause(), Cause::Software);
+    assert_eq!(ResetCause::from_csr(0x2400_0000).cause(), Cause::IndependentWatchdog);
+    assert_eq!(ResetCause::from_csr(0x4400_0000).cause(), Cause::WindowWatchdog);
+    assert_eq!(ResetCause::from_csr(0x0400_0000).cause(), Cause::Pin);
+    // RMVF and LSI bits are not reset flags
+    assert_eq!(ResetCause::from_csr(0x0100_0003).cause(), Cause::Unknown);
+}
@@ -0,0 +1,204 @@
+//! Acceptance filters: whatever the router accepts must pass the filters generated for it, however
+//! many subscriptions are merged into however few hardware filters.
+
+use heapless::Vec as HVec;
+use uavcan_llr::types::{CanId, NodeId, Priority, ServiceId, SubjectId};
+use vhrdcan::{Frame, FrameId};
+use vhrd_module_tools::uavcan::filter::{generate, merge_closest, Filter, TwoMaskFilters, MAX_FILTERS};
+use vhrd_module_tools::uavcan::router::{route, Endpoint, Port, Source, Subscription};
+
+const LOCAL: u8 = 42;
+
+/// xorshift32, tests are reproducible
+struct Rng(u32);
+
+impl Rng {
+    fn next(&mut self) -> u32 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 17;
+        self.0 ^= self.0 << 5;
+        self.0
+    }
+
+    fn below(&mut self, n: u32) -> u32 {
+        self.next() % n
+    }
+}
+
+fn node(id: u8) -> NodeId {
+    NodeId::new(id).unwrap()
+}
+
+fn priority(rng: &mut Rng) -> Priority {
+    [Priority::Exceptional, Priority::Fast, Priority::Nominal, Priority::Low, Priority::Optional][rng.below(5) as usize]
+}
+
+fn source(rng: &mut Rng) -> Source {
+    match rng.below(3) {
+        0 => Source::Any,
+        _ => Source::Node(node(1 + rng.below(120) as u8)),
+    }
+}
+
+fn random_subscription(rng: &mut Rng) -> Subscription {
+    match rng.below(5) {
+        0..=2 => Subscription::subject(source(rng), SubjectId::new(rng.below(8192) as u16).unwrap(), Endpoint::Module),
+        3 => Subscription::service(source(rng), ServiceId::new(rng.below(512) as u16).unwrap(), Endpoint::Module),
+        _ => Subscription::raw_extended_masked(rng.below(1 << 29), 0x1FFF_0000 | rng.below(1 << 16), Endpoint::VescFeedback),
+    }
+}
+
+/// A frame the router accepts for `subscription`
+fn matching_frame(subscription: &Subscription, rng: &mut Rng) -> Frame<8> {
+    let source = match subscription.source {
+        Source::Any => node(rng.below(128) as u8),
+        Source::Node(node_id) => node_id,
+    };
+    let id: FrameId = match subscription.port {
+        Port::Subject(subject_id) => {
+            CanId::new_message_kind(source, subject_id, false, priority(rng)).into()
+        }
+        Port::Service(service_id) => {
+            CanId::new_service_kind(source, node(LOCAL), service_id, rng.below(2) == 0, priority(rng)).into()
+        }
+        Port::RawExtended { id, mask } => {
+            FrameId::new_extended((id & mask) | (rng.below(1 << 29) & !mask)).unwrap()
+        }
+    };
+    Frame::new(id, &[0xE0]).unwrap()
+}
+
+fn raw(frame: &Frame<8>) -> u32 {
+    match frame.id {
+        FrameId::Extended(eid) => eid.inner(),
+        FrameId::Standard(_) => unreachable!(),
+    }
+}
+
+fn two_mask_matches(filters: &TwoMaskFilters, id: u32) -> bool {
+    filters.ids0.contains(&(id & filters.mask0)) || filters.ids1.contains(&(id & filters.mask1))
+}
+
+fn check_generated<const N: usize>(subscriptions: &[Subscription], rng: &mut Rng) {
+    let tables: &[&[Subscription]] = &[subscriptions];
+    let filters = generate::<N>(tables, Some(node(LOCAL)));
+    assert!(!filters.is_empty() && filters.len() <= N);
+    let two_mask = TwoMaskFilters::new(&generate::<6>(tables, Some(node(LOCAL))));
+    for subscription in subscriptions {
+        for _ in 0..8 {
+            let frame = matching_frame(subscription, rng);
+            assert!(route(tables, Some(node(LOCAL)), &frame).is_some(), "{:?}", subscription);
+            let id = raw(&frame);
+            assert!(filters.iter().any(|f| f.matches(id)), "{:08x} of {:?} rejected by {:?}", id, subscription, filters);
+            assert!(two_mask_matches(&two_mask, id), "{:08x} of {:?} rejected by {:?}", id, subscription, two_mask);
+        }
+    }
+    // And the other way around: anything routed passes
+    for _ in 0..2000 {
+        let frame = Frame::new(FrameId::new_extended(rng.below(1 << 29)).unwrap(), &[0xE0]).unwrap();
+        if route(tables, Some(node(LOCAL)), &frame).is_some() {
+            assert!(filters.iter().any(|f| f.matches(raw(&frame))));
+        }
+    }
+}
+
+#[test]
+fn subscribed_ids_pass_merged_filters() {
+    let mut rng = Rng(0x1234_5678);
+    for round in 0..200 {
+        let count = 1 + round % MAX_FILTERS + round / 100;
+        let subscriptions: Vec<Subscription> = (0..count).map(|_| random_subscription(&mut rng)).collect();
+        check_generated::<1>(&subscriptions, &mut rng);
+        check_generated::<2>(&subscriptions, &mut rng);
+        check_generated::<3>(&subscriptions, &mut rng);
+        check_generated::<14>(&subscriptions, &mut rng);
+        check_generated::<32>(&subscriptions, &mut rng);
+    }
+}
+
+#[test]
+fn filters_are_exact_when_they_fit() {
+    let subscriptions = [
+        Subscription::subject(Source::Node(node(3)), SubjectId::new(20).unwrap(), Endpoint::Module),
+        Subscription::service(Source::Any, ServiceId::new(384).unwrap(), Endpoint::RegisterAccess),
+    ];
+    let filters = generate::<4>(&[&subscriptions], Some(node(LOCAL)));
+    let expected: Vec<Filter> = subscriptions.iter().map(|s| Filter::from_subscription(s, Some(node(LOCAL))).unwrap()).collect();
+    assert_eq!(&filters[..], &expected[..]);
+}
+
+#[test]
+fn services_are_skipped_while_anonymous() {
+    let service = Subscription::service(Source::Any, ServiceId::new(384).unwrap(), Endpoint::RegisterAccess);
+    assert_eq!(Filter::from_subscription(&service, None), None);
+    let subject = Subscription::subject(Source::Any, SubjectId::new(8166).unwrap(), Endpoint::PnpAllocation);
+    let filters = generate::<4>(&[&[service, subject]], None);
+    assert_eq!(filters.len(), 1);
+    // Anonymous allocation responses from any source
+    let id: FrameId = CanId::new_message_kind(node(127), SubjectId::new(8166).unwrap(), false, Priority::Slow).into();
+    assert!(filters[0].matches(raw(&Frame::new(id, &[]).unwrap())));
+}
+
+#[test]
+fn covered_filters_are_dropped() {
+    let subscriptions = [
+        Subscription::subject(Source::Node(node(3)), SubjectId::new(20).unwrap(), Endpoint::Module),
+        Subscription::subject(Source::Any, SubjectId::new(20).unwrap(), Endpoint::Module),
+        Subscription::subject(Source::Node(node(4)), SubjectId::new(20).unwrap(), Endpoint::Module),
+    ];
+    let filters = generate::<4>(&[&subscriptions], None);
+    assert_eq!(&filters[..], &[Filter::from_subscription(&subscriptions[1], None).unwrap()]);
+}
+
+#[test]
+fn merge_picks_the_closest_pair() {
+    let a = Filter::new(0b1000_0000, 0xFF);
+    let b = Filter::new(0b1000_0001, 0xFF);
+    let c = Filter::new(0b0111_0000, 0xFF);
+    for order in [[a, b, c], [c, a, b], [b, c, a]] {
+        let mut filters: HVec<Filter, 4> = HVec::from_slice(&order).unwrap();
+        merge_closest(&mut filters);
+        assert_eq!(filters.len(), 2);
+        assert!(filters.contains(&Filter::new(0b1000_0000, 0xFE)), "{:?}", filters);
+        assert!(filters.contains(&c));
+    }
+}
+
+#[test]
+fn merge_drops_newly_covered_filters() {
+    let a = Filter::new(0b00, 0b11);
+    let b = Filter::new(0b01, 0b11);
+    // Covered by the merge of a and b only
+    let c = Filter::new(0b000, 0b110);
+    let mut filters: HVec<Filter, 4> = HVec::from_slice(&[a, b, c]).unwrap();
+    merge_closest(&mut filters);
+    assert_eq!(&filters[..], &[Filter::new(0, 0b10)]);
+}
+
+#[test]
+fn two_masks_group_similar_filters() {
+    // Two families differing in the low bits only, each fits one mask
+    let filters = [
+        Filter::new(0x100, 0xFFF),
+        Filter::new(0x101, 0xFFF),
+        Filter::new(0xA00, 0xFFF),
+        Filter::new(0xA01, 0xFFF),
+        Filter::new(0xA02, 0xFFF),
+    ];
+    let two_mask = TwoMaskFilters::new(&filters);
+    assert_eq!(two_mask.mask0, 0xFFF);
+    assert_eq!(two_mask.mask1, 0xFFF);
+    for f in &filters {
+        assert!(two_mask_matches(&two_mask, f.id));
+    }
+    assert!(!two_mask_matches(&two_mask, 0x102));
+}
+
+#[test]
+fn two_masks_with_few_filters() {
+    let all = TwoMaskFilters::new(&[]);
+    assert_eq!((all.mask0, all.mask1), (0, 0));
+    let one = Filter::new(0x123, 0xFFF);
+    let single = TwoMaskFilters::new(&[one]);
+    assert_eq!(single, TwoMaskFilters { mask0: 0xFFF, ids0: [0x123; 2], mask1: 0xFFF, ids1: [0x123; 4] });
+}
@@ -0,0 +1,91 @@
+//! uavcan.node.GetInfo response, with the build description in the certificate field.
+
+use vhrd_module_tools::uavcan::crc::crc64we;
+use vhrd_module_tools::uavcan::get_info::{NodeInfo, Version, MAX_BUILD_INFO_LEN, MAX_RESPONSE_SIZE, TRUNCATED};
+
+fn info<'a>(name: &'a str, build_info: &'a [&'a str]) -> NodeInfo<'a> {
+    NodeInfo {
+        hardware_version: Version { major: 0, minor: 0 },
+        software_version: Version { major: 1, minor: 2 },
+        software_vcs_revision_id: 0x0102_0304_0506_0708,
+        unique_id: [0xAA; 16],
+        name,
+        software_image_crc: Some(0x1234),
+        build_info,
+    }
+}
+
+fn encode(info: &NodeInfo) -> Vec<u8> {
+    let mut buf = [0u8; MAX_RESPONSE_SIZE];
+    let len = info.encode(&mut buf).unwrap();
+    buf[..len].to_vec()
+}
+
+/// Certificate field: length and bytes at the end of the response
+fn build_info(response: &[u8], name_len: usize) -> Vec<u8> {
+    let at = 2 * 3 + 8 + 16 + 1 + name_len + 1 + 8;
+    let len = response[at] as usize;
+    assert_eq!(response.len(), at + 1 + len);
+    response[at + 1..].to_vec()
+}
+
+#[test]
+fn layout() {
+    let response = encode(&info("tech.vhrd.module.button", &["f051c8u", "mcp25625", "1700000000", "a,b"]));
+    assert_eq!(&response[..6], [1, 0, 0, 0, 1, 2]);
+    assert_eq!(&response[6..14], 0x0102_0304_0506_0708u64.to_le_bytes());
+    assert_eq!(&response[14..30], [0xAA; 16]);
+    assert_eq!(response[30] as usize, "tech.vhrd.module.button".len());
+    assert_eq!(&response[31..54], b"tech.vhrd.module.button");
+    assert_eq!(response[54], 1);
+    assert_eq!(&response[55..63], 0x1234u64.to_le_bytes());
+    assert_eq!(build_info(&response, 23), b"f051c8u mcp25625 1700000000 a,b");
+}
+
+#[test]
+fn no_image_crc_and_no_build_info() {
+    let mut info = info("x", &[]);
+    info.software_image_crc = None;
+    let response = encode(&info);
+    assert_eq!(&response[32..], [0, 0]);
+}
+
+#[test]
+fn long_build_info_is_cut_with_marker() {
+    let features = "f".repeat(200);
+    let response = encode(&info("x", &["f072c8u", "stm", "1700000000", &features]));
+    let cut = build_info(&response, 1);
+    assert_eq!(cut.len(), MAX_BUILD_INFO_LEN);
+    assert!(cut.starts_with(b"f072c8u stm 1700000000 fff"));
+    assert_eq!(*cut.last().unwrap(), TRUNCATED);
+}
+
+#[test]
+fn exactly_fitting_build_info_is_not_marked() {
+    let features = "f".repeat(MAX_BUILD_INFO_LEN - 4);
+    let response = encode(&info("x", &["abc", &features]));
+    let kept = build_info(&response, 1);
+    assert_eq!(kept.len(), MAX_BUILD_INFO_LEN);
+    assert_eq!(*kept.last().unwrap(), b'f');
+}
+
+#[test]
+fn long_name_is_cut() {
+    let name = "n".repeat(60);
+    let response = encode(&info(&name, &["a"]));
+    assert_eq!(response[30], 50);
+    assert_eq!(build_info(&response, 50), b"a");
+}
+
+#[test]
+fn largest_response_fits() {
+    let name = "n".repeat(60);
+    let features = "f".repeat(200);
+    assert_eq!(encode(&info(&name, &[&features])).len(), MAX_RESPONSE_SIZE);
+}
+
+#[test]
+fn image_crc_is_crc64_we() {
+    assert_eq!(crc64we(b"123456789"), 0x62EC_59E3_F1A4_F00A);
+    assert_eq!(crc64we(b""), 0);
+}
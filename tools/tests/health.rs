@@ -0,0 +1,57 @@
+//! Health aggregation: the worst raised condition wins, every reason shows in the report.
+
+use vhrd_module_tools::health::conditions::{Conditions, Reason};
+use vhrd_module_tools::uavcan::health_report::{HealthReport, SIZE};
+use vhrd_module_tools::uavcan::heartbeat::Health;
+
+#[test]
+fn nothing_raised_is_nominal() {
+    let conditions = Conditions::new();
+    assert_eq!(conditions.health(), Health::Norminal);
+    assert_eq!(conditions.report(), HealthReport { health: Health::Norminal, failures: 0, warnings: 0 });
+}
+
+#[test]
+fn worst_condition_wins() {
+    let mut conditions = Conditions::new();
+    assert!(conditions.raise(Reason::VescFeedbackStale, Health::Warning));
+    assert_eq!(conditions.health(), Health::Warning);
+    assert!(conditions.raise(Reason::LoadCellTimeout, Health::Failure));
+    assert_eq!(conditions.health(), Health::Failure);
+    assert!(conditions.raise(Reason::LoadCellTimeout, Health::Norminal));
+    assert_eq!(conditions.health(), Health::Warning);
+    assert_eq!(conditions.report().warnings, 1 << Reason::VescFeedbackStale as u8);
+}
+
+#[test]
+fn raising_again_replaces_severity() {
+    let mut conditions = Conditions::new();
+    conditions.raise(Reason::CanErrors, Health::Failure);
+    assert!(!conditions.raise(Reason::CanErrors, Health::Failure));
+    assert!(conditions.raise(Reason::CanErrors, Health::Warning));
+    let report = conditions.report();
+    assert_eq!((report.failures, report.warnings), (0, 1));
+    assert!(conditions.raise(Reason::CanErrors, Health::Norminal));
+    assert!(!conditions.raise(Reason::CanErrors, Health::Norminal));
+}
+
+#[test]
+fn report_roundtrip() {
+    let mut conditions = Conditions::new();
+    conditions.raise(Reason::GateDriverFault, Health::Failure);
+    conditions.raise(Reason::ConfigCorrupt, Health::Warning);
+    let report = conditions.report();
+    let payload = report.encode();
+    assert_eq!(payload, [Health::Failure as u8, 0b10, 0, 0b10_0000, 0]);
+    assert_eq!(HealthReport::decode(&payload).unwrap(), report);
+    assert!(HealthReport::decode(&payload[..SIZE - 1]).is_err());
+    assert!(HealthReport::decode(&[7, 0, 0, 0, 0]).is_err());
+}
+
+#[test]
+fn reason_numbers_roundtrip() {
+    for i in 0..Reason::COUNT as u8 {
+        assert_eq!(Reason::from_u8(i).map(|r| r as u8), Some(i));
+    }
+    assert_eq!(Reason::from_u8(Reason::COUNT as u8), None);
+}
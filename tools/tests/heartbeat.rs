@@ -0,0 +1,31 @@
+//! Heartbeat encoding: uptime, health and mode packed together, module status in the vendor byte.
+
+use vhrd_module_tools::uavcan::heartbeat::{encode, Health, Mode, SIZE};
+
+#[test]
+fn standard_size_with_status_in_vendor_byte() {
+    let payload = encode(0x0102_0304, Health::Warning, Mode::Firmware, 0xA5);
+    assert_eq!(SIZE, 7);
+    assert_eq!(payload, [0x04, 0x03, 0x02, 0x01, 0b0000_1001, 0, 0xA5]);
+}
+
+#[test]
+fn bootloader_mode_and_failure() {
+    let payload = encode(0, Health::Failure, Mode::Bootloader, 0);
+    assert_eq!(payload, [0, 0, 0, 0, 0b0000_0010, 0, 0]);
+}
+
+#[test]
+fn uptime_past_16_bits() {
+    let uptime_s = 49 * 24 * 3600;
+    let payload = encode(uptime_s, Health::Norminal, Mode::Firmware, 0);
+    assert_eq!(u32::from_le_bytes([payload[0], payload[1], payload[2], payload[3]]), uptime_s);
+}
+
+#[test]
+fn health_numbers_roundtrip() {
+    for health in [Health::Norminal, Health::Warning, Health::Failure] {
+        assert_eq!(Health::from_u8(health as u8), Some(health));
+    }
+    assert_eq!(Health::from_u8(3), None);
+}
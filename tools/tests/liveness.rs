@@ -0,0 +1,50 @@
+//! Watchdog supervisor check-ins: only watched tasks count, deadlines survive the ms counter wrap.
+
+use vhrd_module_tools::liveness::{Liveness, Task};
+
+#[test]
+fn nothing_watched_is_never_late() {
+    let liveness = Liveness::new();
+    assert_eq!(liveness.overdue(0), None);
+    assert_eq!(liveness.overdue(u32::MAX), None);
+}
+
+#[test]
+fn late_task_is_named() {
+    let mut liveness = Liveness::new();
+    liveness.check_in(Task::HealthCheck, 0, 1500);
+    liveness.check_in(Task::Idle, 0, 5000);
+    assert_eq!(liveness.overdue(1500), None);
+    assert_eq!(liveness.overdue(1501), Some((Task::HealthCheck, 1501)));
+
+    liveness.check_in(Task::HealthCheck, 1000, 1500);
+    assert_eq!(liveness.overdue(2000), None);
+    assert_eq!(liveness.overdue(5001), Some((Task::HealthCheck, 4001)));
+    liveness.check_in(Task::HealthCheck, 5001, 1500);
+    assert_eq!(liveness.overdue(5001), Some((Task::Idle, 5001)));
+}
+
+#[test]
+fn forgotten_task_is_not_watched() {
+    let mut liveness = Liveness::new();
+    liveness.check_in(Task::Blink, 0, 520);
+    liveness.forget(Task::Blink);
+    assert_eq!(liveness.overdue(10_000), None);
+}
+
+#[test]
+fn deadline_across_counter_wrap() {
+    let mut liveness = Liveness::new();
+    let now = u32::MAX - 100;
+    liveness.check_in(Task::RampVesc, now, 600);
+    assert_eq!(liveness.overdue(now.wrapping_add(600)), None);
+    assert_eq!(liveness.overdue(now.wrapping_add(601)), Some((Task::RampVesc, 601)));
+}
+
+#[test]
+fn task_numbers_roundtrip() {
+    for i in 0..Task::COUNT as u8 {
+        assert_eq!(Task::from_u8(i).map(|t| t as u8), Some(i));
+    }
+    assert_eq!(Task::from_u8(Task::COUNT as u8), None);
+}
@@ -0,0 +1,41 @@
+//! Runtime log filter: level applies to everything, target mask only to debug and trace.
+
+use vhrd_module_tools::log_filter::{Level, LogFilter, Target, Targets};
+
+#[test]
+fn default_lets_everything_through() {
+    for level in [Level::Trace, Level::Debug, Level::Info, Level::Warn, Level::Error] {
+        assert!(LogFilter::ALL.allows(level, None));
+        assert!(LogFilter::ALL.allows(level, Some(Target::Health)));
+    }
+}
+
+#[test]
+fn verbose_canbus_only() {
+    let filter = LogFilter { level: Level::Trace, targets: Targets::from_bits(1 << Target::Canbus as u8).unwrap() };
+    assert!(filter.allows(Level::Trace, Some(Target::Canbus)));
+    assert!(!filter.allows(Level::Trace, Some(Target::Vesc)));
+    assert!(!filter.allows(Level::Debug, Some(Target::Afe)));
+    // Untagged messages and info and above of other targets are not affected by the mask
+    assert!(filter.allows(Level::Trace, None));
+    assert!(filter.allows(Level::Info, Some(Target::Vesc)));
+    assert!(filter.allows(Level::Warn, Some(Target::Vesc)));
+}
+
+#[test]
+fn level_applies_to_all_targets() {
+    let filter = LogFilter { level: Level::Warn, targets: Targets::ALL };
+    assert!(!filter.allows(Level::Info, Some(Target::Canbus)));
+    assert!(!filter.allows(Level::Info, None));
+    assert!(filter.allows(Level::Error, Some(Target::Button)));
+    let off = LogFilter { level: Level::Off, targets: Targets::ALL };
+    assert!(!off.allows(Level::Error, None));
+}
+
+#[test]
+fn unknown_values_are_rejected() {
+    assert_eq!(Level::from_u8(6), None);
+    assert_eq!(Level::from_u8(5), Some(Level::Off));
+    assert_eq!(Targets::from_bits(1 << 6), None);
+    assert_eq!(Targets::from_bits(0).map(|t| t.bits()), Some(0));
+}
@@ -0,0 +1,101 @@
+//! Log output of several nodes on the log subject, collected back into per node streams.
+
+use uavcan_llr::types::{CanId, NodeId, Priority, SubjectId, TransferId};
+use vhrdcan::Frame;
+use vhrd_module_tools::log_stream::{format_record, LogCollector, LOG_SUBJECT_ID, TEXT_LOG_SUBJECT_ID};
+use vhrd_module_tools::uavcan::diagnostic::{Record, Severity};
+use vhrd_module_tools::uavcan::tx::{self, TransferSlicer};
+
+/// Same chunking as `task::log_can`
+const CHUNK_SIZE: usize = 48;
+
+fn chunks(source: u8, subject_id: u16, stream: &[u8], transfer_id: &mut TransferId) -> Vec<Vec<Frame<8>>> {
+    let source = NodeId::new(source).unwrap();
+    let id = CanId::new_message_kind(source, SubjectId::new(subject_id).unwrap(), false, Priority::Slow);
+    stream.chunks(CHUNK_SIZE)
+        .map(|chunk| {
+            let transfer_id = tx::next_transfer_id(transfer_id);
+            TransferSlicer::<8>::new(id.into(), chunk, transfer_id.inner()).collect()
+        })
+        .collect()
+}
+
+#[test]
+fn interleaved_nodes_are_separated() {
+    let a: Vec<u8> = (0..200).collect();
+    let b: Vec<u8> = (0..100).map(|x| 255 - x).collect();
+    let (mut tid_a, mut tid_b) = (TransferId::default(), TransferId::default());
+    let chunks_a = chunks(5, LOG_SUBJECT_ID, &a, &mut tid_a);
+    let chunks_b = chunks(7, LOG_SUBJECT_ID, &b, &mut tid_b);
+
+    // Frames of the transfers from both nodes alternate on the bus
+    let mut frames = Vec::new();
+    for i in 0..chunks_a.len().max(chunks_b.len()) {
+        let (fa, fb) = (chunks_a.get(i).cloned().unwrap_or_default(), chunks_b.get(i).cloned().unwrap_or_default());
+        for j in 0..fa.len().max(fb.len()) {
+            frames.extend(fa.get(j));
+            frames.extend(fb.get(j));
+        }
+    }
+
+    let mut collector = LogCollector::new(LOG_SUBJECT_ID);
+    let (mut got_a, mut got_b) = (Vec::new(), Vec::new());
+    for frame in &frames {
+        match collector.on_frame(frame, 0) {
+            Some((node_id, chunk)) if node_id.inner() == 5 => got_a.extend(chunk),
+            Some((node_id, chunk)) if node_id.inner() == 7 => got_b.extend(chunk),
+            Some((node_id, _)) => panic!("unexpected node {}", node_id.inner()),
+            None => {}
+        }
+    }
+    assert_eq!(got_a, a);
+    assert_eq!(got_b, b);
+}
+
+#[test]
+fn other_subjects_are_ignored() {
+    let mut collector = LogCollector::new(LOG_SUBJECT_ID);
+    let mut transfer_id = TransferId::default();
+    for frame in chunks(5, LOG_SUBJECT_ID + 1, &[1, 2, 3], &mut transfer_id).concat() {
+        assert!(collector.on_frame(&frame, 0).is_none());
+    }
+}
+
+#[test]
+fn text_records_of_nodes_are_interleaved() {
+    let records = [
+        (5, Record { timestamp_us: 1_500_000, severity: Severity::Info, text: b"Node 5 up" }),
+        (7, Record { timestamp_us: 20_000, severity: Severity::Error, text: &[b'x'; 64] }),
+    ];
+    let mut frames = Vec::new();
+    for (source, record) in &records {
+        let mut buf = [0u8; 128];
+        let len = record.encode(&mut buf).unwrap();
+        let id = CanId::new_message_kind(NodeId::new(*source).unwrap(), SubjectId::new(TEXT_LOG_SUBJECT_ID).unwrap(), false, Priority::Slow);
+        frames.push(TransferSlicer::<8>::new(id.into(), &buf[..len], 0).collect::<Vec<_>>());
+    }
+    // Single frame record of node 5 arrives in the middle of the multi-frame one of node 7
+    let (head, tail) = frames[1].split_at(3);
+    let frames: Vec<Frame<8>> = head.iter().chain(&frames[0]).chain(tail).cloned().collect();
+
+    let mut collector = LogCollector::new(TEXT_LOG_SUBJECT_ID);
+    let lines: Vec<String> = frames.iter()
+        .filter_map(|frame| collector.on_frame(frame, 0))
+        .map(|(node_id, payload)| format_record(node_id, &Record::decode(&payload).unwrap()))
+        .collect();
+    assert_eq!(lines[0], "[  5]      1.500 INFO     Node 5 up");
+    assert_eq!(lines[1], format!("[  7]      0.020 ERROR    {}", "x".repeat(64)));
+}
+
+#[test]
+fn text_is_cut_to_standard_length() {
+    let text = [b'a'; 300];
+    let record = Record { timestamp_us: 1 << 60, severity: Severity::Alert, text: &text };
+    let mut buf = [0u8; 300];
+    let len = record.encode(&mut buf).unwrap();
+    let decoded = Record::decode(&buf[..len]).unwrap();
+    assert_eq!(decoded.text.len(), 255);
+    assert_eq!(decoded.timestamp_us, 0);
+    assert_eq!(decoded.severity, Severity::Alert);
+    assert_eq!(Record::encoded_len(&buf), len);
+}
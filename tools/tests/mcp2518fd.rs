@@ -0,0 +1,88 @@
+//! MCP2518FD message objects: DLC to length mapping, padding and ID layout.
+
+use vhrdcan::{Frame, FrameId};
+use vhrd_module_tools::mcp2518fd::object::{rx_frame, tx_object, MAX_OBJECT_SIZE, MTU};
+use vhrd_module_tools::mcp2518fd::registers::*;
+
+fn extended(id: u32) -> FrameId {
+    FrameId::new_extended(id).unwrap()
+}
+
+fn frame(id: FrameId, len: usize) -> Frame<MTU> {
+    let data: Vec<u8> = (1..=len as u8).collect();
+    Frame::new(id, &data).unwrap()
+}
+
+fn t1(object: &[u8; MAX_OBJECT_SIZE]) -> u32 {
+    u32::from_le_bytes([object[4], object[5], object[6], object[7]])
+}
+
+#[test]
+fn dlc_to_length() {
+    for len in 0..=8 {
+        assert_eq!(len_to_dlc(len), len as u8);
+    }
+    let cases = [(9, 9), (12, 9), (13, 10), (16, 10), (17, 11), (20, 11), (21, 12), (24, 12), (25, 13), (32, 13), (33, 14), (48, 14), (49, 15), (64, 15)];
+    for (len, dlc) in cases {
+        assert_eq!(len_to_dlc(len), dlc, "len {}", len);
+        assert!(DLC_TO_LEN[dlc as usize] >= len);
+        assert!(DLC_TO_LEN[dlc as usize - 1] < len);
+    }
+}
+
+#[test]
+fn payload_sizes() {
+    for len in 0..=MTU {
+        let size = PayloadSize::fitting(len);
+        assert!(size.bytes() >= len);
+        assert_eq!(size.bytes(), DLC_TO_LEN[len_to_dlc(len).max(8) as usize]);
+    }
+    assert_eq!(PayloadSize::Bytes8.bytes(), 8);
+    assert_eq!(PayloadSize::Bytes64.bytes(), 64);
+}
+
+#[test]
+fn classic_frame_is_not_fd() {
+    let (object, object_len) = tx_object(&frame(extended(0x1234_5678), 5));
+    assert_eq!(t1(&object) & OBJ_DLC_MASK, 5);
+    assert_eq!(t1(&object) & (OBJ_FDF | OBJ_BRS), 0);
+    assert_ne!(t1(&object) & OBJ_IDE, 0);
+    // Padded to a whole word
+    assert_eq!(object_len, OBJ_HEADER_SIZE + 8);
+    assert_eq!(&object[OBJ_HEADER_SIZE..OBJ_HEADER_SIZE + 8], &[1, 2, 3, 4, 5, 0, 0, 0]);
+}
+
+#[test]
+fn fd_frame_is_padded_to_dlc_length() {
+    for (len, padded) in [(9, 12), (13, 16), (21, 24), (33, 48), (50, 64), (64, 64)] {
+        let (object, object_len) = tx_object(&frame(extended(1), len));
+        assert_eq!(object_len, OBJ_HEADER_SIZE + padded, "len {}", len);
+        assert_eq!(DLC_TO_LEN[(t1(&object) & OBJ_DLC_MASK) as usize], padded);
+        assert_eq!(t1(&object) & (OBJ_FDF | OBJ_BRS), OBJ_FDF | OBJ_BRS);
+        assert!(object[OBJ_HEADER_SIZE + len..OBJ_HEADER_SIZE + padded].iter().all(|b| *b == 0));
+    }
+}
+
+#[test]
+fn round_trip() {
+    let ids = [extended(0), extended(0x1FFF_FFFF), extended(0x107D_557F), FrameId::new_standard(0x7FF).unwrap(), FrameId::new_standard(0x123).unwrap()];
+    for id in ids {
+        for len in [0, 3, 8, 12, 64] {
+            let sent = frame(id, len);
+            let (object, _) = tx_object(&sent);
+            let received = rx_frame(&object, PayloadSize::Bytes64).unwrap();
+            assert_eq!(received.id, sent.id);
+            assert_eq!(received.data(), sent.data());
+        }
+    }
+}
+
+#[test]
+fn received_payload_is_cut_to_fifo_size() {
+    let (object, _) = tx_object(&frame(extended(1), 20));
+    let received = rx_frame(&object, PayloadSize::Bytes16).unwrap();
+    assert_eq!(received.data(), &(1..=16).collect::<Vec<u8>>()[..]);
+    // Padding up to the DLC length comes back as payload, transfer layer strips it
+    let (object, _) = tx_object(&frame(extended(1), 10));
+    assert_eq!(rx_frame(&object, PayloadSize::Bytes64).unwrap().data().len(), 12);
+}
@@ -0,0 +1,243 @@
+//! Non-volatile config store on a flash mock: power loss, corruption, compaction and bank
+//! sequence wrap-around.
+
+use std::convert::TryInto;
+use vhrd_module_tools::flash::{MemFlash, PAGE_SIZE};
+use vhrd_module_tools::nv_storage::{Store, StoreError, BANK_HEADER_SIZE, BANK_MAGIC, RECORD_HEADER_SIZE};
+use vhrd_module_tools::uavcan::crc::crc16;
+
+const PAGES: usize = 4;
+const BANK_SIZE: usize = PAGES / 2 * PAGE_SIZE;
+const VERSION: u8 = 1;
+
+fn read(store: &Store<MemFlash>, key: u16) -> Option<Vec<u8>> {
+    let mut buf = [0u8; 64];
+    store.read(key, VERSION, &mut buf).map(|len| buf[..len].to_vec())
+}
+
+fn bank_header(sequence: u16) -> [u8; BANK_HEADER_SIZE] {
+    let mut header = [0u8; BANK_HEADER_SIZE];
+    header[0..4].copy_from_slice(&BANK_MAGIC.to_le_bytes());
+    header[4..6].copy_from_slice(&sequence.to_le_bytes());
+    let crc = crc16(&header[0..6]);
+    header[6..8].copy_from_slice(&crc.to_le_bytes());
+    header
+}
+
+fn sequence(flash: &MemFlash, bank: usize) -> Option<u16> {
+    let contents = flash.contents();
+    let header = &contents[bank * BANK_SIZE..bank * BANK_SIZE + BANK_HEADER_SIZE];
+    if header == bank_header(u16::from_le_bytes([header[4], header[5]])) {
+        Some(u16::from_le_bytes([header[4], header[5]]))
+    } else {
+        None
+    }
+}
+
+/// Writes until the store compacts once, returns the last value written
+fn fill_until_compaction(store: &mut Store<MemFlash>, flash: &MemFlash, key: u16) -> Vec<u8> {
+    let erases = flash.erase_count();
+    let mut i = 0u32;
+    loop {
+        let value = i.to_le_bytes().to_vec();
+        store.write(key, VERSION, &value).unwrap();
+        if flash.erase_count() > erases {
+            return value;
+        }
+        i += 1;
+    }
+}
+
+#[test]
+fn empty_flash() {
+    let flash = MemFlash::new(PAGES);
+    let store = Store::new(flash);
+    assert!(store.is_empty());
+    assert!(!store.is_corrupt());
+    assert_eq!(read(&store, 1), None);
+}
+
+#[test]
+fn values_survive_reboot() {
+    let flash = MemFlash::new(PAGES);
+    let mut store = Store::new(flash.clone());
+    store.write(1, VERSION, &[1, 2, 3]).unwrap();
+    store.write(2, VERSION, &[4]).unwrap();
+    store.write(1, VERSION, &[5, 6]).unwrap();
+    let store = Store::new(flash);
+    assert_eq!(read(&store, 1), Some(vec![5, 6]));
+    assert_eq!(read(&store, 2), Some(vec![4]));
+    assert!(!store.is_corrupt());
+}
+
+#[test]
+fn same_value_is_not_written_again() {
+    let flash = MemFlash::new(PAGES);
+    let mut store = Store::new(flash.clone());
+    store.write(1, VERSION, &[1, 2]).unwrap();
+    let before = flash.contents();
+    store.write(1, VERSION, &[1, 2]).unwrap();
+    assert_eq!(flash.contents(), before);
+}
+
+#[test]
+fn other_version_reads_as_absent() {
+    let flash = MemFlash::new(PAGES);
+    let mut store = Store::new(flash);
+    store.write(1, VERSION, &[1]).unwrap();
+    let mut buf = [0u8; 8];
+    assert_eq!(store.read(1, VERSION + 1, &mut buf), None);
+}
+
+#[test]
+fn power_cut_mid_record() {
+    let flash = MemFlash::new(PAGES);
+    let mut store = Store::new(flash.clone());
+    store.write(1, VERSION, &[1, 2, 3, 4]).unwrap();
+    // Header and half of the value make it
+    flash.cut_power_after(4);
+    assert!(store.write(1, VERSION, &[9, 9, 9, 9, 9, 9, 9, 9]).is_err());
+    flash.restore_power();
+
+    let mut store = Store::new(flash.clone());
+    assert_eq!(read(&store, 1), Some(vec![1, 2, 3, 4]));
+    assert!(store.is_corrupt());
+    // Next write goes into a clean bank, the torn record is gone
+    store.write(2, VERSION, &[7]).unwrap();
+    let store = Store::new(flash);
+    assert!(!store.is_corrupt());
+    assert_eq!(read(&store, 1), Some(vec![1, 2, 3, 4]));
+    assert_eq!(read(&store, 2), Some(vec![7]));
+}
+
+#[test]
+fn power_cut_during_compaction_keeps_old_bank() {
+    // Number of writes that fit before compaction, on a twin
+    let twin = MemFlash::new(PAGES);
+    let mut store = Store::new(twin.clone());
+    store.write(2, VERSION, &[42]).unwrap();
+    let fit = u32::from_le_bytes(fill_until_compaction(&mut store, &twin, 1).try_into().unwrap());
+
+    let flash = MemFlash::new(PAGES);
+    let mut store = Store::new(flash.clone());
+    store.write(2, VERSION, &[42]).unwrap();
+    for i in 0..fit {
+        store.write(1, VERSION, &i.to_le_bytes()).unwrap();
+    }
+    // Record of key 2 is copied, bank header is not written
+    flash.cut_power_after(4);
+    assert!(store.write(1, VERSION, &fit.to_le_bytes()).is_err());
+    flash.restore_power();
+
+    let store = Store::new(flash.clone());
+    assert_eq!(sequence(&flash, 1), None);
+    assert_eq!(read(&store, 1), Some((fit - 1).to_le_bytes().to_vec()));
+    assert_eq!(read(&store, 2), Some(vec![42]));
+}
+
+#[test]
+fn power_cut_anywhere_in_compaction_keeps_old_or_new_value() {
+    let twin = MemFlash::new(PAGES);
+    let mut store = Store::new(twin.clone());
+    store.write(2, VERSION, &[42]).unwrap();
+    let fit = u32::from_le_bytes(fill_until_compaction(&mut store, &twin, 1).try_into().unwrap());
+
+    // Key 2 record, key 1 record and the bank header, in half-words
+    let compaction = (8 + 10 + BANK_HEADER_SIZE) / 2;
+    for budget in 0..=compaction {
+        let flash = MemFlash::new(PAGES);
+        let mut store = Store::new(flash.clone());
+        store.write(2, VERSION, &[42]).unwrap();
+        for i in 0..fit {
+            store.write(1, VERSION, &i.to_le_bytes()).unwrap();
+        }
+        flash.cut_power_after(budget);
+        let written = store.write(1, VERSION, &fit.to_le_bytes()).is_ok();
+        flash.restore_power();
+
+        // Either the old bank with the old value or the new bank with the new one
+        let store = Store::new(flash.clone());
+        assert_eq!(written, budget == compaction);
+        assert_eq!(sequence(&flash, 1).is_some(), written);
+        let expected = if written { fit } else { fit - 1 };
+        assert_eq!(read(&store, 1), Some(expected.to_le_bytes().to_vec()));
+        assert_eq!(read(&store, 2), Some(vec![42]));
+        assert!(!store.is_corrupt());
+    }
+}
+
+#[test]
+fn corrupted_record_crc() {
+    let flash = MemFlash::new(PAGES);
+    let mut store = Store::new(flash.clone());
+    store.write(1, VERSION, &[1, 2]).unwrap();
+    store.write(1, VERSION, &[3, 4]).unwrap();
+    // Flip a value bit of the second record
+    let second = BANK_HEADER_SIZE + RECORD_HEADER_SIZE + 2 + 2;
+    let byte = flash.contents()[second + RECORD_HEADER_SIZE];
+    flash.poke(second + RECORD_HEADER_SIZE, &[byte ^ 0x01]);
+
+    let store = Store::new(flash);
+    assert!(store.is_corrupt());
+    assert_eq!(read(&store, 1), Some(vec![1, 2]));
+}
+
+#[test]
+fn compaction_keeps_latest_values() {
+    let flash = MemFlash::new(PAGES);
+    let mut store = Store::new(flash.clone());
+    store.write(2, VERSION, &[42]).unwrap();
+    store.write(3, VERSION, &[1; 64]).unwrap();
+    assert_eq!(sequence(&flash, 0), Some(1));
+    let last = fill_until_compaction(&mut store, &flash, 1);
+    assert_eq!(sequence(&flash, 1), Some(2));
+
+    let store = Store::new(flash);
+    assert_eq!(read(&store, 1), Some(last));
+    assert_eq!(read(&store, 2), Some(vec![42]));
+    assert_eq!(read(&store, 3), Some(vec![1; 64]));
+}
+
+#[test]
+fn sequence_wraps() {
+    let flash = MemFlash::new(PAGES);
+    flash.poke(0, &bank_header(0xFFFF));
+    let mut store = Store::new(flash.clone());
+    store.write(2, VERSION, &[42]).unwrap();
+    let last = fill_until_compaction(&mut store, &flash, 1);
+    // Bank 1 took over with the wrapped sequence number, the old bank is left as it was
+    assert_eq!(sequence(&flash, 0), Some(0xFFFF));
+    assert_eq!(sequence(&flash, 1), Some(0));
+
+    let store = Store::new(flash);
+    assert_eq!(read(&store, 1), Some(last));
+    assert_eq!(read(&store, 2), Some(vec![42]));
+}
+
+#[test]
+fn newer_bank_wins_across_wrap() {
+    let flash = MemFlash::new(PAGES);
+    flash.poke(0, &bank_header(0xFFFE));
+    flash.poke(BANK_SIZE, &bank_header(1));
+    let mut store = Store::new(flash.clone());
+    store.write(1, VERSION, &[1]).unwrap();
+    assert_eq!(&flash.contents()[BANK_SIZE + BANK_HEADER_SIZE..BANK_SIZE + BANK_HEADER_SIZE + 2], [1, 0]);
+}
+
+#[test]
+fn rejected_writes() {
+    let flash = MemFlash::new(PAGES);
+    let mut store = Store::new(flash);
+    assert_eq!(store.write(0xFFFF, VERSION, &[1]), Err(StoreError::ReservedKey));
+    assert_eq!(store.write(1, VERSION, &[0; 65]), Err(StoreError::TooLong));
+}
+
+#[test]
+fn clear_erases_everything() {
+    let flash = MemFlash::new(PAGES);
+    let mut store = Store::new(flash.clone());
+    store.write(1, VERSION, &[1]).unwrap();
+    store.clear().unwrap();
+    assert!(flash.contents().iter().all(|&b| b == 0xFF));
+    assert_eq!(read(&Store::new(flash), 1), None);
+}
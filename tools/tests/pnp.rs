@@ -0,0 +1,165 @@
+//! Plug-and-play allocation: allocatee side of the firmware against the allocator from tools.
+
+use std::convert::TryFrom;
+use uavcan_llr::types::{CanId, NodeId, Priority, SubjectId, TransferKind};
+use vhrdcan::{Frame, FrameId};
+use vhrd_module_tools::allocator::{Allocator, MAX_ALLOCATED_NODE_ID};
+use vhrd_module_tools::uavcan::assembler::Assembler;
+use vhrd_module_tools::uavcan::pnp::{self, Allocatee, NodeIdAllocationData};
+use vhrd_module_tools::uavcan::tx::TransferSlicer;
+
+const ALLOCATOR_NODE_ID: u8 = 127;
+
+fn node_id(id: u8) -> NodeId {
+    NodeId::new(id).unwrap()
+}
+
+fn request(allocatee: &mut Allocatee) -> Vec<Frame<8>> {
+    let mut payload = [0u8; pnp::REQUEST_SIZE];
+    let len = allocatee.request(&mut payload).unwrap();
+    let subject_id = SubjectId::new(pnp::SUBJECT_ID).unwrap();
+    let id = CanId::new_message_kind(allocatee.pseudo_source_node_id(), subject_id, true, Priority::Slow);
+    TransferSlicer::<8>::new(id.into(), &payload[..len], 0).collect()
+}
+
+/// Allocation response payload, if the allocator sent one
+fn exchange(allocator: &mut Allocator, frames: &[Frame<8>]) -> Option<Vec<u8>> {
+    let response: Vec<Frame<8>> = frames.iter().flat_map(|f| allocator.on_frame(f, 0)).collect();
+    let mut assembler = Assembler::<1, 16>::new(1000);
+    let mut payload = None;
+    for frame in &response {
+        let id = CanId::try_from(frame.id).unwrap();
+        assert_eq!(id.source_node_id, node_id(ALLOCATOR_NODE_ID));
+        assert!(matches!(id.transfer_kind, TransferKind::Message(m) if m.subject_id.inner() == pnp::SUBJECT_ID));
+        let eid = match frame.id {
+            FrameId::Extended(eid) => eid.inner(),
+            FrameId::Standard(_) => unreachable!(),
+        };
+        if let Ok(Some(transfer)) = assembler.push(eid, frame.data(), 0) {
+            payload = Some(transfer.payload.to_vec());
+        }
+    }
+    payload
+}
+
+fn allocate(allocator: &mut Allocator, unique_id: &[u8]) -> Option<NodeId> {
+    let mut allocatee = Allocatee::new(unique_id);
+    let response = exchange(allocator, &request(&mut allocatee))?;
+    allocatee.accept(&response)
+}
+
+fn heartbeat_from(source: u8) -> Frame<8> {
+    let id = CanId::new_message_kind(node_id(source), SubjectId::new(7509).unwrap(), false, Priority::Nominal);
+    TransferSlicer::<8>::new(id.into(), &[0; 7], 0).next().unwrap()
+}
+
+#[test]
+fn request_is_single_frame() {
+    let mut allocatee = Allocatee::new(&[1; 12]);
+    assert_eq!(request(&mut allocatee).len(), 1);
+}
+
+#[test]
+fn allocation_data_round_trip() {
+    let mut buf = [0u8; pnp::MAX_SIZE];
+    for data in [
+        NodeIdAllocationData { unique_id_hash: 0x1234_5678_9abc, allocated_node_id: None },
+        NodeIdAllocationData { unique_id_hash: 0xffff_ffff_ffff, allocated_node_id: Some(node_id(42)) },
+    ] {
+        let len = data.encode(&mut buf).unwrap();
+        assert_eq!(NodeIdAllocationData::decode(&buf[..len]), Ok(data));
+    }
+}
+
+#[test]
+fn out_of_range_node_id_is_rejected() {
+    // Hash, one element, node ID 300 which would be 44 if cut down to a byte
+    let mut payload = [0u8; pnp::MAX_SIZE];
+    payload[6] = 1;
+    payload[7..9].copy_from_slice(&300u16.to_le_bytes());
+    assert!(NodeIdAllocationData::decode(&payload).is_err());
+    payload[7..9].copy_from_slice(&128u16.to_le_bytes());
+    assert!(NodeIdAllocationData::decode(&payload).is_err());
+    payload[7..9].copy_from_slice(&127u16.to_le_bytes());
+    assert_eq!(NodeIdAllocationData::decode(&payload).unwrap().allocated_node_id, Some(node_id(127)));
+}
+
+#[test]
+fn allocatee_accepts_only_own_response() {
+    let allocatee = Allocatee::new(&[1; 12]);
+    let other = Allocatee::new(&[2; 12]);
+    let mut buf = [0u8; pnp::MAX_SIZE];
+    let response = NodeIdAllocationData { unique_id_hash: allocatee.unique_id_hash(), allocated_node_id: Some(node_id(42)) };
+    let len = response.encode(&mut buf).unwrap();
+    assert_eq!(allocatee.accept(&buf[..len]), Some(node_id(42)));
+    assert_eq!(other.accept(&buf[..len]), None);
+    // Request of another node with the same hash, or of this one seen on the bus
+    let len = allocatee.request(&mut buf).unwrap();
+    assert_eq!(allocatee.accept(&buf[..len]), None);
+    assert_eq!(allocatee.accept(&[]), None);
+}
+
+#[test]
+fn request_delays_are_random_and_bounded() {
+    let mut a = Allocatee::new(&[1; 12]);
+    let mut b = Allocatee::new(&[2; 12]);
+    let delays_a: Vec<u32> = (0..100).map(|_| a.next_request_delay_ms()).collect();
+    let delays_b: Vec<u32> = (0..100).map(|_| b.next_request_delay_ms()).collect();
+    assert!(delays_a.iter().chain(&delays_b).all(|d| (1..=Allocatee::MAX_REQUEST_PERIOD_MS).contains(d)));
+    assert_ne!(delays_a, delays_b);
+    // Seeded from the unique ID only
+    let mut a2 = Allocatee::new(&[1; 12]);
+    assert_eq!((0..100).map(|_| a2.next_request_delay_ms()).collect::<Vec<_>>(), delays_a);
+}
+
+#[test]
+fn new_ids_are_taken_from_the_top() {
+    let mut allocator = Allocator::new(node_id(ALLOCATOR_NODE_ID));
+    assert_eq!(allocate(&mut allocator, &[1; 12]), Some(node_id(MAX_ALLOCATED_NODE_ID)));
+    assert_eq!(allocate(&mut allocator, &[2; 12]), Some(node_id(MAX_ALLOCATED_NODE_ID - 1)));
+    // Same node asking again after a reboot gets the same ID
+    assert_eq!(allocate(&mut allocator, &[1; 12]), Some(node_id(MAX_ALLOCATED_NODE_ID)));
+    assert_eq!(allocator.allocations().len(), 2);
+}
+
+#[test]
+fn ids_seen_on_the_bus_are_skipped() {
+    let mut allocator = Allocator::new(node_id(ALLOCATOR_NODE_ID));
+    assert!(allocator.on_frame(&heartbeat_from(MAX_ALLOCATED_NODE_ID), 0).is_empty());
+    assert_eq!(allocate(&mut allocator, &[1; 12]), Some(node_id(MAX_ALLOCATED_NODE_ID - 1)));
+}
+
+#[test]
+fn reserved_ids_are_kept() {
+    let mut allocator = Allocator::new(node_id(ALLOCATOR_NODE_ID));
+    let button = Allocatee::new(&[3; 12]);
+    allocator.reserve(button.unique_id_hash(), node_id(MAX_ALLOCATED_NODE_ID));
+    assert_eq!(allocate(&mut allocator, &[1; 12]), Some(node_id(MAX_ALLOCATED_NODE_ID - 1)));
+    assert_eq!(allocate(&mut allocator, &[3; 12]), Some(node_id(MAX_ALLOCATED_NODE_ID)));
+}
+
+#[test]
+fn responses_of_other_allocators_are_ignored() {
+    let mut allocator = Allocator::new(node_id(ALLOCATOR_NODE_ID));
+    let mut buf = [0u8; pnp::MAX_SIZE];
+    let response = NodeIdAllocationData { unique_id_hash: 1, allocated_node_id: Some(node_id(42)) };
+    let len = response.encode(&mut buf).unwrap();
+    let id = CanId::new_message_kind(node_id(126), SubjectId::new(pnp::SUBJECT_ID).unwrap(), false, Priority::Slow);
+    let frames: Vec<Frame<8>> = TransferSlicer::<8>::new(id.into(), &buf[..len], 0).collect();
+    assert_eq!(exchange(&mut allocator, &frames), None);
+    assert!(allocator.allocations().is_empty());
+}
+
+#[test]
+fn allocations_survive_save_and_load() {
+    let mut allocator = Allocator::new(node_id(ALLOCATOR_NODE_ID));
+    allocate(&mut allocator, &[1; 12]).unwrap();
+    allocator.reserve(0xabc, node_id(3));
+    let path = std::env::temp_dir().join(format!("pnp_allocations_{}.txt", std::process::id()));
+    allocator.save(&path).unwrap();
+    let mut loaded = Allocator::new(node_id(ALLOCATOR_NODE_ID));
+    loaded.load(&path).unwrap();
+    std::fs::remove_file(&path).ok();
+    assert_eq!(loaded.allocations(), allocator.allocations());
+    assert_eq!(allocate(&mut loaded, &[1; 12]), Some(node_id(MAX_ALLOCATED_NODE_ID)));
+}
@@ -0,0 +1,108 @@
+//! uavcan.register.Access and List serialization.
+
+use vhrd_module_tools::uavcan::register::{
+    decode_list_request, encode_access_response, encode_list_response, AccessRequest, Error, NumKind, Reader,
+    Value, Writer, MAX_NAME_LEN,
+};
+
+fn encode(value: &Value) -> Vec<u8> {
+    let mut buf = [0u8; 256];
+    let mut w = Writer::new(&mut buf);
+    value.encode(&mut w).unwrap();
+    let len = w.position();
+    buf[..len].to_vec()
+}
+
+fn decode(bytes: &[u8]) -> Result<Value, Error> {
+    Value::decode(&mut Reader::new(bytes))
+}
+
+fn request(name: &str, value: &[u8]) -> Vec<u8> {
+    let mut payload = vec![name.len() as u8];
+    payload.extend_from_slice(name.as_bytes());
+    payload.extend_from_slice(value);
+    payload
+}
+
+#[test]
+fn value_layouts() {
+    assert_eq!(encode(&Value::Empty), [0]);
+    assert_eq!(encode(&Value::string("ab")), [1, 2, 0, b'a', b'b']);
+    assert_eq!(encode(&Value::bit(true)), [3, 1, 0, 0b1]);
+    assert_eq!(encode(&Value::number(NumKind::Natural16, &[0x1234, 1])), [10, 2, 0x34, 0x12, 1, 0]);
+    // 8 bit arrays have a 16 bit length prefix
+    assert_eq!(encode(&Value::number(NumKind::Natural8, &[7])), [11, 1, 0, 7]);
+    assert_eq!(encode(&Value::number(NumKind::Integer32, &[-2])), [5, 1, 0xFE, 0xFF, 0xFF, 0xFF]);
+}
+
+#[test]
+fn values_roundtrip() {
+    let values = [
+        Value::Empty,
+        Value::string("uavcan.node.id"),
+        Value::Bit([true, false, true].iter().copied().collect()),
+        Value::number(NumKind::Integer64, &[i64::MIN, i64::MAX]),
+        Value::number(NumKind::Integer8, &[-128, 127, 0, -1]),
+        Value::number(NumKind::Natural64, &[u32::MAX as i64]),
+    ];
+    for value in values.iter() {
+        assert_eq!(decode(&encode(value)).as_ref(), Ok(value));
+    }
+}
+
+#[test]
+fn signed_values_are_sign_extended() {
+    assert_eq!(decode(&[6, 1, 0x00, 0x80]), Ok(Value::number(NumKind::Integer16, &[-32768])));
+    assert_eq!(decode(&[10, 1, 0x00, 0x80]), Ok(Value::number(NumKind::Natural16, &[32768])));
+}
+
+#[test]
+fn decode_errors() {
+    assert_eq!(decode(&[]), Err(Error::Truncated));
+    assert_eq!(decode(&[1, 3, 0, b'a']), Err(Error::Truncated));
+    assert_eq!(decode(&[9, 2, 1, 0, 0, 0]), Err(Error::Truncated));
+    // real64 and unstructured
+    assert_eq!(decode(&[12, 0]), Err(Error::Unsupported));
+    assert_eq!(decode(&[2, 0, 0]), Err(Error::Unsupported));
+    assert_eq!(decode(&[11, 5, 0, 1, 2, 3, 4, 5]), Err(Error::TooLong));
+}
+
+#[test]
+fn access_request() {
+    let request = request("uavcan.node.id", &[10, 1, 42, 0]);
+    let decoded = AccessRequest::decode(&request).unwrap();
+    assert_eq!(decoded.name, "uavcan.node.id");
+    assert_eq!(decoded.value, Value::number(NumKind::Natural16, &[42]));
+
+    let request = self::request("uavcan.node.id", &[0]);
+    let read = AccessRequest::decode(&request).unwrap();
+    assert!(read.value.is_empty());
+}
+
+#[test]
+fn long_name_is_decoded() {
+    let name = "x".repeat(MAX_NAME_LEN + 20);
+    let request = request(&name, &[0]);
+    let decoded = AccessRequest::decode(&request).unwrap();
+    assert_eq!(decoded.name, name);
+}
+
+#[test]
+fn access_response_layout() {
+    let mut buf = [0u8; 16];
+    let len = encode_access_response(true, false, &Value::number(NumKind::Natural8, &[3]), &mut buf).unwrap();
+    assert_eq!(&buf[..len], [0, 0, 0, 0, 0, 0, 0, 0b01, 11, 1, 0, 3]);
+    let len = encode_access_response(false, true, &Value::Empty, &mut buf).unwrap();
+    assert_eq!(&buf[..len], [0, 0, 0, 0, 0, 0, 0, 0b10, 0]);
+    assert_eq!(encode_access_response(true, true, &Value::string("too long"), &mut buf[..10]), Err(Error::BufferTooSmall));
+}
+
+#[test]
+fn list() {
+    assert_eq!(decode_list_request(&[3, 1]), Ok(0x0103));
+    assert_eq!(decode_list_request(&[3]), Err(Error::Truncated));
+    let mut buf = [0u8; 8];
+    let len = encode_list_response("a.b", &mut buf).unwrap();
+    assert_eq!(&buf[..len], [3, b'a', b'.', b'b']);
+    assert_eq!(encode_list_response("", &mut buf), Ok(1));
+}
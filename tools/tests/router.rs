@@ -0,0 +1,186 @@
+//! Routing of received frames through subscription tables, frames built from UAVCAN IDs.
+
+use uavcan_llr::types::{CanId, NodeId, Priority, ServiceId, SubjectId, TransferId};
+use vhrdcan::{Frame, FrameId};
+use vhrd_module_tools::uavcan::assembler::{Assembler, Error, TailByte};
+use vhrd_module_tools::uavcan::crc::crc16;
+use vhrd_module_tools::uavcan::router::{receive, route, Dispatch, Endpoint, Source, Subscription};
+
+const LOCAL: u8 = 10;
+const PEER: u8 = 20;
+const STRANGER: u8 = 30;
+
+fn node(id: u8) -> NodeId {
+    NodeId::new(id).unwrap()
+}
+
+fn subject(id: u16) -> SubjectId {
+    SubjectId::new(id).unwrap()
+}
+
+fn service(id: u16) -> ServiceId {
+    ServiceId::new(id).unwrap()
+}
+
+const COMMON: &[Subscription] = &[
+    Subscription::service(Source::Any, ServiceId::new(384).unwrap(), Endpoint::RegisterAccess),
+    Subscription::subject(Source::Any, SubjectId::new(8166).unwrap(), Endpoint::PnpAllocation),
+];
+const MODULE: &[Subscription] = &[
+    Subscription::subject(Source::Node(NodeId::new(PEER).unwrap()), SubjectId::new(100).unwrap(), Endpoint::Module),
+    Subscription::service(Source::Node(NodeId::new(PEER).unwrap()), ServiceId::new(7).unwrap(), Endpoint::Module),
+    Subscription::raw_extended_masked(0x0900, 0xFF00, Endpoint::VescFeedback),
+];
+const TABLES: &[&[Subscription]] = &[COMMON, MODULE];
+
+fn message(source: u8, subject_id: u16, anonymous: bool) -> Frame<8> {
+    let id: FrameId = CanId::new_message_kind(node(source), subject(subject_id), anonymous, Priority::Nominal).into();
+    let id = match id {
+        // Anonymous bit, whether or not the ID was built with it
+        FrameId::Extended(eid) if anonymous => FrameId::new_extended(eid.inner() | 1 << 24).unwrap(),
+        id => id,
+    };
+    Frame::new(id, &[1, 2, 0xE0]).unwrap()
+}
+
+fn request(source: u8, destination: u8, service_id: u16) -> Frame<8> {
+    let id = CanId::new_service_kind(node(source), node(destination), service(service_id), true, Priority::Nominal);
+    Frame::new(id.into(), &[3, 0xE0]).unwrap()
+}
+
+#[test]
+fn subject_from_subscribed_source() {
+    let frame = message(PEER, 100, false);
+    match route(TABLES, Some(node(LOCAL)), &frame) {
+        Some(Dispatch::Message { endpoint: Endpoint::Module, source, message, payload }) => {
+            assert_eq!(source, node(PEER));
+            assert_eq!(message.subject_id, subject(100));
+            assert_eq!(payload, [1, 2, 0xE0]);
+        }
+        d => panic!("{:?}", d),
+    }
+}
+
+#[test]
+fn subject_from_other_source_is_dropped() {
+    assert!(route(TABLES, Some(node(LOCAL)), &message(STRANGER, 100, false)).is_none());
+}
+
+#[test]
+fn service_addressed_to_this_node() {
+    let frame = request(STRANGER, LOCAL, 384);
+    match route(TABLES, Some(node(LOCAL)), &frame) {
+        Some(Dispatch::Service { endpoint: Endpoint::RegisterAccess, source, service: s, payload }) => {
+            assert_eq!(source, node(STRANGER));
+            assert_eq!(s.service_id, service(384));
+            assert_eq!(payload, [3, 0xE0]);
+        }
+        d => panic!("{:?}", d),
+    }
+    let frame = request(PEER, LOCAL, 7);
+    assert!(matches!(route(TABLES, Some(node(LOCAL)), &frame), Some(Dispatch::Service { endpoint: Endpoint::Module, .. })));
+}
+
+#[test]
+fn service_for_other_destination_is_dropped() {
+    assert!(route(TABLES, Some(node(LOCAL)), &request(STRANGER, PEER, 384)).is_none());
+}
+
+#[test]
+fn services_are_dropped_without_node_id() {
+    assert!(route(TABLES, None, &request(STRANGER, LOCAL, 384)).is_none());
+}
+
+#[test]
+fn anonymous_source_only_reaches_any_source() {
+    let frame = message(STRANGER, 8166, true);
+    assert!(matches!(route(TABLES, None, &frame), Some(Dispatch::Message { endpoint: Endpoint::PnpAllocation, .. })));
+    // Pseudo ID of an anonymous node may happen to be the subscribed one
+    assert!(route(TABLES, Some(node(LOCAL)), &message(PEER, 100, true)).is_none());
+}
+
+#[test]
+fn no_matching_subscription() {
+    assert!(route(TABLES, Some(node(LOCAL)), &message(PEER, 101, false)).is_none());
+    assert!(route(TABLES, Some(node(LOCAL)), &request(PEER, LOCAL, 8)).is_none());
+    let standard = Frame::<8>::new(FrameId::new_standard(0x123).unwrap(), &[1]).unwrap();
+    assert!(route(TABLES, Some(node(LOCAL)), &standard).is_none());
+}
+
+#[test]
+fn raw_extended_in_mask() {
+    let frame = Frame::<8>::new(FrameId::new_extended(0x0942).unwrap(), &[9, 8]).unwrap();
+    match route(TABLES, Some(node(LOCAL)), &frame) {
+        Some(Dispatch::Raw { endpoint: Endpoint::VescFeedback, id, payload }) => {
+            assert_eq!(id, 0x0942);
+            assert_eq!(payload, [9, 8]);
+        }
+        d => panic!("{:?}", d),
+    }
+}
+
+#[test]
+fn first_table_wins() {
+    const SHADOWED: &[Subscription] = &[Subscription::subject(Source::Any, SubjectId::new(100).unwrap(), Endpoint::Bridge)];
+    let frame = message(PEER, 100, false);
+    let tables: &[&[Subscription]] = &[SHADOWED, MODULE];
+    assert!(matches!(route(tables, Some(node(LOCAL)), &frame), Some(Dispatch::Message { endpoint: Endpoint::Bridge, .. })));
+}
+
+#[test]
+fn receive_hands_out_raw_frames_as_is() {
+    let mut assembler: Assembler<2, 16> = Assembler::new(1000);
+    let frame = Frame::<8>::new(FrameId::new_extended(0x0907).unwrap(), &[1, 2, 3]).unwrap();
+    match receive(TABLES, Some(node(LOCAL)), &frame, &mut assembler, 0, |_, _| false) {
+        Ok(Some((Dispatch::Raw { endpoint: Endpoint::VescFeedback, payload, .. }, transfer_id))) => {
+            assert_eq!(payload, [1, 2, 3]);
+            assert_eq!(transfer_id, TransferId::default());
+        }
+        r => panic!("{:?}", r),
+    }
+}
+
+#[test]
+fn receive_reassembles_transfers() {
+    let mut assembler: Assembler<2, 16> = Assembler::new(1000);
+    let id: FrameId = CanId::new_message_kind(node(PEER), subject(100), false, Priority::Nominal).into();
+    // 7 bytes of payload and 2 of CRC take two frames
+    let payload = [1, 2, 3, 4, 5, 6, 7];
+    let crc = crc16(&payload).to_be_bytes();
+    let first = Frame::<8>::new(id, &[1, 2, 3, 4, 5, 6, 7, TailByte::new(true, false, true, 3).0]).unwrap();
+    let last = Frame::<8>::new(id, &[crc[0], crc[1], TailByte::new(false, true, false, 3).0]).unwrap();
+
+    let mut accepted = None;
+    assert!(matches!(receive(TABLES, Some(node(LOCAL)), &first, &mut assembler, 0, |_, _| true), Ok(None)));
+    match receive(TABLES, Some(node(LOCAL)), &last, &mut assembler, 1, |id, transfer_id| { accepted = Some((id, transfer_id)); true }) {
+        Ok(Some((Dispatch::Message { endpoint: Endpoint::Module, payload: p, .. }, transfer_id))) => {
+            assert_eq!(p, payload);
+            assert_eq!(transfer_id, TransferId::new(3).unwrap());
+        }
+        r => panic!("{:?}", r),
+    }
+    let eid = match id {
+        FrameId::Extended(eid) => eid.inner(),
+        FrameId::Standard(_) => unreachable!(),
+    };
+    assert_eq!(accepted, Some((eid, 3)));
+}
+
+#[test]
+fn receive_drops_transfers_not_accepted() {
+    let mut assembler: Assembler<2, 16> = Assembler::new(1000);
+    let frame = message(PEER, 100, false);
+    assert!(matches!(receive(TABLES, Some(node(LOCAL)), &frame, &mut assembler, 0, |_, _| false), Ok(None)));
+    assert!(matches!(receive(TABLES, Some(node(LOCAL)), &frame, &mut assembler, 1, |_, _| true), Ok(Some(_))));
+}
+
+#[test]
+fn receive_reports_broken_transfers() {
+    let mut assembler: Assembler<2, 16> = Assembler::new(1000);
+    let id: FrameId = CanId::new_message_kind(node(PEER), subject(100), false, Priority::Nominal).into();
+    let frame = Frame::<8>::new(id, &[1, TailByte::new(false, true, false, 0).0]).unwrap();
+    assert_eq!(receive(TABLES, Some(node(LOCAL)), &frame, &mut assembler, 0, |_, _| true).err(), Some(Error::NoSession));
+    // Unsubscribed frames never reach the assembler
+    let frame = message(STRANGER, 100, false);
+    assert!(matches!(receive(TABLES, Some(node(LOCAL)), &frame, &mut assembler, 0, |_, _| true), Ok(None)));
+}
@@ -0,0 +1,203 @@
+//! Modules, a Pi stand-in and a mock VESC on the simulated bus.
+
+use std::convert::TryFrom;
+use uavcan_llr::types::{CanId, NodeId, SubjectId, TransferId, TransferKind};
+use vhrdcan::{Frame, FrameId};
+use vhrd_module_tools::button_logic::BRAKE_CURRENT_MILLIAMPS;
+use vhrd_module_tools::config::{BUTTON_UAVCAN_NODE_ID, DUTY_RAMP_TARGET_SUBJECT_ID, PI_NODE_ID, POWER_BUTTON_SUBJECT};
+use vhrd_module_tools::config::{RMP_RAMP_TARGET_SUBJECT_ID, SAFETY_BUTTON_SUBJECT};
+use vhrd_module_tools::node::{self, ButtonNode, MockVesc, Node, NodeConfig, VescCommand, VescNode};
+use vhrd_module_tools::sim::{SimBus, SimPort};
+use vhrd_module_tools::uavcan::heartbeat;
+
+const MODULE_NODE_ID: u8 = 20;
+
+/// Publishes a target every 100ms until `stop_ms`.
+struct Pi {
+    port: SimPort,
+    subject_id: SubjectId,
+    target: i32,
+    stop_ms: u32,
+    transfer_id: TransferId,
+}
+
+impl Pi {
+    fn new(bus: &SimBus, subject_id: SubjectId, target: i32, stop_ms: u32) -> Self {
+        Pi { port: bus.port(), subject_id, target, stop_ms, transfer_id: TransferId::default() }
+    }
+}
+
+impl Node for Pi {
+    fn poll(&mut self, now_ms: u32) {
+        while self.port.receive().is_some() {}
+        if now_ms % 100 == 0 && now_ms < self.stop_ms {
+            node::publish(&mut self.port, PI_NODE_ID, self.subject_id, &self.target.to_le_bytes(), &mut self.transfer_id);
+        }
+    }
+}
+
+/// Single frame messages from `source` on `subject_id` seen by `port`, payload without tail byte.
+fn messages(port: &mut SimPort, source: NodeId, subject_id: SubjectId) -> Vec<Vec<u8>> {
+    let mut messages = Vec::new();
+    while let Some(frame) = port.receive() {
+        if let Some(payload) = message(&frame, source, subject_id) {
+            messages.push(payload);
+        }
+    }
+    messages
+}
+
+fn message(frame: &Frame<8>, source: NodeId, subject_id: SubjectId) -> Option<Vec<u8>> {
+    if let FrameId::Standard(_) = frame.id {
+        return None;
+    }
+    let id = CanId::try_from(frame.id).ok()?;
+    match id.transfer_kind {
+        TransferKind::Message(m) if m.subject_id == subject_id && id.source_node_id == source => {
+            let (_tail, payload) = frame.data().split_last()?;
+            Some(payload.to_vec())
+        }
+        _ => None,
+    }
+}
+
+fn duties(commands: &[(u32, VescCommand)]) -> Vec<i32> {
+    commands.iter().filter_map(|(_, c)| match c {
+        VescCommand::Duty(duty) => Some(*duty),
+        _ => None,
+    }).collect()
+}
+
+fn module_node_id() -> NodeId {
+    NodeId::new(MODULE_NODE_ID).unwrap()
+}
+
+fn heartbeat_subject() -> SubjectId {
+    SubjectId::new(heartbeat::SUBJECT_ID).unwrap()
+}
+
+fn config() -> NodeConfig {
+    NodeConfig::new(module_node_id())
+}
+
+#[test]
+fn heartbeat_uptime() {
+    let bus = SimBus::new();
+    let mut observer = bus.port();
+    let mut module = VescNode::new(bus.port(), config());
+    node::run(&mut [&mut module], 0, 3500, 1);
+
+    let heartbeats = messages(&mut observer, module_node_id(), heartbeat_subject());
+    assert_eq!(heartbeats.len(), 4);
+    for (i, payload) in heartbeats.iter().enumerate() {
+        assert_eq!(payload.len(), heartbeat::SIZE);
+        let uptime = u32::from_le_bytes([payload[0], payload[1], payload[2], payload[3]]);
+        assert_eq!(uptime, i as u32);
+        assert_eq!(payload[4], heartbeat::Health::Norminal as u8 | (heartbeat::Mode::Firmware as u8) << 3);
+    }
+}
+
+#[test]
+fn heartbeat_uptime_with_other_period() {
+    let bus = SimBus::new();
+    let mut observer = bus.port();
+    let mut config = config();
+    config.heartbeat_period_ms = 400;
+    let mut module = VescNode::new(bus.port(), config);
+    node::run(&mut [&mut module], 0, 2100, 1);
+
+    let heartbeats = messages(&mut observer, module_node_id(), heartbeat_subject());
+    let uptimes: Vec<u32> = heartbeats.iter().map(|p| u32::from_le_bytes([p[0], p[1], p[2], p[3]])).collect();
+    assert_eq!(uptimes, [0, 0, 0, 1, 1, 2]);
+}
+
+#[test]
+fn duty_ramp_and_watchdog_release() {
+    let bus = SimBus::new();
+    let config = config();
+    let mut pi = Pi::new(&bus, DUTY_RAMP_TARGET_SUBJECT_ID, 10_000, 3000);
+    let mut module = VescNode::new(bus.port(), config);
+    let mut vesc = MockVesc::new(bus.port(), config.vesc_id);
+    node::run(&mut [&mut pi, &mut module, &mut vesc], 0, 3000, 1);
+
+    let ramp = duties(&vesc.commands);
+    assert!(!ramp.is_empty());
+    assert!(ramp[0] >= config.vesc_params.duty_min);
+    assert!(ramp.windows(2).all(|w| w[0] <= w[1]), "{:?}", ramp);
+    let rate_up = config.vesc_params.ramp_rates[0] as i32;
+    let last = *ramp.last().unwrap();
+    assert!(last > ramp[0] + rate_up && last <= 10_000, "{:?}", ramp);
+    assert!(vesc.erpm() > 0);
+
+    // Pi goes silent after its last target at 2900ms, motor is released once after the timeout
+    node::run(&mut [&mut pi, &mut module, &mut vesc], 3000, 5000, 1);
+    let last_input_ms = 2900;
+    let released: Vec<_> = vesc.commands.iter().filter(|(_, c)| *c == VescCommand::Current(0)).collect();
+    assert_eq!(released.len(), 1);
+    let (released_ms, _) = *released[0];
+    assert!(released_ms > last_input_ms + config.vesc_input_timeout_ms && released_ms <= last_input_ms + config.vesc_input_timeout_ms + 100);
+    assert!(vesc.commands.iter().all(|(t, c)| *t <= released_ms || !matches!(c, VescCommand::Duty(_))));
+    assert_eq!(vesc.duty_p5(), 0);
+}
+
+#[test]
+fn rpm_search_holds_duty() {
+    let bus = SimBus::new();
+    let config = config();
+    let target_erpm = 10_000;
+    let mut pi = Pi::new(&bus, RMP_RAMP_TARGET_SUBJECT_ID, target_erpm, 60_000);
+    let mut module = VescNode::new(bus.port(), config);
+    let mut vesc = MockVesc::new(bus.port(), config.vesc_id);
+    node::run(&mut [&mut pi, &mut module, &mut vesc], 0, 60_000, 1);
+
+    assert!((vesc.erpm() - target_erpm).abs() < 500, "erpm {}", vesc.erpm());
+    let duties = duties(&vesc.commands);
+    let tail = &duties[duties.len() - 20..];
+    assert!(tail.iter().all(|d| *d == tail[0]), "{:?}", tail);
+}
+
+#[test]
+fn button_estop_and_power() {
+    let bus = SimBus::new();
+    let config = config();
+    let mut observer = bus.port();
+    let mut button = ButtonNode::new(bus.port(), config.vesc_id);
+    let mut vesc = MockVesc::new(bus.port(), config.vesc_id);
+
+    node::run(&mut [&mut button, &mut vesc], 0, 500, 1);
+    assert!(vesc.commands.iter().all(|(_, c)| *c == VescCommand::ResetEstopTimeout));
+    assert!(messages(&mut observer, BUTTON_UAVCAN_NODE_ID, SAFETY_BUTTON_SUBJECT).is_empty());
+
+    // Any of the two contacts brakes the motor and is published on every poll
+    button.inputs.estop_b = true;
+    vesc.commands.clear();
+    node::run(&mut [&mut button, &mut vesc], 500, 1000, 1);
+    assert_eq!(vesc.commands.len(), 5);
+    assert!(vesc.commands.iter().all(|(_, c)| *c == VescCommand::CurrentBrake(BRAKE_CURRENT_MILLIAMPS)));
+    assert_eq!(messages(&mut observer, BUTTON_UAVCAN_NODE_ID, SAFETY_BUTTON_SUBJECT).len(), 5);
+
+    // Power button held for 3s is reported once
+    button.inputs.estop_b = false;
+    button.inputs.power = true;
+    node::run(&mut [&mut button, &mut vesc], 1000, 4000, 1);
+    assert_eq!(messages(&mut observer, BUTTON_UAVCAN_NODE_ID, POWER_BUTTON_SUBJECT).len(), 1);
+    assert_eq!(vesc.commands.last().map(|(_, c)| *c), Some(VescCommand::ResetEstopTimeout));
+}
+
+#[test]
+fn estop_overrides_module() {
+    let bus = SimBus::new();
+    let config = config();
+    let mut pi = Pi::new(&bus, DUTY_RAMP_TARGET_SUBJECT_ID, 10_000, 10_000);
+    let mut module = VescNode::new(bus.port(), config);
+    let mut button = ButtonNode::new(bus.port(), config.vesc_id);
+    let mut vesc = MockVesc::new(bus.port(), config.vesc_id);
+    node::run(&mut [&mut pi, &mut module, &mut button, &mut vesc], 0, 2000, 1);
+    assert!(vesc.duty_p5() >= config.vesc_params.duty_min);
+
+    // Button polls after the module in the same millisecond, brake is the last command
+    button.inputs.estop_a = true;
+    node::run(&mut [&mut pi, &mut module, &mut button, &mut vesc], 2000, 2001, 1);
+    assert_eq!(vesc.commands.last().map(|(_, c)| *c), Some(VescCommand::CurrentBrake(BRAKE_CURRENT_MILLIAMPS)));
+    assert_eq!(vesc.duty_p5(), 0);
+}
@@ -0,0 +1,396 @@
+//! Transfer slicing at the frame boundaries, TX queue ordering and behaviour when it fills up, and
+//! moving frames into controller mailboxes.
+
+use uavcan_llr::types::{CanId, NodeId, Priority, SubjectId, TransferId};
+use vhrdcan::{Frame, FrameId};
+use vhrd_module_tools::uavcan::assembler::{Assembler, TailByte};
+use vhrd_module_tools::uavcan::tx::{frame_count, MailboxAction, TransferSlicer, TxError, TxMailboxes, TxQueue};
+
+fn id(subject_id: u16, priority: Priority) -> FrameId {
+    CanId::new_message_kind(NodeId::new(5).unwrap(), SubjectId::new(subject_id).unwrap(), false, priority).into()
+}
+
+fn raw(id: FrameId) -> u32 {
+    match id {
+        FrameId::Extended(eid) => eid.inner(),
+        FrameId::Standard(_) => unreachable!(),
+    }
+}
+
+fn lengths<const MTU: usize>(frames: &[Frame<MTU>]) -> Vec<usize> {
+    frames.iter().map(|f| f.data().len()).collect()
+}
+
+/// Reassembled payload, with any padding
+fn roundtrip<const MTU: usize>(frames: &[Frame<MTU>]) -> Vec<u8> {
+    let mut assembler = Assembler::<1, 256>::new(100);
+    let mut result = None;
+    for frame in frames {
+        if let Some(t) = assembler.push(raw(frame.id), frame.data(), 0).unwrap() {
+            result = Some(t.payload.to_vec());
+        }
+    }
+    result.unwrap()
+}
+
+#[test]
+fn classic_single_frame_boundary() {
+    let id = id(100, Priority::Nominal);
+    let payload: Vec<u8> = (0..7).collect();
+    let frames: Vec<Frame<8>> = TransferSlicer::new(id, &payload, 1).collect();
+    assert_eq!(lengths(&frames), [8]);
+    assert_eq!(TailByte(frames[0].data()[7]), TailByte::new(true, true, true, 1));
+    assert_eq!(roundtrip(&frames), payload);
+
+    let payload: Vec<u8> = (0..8).collect();
+    let frames: Vec<Frame<8>> = TransferSlicer::new(id, &payload, 1).collect();
+    // 8 bytes and the CRC in 7 byte chunks
+    assert_eq!(lengths(&frames), [8, 4]);
+    assert_eq!(frame_count(8, 8), 2);
+    assert_eq!(roundtrip(&frames), payload);
+}
+
+#[test]
+fn classic_crc_fills_last_frame() {
+    let id = id(100, Priority::Nominal);
+    for (len, expected) in [(12, vec![8, 8]), (13, vec![8, 8, 2]), (19, vec![8, 8, 8])] {
+        let payload: Vec<u8> = (0..len as u8).collect();
+        let frames: Vec<Frame<8>> = TransferSlicer::new(id, &payload, 0).collect();
+        assert_eq!(lengths(&frames), expected, "{} bytes", len);
+        assert_eq!(frame_count(len, 8), expected.len());
+        let tails: Vec<TailByte> = frames.iter().map(|f| TailByte(*f.data().last().unwrap())).collect();
+        assert!(tails[0].is_start() && !tails[0].is_end());
+        assert!(tails.last().unwrap().is_end());
+        assert!(tails.windows(2).all(|w| w[0].toggle() != w[1].toggle()));
+        assert_eq!(roundtrip(&frames), payload);
+    }
+}
+
+#[test]
+fn fd_single_frame_boundary() {
+    let id = id(100, Priority::Nominal);
+    let payload: Vec<u8> = (0..63).collect();
+    let frames: Vec<Frame<64>> = TransferSlicer::new(id, &payload, 0).collect();
+    assert_eq!(lengths(&frames), [64]);
+    assert_eq!(roundtrip(&frames), payload);
+
+    let payload: Vec<u8> = (0..64).collect();
+    let frames: Vec<Frame<64>> = TransferSlicer::new(id, &payload, 0).collect();
+    // 1 byte and the CRC left for the second frame, which fits into 4 bytes with the tail
+    assert_eq!(lengths(&frames), [64, 4]);
+    assert_eq!(roundtrip(&frames), payload);
+}
+
+#[test]
+fn fd_padding_before_crc() {
+    let id = id(100, Priority::Nominal);
+    // Single frame: 9 bytes and tail padded to 12
+    let payload: Vec<u8> = (0..9).collect();
+    let frames: Vec<Frame<64>> = TransferSlicer::new(id, &payload, 0).collect();
+    assert_eq!(lengths(&frames), [12]);
+    assert_eq!(&frames[0].data()[9..11], [0, 0]);
+
+    // 70 bytes and CRC: 63 in the first frame, 9 left, padded to 11 and the tail
+    let payload: Vec<u8> = (0..70).collect();
+    let frames: Vec<Frame<64>> = TransferSlicer::new(id, &payload, 0).collect();
+    assert_eq!(lengths(&frames), [64, 12]);
+    let assembled = roundtrip(&frames);
+    assert_eq!(&assembled[..70], &payload[..]);
+    assert_eq!(&assembled[70..], [0, 0]);
+}
+
+#[test]
+fn empty_payload() {
+    let frames: Vec<Frame<8>> = TransferSlicer::new(id(100, Priority::Nominal), &[], 3).collect();
+    assert_eq!(lengths(&frames), [1]);
+    assert_eq!(roundtrip(&frames), Vec::<u8>::new());
+}
+
+#[test]
+fn transfer_is_all_or_nothing() {
+    let mut queue = TxQueue::<8, 4>::new();
+    let id = id(100, Priority::Nominal);
+    queue.push_transfer(id, &[0; 12], TransferId::new(0).unwrap()).unwrap();
+    assert_eq!(queue.free(), 2);
+    // Three frames, two free slots
+    assert_eq!(queue.push_transfer(id, &[0; 13], TransferId::new(1).unwrap()), Err(TxError::QueueFull));
+    assert_eq!(queue.len(), 2);
+    queue.push_transfer(id, &[0; 12], TransferId::new(1).unwrap()).unwrap();
+    assert_eq!(queue.free(), 0);
+    assert_eq!(queue.push_transfer(id, &[], TransferId::new(2).unwrap()), Err(TxError::QueueFull));
+}
+
+#[test]
+fn full_queue_returns_the_frame() {
+    let mut queue = TxQueue::<8, 2>::new();
+    let id = id(100, Priority::Nominal);
+    for i in 0..2 {
+        queue.push(Frame::new(id, &[i]).unwrap()).unwrap();
+    }
+    let rejected = Frame::new(id, &[2]).unwrap();
+    assert_eq!(queue.push(rejected), Err(rejected));
+    assert_eq!(queue.pop().unwrap().data(), [0]);
+    assert_eq!(queue.pop().unwrap().data(), [1]);
+    assert!(queue.is_empty());
+}
+
+/// Frame tagged with its session and sequence number within it
+fn tagged(subject_id: u16, priority: Priority, seq: u8) -> Frame<8> {
+    Frame::new(id(subject_id, priority), &[subject_id as u8, seq]).unwrap()
+}
+
+fn tags(frames: &[Frame<8>]) -> Vec<(u8, u8)> {
+    frames.iter().map(|f| (f.data()[0], f.data()[1])).collect()
+}
+
+fn drain<const N: usize>(queue: &mut TxQueue<8, N>) -> Vec<Frame<8>> {
+    std::iter::from_fn(|| queue.pop()).collect()
+}
+
+#[test]
+fn queue_pops_by_priority() {
+    let mut queue = TxQueue::<8, 8>::new();
+    queue.push(tagged(1, Priority::Low, 0)).unwrap();
+    queue.push(tagged(2, Priority::Fast, 0)).unwrap();
+    queue.push(tagged(3, Priority::Nominal, 0)).unwrap();
+    // Same priority, lower subject ID wins arbitration
+    queue.push(tagged(0, Priority::Nominal, 0)).unwrap();
+    assert_eq!(tags(&drain(&mut queue)), [(2, 0), (0, 0), (3, 0), (1, 0)]);
+}
+
+#[test]
+fn queue_keeps_session_order() {
+    let mut queue = TxQueue::<8, 16>::new();
+    // Enough to wrap the sequence numbers a few times, a session interleaved with another one
+    let mut popped = Vec::new();
+    for i in 0..200_000u32 {
+        queue.push(tagged(1, Priority::Nominal, i as u8)).unwrap();
+        queue.push(tagged(2, Priority::Nominal, i as u8)).unwrap();
+        if queue.free() < 4 {
+            popped.extend(drain(&mut queue));
+        }
+    }
+    popped.extend(drain(&mut queue));
+    for session in [1, 2] {
+        let seqs: Vec<u8> = tags(&popped).into_iter().filter(|(s, _)| *s == session).map(|(_, seq)| seq).collect();
+        assert_eq!(seqs.len(), 200_000);
+        assert!(seqs.windows(2).all(|w| w[1] == w[0].wrapping_add(1)));
+    }
+}
+
+#[test]
+fn pushed_out_frame_goes_before_its_session() {
+    let mut queue = TxQueue::<8, 8>::new();
+    for seq in 0..3 {
+        queue.push(tagged(1, Priority::Low, seq)).unwrap();
+    }
+    queue.push(tagged(2, Priority::Fast, 0)).unwrap();
+    // bxCAN driver: head taken into a mailbox, pushing out the pending frame 0 of session 1
+    let head = queue.pop().unwrap();
+    assert_eq!(tags(&[head]), [(2, 0)]);
+    let pushed_out = queue.pop().unwrap();
+    queue.push_front(pushed_out).unwrap();
+    queue.push(tagged(1, Priority::Low, 3)).unwrap();
+    assert_eq!(tags(&drain(&mut queue)), [(1, 0), (1, 1), (1, 2), (1, 3)]);
+
+    // Session with nothing queued, and after the sequence numbers wrapped
+    for seq in 0..70_000u32 {
+        queue.push(tagged(3, Priority::Nominal, seq as u8)).unwrap();
+        queue.pop().unwrap();
+    }
+    queue.push_front(tagged(1, Priority::Low, 9)).unwrap();
+    queue.push(tagged(1, Priority::Low, 10)).unwrap();
+    queue.push_front(tagged(1, Priority::Low, 8)).unwrap();
+    assert_eq!(tags(&drain(&mut queue)), [(1, 8), (1, 9), (1, 10)]);
+}
+
+#[test]
+fn push_front_into_full_queue_fails() {
+    let mut queue = TxQueue::<8, 1>::new();
+    queue.push(tagged(1, Priority::Low, 0)).unwrap();
+    let frame = tagged(1, Priority::Low, 1);
+    assert_eq!(queue.push_front(frame), Err(frame));
+}
+
+/// Controller transmitting the most important of its loaded mailboxes, as bxCAN and MCP2515 do
+struct Controller<const M: usize> {
+    mailboxes: [Option<Frame<8>>; M],
+    sent: Vec<Frame<8>>,
+}
+
+impl<const M: usize> Controller<M> {
+    fn new() -> Self {
+        Controller { mailboxes: [None; M], sent: Vec::new() }
+    }
+
+    /// Follow the actions until Wait, aborts succeed unless `abort_fails`
+    fn service<const N: usize>(&mut self, tx: &mut TxMailboxes<8, M>, queue: &mut TxQueue<8, N>, abort_fails: bool) {
+        loop {
+            match tx.next(queue) {
+                MailboxAction::Load { mailbox, frame } => {
+                    assert!(self.mailboxes[mailbox].is_none());
+                    self.mailboxes[mailbox] = Some(frame);
+                }
+                MailboxAction::Abort { mailbox } => {
+                    assert!(tx.is_aborting(mailbox));
+                    if abort_fails {
+                        // Already on the bus
+                        self.sent.push(self.mailboxes[mailbox].take().unwrap());
+                        tx.completed(mailbox);
+                    } else {
+                        self.mailboxes[mailbox] = None;
+                        tx.returned(mailbox);
+                    }
+                }
+                MailboxAction::Wait => {
+                    self.check_no_inversion(queue);
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Head of the queue waits only for a frame of its own session or for more important ones
+    fn check_no_inversion<const N: usize>(&self, queue: &TxQueue<8, N>) {
+        if let Some(head) = queue.peek() {
+            let loaded = self.mailboxes.iter().flatten();
+            let same_session = loaded.clone().any(|f| f.id == head.id);
+            assert!(same_session || loaded.clone().count() == M && loaded.clone().all(|f| f.id < head.id), "{:?} waits behind {:?}", head, self.mailboxes);
+        }
+    }
+
+    fn transmit_one(&mut self, tx: &mut TxMailboxes<8, M>) -> bool {
+        let next = (0..M).filter(|i| self.mailboxes[*i].is_some()).min_by_key(|i| self.mailboxes[*i].unwrap().id);
+        match next {
+            Some(mailbox) => {
+                self.sent.push(self.mailboxes[mailbox].take().unwrap());
+                tx.completed(mailbox);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+fn check_sessions_in_order(sent: &[Frame<8>], sessions: &[u16], per_session: u8) {
+    for session in sessions {
+        let seqs: Vec<u8> = tags(sent).into_iter().filter(|(s, _)| *s == *session as u8).map(|(_, seq)| seq).collect();
+        assert_eq!(seqs, (0..per_session).collect::<Vec<u8>>(), "session {}", session);
+    }
+}
+
+#[test]
+fn mailboxes_keep_priority_and_session_order() {
+    let priorities = [Priority::Fast, Priority::Nominal, Priority::Low, Priority::Optional];
+    let sessions: Vec<u16> = (1..=8).collect();
+    for abort_fails in [false, true] {
+        let mut queue = TxQueue::<8, 64>::new();
+        let mut tx = TxMailboxes::<8, 3>::new();
+        let mut controller = Controller::<3>::new();
+        let mut rng = 0x2545_f491u32;
+        let mut next_seq = [0u8; 9];
+        let mut steps = 0;
+        while next_seq[1..].iter().any(|s| *s < 20) || controller.mailboxes.iter().any(Option::is_some) || !queue.is_empty() {
+            rng ^= rng << 13;
+            rng ^= rng >> 17;
+            rng ^= rng << 5;
+            let session = sessions[rng as usize % sessions.len()];
+            if next_seq[session as usize] < 20 && queue.free() > 0 && rng % 3 != 0 {
+                let priority = priorities[session as usize % priorities.len()];
+                queue.push(tagged(session, priority, next_seq[session as usize])).unwrap();
+                next_seq[session as usize] += 1;
+            }
+            controller.service(&mut tx, &mut queue, abort_fails);
+            if rng % 2 == 0 {
+                controller.transmit_one(&mut tx);
+            }
+            steps += 1;
+            assert!(steps < 100_000);
+        }
+        assert_eq!(controller.sent.len(), sessions.len() * 20);
+        check_sessions_in_order(&controller.sent, &sessions, 20);
+    }
+}
+
+#[test]
+fn more_important_frame_aborts_least_important() {
+    let mut queue = TxQueue::<8, 8>::new();
+    let mut tx = TxMailboxes::<8, 2>::new();
+    let mut controller = Controller::<2>::new();
+    queue.push(tagged(1, Priority::Low, 0)).unwrap();
+    queue.push(tagged(2, Priority::Optional, 0)).unwrap();
+    controller.service(&mut tx, &mut queue, false);
+    assert!(tx.is_loaded(0) && tx.is_loaded(1));
+
+    queue.push(tagged(3, Priority::Fast, 0)).unwrap();
+    match tx.next(&mut queue) {
+        MailboxAction::Abort { mailbox } => {
+            assert_eq!(tags(&[controller.mailboxes[mailbox].unwrap()]), [(2, 0)]);
+            // Nothing else happens until the abort completes
+            assert!(matches!(tx.next(&mut queue), MailboxAction::Wait));
+            controller.mailboxes[mailbox] = None;
+            tx.returned(mailbox);
+        }
+        a => panic!("{:?}", a),
+    }
+    controller.service(&mut tx, &mut queue, false);
+    while controller.transmit_one(&mut tx) {
+        controller.service(&mut tx, &mut queue, false);
+    }
+    assert_eq!(tags(&controller.sent), [(3, 0), (1, 0), (2, 0)]);
+}
+
+#[test]
+fn returned_frame_is_loaded_before_its_session() {
+    let mut queue = TxQueue::<8, 8>::new();
+    let mut tx = TxMailboxes::<8, 1>::new();
+    for seq in 0..3 {
+        queue.push(tagged(1, Priority::Nominal, seq)).unwrap();
+    }
+    let mailbox = match tx.next(&mut queue) {
+        MailboxAction::Load { mailbox, frame } => {
+            assert_eq!(tags(&[frame]), [(1, 0)]);
+            mailbox
+        }
+        a => panic!("{:?}", a),
+    };
+    // Could not be loaded into the controller
+    tx.returned(mailbox);
+    assert!(!tx.is_loaded(mailbox));
+    match tx.next(&mut queue) {
+        MailboxAction::Load { frame, .. } => assert_eq!(tags(&[frame]), [(1, 0)]),
+        a => panic!("{:?}", a),
+    }
+    assert_eq!(queue.len(), 2);
+}
+
+#[test]
+fn reset_after_bus_off_requeues_everything() {
+    let mut queue = TxQueue::<8, 16>::new();
+    let mut tx = TxMailboxes::<8, 3>::new();
+    let mut controller = Controller::<3>::new();
+    for seq in 0..3 {
+        queue.push(tagged(1, Priority::Nominal, seq)).unwrap();
+        queue.push(tagged(2, Priority::Low, seq)).unwrap();
+        queue.push(tagged(3, Priority::Fast, seq)).unwrap();
+    }
+    controller.service(&mut tx, &mut queue, false);
+    controller.transmit_one(&mut tx);
+    controller.service(&mut tx, &mut queue, false);
+    // Head of the queue waits for the previous frame of its session
+    assert_eq!(queue.len(), 7);
+    assert_eq!(tags(&controller.mailboxes.iter().flatten().copied().collect::<Vec<_>>()), [(3, 1)]);
+    // Bus-off: controller mailboxes are lost, the frames are not
+    controller.mailboxes = [None; 3];
+    tx.reset(&mut queue);
+    assert!((0..3).all(|i| !tx.is_loaded(i)));
+    assert_eq!(queue.len(), 8);
+    loop {
+        controller.service(&mut tx, &mut queue, false);
+        if !controller.transmit_one(&mut tx) {
+            break;
+        }
+    }
+    check_sessions_in_order(&controller.sent, &[1, 2, 3], 3);
+    assert_eq!(tags(&controller.sent[..3]), [(3, 0), (3, 1), (3, 2)]);
+}